@@ -0,0 +1,375 @@
+//! Syntax tree produced by the parser.
+
+use super::value::{Array, CellError};
+use crate::graph::CellRange;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Number of rows and columns in a sheet, as in Excel.
+pub const MAX_ROWS: i32 = 1_048_576;
+pub const MAX_COLS: i32 = 16_384;
+
+/// Character offsets `[start, end)` into the formula text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Span { start, end }
+    }
+
+    /// The smallest span covering both `self` and `other`.
+    pub fn to(self, other: Span) -> Span {
+        Span::new(self.start.min(other.start), self.end.max(other.end))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExprKind {
+    Number(f64),
+    Text(String),
+    Bool(bool),
+    Error(CellError),
+    /// An array constant such as `{1,2;3,4}`.
+    Array(Array),
+    /// A reference, on `sheet` if qualified and otherwise on the sheet of
+    /// the formula itself.
+    Reference {
+        sheet: Option<String>,
+        reference: Reference,
+    },
+    /// An identifier that is neither a function call nor a cell reference.
+    Name(String),
+    /// A structured reference such as `Sales[Amount]`, to part of a table.
+    Table {
+        table: String,
+        item: TableItem,
+    },
+    Unary {
+        op: UnaryOp,
+        operand: Box<Expr>,
+    },
+    Binary {
+        op: BinaryOp,
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    Call {
+        name: String,
+        args: Vec<Expr>,
+    },
+    /// A function argument left empty, which evaluates as blank.
+    Missing,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Plus,
+    Minus,
+    /// Postfix `%`, dividing by 100.
+    Percent,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Pow,
+    Concat,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// A zero-based cell position. `$` marks a coordinate as absolute, so it
+/// stays put when the formula is copied elsewhere.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CellRef {
+    pub row: i32,
+    pub col: i32,
+    pub row_absolute: bool,
+    pub col_absolute: bool,
+}
+
+/// A zero-based whole row or column index, as in `$A:C` or `3:$5`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineRef {
+    pub index: i32,
+    pub absolute: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reference {
+    /// `A1`
+    Cell(CellRef),
+    /// `A1:B10`
+    Range(CellRef, CellRef),
+    /// `A:C`
+    Columns(LineRef, LineRef),
+    /// `3:5`
+    Rows(LineRef, LineRef),
+}
+
+/// The part of a table a structured reference picks out. Column names are
+/// matched against the header row case-insensitively.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TableItem {
+    /// `Sales[#All]`: the header row and the data.
+    All,
+    /// `Sales[]` or `Sales[#Data]`: the rows below the header.
+    Data,
+    /// `Sales[#Headers]`: the header row.
+    Headers,
+    /// `Sales[Amount]`, or `Sales[[Qty]:[Price]]` for adjacent columns: the
+    /// data in those columns.
+    Columns(String, String),
+    /// `Sales[@Price]`: the column's value in the formula's own row.
+    ThisRow(String),
+}
+
+impl Reference {
+    /// The rectangle of cells this reference covers.
+    pub fn range(&self) -> CellRange {
+        match *self {
+            Reference::Cell(cell) => CellRange::new(cell.row, cell.col, cell.row, cell.col),
+            Reference::Range(a, b) => CellRange::new(a.row, a.col, b.row, b.col),
+            Reference::Columns(a, b) => CellRange::new(0, a.index, i32::MAX, b.index),
+            Reference::Rows(a, b) => CellRange::new(a.index, 0, b.index, i32::MAX),
+        }
+    }
+
+    /// The reference as it reads in a formula copied `rows` down and `cols`
+    /// across: relative coordinates move, absolute ones stay. `None` if it
+    /// would move off the sheet.
+    pub fn shifted(&self, rows: i32, cols: i32) -> Option<Reference> {
+        let cell = |c: CellRef| {
+            Some(CellRef {
+                row: shift(c.row, c.row_absolute, rows, MAX_ROWS)?,
+                col: shift(c.col, c.col_absolute, cols, MAX_COLS)?,
+                ..c
+            })
+        };
+        let line = |l: LineRef, by: i32, limit: i32| {
+            Some(LineRef {
+                index: shift(l.index, l.absolute, by, limit)?,
+                ..l
+            })
+        };
+        Some(match *self {
+            Reference::Cell(c) => Reference::Cell(cell(c)?),
+            Reference::Range(a, b) => Reference::Range(cell(a)?, cell(b)?),
+            Reference::Columns(a, b) => {
+                Reference::Columns(line(a, cols, MAX_COLS)?, line(b, cols, MAX_COLS)?)
+            }
+            Reference::Rows(a, b) => {
+                Reference::Rows(line(a, rows, MAX_ROWS)?, line(b, rows, MAX_ROWS)?)
+            }
+        })
+    }
+}
+
+fn shift(index: i32, absolute: bool, by: i32, limit: i32) -> Option<i32> {
+    if absolute {
+        return Some(index);
+    }
+    let index = index.checked_add(by)?;
+    (0..limit).contains(&index).then_some(index)
+}
+
+impl fmt::Display for Reference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let dollar = |absolute: bool| if absolute { "$" } else { "" };
+        let cell = |f: &mut fmt::Formatter<'_>, c: CellRef| {
+            write!(
+                f,
+                "{}{}{}{}",
+                dollar(c.col_absolute),
+                column_name(c.col),
+                dollar(c.row_absolute),
+                c.row + 1
+            )
+        };
+        match *self {
+            Reference::Cell(c) => cell(f, c),
+            Reference::Range(a, b) => {
+                cell(f, a)?;
+                write!(f, ":")?;
+                cell(f, b)
+            }
+            Reference::Columns(a, b) => write!(
+                f,
+                "{}{}:{}{}",
+                dollar(a.absolute),
+                column_name(a.index),
+                dollar(b.absolute),
+                column_name(b.index)
+            ),
+            Reference::Rows(a, b) => write!(
+                f,
+                "{}{}:{}{}",
+                dollar(a.absolute),
+                a.index + 1,
+                dollar(b.absolute),
+                b.index + 1
+            ),
+        }
+    }
+}
+
+impl Expr {
+    /// Every reference in the expression with its sheet qualifier, in source
+    /// order.
+    pub fn references(&self) -> Vec<(Option<String>, Reference)> {
+        let mut refs = Vec::new();
+        self.walk(&mut |expr| {
+            if let ExprKind::Reference { sheet, reference } = &expr.kind {
+                refs.push((sheet.clone(), *reference));
+            }
+        });
+        refs
+    }
+
+    /// Every identifier the expression uses as a value or calls as a
+    /// function, uppercase and in source order. Besides defined names these
+    /// include tables, built-in functions and names bound by `LET` or
+    /// `LAMBDA`.
+    pub fn names(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        self.walk(&mut |expr| match &expr.kind {
+            ExprKind::Name(name) => names.push(name.to_ascii_uppercase()),
+            ExprKind::Call { name, .. } => names.push(name.clone()),
+            ExprKind::Table { table, .. } => names.push(table.to_ascii_uppercase()),
+            _ => {}
+        });
+        names
+    }
+
+    /// Every structured reference in the expression, in source order.
+    pub fn tables(&self) -> Vec<(String, TableItem)> {
+        let mut tables = Vec::new();
+        self.walk(&mut |expr| {
+            if let ExprKind::Table { table, item } = &expr.kind {
+                tables.push((table.clone(), item.clone()));
+            }
+        });
+        tables
+    }
+
+    /// Visits `self` and every sub-expression, parents before children.
+    pub fn walk(&self, visit: &mut impl FnMut(&Expr)) {
+        visit(self);
+        match &self.kind {
+            ExprKind::Unary { operand, .. } => operand.walk(visit),
+            ExprKind::Binary { left, right, .. } => {
+                left.walk(visit);
+                right.walk(visit);
+            }
+            ExprKind::Call { args, .. } => {
+                for arg in args {
+                    arg.walk(visit);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Column letters (`A`, `XFD`) to a zero-based index, if within the sheet.
+pub fn parse_column(letters: &str) -> Option<i32> {
+    if letters.is_empty() || letters.len() > 3 || !letters.chars().all(|c| c.is_ascii_alphabetic())
+    {
+        return None;
+    }
+    let col = letters.chars().fold(0, |acc, c| {
+        acc * 26 + (c.to_ascii_uppercase() as i32 - 'A' as i32 + 1)
+    }) - 1;
+    (col < MAX_COLS).then_some(col)
+}
+
+/// A one-based row number to a zero-based index, if within the sheet.
+pub fn parse_row(digits: &str) -> Option<i32> {
+    if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let row = digits.parse::<i32>().ok()?;
+    (1..=MAX_ROWS).contains(&row).then_some(row - 1)
+}
+
+/// Splits off a leading `$`, reporting whether there was one.
+fn absolute(text: &str) -> (bool, &str) {
+    match text.strip_prefix('$') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    }
+}
+
+/// An `A1`-style cell name, case-insensitive, with optional `$` markers.
+pub fn parse_cell_name(name: &str) -> Option<CellRef> {
+    let (col_absolute, rest) = absolute(name);
+    let split = rest.find(|c: char| c.is_ascii_digit() || c == '$')?;
+    let (letters, digits) = rest.split_at(split);
+    let (row_absolute, digits) = absolute(digits);
+    Some(CellRef {
+        row: parse_row(digits)?,
+        col: parse_column(letters)?,
+        row_absolute,
+        col_absolute,
+    })
+}
+
+/// Column letters with an optional `$`, one end of `A:C`.
+pub fn parse_column_name(name: &str) -> Option<LineRef> {
+    let (absolute, letters) = absolute(name);
+    Some(LineRef {
+        index: parse_column(letters)?,
+        absolute,
+    })
+}
+
+/// A row number with an optional `$`, one end of `3:5`.
+pub fn parse_row_name(name: &str) -> Option<LineRef> {
+    let (absolute, digits) = absolute(name);
+    Some(LineRef {
+        index: parse_row(digits)?,
+        absolute,
+    })
+}
+
+/// A sheet name as written in a reference, quoted when it is not a plain
+/// identifier: `Data`, `'My Sheet'`, `'Bob''s'`.
+pub fn quote_sheet(name: &str) -> String {
+    let plain = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if plain {
+        name.to_string()
+    } else {
+        format!("'{}'", name.replace('\'', "''"))
+    }
+}
+
+/// Column letters for a zero-based column index, e.g. 0 -> `A`, 27 -> `AB`.
+pub fn column_name(col: i32) -> String {
+    let mut name = Vec::new();
+    let mut n = col + 1;
+    while n > 0 {
+        let rem = (n - 1) % 26;
+        name.push(b'A' + rem as u8);
+        n = (n - 1) / 26;
+    }
+    name.reverse();
+    String::from_utf8(name).unwrap()
+}
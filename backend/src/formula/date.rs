@@ -0,0 +1,230 @@
+//! Excel's 1900 date system: a date is the number of days since 1899-12-31,
+//! so 1900-01-01 is 1, and the time of day is the fraction of a day.
+//!
+//! Excel counts a 29 February 1900 that never existed (serial 60), so dates
+//! from March 1900 on are one day further from the epoch than the calendar
+//! says. That quirk is kept so serials match Excel's.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The serial number of 1970-01-01.
+const UNIX_SERIAL: i64 = 25_569;
+/// Days from 1970-01-01 to 1900-03-01, the first date past the phantom leap
+/// day.
+const MARCH_1900: i64 = -25_508;
+/// The serial number of 9999-12-31, the last date Excel accepts.
+pub const MAX_SERIAL: f64 = 2_958_465.0;
+
+/// Days since 1970-01-01 of a date in the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_index = (month + 9) % 12;
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// The date `days` after 1970-01-01.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// The serial number of a date. Months and days outside their usual range
+/// roll over into the next or previous month or year, as in `DATE`.
+pub fn serial(year: i64, month: i64, day: i64) -> f64 {
+    let year = year + (month - 1).div_euclid(12);
+    let month = (month - 1).rem_euclid(12) + 1;
+    let days = days_from_civil(year, month, 1) + day - 1;
+    if days >= MARCH_1900 {
+        (days + UNIX_SERIAL) as f64
+    } else {
+        (days + UNIX_SERIAL - 1) as f64
+    }
+}
+
+/// The year, month and day of a serial number's date. Serial 0 is Excel's
+/// 1900-01-00 and serial 60 the phantom 1900-02-29.
+pub fn civil(serial: f64) -> (i64, u32, u32) {
+    let days = serial.floor() as i64;
+    match days {
+        0 => (1900, 1, 0),
+        60 => (1900, 2, 29),
+        d if d < 60 => civil_from_days(d - UNIX_SERIAL + 1),
+        d => civil_from_days(d - UNIX_SERIAL),
+    }
+}
+
+/// The number of days in a month.
+pub fn days_in_month(year: i64, month: u32) -> u32 {
+    let next = serial(year, i64::from(month) + 1, 1);
+    let first = serial(year, i64::from(month), 1);
+    // 1900-02 has Excel's phantom 29th.
+    if year == 1900 && month == 2 {
+        29
+    } else {
+        (next - first) as u32
+    }
+}
+
+/// The hour, minute and second of a serial number's time of day, to the
+/// nearest second.
+pub fn time_of_day(serial: f64) -> (u32, u32, u32) {
+    let seconds = ((serial - serial.floor()) * 86_400.0).round() as u32 % 86_400;
+    (seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
+
+/// The current date and time as a serial number, from the server's clock in
+/// UTC.
+pub fn now() -> f64 {
+    let elapsed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    UNIX_SERIAL as f64 + elapsed.as_secs_f64() / 86_400.0
+}
+
+/// The serial number of a date or time typed as text: `2026-01-31`,
+/// `2026/01/31`, `1/31/2026`, optionally followed by a time, or a time on
+/// its own such as `14:30`, `14:30:15` or `2:30 PM`.
+pub fn parse(text: &str) -> Option<f64> {
+    let text = text.trim();
+    let (date, time) = match text.split_once(' ') {
+        Some((date, time)) if date.contains(['-', '/']) => (Some(date), Some(time.trim())),
+        _ if text.contains(['-', '/']) => (Some(text), None),
+        _ => (None, Some(text)),
+    };
+    let date = match date {
+        Some(date) => parse_date(date)?,
+        None => 0.0,
+    };
+    let time = match time {
+        Some(time) => parse_time(time)?,
+        None => 0.0,
+    };
+    Some(date + time)
+}
+
+fn parse_date(text: &str) -> Option<f64> {
+    let separator = if text.contains('-') { '-' } else { '/' };
+    let parts: Vec<&str> = text.split(separator).collect();
+    let [a, b, c] = parts[..] else {
+        return None;
+    };
+    let number = |s: &str| -> Option<i64> {
+        (!s.is_empty() && s.len() <= 4 && s.bytes().all(|b| b.is_ascii_digit()))
+            .then(|| s.parse().ok())
+            .flatten()
+    };
+    // Year first (ISO), or month/day/year.
+    let (year, month, day) = if a.len() == 4 {
+        (number(a)?, number(b)?, number(c)?)
+    } else if c.len() == 4 && separator == '/' {
+        (number(c)?, number(a)?, number(b)?)
+    } else {
+        return None;
+    };
+    if !(1900..=9999).contains(&year) || !(1..=12).contains(&month) {
+        return None;
+    }
+    if day < 1 || day > i64::from(days_in_month(year, month as u32)) {
+        return None;
+    }
+    Some(serial(year, month, day))
+}
+
+fn parse_time(text: &str) -> Option<f64> {
+    let upper = text.to_ascii_uppercase();
+    let (clock, meridiem) = if let Some(rest) = upper.strip_suffix("AM") {
+        (rest.trim_end(), Some(false))
+    } else if let Some(rest) = upper.strip_suffix("PM") {
+        (rest.trim_end(), Some(true))
+    } else {
+        (upper.as_str(), None)
+    };
+    let parts: Vec<&str> = clock.split(':').collect();
+    if !(2..=3).contains(&parts.len())
+        || parts
+            .iter()
+            .any(|p| p.is_empty() || p.len() > 2 || !p.bytes().all(|b| b.is_ascii_digit()))
+    {
+        return None;
+    }
+    let mut hour: u32 = parts[0].parse().ok()?;
+    let minute: u32 = parts[1].parse().ok()?;
+    let second: u32 = parts.get(2).map_or(Some(0), |s| s.parse().ok())?;
+    if minute > 59 || second > 59 {
+        return None;
+    }
+    match meridiem {
+        Some(pm) if (1..=12).contains(&hour) => hour = hour % 12 + if pm { 12 } else { 0 },
+        Some(_) => return None,
+        None if hour > 23 => return None,
+        None => {}
+    }
+    Some(f64::from(hour * 3600 + minute * 60 + second) / 86_400.0)
+}
+
+/// A serial number as shown in a cell: `2026-01-31`, `14:30`, or both.
+pub fn display(serial: f64) -> String {
+    let (year, month, day) = civil(serial);
+    let (hour, minute, second) = time_of_day(serial);
+    let time = if second == 0 {
+        format!("{:02}:{:02}", hour, minute)
+    } else {
+        format!("{:02}:{:02}:{:02}", hour, minute, second)
+    };
+    let date = format!("{:04}-{:02}-{:02}", year, month, day);
+    if serial.fract() == 0.0 {
+        date
+    } else if serial < 1.0 {
+        time
+    } else {
+        format!("{} {}", date, time)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serials_match_excel() {
+        assert_eq!(serial(1900, 1, 1), 1.0);
+        assert_eq!(serial(1900, 2, 28), 59.0);
+        assert_eq!(serial(1900, 3, 1), 61.0);
+        assert_eq!(serial(2026, 1, 31), 46053.0);
+        assert_eq!(serial(2025, 14, 0), 46053.0);
+        assert_eq!(civil(46053.75), (2026, 1, 31));
+        assert_eq!(civil(60.0), (1900, 2, 29));
+        assert_eq!(civil(59.0), (1900, 2, 28));
+        assert_eq!(civil(MAX_SERIAL), (9999, 12, 31));
+    }
+
+    #[test]
+    fn typed_dates_parse() {
+        assert_eq!(parse("2026-01-31"), Some(46053.0));
+        assert_eq!(parse("1/31/2026"), Some(46053.0));
+        assert_eq!(parse("2026-01-31 18:00"), Some(46053.75));
+        assert_eq!(parse("6:00 PM"), Some(0.75));
+        assert_eq!(parse("2026-02-30"), None);
+        assert_eq!(parse("12-5"), None);
+        assert_eq!(parse("25:00"), None);
+        assert_eq!(display(46053.75), "2026-01-31 18:00");
+        assert_eq!(display(0.5), "12:00");
+    }
+}
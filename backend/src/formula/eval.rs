@@ -0,0 +1,884 @@
+//! Evaluates a parsed formula against the cells stored in SQLite.
+//!
+//! Excel errors such as `#DIV/0!` are ordinary [`Value`]s that propagate
+//! through operators and functions; `Err` is reserved for formulas that do
+//! not parse and for failures reading the database.
+
+use super::FormulaError;
+use super::ast::{BinaryOp, Expr, ExprKind, Reference, Span, TableItem, UnaryOp};
+use super::date;
+use super::functions;
+use super::parser::parse;
+use super::value::{Array, CellError, Value, ValueType};
+use crate::graph::{CellKey, CellRange, SheetRange};
+use crate::table::{self, Table};
+use rusqlite::{Connection, OptionalExtension, params};
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::rc::Rc;
+
+/// Parses and evaluates `source` on `sheet`, outside any cell. The server
+/// uses [`trace`] instead, to report where errors appeared.
+#[cfg(test)]
+pub fn evaluate(source: &str, sheet: &str, conn: &Connection) -> Result<Value, FormulaError> {
+    Evaluator {
+        conn,
+        sheet,
+        cell: None,
+        scope: Scope::default(),
+        depth: 0,
+        dynamic: &RefCell::default(),
+        trace: None,
+    }
+    .run(source)
+}
+
+/// One sub-expression of a traced formula and what it evaluated to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Step {
+    /// The sub-expression as written.
+    pub expression: String,
+    pub span: Span,
+    /// The cells it refers to, if it is a reference, a name or table
+    /// standing for one, or a call to `INDIRECT` or `OFFSET`.
+    pub reference: Option<SheetRange>,
+    /// The function it calls, if it is a function call.
+    pub function: Option<String>,
+    pub value: Value,
+    /// The sub-expressions evaluated to work it out, in evaluation order.
+    pub steps: Vec<Step>,
+}
+
+impl Step {
+    /// The step where an error first appeared: the first, in evaluation
+    /// order, whose value is an error that none of its own steps gave it.
+    pub fn first_error(&self) -> Option<&Step> {
+        self.steps
+            .iter()
+            .find_map(Step::first_error)
+            .or_else(|| matches!(self.value, Value::Error(_)).then_some(self))
+    }
+}
+
+/// A traced formula.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trace {
+    /// The whole formula, whose value is the formula's result.
+    pub root: Step,
+    /// The ranges it read, in the order first read, including those read
+    /// by the names and lambdas it uses.
+    pub read: Vec<SheetRange>,
+}
+
+/// Parses and evaluates `source` on `sheet`, outside any cell, recording
+/// every sub-expression of it that is evaluated. Defined names and lambda
+/// bodies appear as the value they gave rather than step by step.
+pub fn trace(source: &str, sheet: &str, conn: &Connection) -> Result<Trace, FormulaError> {
+    let tracer = RefCell::new(Tracer {
+        source: source.chars().collect(),
+        exprs: HashSet::new(),
+        steps: vec![Vec::new()],
+        read: Vec::new(),
+    });
+    let value = Evaluator {
+        conn,
+        sheet,
+        cell: None,
+        scope: Scope::default(),
+        depth: 0,
+        dynamic: &RefCell::default(),
+        trace: Some(&tracer),
+    }
+    .run(source)?;
+    let Tracer {
+        mut steps, read, ..
+    } = tracer.into_inner();
+    let mut root = steps.swap_remove(0).swap_remove(0);
+    // The result as a cell would show it, e.g. a blank as 0.
+    root.value = value;
+    Ok(Trace { root, read })
+}
+
+/// The steps of a formula being traced.
+pub(super) struct Tracer {
+    source: Vec<char>,
+    /// The sub-expressions of the formula; those of names and lambdas
+    /// evaluated on the way are not traced.
+    exprs: HashSet<*const Expr>,
+    /// The steps of each sub-expression being evaluated, outermost first.
+    steps: Vec<Vec<Step>>,
+    /// The ranges read so far, each once.
+    read: Vec<SheetRange>,
+}
+
+impl Tracer {
+    /// Starts a step for `expr` if it is part of the formula, returning
+    /// whether it did.
+    fn open(&mut self, expr: &Expr) -> bool {
+        let traced = self.exprs.contains(&(expr as *const Expr));
+        if traced {
+            self.steps.push(Vec::new());
+        }
+        traced
+    }
+
+    /// Notes that `range` on `sheet` was read.
+    fn read(&mut self, sheet: &str, range: CellRange) {
+        let range = SheetRange::new(sheet, range);
+        if !self.read.contains(&range) {
+            self.read.push(range);
+        }
+    }
+}
+
+/// A cell's formula, evaluated.
+#[derive(Debug, PartialEq)]
+pub struct Evaluation {
+    pub value: Value,
+    /// The ranges it reached through `INDIRECT` or `OFFSET`, which only
+    /// evaluating it reveals.
+    pub dynamic: Vec<SheetRange>,
+}
+
+/// Parses and evaluates `source` as the formula of `cell`, which functions
+/// such as `ROW()` refer to.
+pub fn evaluate_cell(
+    source: &str,
+    cell: &CellKey,
+    conn: &Connection,
+) -> Result<Evaluation, FormulaError> {
+    let dynamic = RefCell::default();
+    let value = Evaluator {
+        conn,
+        sheet: &cell.sheet,
+        cell: Some((cell.row, cell.col)),
+        scope: Scope::default(),
+        depth: 0,
+        dynamic: &dynamic,
+        trace: None,
+    }
+    .run(source)?;
+    Ok(Evaluation {
+        value,
+        dynamic: dynamic.into_inner(),
+    })
+}
+
+/// The most cells a range is read in full; larger ones are cut down to the
+/// populated part of the sheet.
+const MAX_DENSE_CELLS: i64 = 1 << 20;
+
+/// How deeply lambda calls and defined names may nest, as when a lambda
+/// calls itself, before the innermost one gives `#NUM!`.
+const MAX_CALL_DEPTH: usize = 64;
+
+/// A formula saved under a defined name.
+pub(super) struct Definition {
+    pub(super) formula: String,
+    /// The sheet the name is scoped to, or `None` for the whole workbook.
+    pub(super) sheet: Option<String>,
+}
+
+/// The definition `name` has for formulas on `sheet`: the one scoped to
+/// that sheet if there is one, or else the workbook's.
+pub(super) fn definition(
+    conn: &Connection,
+    name: &str,
+    sheet: &str,
+) -> rusqlite::Result<Option<Definition>> {
+    conn.prepare_cached(
+        "SELECT formula, scope FROM names WHERE name = ?1 AND scope IN (?2, '')
+         ORDER BY scope = '' LIMIT 1",
+    )?
+    .query_row(params![name, sheet], |r| {
+        let scope: String = r.get(1)?;
+        Ok(Definition {
+            formula: r.get(0)?,
+            sheet: (!scope.is_empty()).then_some(scope),
+        })
+    })
+    .optional()
+}
+
+/// The cells `item` of `table` covers. A column missing from the header
+/// row gives `#REF!`. `@` picks the row `at`, a sheet and row, and gives
+/// `#VALUE!` unless that is one of the table's data rows.
+pub(super) fn structured(
+    conn: &Connection,
+    table: &Table,
+    item: &TableItem,
+    at: Option<(&str, i32)>,
+) -> rusqlite::Result<Result<SheetRange, CellError>> {
+    let column = |columns: &[String], name: &str| {
+        columns
+            .iter()
+            .position(|column| column.eq_ignore_ascii_case(name))
+            .map(|i| table.range.start_col + i as i32)
+            .ok_or(CellError::Ref)
+    };
+    let data = table.data();
+    let range = match item {
+        TableItem::All => Ok(table.range),
+        TableItem::Data => Ok(data),
+        TableItem::Headers => Ok(table.headers()),
+        TableItem::Columns(first, last) => {
+            let columns = table::columns(conn, table)?;
+            column(&columns, first).and_then(|first| {
+                let last = column(&columns, last)?;
+                Ok(CellRange::new(data.start_row, first, data.end_row, last))
+            })
+        }
+        TableItem::ThisRow(name) => {
+            let columns = table::columns(conn, table)?;
+            match at {
+                Some((sheet, row))
+                    if sheet == table.sheet && (data.start_row..=data.end_row).contains(&row) =>
+                {
+                    column(&columns, name).map(|col| CellRange::new(row, col, row, col))
+                }
+                _ => Err(CellError::Value),
+            }
+        }
+    };
+    Ok(range.map(|range| SheetRange::new(&table.sheet, range)))
+}
+
+/// A `LAMBDA`: its parameter names, uppercase, and its body, which sees the
+/// names that were in scope where the lambda was written.
+pub(super) struct Lambda {
+    pub(super) params: Vec<String>,
+    pub(super) body: Expr,
+    pub(super) scope: Scope,
+}
+
+/// What a name stands for within a `LET` or a lambda's body.
+#[derive(Clone)]
+pub(super) enum Binding {
+    Value(Value),
+    Lambda(Rc<Lambda>),
+}
+
+/// The names bound around an expression, innermost first.
+#[derive(Clone, Default)]
+pub(super) struct Scope(Option<Rc<Frame>>);
+
+struct Frame {
+    name: String,
+    binding: Binding,
+    parent: Scope,
+}
+
+impl Scope {
+    /// This scope with `name` bound to `binding`, hiding any outer binding
+    /// of the same name.
+    pub(super) fn with(&self, name: String, binding: Binding) -> Scope {
+        Scope(Some(Rc::new(Frame {
+            name,
+            binding,
+            parent: self.clone(),
+        })))
+    }
+
+    fn get(&self, name: &str) -> Option<&Binding> {
+        let mut scope = self;
+        while let Some(frame) = &scope.0 {
+            if frame.name.eq_ignore_ascii_case(name) {
+                return Some(&frame.binding);
+            }
+            scope = &frame.parent;
+        }
+        None
+    }
+}
+
+pub(super) struct Evaluator<'a> {
+    conn: &'a Connection,
+    sheet: &'a str,
+    /// The row and column of the cell being calculated, if any.
+    cell: Option<(i32, i32)>,
+    /// Names bound by enclosing `LET`s and lambda calls.
+    scope: Scope,
+    /// How many lambda calls and defined names deep the evaluation is.
+    depth: usize,
+    /// Ranges reached through `INDIRECT` and `OFFSET` so far.
+    dynamic: &'a RefCell<Vec<SheetRange>>,
+    /// Where to record each step, when tracing.
+    trace: Option<&'a RefCell<Tracer>>,
+}
+
+impl Evaluator<'_> {
+    fn run(&self, source: &str) -> Result<Value, FormulaError> {
+        let expr = parse(source)?;
+        if let Some(tracer) = self.trace {
+            expr.walk(&mut |e| {
+                tracer.borrow_mut().exprs.insert(e);
+            });
+        }
+        // A formula that only points at a blank cell shows 0, as in Excel.
+        let blank_as_zero = |value| match value {
+            Value::Empty => Value::Number(0.0),
+            value => value,
+        };
+        Ok(match self.eval(&expr)? {
+            Value::Empty => Value::Number(0.0),
+            Value::Array(array) if array.rows == 1 && array.cols == 1 => {
+                blank_as_zero(array.values.into_iter().next().unwrap())
+            }
+            // A block of values spills into the cells around the formula.
+            Value::Array(array) => Value::Array(Array::new(
+                array.rows,
+                array.cols,
+                array.values.into_iter().map(blank_as_zero).collect(),
+            )),
+            Value::Number(n) if (0.0..=date::MAX_SERIAL).contains(&n) && self.is_date(&expr) => {
+                Value::Date(n)
+            }
+            value => value,
+        })
+    }
+
+    /// Whether `expr` works out a date, so its result is shown as one: a
+    /// call to a function such as `DATE` or `TODAY`, a date cell, or a date
+    /// plus or minus a number of days.
+    fn is_date(&self, expr: &Expr) -> bool {
+        match &expr.kind {
+            ExprKind::Call { name, .. } => functions::returns_date(name),
+            ExprKind::Reference {
+                sheet,
+                reference: Reference::Cell(cell),
+            } => self
+                .conn
+                .prepare_cached(
+                    "SELECT value_type FROM cells WHERE sheet = ?1 AND row = ?2 AND col = ?3",
+                )
+                .and_then(|mut stmt| {
+                    stmt.query_row(
+                        params![sheet.as_deref().unwrap_or(self.sheet), cell.row, cell.col],
+                        |r| r.get::<_, Option<String>>(0),
+                    )
+                })
+                .is_ok_and(|value_type| value_type.as_deref() == Some(ValueType::Date.as_str())),
+            ExprKind::Binary {
+                op: BinaryOp::Add,
+                left,
+                right,
+            } => self.is_date(left) || self.is_date(right),
+            ExprKind::Binary {
+                op: BinaryOp::Sub,
+                left,
+                right,
+            } => self.is_date(left) && !self.is_date(right),
+            _ => false,
+        }
+    }
+
+    pub(super) fn eval(&self, expr: &Expr) -> Result<Value, FormulaError> {
+        let traced = self.trace.filter(|tracer| tracer.borrow_mut().open(expr));
+        let value = match &expr.kind {
+            ExprKind::Number(n) => Ok(Value::Number(*n)),
+            ExprKind::Text(text) => Ok(Value::Text(text.clone())),
+            ExprKind::Bool(b) => Ok(Value::Bool(*b)),
+            ExprKind::Error(e) => Ok(Value::Error(*e)),
+            ExprKind::Array(array) => Ok(Value::Array(array.clone())),
+            ExprKind::Reference { sheet, reference } => {
+                self.reference(sheet.as_deref().unwrap_or(self.sheet), reference)
+            }
+            ExprKind::Table { table, item } => self.table(table, item),
+            ExprKind::Name(name) => match self.scope.get(name) {
+                Some(Binding::Value(value)) => Ok(value.clone()),
+                // A lambda only has a value once it is called.
+                Some(Binding::Lambda(_)) => Ok(Value::Error(CellError::Calc)),
+                None => self.defined_name(name),
+            },
+            ExprKind::Missing => Ok(Value::Empty),
+            ExprKind::Unary { op, operand } => self.unary(*op, operand),
+            ExprKind::Binary { op, left, right } => self.binary(*op, left, right),
+            ExprKind::Call { name, args } => functions::call(self, name, args),
+        };
+        match traced {
+            Some(tracer) => self.record(tracer, expr, value),
+            None => value,
+        }
+    }
+
+    /// `+x`, `-x` or `x%`, element by element.
+    fn unary(&self, op: UnaryOp, operand: &Expr) -> Result<Value, FormulaError> {
+        Ok(elementwise(
+            &self.eval(operand)?,
+            &Value::Empty,
+            |value, _| {
+                let value = match value.to_number() {
+                    Ok(value) => value,
+                    Err(e) => return Value::Error(e),
+                };
+                Value::Number(match op {
+                    UnaryOp::Plus => value,
+                    UnaryOp::Minus => -value,
+                    UnaryOp::Percent => value / 100.0,
+                })
+            },
+        ))
+    }
+
+    /// `left op right`, element by element.
+    fn binary(&self, op: BinaryOp, left: &Expr, right: &Expr) -> Result<Value, FormulaError> {
+        let left = self.eval(left)?;
+        let right = self.eval(right)?;
+        Ok(elementwise(&left, &right, |left, right| {
+            binary(op, left, right).unwrap_or_else(Value::Error)
+        }))
+    }
+
+    /// Records `expr` as a step holding the steps recorded while it was
+    /// evaluated.
+    fn record(
+        &self,
+        tracer: &RefCell<Tracer>,
+        expr: &Expr,
+        value: Result<Value, FormulaError>,
+    ) -> Result<Value, FormulaError> {
+        let steps = tracer.borrow_mut().steps.pop().unwrap_or_default();
+        let value = value?;
+        let reference = match &expr.kind {
+            ExprKind::Reference { .. }
+            | ExprKind::Table { .. }
+            | ExprKind::Name(_)
+            | ExprKind::Call { .. } => self.untraced().target(expr)?.and_then(Result::ok),
+            _ => None,
+        };
+        let mut tracer = tracer.borrow_mut();
+        let expression = tracer.source[expr.span.start..expr.span.end]
+            .iter()
+            .collect();
+        let function = match &expr.kind {
+            ExprKind::Call { name, .. } => Some(name.clone()),
+            _ => None,
+        };
+        if let Some(parent) = tracer.steps.last_mut() {
+            parent.push(Step {
+                expression,
+                span: expr.span,
+                reference,
+                function,
+                value: value.clone(),
+                steps,
+            });
+        }
+        Ok(value)
+    }
+
+    /// This evaluator without tracing, to look at an expression again
+    /// without recording it twice.
+    fn untraced(&self) -> Evaluator<'_> {
+        Evaluator {
+            trace: None,
+            ..self.within(self.scope.clone())
+        }
+    }
+
+    /// The row and column of the cell being calculated, if any.
+    pub(super) fn cell(&self) -> Option<(i32, i32)> {
+        self.cell
+    }
+
+    /// What `name` is bound to by an enclosing `LET` or lambda call.
+    pub(super) fn binding(&self, name: &str) -> Option<&Binding> {
+        self.scope.get(name)
+    }
+
+    /// An evaluator for expressions inside `scope`, as in the body of a
+    /// `LET`.
+    pub(super) fn within(&self, scope: Scope) -> Evaluator<'_> {
+        Evaluator {
+            conn: self.conn,
+            sheet: self.sheet,
+            cell: self.cell,
+            scope,
+            depth: self.depth,
+            dynamic: self.dynamic,
+            trace: self.trace,
+        }
+    }
+
+    /// The scope expressions are evaluated in.
+    pub(super) fn scope(&self) -> &Scope {
+        &self.scope
+    }
+
+    /// Calls `lambda` with `args`, one for each of its parameters.
+    pub(super) fn invoke(
+        &self,
+        lambda: &Lambda,
+        args: Vec<Binding>,
+    ) -> Result<Value, FormulaError> {
+        if args.len() != lambda.params.len() {
+            return Ok(Value::Error(CellError::Value));
+        }
+        if self.depth >= MAX_CALL_DEPTH {
+            return Ok(Value::Error(CellError::Num));
+        }
+        let mut scope = lambda.scope.clone();
+        for (param, arg) in lambda.params.iter().zip(args) {
+            scope = scope.with(param.clone(), arg);
+        }
+        Evaluator {
+            depth: self.depth + 1,
+            ..self.within(scope)
+        }
+        .eval(&lambda.body)
+    }
+
+    /// An evaluator for the formula of `definition`, which sees no `LET`
+    /// names and counts as one call deeper.
+    fn in_definition<'b>(&'b self, definition: &'b Definition) -> Evaluator<'b> {
+        Evaluator {
+            conn: self.conn,
+            sheet: definition.sheet.as_deref().unwrap_or(self.sheet),
+            cell: self.cell,
+            scope: Scope::default(),
+            depth: self.depth + 1,
+            dynamic: self.dynamic,
+            trace: self.trace,
+        }
+    }
+
+    /// The cells `expr` refers to, if it is a reference: written out, as a
+    /// structured reference, as a defined name for one, or built by
+    /// `INDIRECT` or `OFFSET`. `Err` holds the error a reference that
+    /// cannot be resolved gives, such as `#REF!`.
+    pub(super) fn target(
+        &self,
+        expr: &Expr,
+    ) -> Result<Option<Result<SheetRange, CellError>>, FormulaError> {
+        Ok(match &expr.kind {
+            ExprKind::Reference { sheet, reference } => Some(Ok(SheetRange::new(
+                sheet.as_deref().unwrap_or(self.sheet),
+                reference.range(),
+            ))),
+            ExprKind::Table { table, item } => {
+                let Some(table) = table::find(self.conn, table).map_err(storage)? else {
+                    return Ok(Some(Err(CellError::Ref)));
+                };
+                let at = self.cell.map(|(row, _)| (self.sheet, row));
+                Some(structured(self.conn, &table, item, at).map_err(storage)?)
+            }
+            ExprKind::Name(name) if self.scope.get(name).is_none() => {
+                let Some(definition) = definition(self.conn, name, self.sheet).map_err(storage)?
+                else {
+                    return Ok(None);
+                };
+                if self.depth >= MAX_CALL_DEPTH {
+                    return Ok(None);
+                }
+                let expr = parse(&definition.formula)?;
+                self.in_definition(&definition).target(&expr)?
+            }
+            ExprKind::Call { name, args } if self.scope.get(name).is_none() => {
+                functions::target(self, name, args)?
+            }
+            _ => None,
+        })
+    }
+
+    /// Notes that the formula reached `range` in a way only evaluating it
+    /// reveals, as through `INDIRECT`.
+    pub(super) fn reached(&self, range: &SheetRange) {
+        let mut dynamic = self.dynamic.borrow_mut();
+        if !dynamic.contains(range) {
+            dynamic.push(range.clone());
+        }
+    }
+
+    /// The values of a structured reference; `#REF!` if there is no such
+    /// table.
+    fn table(&self, name: &str, item: &TableItem) -> Result<Value, FormulaError> {
+        let Some(table) = table::find(self.conn, name).map_err(storage)? else {
+            return Ok(Value::Error(CellError::Ref));
+        };
+        let at = self.cell.map(|(row, _)| (self.sheet, row));
+        match structured(self.conn, &table, item, at).map_err(storage)? {
+            Ok(target) => self.fetch(Some(&target.sheet), &target.range),
+            Err(e) => Ok(Value::Error(e)),
+        }
+    }
+
+    /// The `LAMBDA` saved under the defined name `name`, if there is one.
+    pub(super) fn defined_lambda(&self, name: &str) -> Result<Option<Rc<Lambda>>, FormulaError> {
+        let Some(definition) = definition(self.conn, name, self.sheet).map_err(storage)? else {
+            return Ok(None);
+        };
+        // Saved lambdas only see their own parameters.
+        Ok(lambda(&parse(&definition.formula)?, &Scope::default()))
+    }
+
+    /// The value of the defined name `name`. References in a name scoped to
+    /// a sheet are on that sheet; in a workbook name they are on the sheet
+    /// of the formula using it.
+    fn defined_name(&self, name: &str) -> Result<Value, FormulaError> {
+        let Some(definition) = definition(self.conn, name, self.sheet).map_err(storage)? else {
+            return Ok(Value::Error(CellError::Name));
+        };
+        let expr = parse(&definition.formula)?;
+        if lambda(&expr, &Scope::default()).is_some() {
+            // A lambda only has a value once it is called.
+            return Ok(Value::Error(CellError::Calc));
+        }
+        if self.depth >= MAX_CALL_DEPTH {
+            return Ok(Value::Error(CellError::Num));
+        }
+        self.in_definition(&definition).eval(&expr)
+    }
+
+    /// A single cell evaluates to its value; a range to an array of the
+    /// values it covers.
+    fn reference(&self, sheet: &str, reference: &Reference) -> Result<Value, FormulaError> {
+        match reference {
+            Reference::Cell(cell) => self.cell_value(sheet, cell.row, cell.col),
+            _ => self.fetch(Some(sheet), &reference.range()),
+        }
+    }
+
+    /// The values in `range` on `sheet` (by default the formula's own): the
+    /// value itself for a single cell, and otherwise an array.
+    pub(super) fn fetch(
+        &self,
+        sheet: Option<&str>,
+        range: &CellRange,
+    ) -> Result<Value, FormulaError> {
+        let sheet = sheet.unwrap_or(self.sheet);
+        if range.is_single() {
+            return self.cell_value(sheet, range.start_row, range.start_col);
+        }
+        if let Some(tracer) = self.trace {
+            tracer.borrow_mut().read(sheet, *range);
+        }
+        self.range_values(sheet, range).map_err(storage)
+    }
+
+    fn cell_value(&self, sheet: &str, row: i32, col: i32) -> Result<Value, FormulaError> {
+        if let Some(tracer) = self.trace {
+            tracer
+                .borrow_mut()
+                .read(sheet, CellRange::new(row, col, row, col));
+        }
+        self.conn
+            .prepare_cached(
+                "SELECT value, value_type, number FROM cells
+                 WHERE sheet = ?1 AND row = ?2 AND col = ?3",
+            )
+            .and_then(|mut stmt| {
+                stmt.query_row(params![sheet, row, col], |r| {
+                    Ok(stored_value(r.get(0)?, r.get(1)?, r.get(2)?))
+                })
+                .optional()
+            })
+            .map(|stored| stored.unwrap_or(Value::Empty))
+            .map_err(storage)
+    }
+
+    /// The values in `range` as an array, fetched with a single query. Whole
+    /// columns and rows, and other ranges too large to hold in memory, only
+    /// reach as far as the sheet's last populated row and column, so `A:A`
+    /// and `B:B` are always the same size; one still too large gives `#NUM!`.
+    fn range_values(&self, sheet: &str, range: &CellRange) -> rusqlite::Result<Value> {
+        let area = |range: &CellRange| {
+            (range.end_row as i64 - range.start_row as i64 + 1)
+                * (range.end_col as i64 - range.start_col as i64 + 1)
+        };
+        let mut range = *range;
+        if area(&range) > MAX_DENSE_CELLS {
+            let (last_row, last_col): (Option<i32>, Option<i32>) = self
+                .conn
+                .prepare_cached("SELECT MAX(row), MAX(col) FROM cells WHERE sheet = ?1")?
+                .query_row(params![sheet], |r| Ok((r.get(0)?, r.get(1)?)))?;
+            range.end_row = range
+                .end_row
+                .min(last_row.unwrap_or(0).max(range.start_row));
+            range.end_col = range
+                .end_col
+                .min(last_col.unwrap_or(0).max(range.start_col));
+            if area(&range) > MAX_DENSE_CELLS {
+                return Ok(Value::Error(CellError::Num));
+            }
+        }
+
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT row, col, value, value_type, number FROM cells
+             WHERE sheet = ?1 AND row BETWEEN ?2 AND ?3 AND col BETWEEN ?4 AND ?5",
+        )?;
+        let rows = range.rows() as usize;
+        let cols = range.cols() as usize;
+        let mut values = vec![Value::Empty; rows * cols];
+        let cells = stmt.query_map(
+            params![
+                sheet,
+                range.start_row,
+                range.end_row,
+                range.start_col,
+                range.end_col
+            ],
+            |r| {
+                Ok((
+                    r.get::<_, i32>(0)?,
+                    r.get::<_, i32>(1)?,
+                    stored_value(r.get(2)?, r.get(3)?, r.get(4)?),
+                ))
+            },
+        )?;
+        for cell in cells {
+            let (row, col, value) = cell?;
+            let row = (row - range.start_row) as usize;
+            let col = (col - range.start_col) as usize;
+            values[row * cols + col] = value;
+        }
+        Ok(Value::Array(Array::new(rows, cols, values)))
+    }
+}
+
+/// The parameter names of `LAMBDA(param, ..., body)` given its arguments,
+/// if they are distinct names and a body follows them.
+pub(super) fn lambda_params(args: &[Expr]) -> Option<Vec<String>> {
+    let (_, params) = args.split_last()?;
+    let mut names: Vec<String> = Vec::with_capacity(params.len());
+    for param in params {
+        match &param.kind {
+            ExprKind::Name(name) if !names.contains(&name.to_ascii_uppercase()) => {
+                names.push(name.to_ascii_uppercase())
+            }
+            _ => return None,
+        }
+    }
+    Some(names)
+}
+
+/// The lambda that `expr` writes out, if it is a well-formed `LAMBDA`,
+/// closing over `scope`.
+pub(super) fn lambda(expr: &Expr, scope: &Scope) -> Option<Rc<Lambda>> {
+    match &expr.kind {
+        ExprKind::Call { name, args } if name == "LAMBDA" => Some(Rc::new(Lambda {
+            params: lambda_params(args)?,
+            body: args.last()?.clone(),
+            scope: scope.clone(),
+        })),
+        _ => None,
+    }
+}
+
+fn storage(e: rusqlite::Error) -> FormulaError {
+    FormulaError::Storage(e.to_string())
+}
+
+fn stored_value(value: Option<String>, value_type: Option<String>, number: Option<f64>) -> Value {
+    Value::from_stored(
+        value.as_deref(),
+        value_type.as_deref().and_then(ValueType::parse),
+        number,
+    )
+}
+
+/// `f` applied to each pair of values of `left` and `right`, as Excel
+/// applies operators to arrays. A single value, row or column is repeated to
+/// match the other side; past the end of a shorter side the result is
+/// `#N/A`.
+fn elementwise(left: &Value, right: &Value, f: impl Fn(&Value, &Value) -> Value) -> Value {
+    let (left_shape, right_shape) = match (left, right) {
+        (Value::Array(a), Value::Array(b)) => ((a.rows, a.cols), (b.rows, b.cols)),
+        (Value::Array(a), _) => ((a.rows, a.cols), (1, 1)),
+        (_, Value::Array(b)) => ((1, 1), (b.rows, b.cols)),
+        _ => return f(left, right),
+    };
+    let rows = left_shape.0.max(right_shape.0);
+    let cols = left_shape.1.max(right_shape.1);
+    let at = |value: &Value, (value_rows, value_cols): (usize, usize), row, col| {
+        let row = if value_rows == 1 { 0 } else { row };
+        let col = if value_cols == 1 { 0 } else { col };
+        if row >= value_rows || col >= value_cols {
+            return None;
+        }
+        Some(match value {
+            Value::Array(array) => array.get(row, col).clone(),
+            value => value.clone(),
+        })
+    };
+    let mut values = Vec::with_capacity(rows * cols);
+    for row in 0..rows {
+        for col in 0..cols {
+            values.push(
+                match (
+                    at(left, left_shape, row, col),
+                    at(right, right_shape, row, col),
+                ) {
+                    (Some(left), Some(right)) => f(&left, &right),
+                    _ => Value::Error(CellError::NA),
+                },
+            );
+        }
+    }
+    Value::Array(Array::new(rows, cols, values))
+}
+
+/// A computed number, or `#NUM!` if it overflowed or is undefined.
+fn finite(n: f64) -> Result<Value, CellError> {
+    if n.is_finite() {
+        Ok(Value::Number(n))
+    } else {
+        Err(CellError::Num)
+    }
+}
+
+fn binary(op: BinaryOp, left: &Value, right: &Value) -> Result<Value, CellError> {
+    let arithmetic = |f: fn(f64, f64) -> f64| -> Result<Value, CellError> {
+        finite(f(left.to_number()?, right.to_number()?))
+    };
+    match op {
+        BinaryOp::Add => arithmetic(|a, b| a + b),
+        BinaryOp::Sub => arithmetic(|a, b| a - b),
+        BinaryOp::Mul => arithmetic(|a, b| a * b),
+        BinaryOp::Div => {
+            let (dividend, divisor) = (left.to_number()?, right.to_number()?);
+            if divisor == 0.0 {
+                return Err(CellError::Div0);
+            }
+            finite(dividend / divisor)
+        }
+        BinaryOp::Pow => arithmetic(f64::powf),
+        BinaryOp::Concat => Ok(Value::Text(left.to_text()? + &right.to_text()?)),
+        BinaryOp::Eq => Ok(Value::Bool(compare(left, right)? == Ordering::Equal)),
+        BinaryOp::Ne => Ok(Value::Bool(compare(left, right)? != Ordering::Equal)),
+        BinaryOp::Lt => Ok(Value::Bool(compare(left, right)? == Ordering::Less)),
+        BinaryOp::Le => Ok(Value::Bool(compare(left, right)? != Ordering::Greater)),
+        BinaryOp::Gt => Ok(Value::Bool(compare(left, right)? == Ordering::Greater)),
+        BinaryOp::Ge => Ok(Value::Bool(compare(left, right)? != Ordering::Less)),
+    }
+}
+
+/// Excel's ordering: numbers sort before text, text before booleans, text
+/// compares case-insensitively, and a blank matches the other side's zero
+/// value. Errors propagate.
+pub(super) fn compare(left: &Value, right: &Value) -> Result<Ordering, CellError> {
+    fn rank(value: &Value) -> u8 {
+        match value {
+            Value::Number(_) | Value::Date(_) | Value::Empty => 0,
+            Value::Text(_) => 1,
+            Value::Bool(_) => 2,
+            Value::Error(_) | Value::Array(_) => 3,
+        }
+    }
+    Ok(match (left, right) {
+        (Value::Error(e), _) | (_, Value::Error(e)) => return Err(*e),
+        (Value::Array(_), _) | (_, Value::Array(_)) => return Err(CellError::Value),
+        (Value::Empty, Value::Text(s)) => "".cmp(s.as_str()),
+        (Value::Text(s), Value::Empty) => s.as_str().cmp(""),
+        (Value::Empty, Value::Bool(b)) => false.cmp(b),
+        (Value::Bool(b), Value::Empty) => b.cmp(&false),
+        (Value::Text(a), Value::Text(b)) => a.to_lowercase().cmp(&b.to_lowercase()),
+        (Value::Bool(a), Value::Bool(b)) => a.cmp(b),
+        _ if rank(left) == 0 && rank(right) == 0 => {
+            let a = left.to_number().unwrap_or(0.0);
+            let b = right.to_number().unwrap_or(0.0);
+            a.partial_cmp(&b).unwrap_or(Ordering::Equal)
+        }
+        _ => rank(left).cmp(&rank(right)),
+    })
+}
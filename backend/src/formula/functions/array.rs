@@ -0,0 +1,207 @@
+//! Dynamic array functions. Their results are blocks of values that spill
+//! from the formula's cell into the cells below and to the right.
+
+use super::{arity, optional, scalar, single};
+use crate::formula::value::{Array, CellError, Value};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+
+/// The most values an array function builds.
+const MAX_ARRAY: f64 = (1 << 20) as f64;
+
+/// An array argument; a single value is a 1x1 array.
+fn array(arg: &Value) -> Result<Array, CellError> {
+    match arg {
+        Value::Array(array) => Ok(array.clone()),
+        Value::Error(e) => Err(*e),
+        value => Ok(Array::new(1, 1, vec![value.clone()])),
+    }
+}
+
+/// The optional boolean argument at `index`, FALSE when left out.
+fn flag(args: &[Value], index: usize) -> Result<bool, CellError> {
+    optional(args, index).map_or(Ok(false), |arg| single(arg)?.to_bool())
+}
+
+/// The optional numeric argument at `index`, `default` when left out or
+/// empty.
+fn number_or(args: &[Value], index: usize, default: f64) -> Result<f64, CellError> {
+    optional(args, index).map_or(Ok(default), scalar)
+}
+
+fn result(array: Result<Array, CellError>) -> Value {
+    array.map_or_else(Value::Error, Value::Array)
+}
+
+/// `array` with its rows and columns swapped, so functions that work on
+/// rows can handle columns too.
+fn transpose(array: &Array) -> Array {
+    let mut values = Vec::with_capacity(array.values.len());
+    for col in 0..array.cols {
+        for row in 0..array.rows {
+            values.push(array.get(row, col).clone());
+        }
+    }
+    Array::new(array.cols, array.rows, values)
+}
+
+/// The rows of `array` that `keep` picks, as a new array. `#CALC!` if none
+/// are left.
+fn rows_where(array: &Array, keep: impl Fn(usize) -> bool) -> Result<Array, CellError> {
+    let rows: Vec<&[Value]> = array
+        .values
+        .chunks(array.cols)
+        .enumerate()
+        .filter(|(i, _)| keep(*i))
+        .map(|(_, row)| row)
+        .collect();
+    if rows.is_empty() {
+        return Err(CellError::Calc);
+    }
+    Ok(Array::new(rows.len(), array.cols, rows.concat()))
+}
+
+/// `SEQUENCE(rows, [columns], [start], [step])`: numbers counting up by
+/// `step` from `start`, across each row and then down.
+pub(super) fn sequence(args: &[Value]) -> Value {
+    result(arity(args, 1, 4).and_then(|_| {
+        let rows = scalar(&args[0])?.trunc();
+        let cols = number_or(args, 1, 1.0)?.trunc();
+        let start = number_or(args, 2, 1.0)?;
+        let step = number_or(args, 3, 1.0)?;
+        if rows < 1.0 || cols < 1.0 {
+            return Err(CellError::Calc);
+        }
+        if rows * cols > MAX_ARRAY {
+            return Err(CellError::Num);
+        }
+        let (rows, cols) = (rows as usize, cols as usize);
+        let values = (0..rows * cols)
+            .map(|i| match start + step * i as f64 {
+                n if n.is_finite() => Ok(Value::Number(n)),
+                _ => Err(CellError::Num),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Array::new(rows, cols, values))
+    }))
+}
+
+/// The order SORT puts values in: numbers, then text, then booleans, then
+/// errors, with blanks last. Text ignores case.
+fn sort_order(a: &Value, b: &Value) -> Ordering {
+    fn rank(value: &Value) -> u8 {
+        match value {
+            Value::Number(_) | Value::Date(_) => 0,
+            Value::Text(_) => 1,
+            Value::Bool(_) => 2,
+            Value::Error(_) | Value::Array(_) => 3,
+            Value::Empty => 4,
+        }
+    }
+    match (a, b) {
+        (Value::Number(x) | Value::Date(x), Value::Number(y) | Value::Date(y)) => x.total_cmp(y),
+        (Value::Text(x), Value::Text(y)) => x.to_lowercase().cmp(&y.to_lowercase()),
+        (Value::Bool(x), Value::Bool(y)) => x.cmp(y),
+        _ => rank(a).cmp(&rank(b)),
+    }
+}
+
+/// `SORT(array, [sort_index], [sort_order], [by_col])`: the rows of
+/// `array` ordered by the values in column `sort_index` (by default the
+/// first), ascending unless `sort_order` is -1. With `by_col` TRUE, the
+/// columns are sorted by a row instead.
+pub(super) fn sort(args: &[Value]) -> Value {
+    result(arity(args, 1, 4).and_then(|_| {
+        let by_col = flag(args, 3)?;
+        let array = match by_col {
+            true => transpose(&array(&args[0])?),
+            false => array(&args[0])?,
+        };
+        let index = number_or(args, 1, 1.0)?.trunc();
+        if index < 1.0 || index > array.cols as f64 {
+            return Err(CellError::Value);
+        }
+        let index = index as usize - 1;
+        let descending = match number_or(args, 2, 1.0)? {
+            1.0 => false,
+            -1.0 => true,
+            _ => return Err(CellError::Value),
+        };
+        let mut rows: Vec<&[Value]> = array.values.chunks(array.cols).collect();
+        rows.sort_by(|a, b| {
+            let order = sort_order(&a[index], &b[index]);
+            if descending { order.reverse() } else { order }
+        });
+        let sorted = Array::new(array.rows, array.cols, rows.concat());
+        Ok(if by_col { transpose(&sorted) } else { sorted })
+    }))
+}
+
+/// `FILTER(array, include, [if_empty])`: the rows of `array` (or columns,
+/// for a one-row `include`) where `include` is TRUE. If none are, the
+/// `if_empty` value, or `#CALC!`.
+pub(super) fn filter(args: &[Value]) -> Value {
+    let filtered = arity(args, 2, 3).and_then(|_| {
+        let array = array(&args[0])?;
+        let include = match &args[1] {
+            Value::Array(include) => include.clone(),
+            Value::Error(e) => return Err(*e),
+            value => Array::new(1, 1, vec![value.clone()]),
+        };
+        let keep = include
+            .values
+            .iter()
+            .map(Value::to_bool)
+            .collect::<Result<Vec<_>, _>>()?;
+        let kept = if include.cols == 1 && include.rows == array.rows {
+            rows_where(&array, |i| keep[i])
+        } else if include.rows == 1 && include.cols == array.cols {
+            rows_where(&transpose(&array), |i| keep[i]).map(|kept| transpose(&kept))
+        } else {
+            return Err(CellError::Value);
+        };
+        match (kept, optional(args, 2)) {
+            (Err(CellError::Calc), Some(if_empty)) => Ok(if_empty.clone()),
+            (kept, _) => kept.map(Value::Array),
+        }
+    });
+    filtered.unwrap_or_else(Value::Error)
+}
+
+/// A row as UNIQUE compares it: numbers by value, text ignoring case, and
+/// booleans, errors and blanks as themselves.
+fn row_key(row: &[Value]) -> Vec<String> {
+    row.iter()
+        .map(|value| match value {
+            Value::Number(n) | Value::Date(n) => format!("n{}", n),
+            Value::Text(text) => format!("t{}", text.to_lowercase()),
+            value => format!("v{}", value.display()),
+        })
+        .collect()
+}
+
+/// `UNIQUE(array, [by_col], [exactly_once])`: the distinct rows of `array`
+/// (or columns, with `by_col` TRUE) in the order they first appear, or with
+/// `exactly_once` TRUE only those that appear once.
+pub(super) fn unique(args: &[Value]) -> Value {
+    result(arity(args, 1, 3).and_then(|_| {
+        let by_col = flag(args, 1)?;
+        let exactly_once = flag(args, 2)?;
+        let array = match by_col {
+            true => transpose(&array(&args[0])?),
+            false => array(&args[0])?,
+        };
+        let keys: Vec<Vec<String>> = array.values.chunks(array.cols).map(row_key).collect();
+        let mut counts: HashMap<&[String], usize> = HashMap::new();
+        for key in &keys {
+            *counts.entry(key).or_default() += 1;
+        }
+        let mut seen = HashSet::new();
+        let keep: Vec<bool> = keys
+            .iter()
+            .map(|key| seen.insert(key) && !(exactly_once && counts[key.as_slice()] > 1))
+            .collect();
+        let kept = rows_where(&array, |i| keep[i])?;
+        Ok(if by_col { transpose(&kept) } else { kept })
+    }))
+}
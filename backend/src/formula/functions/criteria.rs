@@ -0,0 +1,222 @@
+//! Criteria for the conditional aggregates such as SUMIF and COUNTIFS.
+//!
+//! A criterion is a value to match, or text such as `">10"`, `"<>x"` or
+//! `"app*"` that pairs a comparison with its operand. Text operands match
+//! case-insensitively, and `=` and `<>` accept the wildcards `*` and `?`,
+//! with `~` escaping them. Operands such as `">=2026-01-01"` may be dates.
+
+use super::{shape, single, values};
+use crate::formula::date;
+use crate::formula::value::{CellError, Value, parse_number};
+use std::cmp::Ordering;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Operand {
+    /// Nothing after the operator: blank cells.
+    Blank,
+    Number(f64),
+    Bool(bool),
+    Error(CellError),
+    /// Lowercased text.
+    Text(Vec<char>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Criterion {
+    op: Op,
+    operand: Operand,
+}
+
+impl Criterion {
+    /// The criterion a function argument stands for. An error given as the
+    /// criterion is returned as the function's result.
+    pub(super) fn new(value: &Value) -> Result<Criterion, CellError> {
+        let eq = |operand| Criterion {
+            op: Op::Eq,
+            operand,
+        };
+        Ok(match value {
+            Value::Number(n) | Value::Date(n) => eq(Operand::Number(*n)),
+            Value::Bool(b) => eq(Operand::Bool(*b)),
+            Value::Empty => eq(Operand::Number(0.0)),
+            Value::Error(e) => return Err(*e),
+            Value::Array(_) => return Err(CellError::Value),
+            Value::Text(text) => {
+                let (op, operand) = [
+                    (">=", Op::Ge),
+                    ("<=", Op::Le),
+                    ("<>", Op::Ne),
+                    (">", Op::Gt),
+                    ("<", Op::Lt),
+                    ("=", Op::Eq),
+                ]
+                .into_iter()
+                .find_map(|(prefix, op)| text.strip_prefix(prefix).map(|rest| (op, rest)))
+                .unwrap_or((Op::Eq, text));
+                Criterion {
+                    op,
+                    operand: operand_of(operand),
+                }
+            }
+        })
+    }
+
+    pub(super) fn matches(&self, value: &Value) -> bool {
+        match self.op {
+            Op::Eq => self.equals(value),
+            Op::Ne => !self.equals(value),
+            Op::Lt => self.ordering(value) == Some(Ordering::Less),
+            Op::Le => matches!(self.ordering(value), Some(Ordering::Less | Ordering::Equal)),
+            Op::Gt => self.ordering(value) == Some(Ordering::Greater),
+            Op::Ge => matches!(
+                self.ordering(value),
+                Some(Ordering::Greater | Ordering::Equal)
+            ),
+        }
+    }
+
+    fn equals(&self, value: &Value) -> bool {
+        match (&self.operand, value) {
+            (Operand::Blank, Value::Empty) => true,
+            (Operand::Blank, Value::Text(text)) => text.is_empty(),
+            (Operand::Number(n), Value::Number(m)) => n == m,
+            (Operand::Number(n), Value::Text(text)) => parse_number(text) == Some(*n),
+            (Operand::Bool(a), Value::Bool(b)) => a == b,
+            (Operand::Error(a), Value::Error(b)) => a == b,
+            (Operand::Text(pattern), Value::Text(text)) => {
+                let text: Vec<char> = text.to_lowercase().chars().collect();
+                wildcard(pattern, &text)
+            }
+            _ => false,
+        }
+    }
+
+    /// How `value` compares with the operand, if they are of a kind that
+    /// can be ordered.
+    fn ordering(&self, value: &Value) -> Option<Ordering> {
+        match (&self.operand, value) {
+            (Operand::Number(n), Value::Number(m)) => m.partial_cmp(n),
+            (Operand::Bool(a), Value::Bool(b)) => Some(b.cmp(a)),
+            (Operand::Text(pattern), Value::Text(text)) => {
+                Some(text.to_lowercase().chars().cmp(pattern.iter().copied()))
+            }
+            _ => None,
+        }
+    }
+}
+
+fn operand_of(text: &str) -> Operand {
+    if text.is_empty() {
+        Operand::Blank
+    } else if let Some(n) = parse_number(text).or_else(|| date::parse(text)) {
+        Operand::Number(n)
+    } else if text.eq_ignore_ascii_case("TRUE") || text.eq_ignore_ascii_case("FALSE") {
+        Operand::Bool(text.eq_ignore_ascii_case("TRUE"))
+    } else if let Some(e) = CellError::from_code(text) {
+        Operand::Error(e)
+    } else {
+        Operand::Text(text.to_lowercase().chars().collect())
+    }
+}
+
+/// Whether `text` matches `pattern`, where `*` stands for any run of
+/// characters, `?` for any one character and `~` makes the next character
+/// literal.
+pub(super) fn wildcard(pattern: &[char], text: &[char]) -> bool {
+    // The positions to resume from after the last `*`, if any.
+    let mut star: Option<(usize, usize)> = None;
+    let (mut p, mut t) = (0, 0);
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p + 1, t));
+                p += 1;
+                continue;
+            }
+            Some('?') => {
+                p += 1;
+                t += 1;
+                continue;
+            }
+            Some('~') if p + 1 < pattern.len() && pattern[p + 1] == text[t] => {
+                p += 2;
+                t += 1;
+                continue;
+            }
+            Some(&c) if c != '~' && c == text[t] => {
+                p += 1;
+                t += 1;
+                continue;
+            }
+            _ => {}
+        }
+        match star {
+            Some((star_p, star_t)) => {
+                p = star_p;
+                t = star_t + 1;
+                star = Some((star_p, star_t + 1));
+            }
+            None => return false,
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Which cells of `range` meet `criterion`, in row-major order.
+pub(super) fn mask(range: &Value, criterion: &Criterion) -> Vec<bool> {
+    values(range).iter().map(|v| criterion.matches(v)).collect()
+}
+
+/// Which cells meet every `(range, criterion)` pair in `pairs`, as in
+/// SUMIFS. Every range must have the given shape.
+pub(super) fn masks(shape_of: (usize, usize), pairs: &[Value]) -> Result<Vec<bool>, CellError> {
+    if pairs.is_empty() || !pairs.len().is_multiple_of(2) {
+        return Err(CellError::Value);
+    }
+    let mut all = vec![true; shape_of.0 * shape_of.1];
+    for pair in pairs.chunks(2) {
+        if shape(&pair[0]) != shape_of {
+            return Err(CellError::Value);
+        }
+        let criterion = Criterion::new(single(&pair[1])?)?;
+        for (keep, matched) in all.iter_mut().zip(mask(&pair[0], &criterion)) {
+            *keep &= matched;
+        }
+    }
+    Ok(all)
+}
+
+/// The numbers in `target` at the positions `mask` selects, where `mask`
+/// covers a block of shape `shape_of` laid over the top-left of `target`.
+/// Errors at selected positions are returned.
+pub(super) fn selected(
+    target: &Value,
+    shape_of: (usize, usize),
+    mask: &[bool],
+) -> Result<Vec<f64>, CellError> {
+    let (rows, cols) = shape(target);
+    let target = values(target);
+    let mut numbers = Vec::new();
+    for (i, _) in mask.iter().enumerate().filter(|(_, keep)| **keep) {
+        let (row, col) = (i / shape_of.1, i % shape_of.1);
+        if row >= rows || col >= cols {
+            continue;
+        }
+        match &target[row * cols + col] {
+            Value::Number(n) => numbers.push(*n),
+            Value::Error(e) => return Err(*e),
+            _ => {}
+        }
+    }
+    Ok(numbers)
+}
@@ -0,0 +1,210 @@
+//! Date and time functions. Dates are serial numbers in Excel's 1900 date
+//! system (see [`date`]); arguments may also be dates typed as text, such as
+//! `"2026-01-31"`.
+
+use super::{arity, number, scalar, scalar_or, single, values};
+use crate::formula::date::{self, MAX_SERIAL};
+use crate::formula::value::{CellError, Value};
+
+/// A date argument, truncated to the whole day. `#NUM!` outside the dates
+/// Excel can show.
+fn day(arg: &Value) -> Result<f64, CellError> {
+    let serial = scalar(arg)?;
+    if !(0.0..MAX_SERIAL + 1.0).contains(&serial) {
+        return Err(CellError::Num);
+    }
+    Ok(serial.floor())
+}
+
+/// A serial number, or `#NUM!` if it falls outside 1900-01-00..9999-12-31.
+fn checked(serial: f64) -> Result<f64, CellError> {
+    if (0.0..=MAX_SERIAL).contains(&serial) {
+        Ok(serial)
+    } else {
+        Err(CellError::Num)
+    }
+}
+
+/// The day of the week, with Sunday as 0. Serial 1, Excel's 1900-01-01, is
+/// a Sunday.
+fn day_of_week(serial: f64) -> i64 {
+    (serial as i64 - 1).rem_euclid(7)
+}
+
+/// `TODAY()`: the current date, from the server's clock in UTC.
+pub(super) fn today(args: &[Value]) -> Value {
+    number(arity(args, 0, 0).map(|_| date::now().floor()))
+}
+
+/// `NOW()`: the current date and time, from the server's clock in UTC.
+pub(super) fn now(args: &[Value]) -> Value {
+    number(arity(args, 0, 0).map(|_| date::now()))
+}
+
+/// `DATE(year, month, day)`: years below 1900 are counted from 1900, and
+/// months and days past their range roll over.
+pub(super) fn date(args: &[Value]) -> Value {
+    number(arity(args, 3, 3).and_then(|_| {
+        let mut year = scalar(&args[0])?.trunc();
+        let month = scalar(&args[1])?.trunc();
+        let day = scalar(&args[2])?.trunc();
+        if (0.0..1900.0).contains(&year) {
+            year += 1900.0;
+        }
+        if !(0.0..10_000.0).contains(&year) || month.abs() > 1e6 || day.abs() > 1e8 {
+            return Err(CellError::Num);
+        }
+        checked(date::serial(year as i64, month as i64, day as i64))
+    }))
+}
+
+fn part(args: &[Value], part: fn((i64, u32, u32)) -> f64) -> Value {
+    number(arity(args, 1, 1).and_then(|_| Ok(part(date::civil(day(&args[0])?)))))
+}
+
+pub(super) fn year(args: &[Value]) -> Value {
+    part(args, |(year, _, _)| year as f64)
+}
+
+pub(super) fn month(args: &[Value]) -> Value {
+    part(args, |(_, month, _)| f64::from(month))
+}
+
+pub(super) fn day_(args: &[Value]) -> Value {
+    part(args, |(_, _, day)| f64::from(day))
+}
+
+/// The year and month `months` after the month of `serial`.
+fn shift(serial: f64, months: f64) -> Result<(i64, u32, u32), CellError> {
+    if months.abs() > 120_000.0 {
+        return Err(CellError::Num);
+    }
+    let (year, month, day) = date::civil(serial);
+    let index = year * 12 + i64::from(month) - 1 + months.trunc() as i64;
+    Ok((index.div_euclid(12), (index.rem_euclid(12) + 1) as u32, day))
+}
+
+/// `EDATE(start, months)`: the same day `months` later or earlier, or the
+/// last day of the month if it is shorter.
+pub(super) fn edate(args: &[Value]) -> Value {
+    number(arity(args, 2, 2).and_then(|_| {
+        let (year, month, day) = shift(day(&args[0])?, scalar(&args[1])?)?;
+        let day = day.min(date::days_in_month(year, month));
+        checked(date::serial(year, i64::from(month), i64::from(day)))
+    }))
+}
+
+/// `EOMONTH(start, months)`: the last day of the month `months` later or
+/// earlier.
+pub(super) fn eomonth(args: &[Value]) -> Value {
+    number(arity(args, 2, 2).and_then(|_| {
+        let (year, month, _) = shift(day(&args[0])?, scalar(&args[1])?)?;
+        checked(date::serial(year, i64::from(month) + 1, 0))
+    }))
+}
+
+/// `NETWORKDAYS(start, end, [holidays])`: the working days from `start` to
+/// `end`, both included, skipping weekends and any holidays. Negative if
+/// `end` comes first.
+pub(super) fn networkdays(args: &[Value]) -> Value {
+    number(arity(args, 2, 3).and_then(|_| {
+        let start = day(&args[0])?;
+        let end = day(&args[1])?;
+        let mut holidays = Vec::new();
+        for holiday in args.get(2).map_or(&[][..], values) {
+            match holiday {
+                Value::Empty => {}
+                value => holidays.push(day(value)?),
+            }
+        }
+        let (first, last, sign) = if start <= end {
+            (start, end, 1.0)
+        } else {
+            (end, start, -1.0)
+        };
+        let weekday = |serial: f64| (1..=5).contains(&day_of_week(serial));
+        let days = (last - first) as i64 + 1;
+        let mut count = days / 7 * 5;
+        for offset in 0..days % 7 {
+            if weekday(last - offset as f64) {
+                count += 1;
+            }
+        }
+        holidays.sort_by(f64::total_cmp);
+        holidays.dedup();
+        let off = holidays
+            .iter()
+            .filter(|&&h| (first..=last).contains(&h) && weekday(h))
+            .count() as i64;
+        Ok(sign * (count - off) as f64)
+    }))
+}
+
+/// `WEEKDAY(date, [type])`: the day of the week as a number. Type 1 (the
+/// default) counts Sunday as 1, type 2 Monday as 1, type 3 Monday as 0, and
+/// types 11 to 17 start the week on Monday through Sunday.
+pub(super) fn weekday(args: &[Value]) -> Value {
+    number(arity(args, 1, 2).and_then(|_| {
+        let serial = day(&args[0])?;
+        // The day the week starts on, with Sunday as 0, and its number.
+        let (first, base) = match scalar_or(args, 1, 1.0)?.trunc() as i64 {
+            1 => (0, 1),
+            2 => (1, 1),
+            3 => (1, 0),
+            kind @ 11..=17 => ((kind - 10) % 7, 1),
+            _ => return Err(CellError::Num),
+        };
+        Ok(((day_of_week(serial) - first).rem_euclid(7) + base) as f64)
+    }))
+}
+
+/// `DATEDIF(start, end, unit)`: the time between two dates in whole years
+/// (`"Y"`), months (`"M"`) or days (`"D"`), or the days ignoring months and
+/// years (`"MD"`), the months ignoring years (`"YM"`) or the days ignoring
+/// years (`"YD"`).
+pub(super) fn datedif(args: &[Value]) -> Value {
+    number(arity(args, 3, 3).and_then(|_| {
+        let start = day(&args[0])?;
+        let end = day(&args[1])?;
+        let unit = single(&args[2])?.to_text()?.to_uppercase();
+        if start > end {
+            return Err(CellError::Num);
+        }
+        let (y1, m1, d1) = date::civil(start);
+        let (y2, m2, d2) = date::civil(end);
+        let months = (y2 - y1) * 12 + i64::from(m2) - i64::from(m1) - i64::from(d2 < d1);
+        Ok(match unit.as_str() {
+            "Y" => (months / 12) as f64,
+            "M" => months as f64,
+            "D" => end - start,
+            "YM" => (months % 12) as f64,
+            "MD" if d2 >= d1 => f64::from(d2 - d1),
+            "MD" => {
+                let (year, month) = if m2 == 1 { (y2 - 1, 12) } else { (y2, m2 - 1) };
+                f64::from(date::days_in_month(year, month).saturating_sub(d1) + d2)
+            }
+            "YD" => {
+                let mut anniversary = date::serial(y2, i64::from(m1), i64::from(d1));
+                if anniversary > end {
+                    anniversary = date::serial(y2 - 1, i64::from(m1), i64::from(d1));
+                }
+                end - anniversary
+            }
+            _ => return Err(CellError::Num),
+        })
+    }))
+}
+
+/// `TIME(hour, minute, second)`: the fraction of a day, wrapping past
+/// midnight.
+pub(super) fn time(args: &[Value]) -> Value {
+    number(arity(args, 3, 3).and_then(|_| {
+        let seconds = scalar(&args[0])?.trunc() * 3600.0
+            + scalar(&args[1])?.trunc() * 60.0
+            + scalar(&args[2])?.trunc();
+        if seconds < 0.0 {
+            return Err(CellError::Num);
+        }
+        Ok(seconds.rem_euclid(86_400.0) / 86_400.0)
+    }))
+}
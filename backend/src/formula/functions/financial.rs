@@ -0,0 +1,378 @@
+//! Financial functions. As in Excel, money paid out is negative and money
+//! received is positive, and `type` is 1 for payments at the start of each
+//! period and 0 (the default) for payments at the end.
+//!
+//! `IRR`, `XIRR` and `RATE` have no closed form and are solved with Newton's
+//! method; a rate that cannot be found is `#NUM!`.
+
+use super::{arity, number, numbers, scalar, scalar_or, values};
+use crate::formula::value::{CellError, Value};
+
+/// The most steps the solvers take before giving up.
+const MAX_ITERATIONS: usize = 100;
+/// How close successive guesses must be for a solver to stop.
+const TOLERANCE: f64 = 1e-10;
+/// The longest life `DB` works through period by period.
+const MAX_LIFE: f64 = 10_000.0;
+
+/// The optional `type` argument at `index`: whether payments are due at the
+/// start of each period.
+fn due(args: &[Value], index: usize) -> Result<f64, CellError> {
+    Ok(if scalar_or(args, index, 0.0)? != 0.0 {
+        1.0
+    } else {
+        0.0
+    })
+}
+
+/// The future value of `pv` now and `pmt` each period for `nper` periods.
+fn future_value(rate: f64, nper: f64, pmt: f64, pv: f64, due: f64) -> f64 {
+    if rate == 0.0 {
+        return -(pv + pmt * nper);
+    }
+    let growth = (1.0 + rate).powf(nper);
+    -(pv * growth + pmt * (1.0 + rate * due) * (growth - 1.0) / rate)
+}
+
+/// The payment each period that pays `pv` down to `fv` in `nper` periods.
+fn payment(rate: f64, nper: f64, pv: f64, fv: f64, due: f64) -> Result<f64, CellError> {
+    if nper == 0.0 {
+        return Err(CellError::Num);
+    }
+    if rate == 0.0 {
+        return Ok(-(pv + fv) / nper);
+    }
+    let growth = (1.0 + rate).powf(nper);
+    Ok(-(rate * (pv * growth + fv)) / ((1.0 + rate * due) * (growth - 1.0)))
+}
+
+/// The interest part of payment `per`.
+fn interest(rate: f64, per: f64, nper: f64, pv: f64, fv: f64, due: f64) -> Result<f64, CellError> {
+    if per < 1.0 || per > nper {
+        return Err(CellError::Num);
+    }
+    let pmt = payment(rate, nper, pv, fv, due)?;
+    // The balance at the start of the period, after any payment due then.
+    let balance = if per == 1.0 {
+        if due == 1.0 { 0.0 } else { -pv }
+    } else if due == 1.0 {
+        future_value(rate, per - 2.0, pmt, pv, 1.0) - pmt
+    } else {
+        future_value(rate, per - 1.0, pmt, pv, 0.0)
+    };
+    Ok(balance * rate)
+}
+
+/// A root of `f` found with Newton's method from `guess`, using a numerical
+/// derivative. `#NUM!` if the guesses diverge or do not settle, or the rate
+/// falls to -100% or below.
+fn solve(guess: f64, f: impl Fn(f64) -> f64) -> Result<f64, CellError> {
+    let mut rate = guess;
+    for _ in 0..MAX_ITERATIONS {
+        let value = f(rate);
+        let h = 1e-7 * rate.abs().max(1.0);
+        let slope = (f(rate + h) - f(rate - h)) / (2.0 * h);
+        if !value.is_finite() || !slope.is_finite() || slope == 0.0 {
+            break;
+        }
+        let next = rate - value / slope;
+        if next <= -1.0 || !next.is_finite() {
+            break;
+        }
+        if (next - rate).abs() < TOLERANCE * next.abs().max(1.0) {
+            return Ok(next);
+        }
+        rate = next;
+    }
+    Err(CellError::Num)
+}
+
+/// `PMT(rate, nper, pv, [fv], [type])`: the payment each period of a loan
+/// or investment.
+pub(super) fn pmt(args: &[Value]) -> Value {
+    number(arity(args, 3, 5).and_then(|_| {
+        payment(
+            scalar(&args[0])?,
+            scalar(&args[1])?,
+            scalar(&args[2])?,
+            scalar_or(args, 3, 0.0)?,
+            due(args, 4)?,
+        )
+    }))
+}
+
+/// `PV(rate, nper, pmt, [fv], [type])`: what a series of payments is worth
+/// now.
+pub(super) fn pv(args: &[Value]) -> Value {
+    number(arity(args, 3, 5).and_then(|_| {
+        let rate = scalar(&args[0])?;
+        let nper = scalar(&args[1])?;
+        let pmt = scalar(&args[2])?;
+        let fv = scalar_or(args, 3, 0.0)?;
+        let due = due(args, 4)?;
+        if rate == 0.0 {
+            return Ok(-(fv + pmt * nper));
+        }
+        let growth = (1.0 + rate).powf(nper);
+        Ok(-(fv + pmt * (1.0 + rate * due) * (growth - 1.0) / rate) / growth)
+    }))
+}
+
+/// `FV(rate, nper, pmt, [pv], [type])`: what a series of payments is worth
+/// at the end.
+pub(super) fn fv(args: &[Value]) -> Value {
+    number(arity(args, 3, 5).and_then(|_| {
+        Ok(future_value(
+            scalar(&args[0])?,
+            scalar(&args[1])?,
+            scalar(&args[2])?,
+            scalar_or(args, 3, 0.0)?,
+            due(args, 4)?,
+        ))
+    }))
+}
+
+/// `NPER(rate, pmt, pv, [fv], [type])`: the number of periods needed to pay
+/// `pv` down to `fv`.
+pub(super) fn nper(args: &[Value]) -> Value {
+    number(arity(args, 3, 5).and_then(|_| {
+        let rate = scalar(&args[0])?;
+        let pmt = scalar(&args[1])?;
+        let pv = scalar(&args[2])?;
+        let fv = scalar_or(args, 3, 0.0)?;
+        let due = due(args, 4)?;
+        if rate == 0.0 {
+            if pmt == 0.0 {
+                return Err(CellError::Div0);
+            }
+            return Ok(-(pv + fv) / pmt);
+        }
+        let per_period = pmt * (1.0 + rate * due);
+        let ratio = (per_period - fv * rate) / (per_period + pv * rate);
+        if ratio <= 0.0 {
+            return Err(CellError::Num);
+        }
+        Ok(ratio.ln() / (1.0 + rate).ln())
+    }))
+}
+
+/// `IPMT(rate, per, nper, pv, [fv], [type])`: the interest paid in period
+/// `per`.
+pub(super) fn ipmt(args: &[Value]) -> Value {
+    number(arity(args, 4, 6).and_then(|_| {
+        interest(
+            scalar(&args[0])?,
+            scalar(&args[1])?,
+            scalar(&args[2])?,
+            scalar(&args[3])?,
+            scalar_or(args, 4, 0.0)?,
+            due(args, 5)?,
+        )
+    }))
+}
+
+/// `PPMT(rate, per, nper, pv, [fv], [type])`: the principal repaid in
+/// period `per`.
+pub(super) fn ppmt(args: &[Value]) -> Value {
+    number(arity(args, 4, 6).and_then(|_| {
+        let rate = scalar(&args[0])?;
+        let per = scalar(&args[1])?;
+        let nper = scalar(&args[2])?;
+        let pv = scalar(&args[3])?;
+        let fv = scalar_or(args, 4, 0.0)?;
+        let due = due(args, 5)?;
+        Ok(payment(rate, nper, pv, fv, due)? - interest(rate, per, nper, pv, fv, due)?)
+    }))
+}
+
+/// The net present value of `flows`, the first one period from now.
+fn present_value(rate: f64, flows: &[f64]) -> f64 {
+    flows
+        .iter()
+        .enumerate()
+        .map(|(i, flow)| flow / (1.0 + rate).powi(i as i32 + 1))
+        .sum()
+}
+
+/// `NPV(rate, value1, ...)`: the value now of cash flows at the end of each
+/// coming period.
+pub(super) fn npv(args: &[Value]) -> Value {
+    number(arity(args, 2, usize::MAX).and_then(|_| {
+        let rate = scalar(&args[0])?;
+        if rate == -1.0 {
+            return Err(CellError::Div0);
+        }
+        Ok(present_value(rate, &numbers(&args[1..])?))
+    }))
+}
+
+/// `IRR(values, [guess])`: the rate at which the cash flows' net present
+/// value is zero. The flows need at least one payment and one receipt.
+pub(super) fn irr(args: &[Value]) -> Value {
+    number(arity(args, 1, 2).and_then(|_| {
+        let flows = numbers(&args[..1])?;
+        if !flows.iter().any(|&f| f > 0.0) || !flows.iter().any(|&f| f < 0.0) {
+            return Err(CellError::Num);
+        }
+        // The first flow is now rather than a period away.
+        solve(scalar_or(args, 1, 0.1)?, |rate| {
+            present_value(rate, &flows) * (1.0 + rate)
+        })
+    }))
+}
+
+/// Cash flows and the dates they fall on, for XNPV and XIRR. Every value
+/// must be a number, and no date may come before the first.
+fn schedule(flows: &Value, dates: &Value) -> Result<Vec<(f64, f64)>, CellError> {
+    let number = |value: &Value| match value {
+        Value::Number(n) => Ok(*n),
+        Value::Error(e) => Err(*e),
+        _ => Err(CellError::Value),
+    };
+    let flows = values(flows);
+    let dates = values(dates);
+    if flows.len() != dates.len() || flows.is_empty() {
+        return Err(CellError::Num);
+    }
+    let mut schedule = Vec::new();
+    for (flow, date) in flows.iter().zip(dates) {
+        schedule.push((number(flow)?, number(date)?.floor()));
+    }
+    let start = schedule[0].1;
+    if schedule.iter().any(|&(_, date)| date < start) {
+        return Err(CellError::Num);
+    }
+    Ok(schedule)
+}
+
+/// The net present value of dated flows, discounted to the first date with
+/// a 365-day year.
+fn dated_present_value(rate: f64, schedule: &[(f64, f64)]) -> f64 {
+    let start = schedule[0].1;
+    schedule
+        .iter()
+        .map(|&(flow, date)| flow / (1.0 + rate).powf((date - start) / 365.0))
+        .sum()
+}
+
+/// `XNPV(rate, values, dates)`: the net present value of cash flows on the
+/// given dates.
+pub(super) fn xnpv(args: &[Value]) -> Value {
+    number(arity(args, 3, 3).and_then(|_| {
+        let rate = scalar(&args[0])?;
+        if rate <= -1.0 {
+            return Err(CellError::Num);
+        }
+        Ok(dated_present_value(rate, &schedule(&args[1], &args[2])?))
+    }))
+}
+
+/// `XIRR(values, dates, [guess])`: the yearly rate at which the dated cash
+/// flows' net present value is zero.
+pub(super) fn xirr(args: &[Value]) -> Value {
+    number(arity(args, 2, 3).and_then(|_| {
+        let schedule = schedule(&args[0], &args[1])?;
+        if !schedule.iter().any(|&(f, _)| f > 0.0) || !schedule.iter().any(|&(f, _)| f < 0.0) {
+            return Err(CellError::Num);
+        }
+        solve(scalar_or(args, 2, 0.1)?, |rate| {
+            dated_present_value(rate, &schedule)
+        })
+    }))
+}
+
+/// `RATE(nper, pmt, pv, [fv], [type], [guess])`: the interest rate per
+/// period that makes the payments pay `pv` down to `fv`.
+pub(super) fn rate(args: &[Value]) -> Value {
+    number(arity(args, 3, 6).and_then(|_| {
+        let nper = scalar(&args[0])?;
+        let pmt = scalar(&args[1])?;
+        let pv = scalar(&args[2])?;
+        let fv = scalar_or(args, 3, 0.0)?;
+        let due = due(args, 4)?;
+        if nper <= 0.0 {
+            return Err(CellError::Num);
+        }
+        solve(scalar_or(args, 5, 0.1)?, |rate| {
+            fv - future_value(rate, nper, pmt, pv, due)
+        })
+    }))
+}
+
+/// `SLN(cost, salvage, life)`: straight-line depreciation per period.
+pub(super) fn sln(args: &[Value]) -> Value {
+    number(arity(args, 3, 3).and_then(|_| {
+        let life = scalar(&args[2])?;
+        if life == 0.0 {
+            return Err(CellError::Div0);
+        }
+        Ok((scalar(&args[0])? - scalar(&args[1])?) / life)
+    }))
+}
+
+/// `DB(cost, salvage, life, period, [month])`: fixed-declining balance
+/// depreciation, with the rate rounded to three places as Excel does.
+/// `month` is the number of months in the first year, so a part-year may
+/// follow the last full one. A life over [`MAX_LIFE`] periods is `#NUM!`.
+pub(super) fn db(args: &[Value]) -> Value {
+    number(arity(args, 4, 5).and_then(|_| {
+        let cost = scalar(&args[0])?;
+        let salvage = scalar(&args[1])?;
+        let life = scalar(&args[2])?.trunc();
+        let period = scalar(&args[3])?.trunc();
+        let month = scalar_or(args, 4, 12.0)?.trunc();
+        let last = if month < 12.0 { life + 1.0 } else { life };
+        if cost < 0.0
+            || salvage < 0.0
+            || !(1.0..=MAX_LIFE).contains(&life)
+            || !(1.0..=12.0).contains(&month)
+            || !(1.0..=last).contains(&period)
+        {
+            return Err(CellError::Num);
+        }
+        if cost == 0.0 {
+            return Ok(0.0);
+        }
+        let rate = ((1.0 - (salvage / cost).powf(1.0 / life)) * 1000.0).round() / 1000.0;
+        let mut total = 0.0;
+        let mut depreciation = 0.0;
+        for p in 1..=period as usize {
+            depreciation = if p == 1 {
+                cost * rate * month / 12.0
+            } else if p as f64 == life + 1.0 {
+                (cost - total) * rate * (12.0 - month) / 12.0
+            } else {
+                (cost - total) * rate
+            };
+            total += depreciation;
+        }
+        Ok(depreciation)
+    }))
+}
+
+/// `DDB(cost, salvage, life, period, [factor])`: declining balance
+/// depreciation at `factor` (by default 2) times the straight-line rate,
+/// never taking the book value below `salvage`.
+pub(super) fn ddb(args: &[Value]) -> Value {
+    number(arity(args, 4, 5).and_then(|_| {
+        let cost = scalar(&args[0])?;
+        let salvage = scalar(&args[1])?;
+        let life = scalar(&args[2])?;
+        let period = scalar(&args[3])?;
+        let factor = scalar_or(args, 4, 2.0)?;
+        if cost < 0.0
+            || salvage < 0.0
+            || life <= 0.0
+            || factor <= 0.0
+            || period < 1.0
+            || period > life
+        {
+            return Err(CellError::Num);
+        }
+        let rate = (factor / life).min(1.0);
+        // The book value at the start of the period, which declines by
+        // `rate` each period until it reaches `salvage`.
+        let book = (cost * (1.0 - rate).powf(period.ceil() - 1.0)).max(salvage);
+        Ok((book * rate).min((book - salvage).max(0.0)))
+    }))
+}
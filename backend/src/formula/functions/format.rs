@@ -0,0 +1,513 @@
+//! Excel number format codes, as used by `TEXT`.
+//!
+//! A format has up to four `;`-separated sections, for positive numbers,
+//! negative numbers, zero and text. Within a section `0`, `#` and `?` are
+//! digit placeholders (showing a digit, nothing, or a space where there is
+//! no digit), `.` marks the decimals, `,` groups thousands (or, after the
+//! last placeholder, divides by 1000), `%` multiplies by 100, `E+00` gives
+//! scientific notation, `@` stands for text, and `"..."` or `\x` are shown
+//! as they are.
+//!
+//! A section with any of the codes `y`, `m`, `d`, `h` or `s` lays out the
+//! number as a date and time instead: `yy` or `yyyy`, `m` to `mmmmm` for the
+//! month (or minutes, beside `h` or `s`), `d` to `dddd`, `h`, `s`, and
+//! `AM/PM` or `A/P` for a 12-hour clock.
+
+use super::math::significant;
+use crate::formula::date::{self, MAX_SERIAL};
+use crate::formula::value::{CellError, format_number};
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Literal(String),
+    /// `0`, `#` or `?`.
+    Digit(char),
+    Point,
+    Comma,
+    Percent,
+    /// `E+` or `E-`, and whether the sign is always shown.
+    Exponent(bool),
+    At,
+}
+
+/// `value` formatted with `format`. `#VALUE!` for a date format and a
+/// number that is not a date.
+pub(super) fn format_number_with(value: f64, format: &str) -> Result<String, CellError> {
+    if format.eq_ignore_ascii_case("General") {
+        return Ok(format_number(value));
+    }
+    let sections = sections(format);
+    if let Some(parts) = date_parts(&sections[0]) {
+        if !(0.0..MAX_SERIAL + 1.0).contains(&value) {
+            return Err(CellError::Value);
+        }
+        return Ok(format_date(&parts, value));
+    }
+    let (section, value, sign) = match (value, sections.len()) {
+        (v, _) if v > 0.0 => (&sections[0], v, false),
+        (v, n) if v < 0.0 && n >= 2 => (&sections[1], -v, false),
+        (v, _) if v < 0.0 => (&sections[0], -v, true),
+        (v, n) if n >= 3 => (&sections[2], v, false),
+        (v, _) => (&sections[0], v, false),
+    };
+    let formatted = section_number(&tokens(section), value);
+    Ok(
+        if sign && formatted.chars().any(|c| c.is_ascii_digit() && c != '0') {
+            format!("-{}", formatted)
+        } else {
+            formatted
+        },
+    )
+}
+
+/// `text` formatted with `format`: only a section with `@` changes it.
+pub(super) fn format_text_with(text: &str, format: &str) -> String {
+    let sections = sections(format);
+    let section = match sections.len() {
+        4 => &sections[3],
+        1 if sections[0].contains('@') => &sections[0],
+        _ => return text.to_string(),
+    };
+    tokens(section)
+        .into_iter()
+        .map(|token| match token {
+            Token::At => text.to_string(),
+            Token::Literal(s) => s,
+            _ => String::new(),
+        })
+        .collect()
+}
+
+/// The sections of `format`, split on `;` outside quotes.
+fn sections(format: &str) -> Vec<String> {
+    let mut sections = vec![String::new()];
+    let mut chars = format.chars();
+    let mut quoted = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => quoted = !quoted,
+            '\\' if !quoted => {
+                sections.last_mut().unwrap().push(c);
+                if let Some(next) = chars.next() {
+                    sections.last_mut().unwrap().push(next);
+                }
+                continue;
+            }
+            ';' if !quoted => {
+                sections.push(String::new());
+                continue;
+            }
+            _ => {}
+        }
+        sections.last_mut().unwrap().push(c);
+    }
+    sections
+}
+
+fn tokens(section: &str) -> Vec<Token> {
+    let chars: Vec<char> = section.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        i += 1;
+        tokens.push(match c {
+            '0' | '#' | '?' => Token::Digit(c),
+            '.' => Token::Point,
+            ',' => Token::Comma,
+            '%' => Token::Percent,
+            '@' => Token::At,
+            'E' | 'e' if matches!(chars.get(i), Some('+' | '-')) => {
+                i += 1;
+                Token::Exponent(chars[i - 1] == '+')
+            }
+            '"' => {
+                let end = chars[i..]
+                    .iter()
+                    .position(|&c| c == '"')
+                    .map_or(chars.len(), |p| i + p);
+                let literal = chars[i..end].iter().collect();
+                i = end + 1;
+                Token::Literal(literal)
+            }
+            '\\' if i < chars.len() => {
+                i += 1;
+                Token::Literal(chars[i - 1].to_string())
+            }
+            // Padding and alignment codes take no space in text.
+            '_' | '*' => {
+                i += 1;
+                continue;
+            }
+            _ => Token::Literal(c.to_string()),
+        });
+    }
+    tokens
+}
+
+/// A non-negative number laid out by one section's tokens.
+fn section_number(tokens: &[Token], mut value: f64) -> String {
+    if !tokens.iter().any(|t| matches!(t, Token::Digit(_))) {
+        // No placeholders: the section is all literal.
+        return literals(tokens);
+    }
+    let point = tokens.iter().position(|t| *t == Token::Point);
+    let exponent = tokens.iter().position(|t| matches!(t, Token::Exponent(_)));
+    let int_end = point.or(exponent).unwrap_or(tokens.len());
+
+    for token in tokens {
+        if *token == Token::Percent {
+            value *= 100.0;
+        }
+    }
+    // Commas right after the last integer placeholder, or after the last
+    // placeholder of all, scale by 1000 each.
+    let last_int_digit = tokens[..int_end]
+        .iter()
+        .rposition(|t| matches!(t, Token::Digit(_)));
+    let last_digit = tokens.iter().rposition(|t| matches!(t, Token::Digit(_)));
+    let scaling_after = |last: usize, end: usize| {
+        tokens[last + 1..end]
+            .iter()
+            .take_while(|t| **t == Token::Comma)
+            .count()
+    };
+    let mut scaling = last_int_digit.map_or(0, |last| scaling_after(last, int_end));
+    if let Some(last) = last_digit.filter(|&last| last > int_end) {
+        scaling += scaling_after(last, tokens.len());
+    }
+    value /= 1000f64.powi(scaling as i32);
+    let grouping = match last_int_digit {
+        Some(last) => tokens[..last].contains(&Token::Comma),
+        None => false,
+    };
+
+    let frac_end = exponent.unwrap_or(tokens.len());
+    let frac_tokens = point.map_or(&[][..], |p| &tokens[p + 1..frac_end]);
+    let decimals = frac_tokens
+        .iter()
+        .filter(|t| matches!(t, Token::Digit(_)))
+        .count();
+
+    let mut exponent_value = 0;
+    if exponent.is_some() && value != 0.0 {
+        let int_digits = tokens[..int_end]
+            .iter()
+            .filter(|t| matches!(t, Token::Digit(_)))
+            .count()
+            .max(1) as i32;
+        exponent_value = value.log10().floor() as i32 - (int_digits - 1);
+        value /= 10f64.powi(exponent_value);
+        if rounded(value, decimals) >= 10f64.powi(int_digits) {
+            value /= 10.0;
+            exponent_value += 1;
+        }
+    }
+
+    let text = format!("{:.*}", decimals, rounded(value, decimals));
+    let (int_text, frac_text) = text.split_once('.').unwrap_or((&text, ""));
+    let int_text = if int_text == "0" { "" } else { int_text };
+
+    let mut out = integer_part(&tokens[..int_end], int_text, grouping);
+    if point.is_some() {
+        out.push_str(&fraction_part(frac_tokens, frac_text));
+    }
+    if let Some(e) = exponent {
+        let Token::Exponent(always_sign) = tokens[e] else {
+            unreachable!()
+        };
+        out.push('E');
+        if exponent_value < 0 {
+            out.push('-');
+        } else if always_sign {
+            out.push('+');
+        }
+        let width = tokens[e + 1..]
+            .iter()
+            .filter(|t| matches!(t, Token::Digit(_)))
+            .count();
+        out.push_str(&format!("{:0width$}", exponent_value.abs(), width = width));
+        out.push_str(&literals(&tokens[e + 1..]));
+    }
+    out
+}
+
+/// `value` rounded half away from zero to `decimals` places.
+fn rounded(value: f64, decimals: usize) -> f64 {
+    let scale = 10f64.powi(decimals as i32);
+    significant(value * scale).round() / scale
+}
+
+fn literals(tokens: &[Token]) -> String {
+    tokens
+        .iter()
+        .filter_map(|t| match t {
+            Token::Literal(s) => Some(s.as_str()),
+            Token::Percent => Some("%"),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The integer digits laid into the placeholders before the decimal point,
+/// right to left, with any extra digits going to the leftmost placeholder.
+fn integer_part(tokens: &[Token], digits: &str, grouping: bool) -> String {
+    let placeholders: Vec<char> = tokens
+        .iter()
+        .filter_map(|t| match t {
+            Token::Digit(c) => Some(*c),
+            _ => None,
+        })
+        .collect();
+    let mut digits: Vec<char> = digits.chars().collect();
+    // Placeholders past the number's own digits show as 0, a space or
+    // nothing.
+    let padding: Vec<char> = placeholders
+        .iter()
+        .rev()
+        .skip(digits.len())
+        .filter_map(|p| match p {
+            '0' => Some('0'),
+            '?' => Some(' '),
+            _ => None,
+        })
+        .collect();
+    digits.splice(0..0, padding.into_iter().rev());
+
+    if grouping {
+        let grouped = group_thousands(&digits);
+        let first = tokens.iter().position(|t| matches!(t, Token::Digit(_)));
+        let last = tokens.iter().rposition(|t| matches!(t, Token::Digit(_)));
+        return match (first, last) {
+            (Some(first), Some(last)) => {
+                literals(&tokens[..first]) + &grouped + &literals(&tokens[last + 1..])
+            }
+            _ => literals(tokens) + &grouped,
+        };
+    }
+
+    let mut out = Vec::new();
+    let mut remaining = digits.len();
+    let mut seen = 0;
+    for token in tokens.iter().rev() {
+        match token {
+            Token::Digit(_) => {
+                seen += 1;
+                if seen == placeholders.len() {
+                    out.extend(digits[..remaining].iter().rev());
+                    remaining = 0;
+                } else if remaining > 0 {
+                    remaining -= 1;
+                    out.push(digits[remaining]);
+                }
+            }
+            Token::Literal(s) => out.extend(s.chars().rev()),
+            Token::Percent => out.push('%'),
+            _ => {}
+        }
+    }
+    out.iter().rev().collect()
+}
+
+/// The decimals laid into the placeholders after the point, left to right.
+/// Trailing zeros under `#` are dropped, and under `?` become spaces.
+fn fraction_part(tokens: &[Token], digits: &str) -> String {
+    let placeholders: Vec<char> = tokens
+        .iter()
+        .filter_map(|t| match t {
+            Token::Digit(c) => Some(*c),
+            _ => None,
+        })
+        .collect();
+    let mut digits: Vec<Option<char>> = digits.chars().map(Some).collect();
+    for (i, p) in placeholders.iter().enumerate().rev() {
+        if digits[i] != Some('0') || *p == '0' {
+            break;
+        }
+        digits[i] = if *p == '?' { Some(' ') } else { None };
+    }
+    let mut out = String::from(".");
+    let mut next = digits.into_iter();
+    for token in tokens {
+        match token {
+            Token::Digit(_) => out.extend(next.next().flatten()),
+            Token::Literal(s) => out.push_str(s),
+            Token::Percent => out.push('%'),
+            _ => {}
+        }
+    }
+    out
+}
+
+/// A part of a date format.
+#[derive(Debug, Clone, PartialEq)]
+enum DatePart {
+    Literal(String),
+    Year(usize),
+    Month(usize),
+    Day(usize),
+    Hour(usize),
+    Minute(usize),
+    Second(usize),
+    /// `AM/PM`, or `A/P` when false.
+    Meridiem(bool),
+}
+
+const MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+const WEEKDAYS: [&str; 7] = [
+    "Sunday",
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+];
+
+/// The parts of `section` if it is a date format.
+fn date_parts(section: &str) -> Option<Vec<DatePart>> {
+    let chars: Vec<char> = section.chars().collect();
+    let mut parts = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let rest: String = chars[i..].iter().collect::<String>().to_uppercase();
+        if rest.starts_with("AM/PM") {
+            parts.push(DatePart::Meridiem(true));
+            i += 5;
+            continue;
+        }
+        if rest.starts_with("A/P") {
+            parts.push(DatePart::Meridiem(false));
+            i += 3;
+            continue;
+        }
+        let lower = c.to_ascii_lowercase();
+        if matches!(lower, 'y' | 'm' | 'd' | 'h' | 's') {
+            let run = chars[i..]
+                .iter()
+                .take_while(|d| d.to_ascii_lowercase() == lower)
+                .count();
+            i += run;
+            parts.push(match lower {
+                'y' => DatePart::Year(run),
+                'm' => DatePart::Month(run),
+                'd' => DatePart::Day(run),
+                'h' => DatePart::Hour(run),
+                _ => DatePart::Second(run),
+            });
+            continue;
+        }
+        i += 1;
+        parts.push(DatePart::Literal(match c {
+            '"' => {
+                let end = chars[i..]
+                    .iter()
+                    .position(|&c| c == '"')
+                    .map_or(chars.len(), |p| i + p);
+                let literal = chars[i..end].iter().collect();
+                i = end + 1;
+                literal
+            }
+            '\\' if i < chars.len() => {
+                i += 1;
+                chars[i - 1].to_string()
+            }
+            '_' | '*' => {
+                i += 1;
+                continue;
+            }
+            c => c.to_string(),
+        }));
+    }
+    if !parts
+        .iter()
+        .any(|p| !matches!(p, DatePart::Literal(_) | DatePart::Meridiem(_)))
+    {
+        return None;
+    }
+    // `m` right after an hour or right before seconds means minutes.
+    let codes: Vec<usize> = (0..parts.len())
+        .filter(|&i| !matches!(parts[i], DatePart::Literal(_)))
+        .collect();
+    for (k, &i) in codes.iter().enumerate() {
+        if let DatePart::Month(n) = parts[i] {
+            let after_hour = k > 0 && matches!(parts[codes[k - 1]], DatePart::Hour(_));
+            let before_second = codes
+                .get(k + 1)
+                .is_some_and(|&j| matches!(parts[j], DatePart::Second(_)));
+            if n <= 2 && (after_hour || before_second) {
+                parts[i] = DatePart::Minute(n);
+            }
+        }
+    }
+    Some(parts)
+}
+
+/// A serial number laid out by a date format's parts.
+fn format_date(parts: &[DatePart], serial: f64) -> String {
+    // Round to the second first, so 23:59:59.6 shows as the next day.
+    let serial = (serial * 86_400.0).round() / 86_400.0;
+    let (year, month, day) = date::civil(serial);
+    let (hour, minute, second) = date::time_of_day(serial);
+    let twelve_hour = parts.iter().any(|p| matches!(p, DatePart::Meridiem(_)));
+    let padded = |n: u32, width: usize| {
+        if width >= 2 {
+            format!("{:02}", n)
+        } else {
+            n.to_string()
+        }
+    };
+    let month_name = MONTHS[(month as usize + 11) % 12];
+    parts
+        .iter()
+        .map(|part| match *part {
+            DatePart::Literal(ref s) => s.clone(),
+            DatePart::Year(n) if n <= 2 => format!("{:02}", year % 100),
+            DatePart::Year(_) => format!("{:04}", year),
+            DatePart::Month(n @ 1..=2) => padded(month, n),
+            DatePart::Month(3) => month_name[..3].to_string(),
+            DatePart::Month(4) => month_name.to_string(),
+            DatePart::Month(_) => month_name[..1].to_string(),
+            DatePart::Day(n @ 1..=2) => padded(day, n),
+            DatePart::Day(3) => WEEKDAYS[(serial as usize + 6) % 7][..3].to_string(),
+            DatePart::Day(_) => WEEKDAYS[(serial as usize + 6) % 7].to_string(),
+            DatePart::Hour(n) if twelve_hour => padded((hour + 11) % 12 + 1, n),
+            DatePart::Hour(n) => padded(hour, n),
+            DatePart::Minute(n) => padded(minute, n),
+            DatePart::Second(n) => padded(second, n),
+            DatePart::Meridiem(full) => match (hour < 12, full) {
+                (true, true) => "AM",
+                (false, true) => "PM",
+                (true, false) => "A",
+                (false, false) => "P",
+            }
+            .to_string(),
+        })
+        .collect()
+}
+
+fn group_thousands(digits: &[char]) -> String {
+    let mut out = String::new();
+    for (i, c) in digits.iter().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) && c.is_ascii_digit() {
+            out.push(',');
+        }
+        out.push(*c);
+    }
+    out
+}
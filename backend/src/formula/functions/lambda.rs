@@ -0,0 +1,212 @@
+//! `LET`, `LAMBDA` and the functions that call a lambda for each part of an
+//! array. A lambda is not a value in its own right: it is written out with
+//! `LAMBDA(...)`, bound to a name by `LET` or a parameter, or saved under a
+//! workbook name, and means something only when called.
+
+use super::argument;
+use crate::formula::FormulaError;
+use crate::formula::ast::{Expr, ExprKind};
+use crate::formula::eval::{self, Binding, Evaluator, Lambda};
+use crate::formula::value::{Array, CellError, Value};
+use std::rc::Rc;
+
+type Lazy = Result<Value, FormulaError>;
+
+/// The lambda `expr` stands for: a `LAMBDA(...)` written in place, or a
+/// name bound to one.
+fn resolve(ev: &Evaluator, expr: &Expr) -> Result<Option<Rc<Lambda>>, FormulaError> {
+    match &expr.kind {
+        ExprKind::Call { .. } => Ok(eval::lambda(expr, ev.scope())),
+        ExprKind::Name(name) => match ev.binding(name) {
+            Some(Binding::Lambda(lambda)) => Ok(Some(lambda.clone())),
+            Some(Binding::Value(_)) => Ok(None),
+            None => ev.defined_lambda(name),
+        },
+        _ => Ok(None),
+    }
+}
+
+/// What `expr` binds a name to: a lambda, or else its value.
+fn bind(ev: &Evaluator, expr: &Expr) -> Result<Binding, FormulaError> {
+    Ok(match resolve(ev, expr)? {
+        Some(lambda) => Binding::Lambda(lambda),
+        None => Binding::Value(argument(ev, expr)?),
+    })
+}
+
+/// A call of a lambda by name, as in `f(1, 2)`.
+pub(super) fn call(ev: &Evaluator, lambda: &Lambda, args: &[Expr]) -> Lazy {
+    let args = args
+        .iter()
+        .map(|arg| bind(ev, arg))
+        .collect::<Result<Vec<_>, _>>()?;
+    ev.invoke(lambda, args)
+}
+
+/// `LET(name1, value1, [name2, value2, ...], calculation)`: `calculation`
+/// with each name standing for the value after it. Each value can use the
+/// names before it.
+pub(super) fn let_(ev: &Evaluator, args: &[Expr]) -> Lazy {
+    if args.len() < 3 || args.len().is_multiple_of(2) {
+        return Ok(Value::Error(CellError::Value));
+    }
+    let (calculation, pairs) = args.split_last().unwrap();
+    let mut scope = ev.scope().clone();
+    for pair in pairs.chunks(2) {
+        let ExprKind::Name(name) = &pair[0].kind else {
+            return Ok(Value::Error(CellError::Value));
+        };
+        let binding = bind(&ev.within(scope.clone()), &pair[1])?;
+        scope = scope.with(name.to_ascii_uppercase(), binding);
+    }
+    ev.within(scope).eval(calculation)
+}
+
+/// `LAMBDA(param, ..., calculation)` that is not called, which shows as
+/// `#CALC!`.
+pub(super) fn lambda(_: &Evaluator, args: &[Expr]) -> Lazy {
+    Ok(Value::Error(match eval::lambda_params(args) {
+        Some(_) => CellError::Calc,
+        None => CellError::Value,
+    }))
+}
+
+fn array(ev: &Evaluator, expr: &Expr) -> Result<Array, FormulaError> {
+    Ok(match argument(ev, expr)? {
+        Value::Array(array) => array,
+        value => Array::new(1, 1, vec![value]),
+    })
+}
+
+/// A lambda's result as one element of an array: a single value, or
+/// `#CALC!` for a block, since arrays cannot nest.
+fn element(value: Value) -> Value {
+    match value {
+        Value::Array(array) if array.rows == 1 && array.cols == 1 => {
+            array.values.into_iter().next().unwrap()
+        }
+        Value::Array(_) => Value::Error(CellError::Calc),
+        value => value,
+    }
+}
+
+/// `MAP(array1, [array2, ...], lambda)`: `lambda` called for each position,
+/// with the element of each array there.
+pub(super) fn map(ev: &Evaluator, args: &[Expr]) -> Lazy {
+    let Some((last, arrays)) = args.split_last() else {
+        return Ok(Value::Error(CellError::Value));
+    };
+    let Some(lambda) = resolve(ev, last)? else {
+        return Ok(Value::Error(CellError::Value));
+    };
+    let arrays = arrays
+        .iter()
+        .map(|arg| array(ev, arg))
+        .collect::<Result<Vec<_>, _>>()?;
+    let Some(first) = arrays.first() else {
+        return Ok(Value::Error(CellError::Value));
+    };
+    let (rows, cols) = (first.rows, first.cols);
+    if arrays.iter().any(|a| (a.rows, a.cols) != (rows, cols)) {
+        return Ok(Value::Error(CellError::Value));
+    }
+    let mut values = Vec::with_capacity(rows * cols);
+    for i in 0..rows * cols {
+        let args = arrays
+            .iter()
+            .map(|a| Binding::Value(a.values[i].clone()))
+            .collect();
+        values.push(element(ev.invoke(&lambda, args)?));
+    }
+    Ok(Value::Array(Array::new(rows, cols, values)))
+}
+
+/// Calls `lambda(accumulator, value)` for each value of the array in
+/// `args[1]`, starting from `args[0]`, and returns each accumulator in turn.
+fn accumulate(
+    ev: &Evaluator,
+    args: &[Expr],
+) -> Result<Result<Vec<Value>, CellError>, FormulaError> {
+    let [initial, array_arg, last] = args else {
+        return Ok(Err(CellError::Value));
+    };
+    let Some(lambda) = resolve(ev, last)? else {
+        return Ok(Err(CellError::Value));
+    };
+    let mut accumulator = element(argument(ev, initial)?);
+    let array = array(ev, array_arg)?;
+    let mut steps = Vec::with_capacity(array.values.len());
+    for value in array.values {
+        let args = vec![Binding::Value(accumulator), Binding::Value(value)];
+        accumulator = ev.invoke(&lambda, args)?;
+        steps.push(accumulator.clone());
+    }
+    Ok(Ok(steps))
+}
+
+/// `REDUCE([initial], array, lambda)`: the accumulator left after
+/// `lambda(accumulator, value)` has been called for each value in turn.
+pub(super) fn reduce(ev: &Evaluator, args: &[Expr]) -> Lazy {
+    Ok(match accumulate(ev, args)? {
+        Ok(mut steps) => match steps.pop() {
+            Some(last) => last,
+            None => element(argument(ev, &args[0])?),
+        },
+        Err(e) => Value::Error(e),
+    })
+}
+
+/// `SCAN([initial], array, lambda)`: like `REDUCE`, but an array the shape
+/// of `array` holding the accumulator after each value.
+pub(super) fn scan(ev: &Evaluator, args: &[Expr]) -> Lazy {
+    Ok(match accumulate(ev, args)? {
+        Ok(steps) => {
+            let array = array(ev, &args[1])?;
+            let values = steps.into_iter().map(element).collect();
+            Value::Array(Array::new(array.rows, array.cols, values))
+        }
+        Err(e) => Value::Error(e),
+    })
+}
+
+/// `BYROW(array, lambda)`: a column holding `lambda` called with each row.
+pub(super) fn byrow(ev: &Evaluator, args: &[Expr]) -> Lazy {
+    by_line(ev, args, false)
+}
+
+/// `BYCOL(array, lambda)`: a row holding `lambda` called with each column.
+pub(super) fn bycol(ev: &Evaluator, args: &[Expr]) -> Lazy {
+    by_line(ev, args, true)
+}
+
+fn by_line(ev: &Evaluator, args: &[Expr], by_col: bool) -> Lazy {
+    let [array_arg, last] = args else {
+        return Ok(Value::Error(CellError::Value));
+    };
+    let Some(lambda) = resolve(ev, last)? else {
+        return Ok(Value::Error(CellError::Value));
+    };
+    let array = array(ev, array_arg)?;
+    let lines = if by_col { array.cols } else { array.rows };
+    let mut values = Vec::with_capacity(lines);
+    for i in 0..lines {
+        let line = match by_col {
+            true => Array::new(
+                array.rows,
+                1,
+                (0..array.rows).map(|r| array.get(r, i).clone()).collect(),
+            ),
+            false => Array::new(
+                1,
+                array.cols,
+                (0..array.cols).map(|c| array.get(i, c).clone()).collect(),
+            ),
+        };
+        let args = vec![Binding::Value(Value::Array(line))];
+        values.push(element(ev.invoke(&lambda, args)?));
+    }
+    Ok(Value::Array(match by_col {
+        true => Array::new(1, lines, values),
+        false => Array::new(lines, 1, values),
+    }))
+}
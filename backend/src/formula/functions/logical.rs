@@ -0,0 +1,140 @@
+//! Logical functions. The conditional ones only evaluate the arguments they
+//! choose, so `IF(A1=0, 0, 1/A1)` never divides by zero.
+
+use super::{argument, single};
+use crate::formula::FormulaError;
+use crate::formula::ast::Expr;
+use crate::formula::eval::{Evaluator, compare};
+use crate::formula::value::{CellError, Value};
+use std::cmp::Ordering;
+
+type Lazy = Result<Value, FormulaError>;
+
+/// Evaluates `expr` as a condition.
+fn condition(ev: &Evaluator, expr: &Expr) -> Result<Result<bool, CellError>, FormulaError> {
+    Ok(single(&argument(ev, expr)?).and_then(Value::to_bool))
+}
+
+/// `IF(condition, value_if_true, [value_if_false])`: a missing
+/// `value_if_false` is FALSE.
+pub(super) fn if_(ev: &Evaluator, args: &[Expr]) -> Lazy {
+    if !(2..=3).contains(&args.len()) {
+        return Ok(Value::Error(CellError::Value));
+    }
+    match condition(ev, &args[0])? {
+        Ok(true) => ev.eval(&args[1]),
+        Ok(false) => args.get(2).map_or(Ok(Value::Bool(false)), |e| ev.eval(e)),
+        Err(e) => Ok(Value::Error(e)),
+    }
+}
+
+/// `IFS(condition1, value1, ...)`: the value beside the first true
+/// condition, or `#N/A` if none is.
+pub(super) fn ifs(ev: &Evaluator, args: &[Expr]) -> Lazy {
+    if args.is_empty() || !args.len().is_multiple_of(2) {
+        return Ok(Value::Error(CellError::Value));
+    }
+    for pair in args.chunks(2) {
+        match condition(ev, &pair[0])? {
+            Ok(true) => return ev.eval(&pair[1]),
+            Ok(false) => {}
+            Err(e) => return Ok(Value::Error(e)),
+        }
+    }
+    Ok(Value::Error(CellError::NA))
+}
+
+/// `IFERROR(value, value_if_error)`
+pub(super) fn iferror(ev: &Evaluator, args: &[Expr]) -> Lazy {
+    fallback(ev, args, |_| true)
+}
+
+/// `IFNA(value, value_if_na)`: only catches `#N/A`.
+pub(super) fn ifna(ev: &Evaluator, args: &[Expr]) -> Lazy {
+    fallback(ev, args, |e| e == CellError::NA)
+}
+
+fn fallback(ev: &Evaluator, args: &[Expr], catches: fn(CellError) -> bool) -> Lazy {
+    if args.len() != 2 {
+        return Ok(Value::Error(CellError::Value));
+    }
+    match ev.eval(&args[0])? {
+        Value::Error(e) if catches(e) => ev.eval(&args[1]),
+        value => Ok(value),
+    }
+}
+
+/// `SWITCH(expression, value1, result1, ..., [default])`: the result beside
+/// the first value equal to `expression`, the default if there is one, or
+/// `#N/A`.
+pub(super) fn switch(ev: &Evaluator, args: &[Expr]) -> Lazy {
+    if args.len() < 3 {
+        return Ok(Value::Error(CellError::Value));
+    }
+    let subject = match single(&argument(ev, &args[0])?) {
+        Ok(value) => value.clone(),
+        Err(e) => return Ok(Value::Error(e)),
+    };
+    let cases = &args[1..];
+    for pair in cases.chunks_exact(2) {
+        let candidate = argument(ev, &pair[0])?;
+        match single(&candidate).and_then(|c| compare(&subject, c)) {
+            Ok(Ordering::Equal) => return ev.eval(&pair[1]),
+            Ok(_) => {}
+            Err(e) => return Ok(Value::Error(e)),
+        }
+    }
+    match cases.chunks_exact(2).remainder() {
+        [default] => ev.eval(default),
+        _ => Ok(Value::Error(CellError::NA)),
+    }
+}
+
+/// The truth values among `args`. In a reference or array only numbers and
+/// booleans count; values typed in directly must convert. `#VALUE!` if
+/// there are none at all.
+fn truths(args: &[Value]) -> Result<Vec<bool>, CellError> {
+    let mut truths = Vec::new();
+    for arg in args {
+        match arg {
+            Value::Array(array) => {
+                for value in &array.values {
+                    match value {
+                        Value::Number(_) | Value::Bool(_) => truths.push(value.to_bool()?),
+                        Value::Error(e) => return Err(*e),
+                        _ => {}
+                    }
+                }
+            }
+            value => truths.push(value.to_bool()?),
+        }
+    }
+    if truths.is_empty() {
+        return Err(CellError::Value);
+    }
+    Ok(truths)
+}
+
+fn logical(result: Result<bool, CellError>) -> Value {
+    result.map_or_else(Value::Error, Value::Bool)
+}
+
+pub(super) fn and(args: &[Value]) -> Value {
+    logical(truths(args).map(|t| t.into_iter().all(|b| b)))
+}
+
+pub(super) fn or(args: &[Value]) -> Value {
+    logical(truths(args).map(|t| t.into_iter().any(|b| b)))
+}
+
+/// `XOR(...)`: TRUE if an odd number of the values are TRUE.
+pub(super) fn xor(args: &[Value]) -> Value {
+    logical(truths(args).map(|t| t.into_iter().filter(|&b| b).count() % 2 == 1))
+}
+
+pub(super) fn not(args: &[Value]) -> Value {
+    if args.len() != 1 {
+        return Value::Error(CellError::Value);
+    }
+    logical(single(&args[0]).and_then(Value::to_bool).map(|b| !b))
+}
@@ -0,0 +1,551 @@
+//! Lookup and reference functions.
+//!
+//! `INDEX`, `ROW`, `COLUMN`, `ROWS`, `COLUMNS` and `OFFSET` look at the
+//! reference they are given rather than its values, so `ROWS(A:A)` is the
+//! height of the sheet and `INDEX(A:A, 500)` reads one cell rather than the
+//! column. `INDIRECT` and `OFFSET` build references, which only evaluating
+//! them reveals, so they tell the evaluator what they reached.
+
+use super::criteria::wildcard;
+use super::{argument, arity, optional, scalar, shape, single, values};
+use crate::formula::FormulaError;
+use crate::formula::ast::{Expr, ExprKind, MAX_COLS, MAX_ROWS, column_name};
+use crate::formula::eval::{Evaluator, compare};
+use crate::formula::parse;
+use crate::formula::value::{Array, CellError, Value};
+use crate::graph::{CellRange, SheetRange};
+use std::cmp::Ordering;
+
+type Lazy = Result<Value, FormulaError>;
+
+/// What a reference-building function gives: the cells it refers to, or the
+/// error it shows instead.
+type Target = Result<SheetRange, CellError>;
+
+/// The sheet and range `expr` refers to, if it is a reference. Whole rows
+/// and columns end at the edge of the sheet.
+fn reference(ev: &Evaluator, expr: &Expr) -> Result<Option<SheetRange>, FormulaError> {
+    let Some(Ok(mut target)) = ev.target(expr)? else {
+        return Ok(None);
+    };
+    target.range.end_row = target.range.end_row.min(MAX_ROWS - 1);
+    target.range.end_col = target.range.end_col.min(MAX_COLS - 1);
+    Ok(Some(target))
+}
+
+/// The values a reference built by a function covers.
+fn values_at(ev: &Evaluator, target: Target) -> Lazy {
+    match target {
+        Ok(target) => ev.fetch(Some(&target.sheet), &target.range),
+        Err(e) => Ok(Value::Error(e)),
+    }
+}
+
+/// `INDIRECT(ref_text, [a1])`: the reference written out in `ref_text`, as
+/// in `INDIRECT("B" & A1)`. With `a1` FALSE the text is in R1C1 style, as
+/// in `R2C3` or `Data!R1C1:R5C2`.
+pub(super) fn indirect(ev: &Evaluator, args: &[Expr]) -> Lazy {
+    values_at(ev, indirect_target(ev, args)?)
+}
+
+pub(super) fn indirect_target(ev: &Evaluator, args: &[Expr]) -> Result<Target, FormulaError> {
+    if !(1..=2).contains(&args.len()) {
+        return Ok(Err(CellError::Value));
+    }
+    let text = match single(&argument(ev, &args[0])?).and_then(Value::to_text) {
+        Ok(text) => text,
+        Err(e) => return Ok(Err(e)),
+    };
+    let a1 = match args.get(1) {
+        Some(arg) => match single(&argument(ev, arg)?).and_then(Value::to_bool) {
+            Ok(a1) => a1,
+            Err(e) => return Ok(Err(e)),
+        },
+        None => true,
+    };
+    let source = if a1 { Some(text) } else { r1c1(&text) };
+    let target = match source.as_deref().map(parse) {
+        Some(Ok(expr))
+            if matches!(
+                expr.kind,
+                ExprKind::Reference { .. } | ExprKind::Table { .. } | ExprKind::Name(_)
+            ) =>
+        {
+            ev.target(&expr)?
+        }
+        _ => None,
+    };
+    let target = target.unwrap_or(Err(CellError::Ref));
+    if let Ok(target) = &target {
+        ev.reached(target);
+    }
+    Ok(target)
+}
+
+/// An R1C1-style reference such as `R2C3` or `Data!R1C1:R5C2` in A1 style.
+fn r1c1(text: &str) -> Option<String> {
+    let (sheet, cells) = match text.rfind('!') {
+        Some(bang) => text.split_at(bang + 1),
+        None => ("", text),
+    };
+    let cell = |text: &str| {
+        let upper = text.trim().to_ascii_uppercase();
+        let (row, col) = upper.strip_prefix('R')?.split_once('C')?;
+        let (row, col) = (row.parse::<i32>().ok()?, col.parse::<i32>().ok()?);
+        ((1..=MAX_ROWS).contains(&row) && (1..=MAX_COLS).contains(&col))
+            .then(|| format!("{}{}", column_name(col - 1), row))
+    };
+    let cells = match cells.split_once(':') {
+        Some((first, last)) => format!("{}:{}", cell(first)?, cell(last)?),
+        None => cell(cells)?,
+    };
+    Some(format!("{}{}", sheet, cells))
+}
+
+/// `OFFSET(reference, rows, cols, [height], [width])`: the block `rows`
+/// down and `cols` across from `reference`, as tall and wide as it unless
+/// `height` and `width` say otherwise.
+pub(super) fn offset(ev: &Evaluator, args: &[Expr]) -> Lazy {
+    values_at(ev, offset_target(ev, args)?)
+}
+
+pub(super) fn offset_target(ev: &Evaluator, args: &[Expr]) -> Result<Target, FormulaError> {
+    if !(3..=5).contains(&args.len()) {
+        return Ok(Err(CellError::Value));
+    }
+    let Some(base) = reference(ev, &args[0])? else {
+        return Ok(Err(match ev.eval(&args[0])? {
+            Value::Error(e) => e,
+            _ => CellError::Value,
+        }));
+    };
+    let range = base.range;
+    let defaults = [0.0, 0.0, range.rows() as f64, range.cols() as f64];
+    let mut numbers = defaults;
+    for (n, arg) in numbers.iter_mut().zip(&args[1..]) {
+        if arg.kind == ExprKind::Missing {
+            continue;
+        }
+        match index_argument(ev, arg)? {
+            Ok(value) => *n = value,
+            Err(e) => return Ok(Err(e)),
+        }
+    }
+    let [rows, cols, height, width] = numbers;
+    let start_row = range.start_row as f64 + rows;
+    let start_col = range.start_col as f64 + cols;
+    let end_row = start_row + height - 1.0;
+    let end_col = start_col + width - 1.0;
+    if height < 1.0
+        || width < 1.0
+        || start_row < 0.0
+        || start_col < 0.0
+        || end_row >= MAX_ROWS as f64
+        || end_col >= MAX_COLS as f64
+    {
+        return Ok(Err(CellError::Ref));
+    }
+    let target = SheetRange::new(
+        &base.sheet,
+        CellRange::new(
+            start_row as i32,
+            start_col as i32,
+            end_row as i32,
+            end_col as i32,
+        ),
+    );
+    ev.reached(&target);
+    Ok(Ok(target))
+}
+
+/// A numeric argument of a lazy function.
+fn index_argument(ev: &Evaluator, expr: &Expr) -> Result<Result<f64, CellError>, FormulaError> {
+    Ok(scalar(&argument(ev, expr)?).map(f64::trunc))
+}
+
+/// `INDEX(array, row, [column])`: the cell at `row` and `column` of
+/// `array`, counting from 1. A row or column of 0 selects the whole column
+/// or row, and a single index into a one-row or one-column range counts
+/// along it.
+pub(super) fn index(ev: &Evaluator, args: &[Expr]) -> Lazy {
+    if !(2..=3).contains(&args.len()) {
+        return Ok(Value::Error(CellError::Value));
+    }
+    let target = reference(ev, &args[0])?;
+    let source = match target {
+        Some(_) => None,
+        None => Some(ev.eval(&args[0])?),
+    };
+    let (rows, cols) = match (&target, &source) {
+        (Some(target), _) => (target.range.rows() as usize, target.range.cols() as usize),
+        (None, Some(value)) => shape(value),
+        (None, None) => unreachable!(),
+    };
+    let first = match index_argument(ev, &args[1])? {
+        Ok(n) => n,
+        Err(e) => return Ok(Value::Error(e)),
+    };
+    let second = match args.get(2).map(|arg| index_argument(ev, arg)).transpose()? {
+        Some(Ok(n)) => Some(n),
+        Some(Err(e)) => return Ok(Value::Error(e)),
+        None => None,
+    };
+    let (row, col) = match second {
+        Some(col) => (first, col),
+        None if rows == 1 => (1.0, first),
+        None if cols == 1 => (first, 1.0),
+        None => (first, 0.0),
+    };
+    if row < 0.0 || col < 0.0 {
+        return Ok(Value::Error(CellError::Value));
+    }
+    let (row, col) = (row as usize, col as usize);
+    if row > rows || col > cols {
+        return Ok(Value::Error(CellError::Ref));
+    }
+    // The selected rows and columns, 0-based and inclusive.
+    let (row_start, row_end) = if row == 0 {
+        (0, rows - 1)
+    } else {
+        (row - 1, row - 1)
+    };
+    let (col_start, col_end) = if col == 0 {
+        (0, cols - 1)
+    } else {
+        (col - 1, col - 1)
+    };
+
+    if let Some(SheetRange { sheet, range }) = target {
+        let selected = CellRange::new(
+            range.start_row + row_start as i32,
+            range.start_col + col_start as i32,
+            range.start_row + row_end as i32,
+            range.start_col + col_end as i32,
+        );
+        return ev.fetch(Some(&sheet), &selected);
+    }
+    let source = source.unwrap();
+    let values = values(&source);
+    let picked: Vec<Value> = (row_start..=row_end)
+        .flat_map(|r| (col_start..=col_end).map(move |c| (r, c)))
+        .map(|(r, c)| values[r * cols + c].clone())
+        .collect();
+    Ok(block(
+        row_end - row_start + 1,
+        col_end - col_start + 1,
+        picked,
+    ))
+}
+
+/// A block of values, or the value itself if there is only one.
+fn block(rows: usize, cols: usize, mut values: Vec<Value>) -> Value {
+    if values.len() == 1 {
+        values.pop().unwrap()
+    } else {
+        Value::Array(Array::new(rows, cols, values))
+    }
+}
+
+/// `ROW([reference])`: the row number of the reference, or of the cell
+/// holding the formula.
+pub(super) fn row(ev: &Evaluator, args: &[Expr]) -> Lazy {
+    position(ev, args, |(row, _)| row, |range| range.start_row)
+}
+
+/// `COLUMN([reference])`
+pub(super) fn column(ev: &Evaluator, args: &[Expr]) -> Lazy {
+    position(ev, args, |(_, col)| col, |range| range.start_col)
+}
+
+fn position(
+    ev: &Evaluator,
+    args: &[Expr],
+    of_cell: fn((i32, i32)) -> i32,
+    of_range: fn(&CellRange) -> i32,
+) -> Lazy {
+    let index = match args {
+        [] => ev.cell().map(of_cell),
+        [arg] => reference(ev, arg)?.map(|target| of_range(&target.range)),
+        _ => None,
+    };
+    Ok(match index {
+        Some(index) => Value::Number(index as f64 + 1.0),
+        None => Value::Error(CellError::Value),
+    })
+}
+
+/// `ROWS(array)`
+pub(super) fn rows(ev: &Evaluator, args: &[Expr]) -> Lazy {
+    size(ev, args, |(rows, _)| rows)
+}
+
+/// `COLUMNS(array)`
+pub(super) fn columns(ev: &Evaluator, args: &[Expr]) -> Lazy {
+    size(ev, args, |(_, cols)| cols)
+}
+
+fn size(ev: &Evaluator, args: &[Expr], pick: fn((usize, usize)) -> usize) -> Lazy {
+    let [arg] = args else {
+        return Ok(Value::Error(CellError::Value));
+    };
+    let shape = match reference(ev, arg)? {
+        Some(SheetRange { range, .. }) => (range.rows() as usize, range.cols() as usize),
+        None => match ev.eval(arg)? {
+            Value::Error(e) => return Ok(Value::Error(e)),
+            value => shape(&value),
+        },
+    };
+    Ok(Value::Number(pick(shape) as f64))
+}
+
+/// `CHOOSE(index, value1, ...)`: only the chosen value is evaluated.
+pub(super) fn choose(ev: &Evaluator, args: &[Expr]) -> Lazy {
+    if args.len() < 2 {
+        return Ok(Value::Error(CellError::Value));
+    }
+    match index_argument(ev, &args[0])? {
+        Ok(n) if n >= 1.0 && (n as usize) < args.len() => ev.eval(&args[n as usize]),
+        Ok(_) => Ok(Value::Error(CellError::Value)),
+        Err(e) => Ok(Value::Error(e)),
+    }
+}
+
+/// Values that compare with each other in a lookup: numbers with numbers,
+/// text with text, booleans with booleans.
+fn same_kind(a: &Value, b: &Value) -> bool {
+    matches!(
+        (a, b),
+        (Value::Number(_), Value::Number(_))
+            | (Value::Text(_), Value::Text(_))
+            | (Value::Bool(_), Value::Bool(_))
+    )
+}
+
+fn equals(needle: &Value, candidate: &Value, wildcards: bool) -> bool {
+    match (needle, candidate) {
+        (Value::Text(pattern), Value::Text(text)) if wildcards => {
+            let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+            let text: Vec<char> = text.to_lowercase().chars().collect();
+            wildcard(&pattern, &text)
+        }
+        _ => same_kind(needle, candidate) && compare(needle, candidate) == Ok(Ordering::Equal),
+    }
+}
+
+/// The first candidate equal to `needle` (the last if `reverse`).
+fn find_exact(
+    needle: &Value,
+    candidates: &[&Value],
+    wildcards: bool,
+    reverse: bool,
+) -> Option<usize> {
+    let matches = |&i: &usize| equals(needle, candidates[i], wildcards);
+    if reverse {
+        (0..candidates.len()).rev().find(matches)
+    } else {
+        (0..candidates.len()).find(matches)
+    }
+}
+
+/// An approximate match in candidates sorted ascending (or descending):
+/// the last one not past `needle`. Candidates of another kind are skipped.
+fn find_sorted(needle: &Value, candidates: &[&Value], descending: bool) -> Option<usize> {
+    let past = if descending {
+        Ordering::Less
+    } else {
+        Ordering::Greater
+    };
+    let mut found = None;
+    for (i, candidate) in candidates.iter().enumerate() {
+        if !same_kind(needle, candidate) {
+            continue;
+        }
+        if compare(candidate, needle) == Ok(past) {
+            break;
+        }
+        found = Some(i);
+    }
+    found
+}
+
+/// An exact match, or else the nearest candidate below `needle` (or above,
+/// if `above`), in any order.
+fn find_nearest(
+    needle: &Value,
+    candidates: &[&Value],
+    above: bool,
+    reverse: bool,
+) -> Option<usize> {
+    if let Some(i) = find_exact(needle, candidates, false, reverse) {
+        return Some(i);
+    }
+    let wanted = if above {
+        Ordering::Greater
+    } else {
+        Ordering::Less
+    };
+    let mut best: Option<usize> = None;
+    for (i, candidate) in candidates.iter().enumerate() {
+        if !same_kind(needle, candidate) || compare(candidate, needle) != Ok(wanted) {
+            continue;
+        }
+        let closer = match best {
+            None => true,
+            Some(b) => compare(candidate, candidates[b]) == Ok(wanted.reverse()),
+        };
+        if closer {
+            best = Some(i);
+        }
+    }
+    best
+}
+
+/// The value to look up: errors are returned, and a blank finds nothing.
+fn needle(arg: &Value) -> Result<Value, CellError> {
+    match single(arg)? {
+        Value::Empty => Err(CellError::NA),
+        value => Ok(value.clone()),
+    }
+}
+
+/// A 1-based index argument, which must be at least 1.
+fn ordinal(arg: &Value) -> Result<usize, CellError> {
+    let n = scalar(arg)?.trunc();
+    if n < 1.0 {
+        return Err(CellError::Value);
+    }
+    Ok(n as usize)
+}
+
+fn result(found: Result<Value, CellError>) -> Value {
+    found.unwrap_or_else(Value::Error)
+}
+
+/// `VLOOKUP(value, table, column, [approximate])`: looks `value` up in the
+/// first column of `table` and returns the cell in `column` of that row.
+/// An approximate match, the default, expects the first column sorted.
+pub(super) fn vlookup(args: &[Value]) -> Value {
+    result(arity(args, 3, 4).and_then(|_| {
+        let needle = needle(&args[0])?;
+        let (rows, cols) = shape(&args[1]);
+        let table = values(&args[1]);
+        let col = ordinal(&args[2])?;
+        if col > cols {
+            return Err(CellError::Ref);
+        }
+        let approximate = args.get(3).map_or(Ok(true), |a| single(a)?.to_bool())?;
+        let keys: Vec<&Value> = (0..rows).map(|r| &table[r * cols]).collect();
+        let row = if approximate {
+            find_sorted(&needle, &keys, false)
+        } else {
+            find_exact(&needle, &keys, true, false)
+        };
+        let row = row.ok_or(CellError::NA)?;
+        Ok(table[row * cols + col - 1].clone())
+    }))
+}
+
+/// `HLOOKUP(value, table, row, [approximate])`: VLOOKUP across the first
+/// row.
+pub(super) fn hlookup(args: &[Value]) -> Value {
+    result(arity(args, 3, 4).and_then(|_| {
+        let needle = needle(&args[0])?;
+        let (rows, cols) = shape(&args[1]);
+        let table = values(&args[1]);
+        let row = ordinal(&args[2])?;
+        if row > rows {
+            return Err(CellError::Ref);
+        }
+        let approximate = args.get(3).map_or(Ok(true), |a| single(a)?.to_bool())?;
+        let keys: Vec<&Value> = table[..cols].iter().collect();
+        let col = if approximate {
+            find_sorted(&needle, &keys, false)
+        } else {
+            find_exact(&needle, &keys, true, false)
+        };
+        let col = col.ok_or(CellError::NA)?;
+        Ok(table[(row - 1) * cols + col].clone())
+    }))
+}
+
+/// `MATCH(value, array, [type])`: the position of `value` in a one-row or
+/// one-column array. Type 1, the default, finds the largest value not above
+/// it in ascending data, -1 the smallest not below it in descending data,
+/// and 0 an exact match.
+pub(super) fn match_(args: &[Value]) -> Value {
+    result(arity(args, 2, 3).and_then(|_| {
+        let needle = needle(&args[0])?;
+        let (rows, cols) = shape(&args[1]);
+        if rows != 1 && cols != 1 {
+            return Err(CellError::NA);
+        }
+        let candidates: Vec<&Value> = values(&args[1]).iter().collect();
+        let found = match args.get(2).map_or(Ok(1.0), scalar)? {
+            t if t > 0.0 => find_sorted(&needle, &candidates, false),
+            t if t < 0.0 => find_sorted(&needle, &candidates, true),
+            _ => find_exact(&needle, &candidates, true, false),
+        };
+        let found = found.ok_or(CellError::NA)?;
+        Ok(Value::Number(found as f64 + 1.0))
+    }))
+}
+
+/// `XLOOKUP(value, lookup, results, [if_not_found], [match_mode],
+/// [search_mode])`: finds `value` in a one-row or one-column `lookup` and
+/// returns the matching row or column of `results`.
+///
+/// Match modes are 0 (exact), -1 (exact or next smaller), 1 (exact or next
+/// larger) and 2 (wildcards). Search modes 1 and 2 search from the first
+/// entry, -1 and -2 from the last.
+pub(super) fn xlookup(args: &[Value]) -> Value {
+    result(arity(args, 3, 6).and_then(|_| {
+        let needle = needle(&args[0]);
+        let (rows, cols) = shape(&args[1]);
+        let (result_rows, result_cols) = shape(&args[2]);
+        let vertical = match (rows, cols) {
+            (_, 1) if result_rows == rows => true,
+            (1, _) if result_cols == cols => false,
+            _ => return Err(CellError::Value),
+        };
+        let match_mode = optional(args, 4).map_or(Ok(0.0), scalar)?;
+        let search_mode = optional(args, 5).map_or(Ok(1.0), scalar)?;
+        let reverse = match search_mode as i32 {
+            1 | 2 => false,
+            -1 | -2 => true,
+            _ => return Err(CellError::Value),
+        };
+        let candidates: Vec<&Value> = values(&args[1]).iter().collect();
+        let found = match needle {
+            Ok(needle) => match match_mode as i32 {
+                0 => find_exact(&needle, &candidates, false, reverse),
+                2 => find_exact(&needle, &candidates, true, reverse),
+                -1 => find_nearest(&needle, &candidates, false, reverse),
+                1 => find_nearest(&needle, &candidates, true, reverse),
+                _ => return Err(CellError::Value),
+            },
+            Err(CellError::NA) => None,
+            Err(e) => return Err(e),
+        };
+        let Some(found) = found else {
+            return match optional(args, 3) {
+                Some(fallback) => Ok(match fallback {
+                    Value::Array(array) if array.rows == 1 && array.cols == 1 => {
+                        array.values[0].clone()
+                    }
+                    value => value.clone(),
+                }),
+                None => Err(CellError::NA),
+            };
+        };
+        let results = values(&args[2]);
+        Ok(if vertical {
+            let start = found * result_cols;
+            block(1, result_cols, results[start..start + result_cols].to_vec())
+        } else {
+            let picked = (0..result_rows)
+                .map(|r| results[r * result_cols + found].clone())
+                .collect();
+            block(result_rows, 1, picked)
+        })
+    }))
+}
@@ -0,0 +1,307 @@
+//! Math and trigonometry functions.
+
+use super::criteria::{self, Criterion};
+use super::{arity, number, numbers, scalar, scalar_or, shape, single, values};
+use crate::formula::value::{CellError, Value};
+use std::f64::consts::PI;
+
+/// A function of one number.
+fn unary(args: &[Value], f: impl Fn(f64) -> Result<f64, CellError>) -> Value {
+    number(arity(args, 1, 1).and_then(|_| f(scalar(&args[0])?)))
+}
+
+/// A function of two numbers.
+fn binary(args: &[Value], f: impl Fn(f64, f64) -> Result<f64, CellError>) -> Value {
+    number(arity(args, 2, 2).and_then(|_| f(scalar(&args[0])?, scalar(&args[1])?)))
+}
+
+/// `n` rounded to the 15 significant digits Excel keeps, so that binary
+/// noise such as `2.675 * 100 = 267.49999999999997` rounds the way the
+/// decimal number would.
+pub(super) fn significant(n: f64) -> f64 {
+    format!("{:.14e}", n).parse().unwrap_or(n)
+}
+
+/// `n` rounded to `digits` decimal places (to the left of the point when
+/// negative) by `f`, which rounds a number to an integer.
+fn round_with(args: &[Value], f: fn(f64) -> f64) -> Value {
+    binary(args, |n, digits| {
+        let digits = digits.trunc() as i32;
+        let scale = 10f64.powi(digits.abs());
+        Ok(if digits >= 0 {
+            f(significant(n * scale)) / scale
+        } else {
+            f(significant(n / scale)) * scale
+        })
+    })
+}
+
+pub(super) fn abs(args: &[Value]) -> Value {
+    unary(args, |n| Ok(n.abs()))
+}
+
+pub(super) fn acos(args: &[Value]) -> Value {
+    unary(args, |n| Ok(n.acos()))
+}
+
+pub(super) fn acosh(args: &[Value]) -> Value {
+    unary(args, |n| Ok(n.acosh()))
+}
+
+pub(super) fn asin(args: &[Value]) -> Value {
+    unary(args, |n| Ok(n.asin()))
+}
+
+pub(super) fn asinh(args: &[Value]) -> Value {
+    unary(args, |n| Ok(n.asinh()))
+}
+
+pub(super) fn atan(args: &[Value]) -> Value {
+    unary(args, |n| Ok(n.atan()))
+}
+
+/// `ATAN2(x, y)`: note Excel's argument order is the reverse of C's.
+pub(super) fn atan2(args: &[Value]) -> Value {
+    binary(args, |x, y| {
+        if x == 0.0 && y == 0.0 {
+            return Err(CellError::Div0);
+        }
+        Ok(y.atan2(x))
+    })
+}
+
+pub(super) fn atanh(args: &[Value]) -> Value {
+    unary(args, |n| Ok(n.atanh()))
+}
+
+/// `CEILING(number, significance)`: rounds up to a multiple of
+/// `significance`, away from zero when both are negative.
+pub(super) fn ceiling(args: &[Value]) -> Value {
+    binary(args, |n, significance| {
+        if significance == 0.0 {
+            return Ok(0.0);
+        }
+        if n > 0.0 && significance < 0.0 {
+            return Err(CellError::Num);
+        }
+        Ok(significant(n / significance).ceil() * significance)
+    })
+}
+
+pub(super) fn cos(args: &[Value]) -> Value {
+    unary(args, |n| Ok(n.cos()))
+}
+
+pub(super) fn cosh(args: &[Value]) -> Value {
+    unary(args, |n| Ok(n.cosh()))
+}
+
+pub(super) fn degrees(args: &[Value]) -> Value {
+    unary(args, |n| Ok(n.to_degrees()))
+}
+
+pub(super) fn exp(args: &[Value]) -> Value {
+    unary(args, |n| Ok(n.exp()))
+}
+
+/// `FLOOR(number, significance)`: rounds down to a multiple of
+/// `significance`, toward zero when both are negative.
+pub(super) fn floor(args: &[Value]) -> Value {
+    binary(args, |n, significance| {
+        if significance == 0.0 {
+            return Err(CellError::Div0);
+        }
+        if n > 0.0 && significance < 0.0 {
+            return Err(CellError::Num);
+        }
+        Ok(significant(n / significance).floor() * significance)
+    })
+}
+
+pub(super) fn int(args: &[Value]) -> Value {
+    unary(args, |n| Ok(n.floor()))
+}
+
+pub(super) fn ln(args: &[Value]) -> Value {
+    unary(args, |n| {
+        if n > 0.0 {
+            Ok(n.ln())
+        } else {
+            Err(CellError::Num)
+        }
+    })
+}
+
+/// `LOG(number, [base])`, base 10 by default.
+pub(super) fn log(args: &[Value]) -> Value {
+    number(arity(args, 1, 2).and_then(|_| {
+        let n = scalar(&args[0])?;
+        let base = scalar_or(args, 1, 10.0)?;
+        if n <= 0.0 || base <= 0.0 {
+            return Err(CellError::Num);
+        }
+        if base == 1.0 {
+            return Err(CellError::Div0);
+        }
+        Ok(n.log(base))
+    }))
+}
+
+pub(super) fn log10(args: &[Value]) -> Value {
+    unary(args, |n| {
+        if n > 0.0 {
+            Ok(n.log10())
+        } else {
+            Err(CellError::Num)
+        }
+    })
+}
+
+/// `MOD(number, divisor)`: the remainder takes the sign of the divisor.
+pub(super) fn modulo(args: &[Value]) -> Value {
+    binary(args, |n, divisor| {
+        if divisor == 0.0 {
+            return Err(CellError::Div0);
+        }
+        Ok(n - divisor * (n / divisor).floor())
+    })
+}
+
+pub(super) fn pi(args: &[Value]) -> Value {
+    number(arity(args, 0, 0).map(|_| PI))
+}
+
+/// `RAND()`: a random number from 0 up to, but not including, 1.
+pub(super) fn rand(args: &[Value]) -> Value {
+    number(arity(args, 0, 0).map(|_| rand::random::<f64>()))
+}
+
+/// `RANDBETWEEN(bottom, top)`: a random integer from `bottom` to `top`
+/// inclusive; `#NUM!` if there is none.
+pub(super) fn randbetween(args: &[Value]) -> Value {
+    binary(args, |bottom, top| {
+        let (bottom, top) = (bottom.ceil(), top.floor());
+        if bottom > top {
+            return Err(CellError::Num);
+        }
+        Ok(rand::random_range(bottom as i64..=top as i64) as f64)
+    })
+}
+
+pub(super) fn power(args: &[Value]) -> Value {
+    binary(args, |base, exponent| {
+        if base == 0.0 && exponent < 0.0 {
+            return Err(CellError::Div0);
+        }
+        Ok(base.powf(exponent))
+    })
+}
+
+/// `PRODUCT(...)`: 0 when there is nothing to multiply.
+pub(super) fn product(args: &[Value]) -> Value {
+    number(numbers(args).map(|n| {
+        if n.is_empty() {
+            0.0
+        } else {
+            n.iter().product()
+        }
+    }))
+}
+
+pub(super) fn radians(args: &[Value]) -> Value {
+    unary(args, |n| Ok(n.to_radians()))
+}
+
+/// `ROUND(number, digits)`: halves round away from zero.
+pub(super) fn round(args: &[Value]) -> Value {
+    round_with(args, f64::round)
+}
+
+pub(super) fn round_down(args: &[Value]) -> Value {
+    round_with(args, f64::trunc)
+}
+
+pub(super) fn round_up(args: &[Value]) -> Value {
+    round_with(args, |n| n.abs().ceil().copysign(n))
+}
+
+pub(super) fn sign(args: &[Value]) -> Value {
+    unary(args, |n| Ok(if n == 0.0 { 0.0 } else { n.signum() }))
+}
+
+pub(super) fn sin(args: &[Value]) -> Value {
+    unary(args, |n| Ok(n.sin()))
+}
+
+pub(super) fn sinh(args: &[Value]) -> Value {
+    unary(args, |n| Ok(n.sinh()))
+}
+
+pub(super) fn sqrt(args: &[Value]) -> Value {
+    unary(args, |n| {
+        if n >= 0.0 {
+            Ok(n.sqrt())
+        } else {
+            Err(CellError::Num)
+        }
+    })
+}
+
+pub(super) fn sum(args: &[Value]) -> Value {
+    number(numbers(args).map(|n| n.iter().sum()))
+}
+
+/// `SUMIF(range, criterion, [sum_range])`: the total of the numbers in
+/// `sum_range` (or `range` itself) beside the cells of `range` that meet
+/// `criterion`.
+pub(super) fn sumif(args: &[Value]) -> Value {
+    number(arity(args, 2, 3).and_then(|_| {
+        let criterion = Criterion::new(single(&args[1])?)?;
+        let target = args.get(2).unwrap_or(&args[0]);
+        let mask = criteria::mask(&args[0], &criterion);
+        Ok(criteria::selected(target, shape(&args[0]), &mask)?
+            .iter()
+            .sum())
+    }))
+}
+
+/// `SUMIFS(sum_range, range1, criterion1, ...)`: like SUMIF, but a cell
+/// only counts if every range meets its criterion.
+pub(super) fn sumifs(args: &[Value]) -> Value {
+    number(arity(args, 3, usize::MAX).and_then(|_| {
+        let mask = criteria::masks(shape(&args[0]), &args[1..])?;
+        Ok(criteria::selected(&args[0], shape(&args[0]), &mask)?
+            .iter()
+            .sum())
+    }))
+}
+
+/// `SUMPRODUCT(array, ...)`: the sum of the element-wise products of
+/// same-sized arrays. Entries that are not numbers count as 0.
+pub(super) fn sumproduct(args: &[Value]) -> Value {
+    if args.is_empty() || args.iter().any(|arg| shape(arg) != shape(&args[0])) {
+        return Value::Error(CellError::Value);
+    }
+    let len = values(&args[0]).len();
+    let mut total = 0.0;
+    for i in 0..len {
+        let mut product = 1.0;
+        for arg in args {
+            match &values(arg)[i] {
+                Value::Number(n) => product *= n,
+                Value::Error(e) => return Value::Error(*e),
+                _ => product = 0.0,
+            }
+        }
+        total += product;
+    }
+    number(Ok(total))
+}
+
+pub(super) fn tan(args: &[Value]) -> Value {
+    unary(args, |n| Ok(n.tan()))
+}
+
+pub(super) fn tanh(args: &[Value]) -> Value {
+    unary(args, |n| Ok(n.tanh()))
+}
@@ -0,0 +1,1233 @@
+//! Built-in worksheet functions, grouped by category as in Excel's
+//! function library.
+//!
+//! Most functions are plain `fn(&[Value]) -> Value`: their arguments are
+//! evaluated first, and references arrive as [`Value::Array`]s, even a
+//! single cell, so functions can treat what a reference points at the way
+//! Excel does (SUM skips text in `A1` but rejects a typed-in `"abc"`).
+//!
+//! Functions such as `IF` that only evaluate some of their arguments get the
+//! unevaluated expressions instead, as do `LET` and the functions that take
+//! a `LAMBDA`.
+
+mod array;
+mod criteria;
+mod datetime;
+mod financial;
+mod format;
+mod lambda;
+mod logical;
+mod lookup;
+mod math;
+mod stats;
+mod text;
+
+use super::FormulaError;
+use super::ast::{Expr, ExprKind};
+use super::eval::{Binding, Evaluator};
+use super::value::{Array, CellError, Value};
+use crate::graph::SheetRange;
+
+type Function = fn(&[Value]) -> Value;
+type LazyFunction = fn(&Evaluator, &[Expr]) -> Result<Value, FormulaError>;
+
+fn lookup_lazy(name: &str) -> Option<LazyFunction> {
+    Some(match name {
+        "IF" => logical::if_,
+        "IFERROR" => logical::iferror,
+        "IFNA" => logical::ifna,
+        "IFS" => logical::ifs,
+        "SWITCH" => logical::switch,
+
+        "BYCOL" => lambda::bycol,
+        "BYROW" => lambda::byrow,
+        "LAMBDA" => lambda::lambda,
+        "LET" => lambda::let_,
+        "MAP" => lambda::map,
+        "REDUCE" => lambda::reduce,
+        "SCAN" => lambda::scan,
+
+        "CHOOSE" => lookup::choose,
+        "COLUMN" => lookup::column,
+        "COLUMNS" => lookup::columns,
+        "INDEX" => lookup::index,
+        "INDIRECT" => lookup::indirect,
+        "OFFSET" => lookup::offset,
+        "ROW" => lookup::row,
+        "ROWS" => lookup::rows,
+        _ => return None,
+    })
+}
+
+fn lookup(name: &str) -> Option<Function> {
+    Some(match name {
+        "ABS" => math::abs,
+        "ACOS" => math::acos,
+        "ACOSH" => math::acosh,
+        "ASIN" => math::asin,
+        "ASINH" => math::asinh,
+        "ATAN" => math::atan,
+        "ATAN2" => math::atan2,
+        "ATANH" => math::atanh,
+        "CEILING" => math::ceiling,
+        "COS" => math::cos,
+        "COSH" => math::cosh,
+        "DEGREES" => math::degrees,
+        "EXP" => math::exp,
+        "FLOOR" => math::floor,
+        "INT" => math::int,
+        "LN" => math::ln,
+        "LOG" => math::log,
+        "LOG10" => math::log10,
+        "MOD" => math::modulo,
+        "PI" => math::pi,
+        "POWER" => math::power,
+        "PRODUCT" => math::product,
+        "RADIANS" => math::radians,
+        "RAND" => math::rand,
+        "RANDBETWEEN" => math::randbetween,
+        "ROUND" => math::round,
+        "ROUNDDOWN" => math::round_down,
+        "ROUNDUP" => math::round_up,
+        "SIGN" => math::sign,
+        "SIN" => math::sin,
+        "SINH" => math::sinh,
+        "SQRT" => math::sqrt,
+        "SUM" => math::sum,
+        "SUMIF" => math::sumif,
+        "SUMIFS" => math::sumifs,
+        "SUMPRODUCT" => math::sumproduct,
+        "TAN" => math::tan,
+        "TANH" => math::tanh,
+
+        "DATE" => datetime::date,
+        "DATEDIF" => datetime::datedif,
+        "DAY" => datetime::day_,
+        "EDATE" => datetime::edate,
+        "EOMONTH" => datetime::eomonth,
+        "MONTH" => datetime::month,
+        "NETWORKDAYS" => datetime::networkdays,
+        "NOW" => datetime::now,
+        "TIME" => datetime::time,
+        "TODAY" => datetime::today,
+        "WEEKDAY" => datetime::weekday,
+        "YEAR" => datetime::year,
+
+        "DB" => financial::db,
+        "DDB" => financial::ddb,
+        "FV" => financial::fv,
+        "IPMT" => financial::ipmt,
+        "IRR" => financial::irr,
+        "NPER" => financial::nper,
+        "NPV" => financial::npv,
+        "PMT" => financial::pmt,
+        "PPMT" => financial::ppmt,
+        "PV" => financial::pv,
+        "RATE" => financial::rate,
+        "SLN" => financial::sln,
+        "XIRR" => financial::xirr,
+        "XNPV" => financial::xnpv,
+
+        "FILTER" => array::filter,
+        "SEQUENCE" => array::sequence,
+        "SORT" => array::sort,
+        "UNIQUE" => array::unique,
+
+        "HLOOKUP" => lookup::hlookup,
+        "MATCH" => lookup::match_,
+        "VLOOKUP" => lookup::vlookup,
+        "XLOOKUP" => lookup::xlookup,
+
+        "AND" => logical::and,
+        "NOT" => logical::not,
+        "OR" => logical::or,
+        "XOR" => logical::xor,
+
+        "AVERAGE" => stats::average,
+        "AVERAGEIF" => stats::averageif,
+        "AVERAGEIFS" => stats::averageifs,
+        "COUNT" => stats::count,
+        "COUNTA" => stats::counta,
+        "COUNTIF" => stats::countif,
+        "COUNTIFS" => stats::countifs,
+        "CORREL" => stats::correl,
+        "COVARIANCE.P" => stats::covariance_p,
+        "COVARIANCE.S" => stats::covariance_s,
+        "FORECAST" => stats::forecast,
+        "FORECAST.LINEAR" => stats::forecast,
+        "INTERCEPT" => stats::intercept,
+        "LARGE" => stats::large,
+        "MAX" => stats::max,
+        "MEDIAN" => stats::median,
+        "MIN" => stats::min,
+        "MODE" => stats::mode,
+        "MODE.SNGL" => stats::mode,
+        "NORM.DIST" => stats::norm_dist,
+        "NORM.INV" => stats::norm_inv,
+        "NORM.S.DIST" => stats::norm_s_dist,
+        "NORM.S.INV" => stats::norm_s_inv,
+        "PEARSON" => stats::correl,
+        "PERCENTILE" => stats::percentile,
+        "PERCENTILE.EXC" => stats::percentile_exc,
+        "PERCENTILE.INC" => stats::percentile,
+        "QUARTILE" => stats::quartile,
+        "QUARTILE.EXC" => stats::quartile_exc,
+        "QUARTILE.INC" => stats::quartile,
+        "RANK" => stats::rank,
+        "RANK.AVG" => stats::rank_avg,
+        "RANK.EQ" => stats::rank,
+        "RSQ" => stats::rsq,
+        "SLOPE" => stats::slope,
+        "SMALL" => stats::small,
+        "STDEV" => stats::stdev_s,
+        "STDEV.P" => stats::stdev_p,
+        "STDEV.S" => stats::stdev_s,
+        "STDEVP" => stats::stdev_p,
+        "VAR" => stats::var_s,
+        "VAR.P" => stats::var_p,
+        "VAR.S" => stats::var_s,
+        "VARP" => stats::var_p,
+
+        "CONCAT" => text::concat,
+        "CONCATENATE" => text::concat,
+        "FIND" => text::find,
+        "LEFT" => text::left,
+        "LEN" => text::len,
+        "LOWER" => text::lower,
+        "MID" => text::mid,
+        "PROPER" => text::proper,
+        "REPLACE" => text::replace,
+        "REPT" => text::rept,
+        "RIGHT" => text::right,
+        "SEARCH" => text::search,
+        "SUBSTITUTE" => text::substitute,
+        "TEXT" => text::text_,
+        "TEXTJOIN" => text::textjoin,
+        "TRIM" => text::trim,
+        "UPPER" => text::upper,
+        "VALUE" => text::value,
+        _ => return None,
+    })
+}
+
+/// Whether the function `name` gives a date, so a formula that ends with it
+/// shows its result as one.
+pub(super) fn returns_date(name: &str) -> bool {
+    matches!(
+        name,
+        "DATE" | "EDATE" | "EOMONTH" | "NOW" | "TIME" | "TODAY"
+    )
+}
+
+/// Whether the function `name` can give a different result each time it is
+/// called, or reads cells it is not given as references, so formulas using
+/// it are recalculated on every pass.
+pub(super) fn is_volatile(name: &str) -> bool {
+    matches!(
+        name,
+        "INDIRECT" | "NOW" | "OFFSET" | "RAND" | "RANDBETWEEN" | "TODAY"
+    )
+}
+
+/// Whether `name`, uppercase, is a built-in function.
+pub(super) fn exists(name: &str) -> bool {
+    lookup_lazy(name).is_some() || lookup(name).is_some()
+}
+
+/// Calls the function `name`, which is already uppercase: a lambda bound by
+/// `LET` or a parameter, a built-in function, or a lambda saved under a
+/// workbook name, in that order. Unknown functions evaluate to `#NAME?`.
+pub(super) fn call(ev: &Evaluator, name: &str, args: &[Expr]) -> Result<Value, FormulaError> {
+    if let Some(Binding::Lambda(function)) = ev.binding(name) {
+        return lambda::call(ev, function, args);
+    }
+    if let Some(function) = lookup_lazy(name) {
+        return function(ev, args);
+    }
+    let Some(function) = lookup(name) else {
+        return match ev.defined_lambda(name)? {
+            Some(function) => lambda::call(ev, &function, args),
+            // A name bound to a value cannot be called.
+            None if ev.binding(name).is_some() => Ok(Value::Error(CellError::Value)),
+            None => Ok(Value::Error(CellError::Name)),
+        };
+    };
+    let args = args
+        .iter()
+        .map(|arg| argument(ev, arg))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(function(&args))
+}
+
+/// The reference built by a call to `name`, for the functions that give
+/// one rather than values; `None` for every other function.
+pub(super) fn target(
+    ev: &Evaluator,
+    name: &str,
+    args: &[Expr],
+) -> Result<Option<Result<SheetRange, CellError>>, FormulaError> {
+    Ok(match name {
+        "INDIRECT" => Some(lookup::indirect_target(ev, args)?),
+        "OFFSET" => Some(lookup::offset_target(ev, args)?),
+        _ => None,
+    })
+}
+
+/// Whether `expr` is a reference, written out or built by a function.
+fn is_reference(expr: &Expr) -> bool {
+    match &expr.kind {
+        ExprKind::Reference { .. } | ExprKind::Table { .. } => true,
+        ExprKind::Call { name, .. } => name == "INDIRECT" || name == "OFFSET",
+        _ => false,
+    }
+}
+
+/// Evaluates an argument, keeping a reference to a single cell as a
+/// one-cell range.
+fn argument(ev: &Evaluator, arg: &Expr) -> Result<Value, FormulaError> {
+    let value = ev.eval(arg)?;
+    Ok(match value {
+        Value::Array(array) => Value::Array(array),
+        Value::Error(e) => Value::Error(e),
+        value if is_reference(arg) => Value::Array(Array::new(1, 1, vec![value])),
+        value => value,
+    })
+}
+
+/// Checks that a function got between `min` and `max` arguments.
+fn arity(args: &[Value], min: usize, max: usize) -> Result<(), CellError> {
+    if (min..=max).contains(&args.len()) {
+        Ok(())
+    } else {
+        Err(CellError::Value)
+    }
+}
+
+/// A single value from an argument: a one-cell range stands for its cell,
+/// and any larger block is `#VALUE!`.
+fn single(arg: &Value) -> Result<&Value, CellError> {
+    match arg {
+        Value::Array(array) if array.rows == 1 && array.cols == 1 => Ok(&array.values[0]),
+        Value::Array(_) => Err(CellError::Value),
+        Value::Error(e) => Err(*e),
+        value => Ok(value),
+    }
+}
+
+/// A numeric argument.
+fn scalar(arg: &Value) -> Result<f64, CellError> {
+    single(arg)?.to_number()
+}
+
+/// The optional numeric argument at `index`, or `default` when it is left
+/// out.
+fn scalar_or(args: &[Value], index: usize, default: f64) -> Result<f64, CellError> {
+    args.get(index).map_or(Ok(default), scalar)
+}
+
+/// The argument at `index`, unless it is left out or empty, as in
+/// `XLOOKUP(1, A:A, B:B, , 1)`.
+fn optional(args: &[Value], index: usize) -> Option<&Value> {
+    args.get(index).filter(|arg| **arg != Value::Empty)
+}
+
+/// The rows and columns of `arg`; a single value is 1x1.
+fn shape(arg: &Value) -> (usize, usize) {
+    match arg {
+        Value::Array(array) => (array.rows, array.cols),
+        _ => (1, 1),
+    }
+}
+
+/// Every value in `arg`, or the value itself if it is not a block.
+fn values(arg: &Value) -> &[Value] {
+    match arg {
+        Value::Array(array) => &array.values,
+        value => std::slice::from_ref(value),
+    }
+}
+
+/// The numbers among `args`, as SUM and AVERAGE see them: numbers, booleans
+/// and numeric text typed in directly all count, but in a reference or array
+/// only numbers do. The first error found is returned instead.
+fn numbers(args: &[Value]) -> Result<Vec<f64>, CellError> {
+    let mut numbers = Vec::new();
+    for arg in args {
+        match arg {
+            Value::Array(array) => {
+                for value in &array.values {
+                    match value {
+                        Value::Number(n) => numbers.push(*n),
+                        Value::Error(e) => return Err(*e),
+                        _ => {}
+                    }
+                }
+            }
+            Value::Empty => {}
+            value => numbers.push(value.to_number()?),
+        }
+    }
+    Ok(numbers)
+}
+
+/// A numeric result, or `#NUM!` if it is not a finite number.
+fn number(result: Result<f64, CellError>) -> Value {
+    match result {
+        Ok(n) if n.is_finite() => Value::Number(n),
+        Ok(_) => Value::Error(CellError::Num),
+        Err(e) => Value::Error(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::formula::ast::parse_cell_name;
+    use crate::formula::{Value, evaluate, evaluate_cell};
+    use crate::graph::{CellKey, CellRange, SheetRange};
+    use rusqlite::{Connection, params};
+
+    /// A sheet "s" holding constant `cells`, given as (name, input) pairs.
+    fn sheet(cells: &[(&str, &str)]) -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::init_db(&conn);
+        for (name, input) in cells {
+            let cell = parse_cell_name(name).unwrap();
+            let value = Value::from_input(input);
+            conn.execute(
+                "INSERT INTO cells (sheet, row, col, value, value_type) VALUES ('s', ?1, ?2, ?3, ?4)",
+                params![cell.row, cell.col, value.display(), value.value_type()],
+            )
+            .unwrap();
+        }
+        conn
+    }
+
+    /// Asserts that each formula displays as expected on `conn`'s sheet.
+    fn check(conn: &Connection, cases: &[(&str, &str)]) {
+        for (formula, expected) in cases {
+            let value = evaluate(formula, "s", conn).unwrap();
+            assert_eq!(value.display(), *expected, "{}", formula);
+        }
+    }
+
+    #[test]
+    fn array_constants_are_arguments() {
+        let conn = sheet(&[("A1", "3")]);
+        check(
+            &conn,
+            &[
+                ("=SUM({1,2;3,4})", "10"),
+                ("=MATCH(A1, {1,3,5}, 0)", "2"),
+                ("=MATCH(\"b\", {\"a\";\"b\"}, 0)", "2"),
+                ("=INDEX({1,2;3,4}, 2, 1)", "3"),
+                ("=VLOOKUP(2, {1,\"one\";2,\"two\"}, 2, FALSE)", "two"),
+                ("=COUNTIF({1,5,9}, \">4\")", "2"),
+                ("=ROWS({1;2;3})", "3"),
+            ],
+        );
+    }
+
+    #[test]
+    fn math_functions() {
+        let conn = sheet(&[
+            ("A1", "1"),
+            ("A2", "2"),
+            ("A3", "text"),
+            ("A5", "TRUE"),
+            ("A6", "-4"),
+            ("B1", "3"),
+            ("B2", "4"),
+            ("C1", "#DIV/0!"),
+        ]);
+        check(
+            &conn,
+            &[
+                ("=PRODUCT(A1:A6)", "-8"),
+                ("=PRODUCT(A3)", "0"),
+                ("=ROUND(2.675, 2)", "2.68"),
+                ("=ROUND(-2.5, 0)", "-3"),
+                ("=ROUND(1234.5, -2)", "1200"),
+                ("=ROUNDUP(-1.21, 1)", "-1.3"),
+                ("=ROUNDDOWN(1.29, 1)", "1.2"),
+                ("=ABS(A6)", "4"),
+                ("=MOD(-3, 2)", "1"),
+                ("=MOD(3, -2)", "-1"),
+                ("=MOD(1, 0)", "#DIV/0!"),
+                ("=POWER(2, 10)", "1024"),
+                ("=POWER(0, -1)", "#DIV/0!"),
+                ("=POWER(-8, 1/3)", "#NUM!"),
+                ("=1E+300/1E-300", "#NUM!"),
+                ("=SQRT(A6)", "#NUM!"),
+                ("=INT(-1.5)", "-2"),
+                ("=CEILING(2.5, 1)", "3"),
+                ("=CEILING(-2.5, 2)", "-2"),
+                ("=CEILING(-2.5, -2)", "-4"),
+                ("=CEILING(0.3, 0.1)", "0.3"),
+                ("=CEILING(2, -1)", "#NUM!"),
+                ("=FLOOR(-2.5, 2)", "-4"),
+                ("=FLOOR(2.5, 0)", "#DIV/0!"),
+                ("=SIGN(A6)", "-1"),
+                ("=PI()", "3.14159265358979"),
+                ("=DEGREES(PI())", "180"),
+                ("=SIN(PI()/2)", "1"),
+                ("=ATAN2(1, 1)*4", "3.14159265358979"),
+                ("=ASIN(2)", "#NUM!"),
+                ("=LN(EXP(2))", "2"),
+                ("=LOG(8, 2)", "3"),
+                ("=LOG(1000)", "3"),
+                ("=LOG10(0)", "#NUM!"),
+                ("=SUMPRODUCT(A1:A2, B1:B2)", "11"),
+                ("=SUMPRODUCT(A1:A3, B1:B3)", "11"),
+                ("=SUMPRODUCT(A1:A2, B1:B3)", "#VALUE!"),
+                ("=SUMPRODUCT(A1:A2, C1:C2)", "#DIV/0!"),
+                ("=ABS(A1:A2)", "#VALUE!"),
+                ("=ABS()", "#VALUE!"),
+                ("=ABS(\"x\")", "#VALUE!"),
+                ("=ABS(A4)", "0"),
+            ],
+        );
+    }
+
+    #[test]
+    fn counting_and_extremes() {
+        let conn = sheet(&[
+            ("A1", "5"),
+            ("A2", "text"),
+            ("A3", "TRUE"),
+            ("A5", "-2"),
+            ("B1", "#N/A"),
+        ]);
+        check(
+            &conn,
+            &[
+                ("=MIN(A1:A5)", "-2"),
+                ("=MAX(A1:A5)", "5"),
+                ("=MAX(A2)", "0"),
+                ("=MAX(A1:A5, \"7\")", "7"),
+                ("=MIN(A1:B1)", "#N/A"),
+                ("=MAX(\"x\")", "#VALUE!"),
+                ("=SUM(A1:A5, TRUE)", "4"),
+                ("=COUNT(A1:A5)", "2"),
+                ("=COUNT(A1:B5, 1, \"2\", \"x\", TRUE)", "5"),
+                ("=COUNTA(A1:B5)", "5"),
+                ("=COUNTA(A4)", "0"),
+                ("=COUNTA(\"\", 1)", "2"),
+                ("=AVERAGE(A1:A5)", "1.5"),
+            ],
+        );
+    }
+
+    #[test]
+    fn logical_functions() {
+        let conn = sheet(&[("A1", "0"), ("A2", "TRUE"), ("A3", "text"), ("B1", "#N/A")]);
+        check(
+            &conn,
+            &[
+                ("=IF(A1=0, \"zero\", 1/A1)", "zero"),
+                ("=IF(A1, 1)", "FALSE"),
+                ("=IF(A3, 1, 2)", "#VALUE!"),
+                ("=IF(TRUE, A2)", "TRUE"),
+                ("=IFS(A1>1, \"big\", A1=0, \"zero\")", "zero"),
+                ("=IFS(FALSE, 1)", "#N/A"),
+                ("=IFERROR(1/A1, -1)", "-1"),
+                ("=IFERROR(A3, -1)", "text"),
+                ("=IFNA(B1, \"missing\")", "missing"),
+                ("=IFNA(1/0, 1)", "#DIV/0!"),
+                ("=AND(A2, 1, \"true\")", "TRUE"),
+                ("=AND(A1:A3)", "FALSE"),
+                ("=OR(A1:A3)", "TRUE"),
+                ("=OR(A3)", "#VALUE!"),
+                ("=XOR(TRUE, TRUE, TRUE)", "TRUE"),
+                ("=NOT(A1)", "TRUE"),
+                ("=SWITCH(A1, 1, \"one\", 0, \"zero\")", "zero"),
+                ("=SWITCH(\"B\", \"a\", 1, \"b\", 2)", "2"),
+                ("=SWITCH(5, 1, \"one\", \"other\")", "other"),
+                ("=SWITCH(5, 1, \"one\")", "#N/A"),
+            ],
+        );
+    }
+
+    #[test]
+    fn conditional_aggregates() {
+        let conn = sheet(&[
+            ("A1", "apple"),
+            ("A2", "Apricot"),
+            ("A3", "banana"),
+            ("A4", "a*b"),
+            ("A6", "5"),
+            ("B1", "10"),
+            ("B2", "20"),
+            ("B3", "30"),
+            ("B4", "40"),
+            ("B5", "50"),
+            ("B6", "60"),
+            ("C1", "x"),
+            ("C2", "y"),
+            ("C3", "x"),
+            ("C4", "x"),
+            ("C5", "y"),
+            ("C6", "x"),
+        ]);
+        check(
+            &conn,
+            &[
+                ("=SUMIF(B1:B6, \">25\")", "180"),
+                ("=SUMIF(A1:A6, \"a*\", B1:B6)", "70"),
+                ("=SUMIF(A1:A6, \"AP???\", B1:B6)", "10"),
+                ("=SUMIF(A1:A6, \"a~*b\", B1:B6)", "40"),
+                ("=SUMIF(A1:A6, \"<>apple\", B1:B6)", "200"),
+                ("=SUMIF(A1:A6, \"\", B1:B6)", "50"),
+                ("=SUMIF(A1:A6, 5, B1:B6)", "60"),
+                ("=SUMIF(A1:A6, \"<b\", B1:B6)", "70"),
+                ("=SUMIFS(B1:B6, C1:C6, \"x\", B1:B6, \">=30\")", "130"),
+                ("=SUMIFS(B1:B6, C1:C5, \"x\")", "#VALUE!"),
+                ("=COUNTIF(C1:C6, \"x\")", "4"),
+                ("=COUNTIF(A1:A6, \"<>\")", "5"),
+                ("=COUNTIF(A:A, \"=\")", "1"),
+                ("=COUNTIFS(C1:C6, \"x\", A1:A6, \"a*\")", "2"),
+                ("=AVERAGEIF(C1:C6, \"y\", B1:B6)", "35"),
+                ("=AVERAGEIF(C1:C6, \"z\", B1:B6)", "#DIV/0!"),
+                (
+                    "=AVERAGEIFS(B1:B6, C1:C6, \"x\", B1:B6, \"<50\")",
+                    "26.6666666666667",
+                ),
+                ("=COUNTIF(C1:C6, #N/A)", "#N/A"),
+            ],
+        );
+    }
+
+    #[test]
+    fn text_functions() {
+        let conn = sheet(&[
+            ("A1", "Widget"),
+            ("A2", "  blue   steel  "),
+            ("A3", "12"),
+            ("B1", "a"),
+            ("B3", "c"),
+        ]);
+        check(
+            &conn,
+            &[
+                ("=A1&\"-\"&A3", "Widget-12"),
+                ("=\"B2\"&A3", "B212"),
+                ("=CONCAT(B1:B3, 1, TRUE)", "ac1TRUE"),
+                ("=TEXTJOIN(\", \", TRUE, B1:B3, A1)", "a, c, Widget"),
+                ("=TEXTJOIN(\"-\", FALSE, B1:B3)", "a--c"),
+                ("=LEFT(A1, 3)&RIGHT(A1)&MID(A1, 2, 2)", "Widtid"),
+                ("=MID(A1, 0, 2)", "#VALUE!"),
+                ("=LEN(A2)", "16"),
+                ("=TRIM(A2)", "blue steel"),
+                ("=UPPER(A1)&LOWER(A1)", "WIDGETwidget"),
+                (
+                    "=PROPER(\"o'neil mcDONALD-smith\")",
+                    "O'Neil Mcdonald-Smith",
+                ),
+                ("=SUBSTITUTE(\"a-b-c\", \"-\", \"+\")", "a+b+c"),
+                ("=SUBSTITUTE(\"a-b-c\", \"-\", \"+\", 2)", "a-b+c"),
+                ("=REPLACE(A1, 1, 3, \"Gad\")", "Gadget"),
+                ("=FIND(\"g\", A1)", "4"),
+                ("=FIND(\"G\", A1)", "#VALUE!"),
+                ("=SEARCH(\"G\", A1)", "4"),
+                ("=SEARCH(\"d?e\", A1)", "3"),
+                ("=SEARCH(\"e\", A1, 6)", "#VALUE!"),
+                ("=VALUE(\"$1,234.5\")+VALUE(\"50%\")", "1235"),
+                ("=VALUE(\"abc\")", "#VALUE!"),
+                ("=REPT(\"ab\", 3)", "ababab"),
+                ("=REPT(\"ab\", 20000)", "#VALUE!"),
+                ("=TEXT(1234.567, \"#,##0.00\")", "1,234.57"),
+                ("=TEXT(0.125, \"0.0%\")", "12.5%"),
+                ("=TEXT(-5, \"0.00;(0.00)\")", "(5.00)"),
+                ("=TEXT(-5, \"$#,##0\")", "-$5"),
+                ("=TEXT(0, \"0;-0;\"\"zero\"\"\")", "zero"),
+                ("=TEXT(5, \"000\")", "005"),
+                ("=TEXT(5551234, \"000-0000\")", "555-1234"),
+                ("=TEXT(12345, \"0.00E+00\")", "1.23E+04"),
+                ("=TEXT(2.5, \"0\")", "3"),
+                ("=TEXT(1500000, \"0.0,,\"\"M\"\"\")", "1.5M"),
+                ("=TEXT(A3, \"0.0\")", "12.0"),
+                ("=TEXT(A1, \"[@]\")", "[Widget]"),
+            ],
+        );
+    }
+
+    #[test]
+    fn lookup_functions() {
+        let conn = sheet(&[
+            ("A1", "10"),
+            ("A2", "20"),
+            ("A3", "30"),
+            ("B1", "apple"),
+            ("B2", "banana"),
+            ("B3", "cherry"),
+            ("C1", "1.5"),
+            ("C2", "2.5"),
+            ("D1", "x"),
+            ("E1", "y"),
+            ("F1", "30"),
+            ("F2", "20"),
+            ("F3", "10"),
+        ]);
+        check(
+            &conn,
+            &[
+                ("=VLOOKUP(20, A1:C3, 2, FALSE)", "banana"),
+                ("=VLOOKUP(25, A1:C3, 2)", "banana"),
+                ("=VLOOKUP(5, A1:C3, 2)", "#N/A"),
+                ("=VLOOKUP(30, A1:C3, 3, FALSE)", "0"),
+                ("=VLOOKUP(20, A1:C3, 4, FALSE)", "#REF!"),
+                ("=VLOOKUP(20, A:C, 3, FALSE)", "2.5"),
+                ("=HLOOKUP(\"b*\", B2:C2, 2, FALSE)", "#REF!"),
+                ("=HLOOKUP(\"x\", D1:E1, 1, FALSE)", "x"),
+                ("=MATCH(\"CH*\", B1:B3, 0)", "3"),
+                ("=MATCH(25, A1:A3)", "2"),
+                ("=MATCH(25, F1:F3, -1)", "1"),
+                ("=MATCH(25, A1:A3, 0)", "#N/A"),
+                ("=XLOOKUP(\"cherry\", B1:B3, A1:A3)", "30"),
+                ("=XLOOKUP(\"fig\", B1:B3, A1:A3, \"none\")", "none"),
+                ("=XLOOKUP(25, A1:A3, B1:B3, , 1)", "cherry"),
+                ("=XLOOKUP(25, A1:A3, B1:B3, , -1)", "banana"),
+                ("=XLOOKUP(\"b*\", B1:B3, A1:A3, , 2)", "20"),
+                ("=XLOOKUP(10, A1:A3, B1:B2)", "#VALUE!"),
+                ("=INDEX(A1:C3, 2, 2)", "banana"),
+                ("=INDEX(B1:B3, 3)", "cherry"),
+                ("=INDEX(A1:C3, 4, 1)", "#REF!"),
+                ("=INDEX(A:A, 500)", "0"),
+                ("=SUM(INDEX(A1:C3, 0, 1))", "60"),
+                ("=INDEX(B1:B3, MATCH(30, A1:A3, 0))", "cherry"),
+                ("=ROW(C5)+COLUMN(C5)", "8"),
+                ("=ROW()", "#VALUE!"),
+                ("=ROWS(A1:C3)*COLUMNS(A1:C3)", "9"),
+                ("=ROWS(A:A)", "1048576"),
+                ("=COLUMNS(1:1)", "16384"),
+                ("=CHOOSE(2, 1/0, \"two\")", "two"),
+                ("=CHOOSE(3, 1, 2)", "#VALUE!"),
+                ("=IF(FALSE, 1, )", "0"),
+            ],
+        );
+        let cell = CellKey::new("s", 4, 2);
+        assert_eq!(
+            evaluate_cell("=ROW()*10+COLUMN()", &cell, &conn).map(|e| e.value),
+            Ok(Value::Number(53.0))
+        );
+    }
+
+    #[test]
+    fn ranges_too_large_to_read_give_num() {
+        let conn = sheet(&[("A1", "1"), ("C1048576", "2")]);
+        check(
+            &conn,
+            &[
+                ("=SUM(A:A)", "1"),
+                ("=SUM(1:1)", "1"),
+                ("=SUM(A:C)", "#NUM!"),
+                ("=ROWS(A:C)", "1048576"),
+            ],
+        );
+    }
+
+    #[test]
+    fn date_functions() {
+        let conn = sheet(&[("A1", "2026-01-31"), ("A2", "1/1/2026"), ("A3", "18:00")]);
+        check(
+            &conn,
+            &[
+                ("=DATE(2026, 1, 31)", "2026-01-31"),
+                ("=DATE(2026, 14, 1)", "2027-02-01"),
+                ("=DATE(126, 1, 0)", "2025-12-31"),
+                ("=DATE(-1, 1, 1)", "#NUM!"),
+                ("=DATE(2026, 1, 31) + TIME(18, 0, 0)", "2026-01-31 18:00"),
+                ("=A1 + 30", "2026-03-02"),
+                ("=A1 - A2", "30"),
+                ("=A3", "18:00"),
+                ("=YEAR(A1) * 100 + MONTH(A1)", "202601"),
+                ("=DAY(\"2026-03-15\")", "15"),
+                ("=EDATE(A1, 1)", "2026-02-28"),
+                ("=EDATE(\"2024-01-31\", 1)", "2024-02-29"),
+                ("=EOMONTH(A1, -1)", "2025-12-31"),
+                ("=EOMONTH(A2, 13)", "2027-02-28"),
+                ("=WEEKDAY(A1)", "7"),
+                ("=WEEKDAY(A1, 2)", "6"),
+                ("=WEEKDAY(A1, 3)", "5"),
+                ("=WEEKDAY(A1, 16)", "1"),
+                ("=WEEKDAY(A1, 4)", "#NUM!"),
+                ("=NETWORKDAYS(A2, A1)", "22"),
+                ("=NETWORKDAYS(A2, A1, A2)", "21"),
+                ("=NETWORKDAYS(A1, A2)", "-22"),
+                ("=DATEDIF(\"2020-02-15\", A1, \"Y\")", "5"),
+                ("=DATEDIF(\"2020-02-15\", A1, \"M\")", "71"),
+                ("=DATEDIF(\"2020-02-15\", A1, \"D\")", "2177"),
+                ("=DATEDIF(\"2020-02-15\", A1, \"MD\")", "16"),
+                ("=DATEDIF(\"2020-02-15\", A1, \"YM\")", "11"),
+                ("=DATEDIF(\"2020-02-15\", A1, \"YD\")", "350"),
+                ("=DATEDIF(A1, A2, \"D\")", "#NUM!"),
+                ("=TIME(25, 30, 0)", "01:30"),
+                ("=TIME(-1, 0, 0)", "#NUM!"),
+                ("=COUNTIF(A1:A2, \">=2026-01-15\")", "1"),
+                ("=TODAY() = INT(NOW())", "TRUE"),
+                (
+                    "=TEXT(A1, \"dddd, mmmm d, yyyy\")",
+                    "Saturday, January 31, 2026",
+                ),
+                ("=TEXT(A1, \"mmm-yy\")", "Jan-26"),
+                ("=TEXT(A3, \"h:mm AM/PM\")", "6:00 PM"),
+                (
+                    "=TEXT(46053.5, \"yyyy-mm-dd hh:mm:ss\")",
+                    "2026-01-31 12:00:00",
+                ),
+                ("=TEXT(-1, \"yyyy\")", "#VALUE!"),
+            ],
+        );
+    }
+
+    #[test]
+    fn financial_functions() {
+        let conn = sheet(&[
+            ("A1", "-10000"),
+            ("A2", "2750"),
+            ("A3", "4250"),
+            ("A4", "3250"),
+            ("A5", "2750"),
+            ("B1", "2008-01-01"),
+            ("B2", "2008-03-01"),
+            ("B3", "2008-10-30"),
+            ("B4", "2009-02-15"),
+            ("B5", "2009-04-01"),
+            ("C1", "-70000"),
+            ("C2", "12000"),
+            ("C3", "15000"),
+            ("C4", "18000"),
+            ("C5", "21000"),
+            ("C6", "26000"),
+        ]);
+        check(
+            &conn,
+            &[
+                ("=ROUND(PMT(8%/12, 10, 10000), 2)", "-1037.03"),
+                ("=ROUND(PMT(0, 10, 1000), 2)", "-100"),
+                ("=ROUND(PV(8%/12, 12*20, 500), 2)", "-59777.15"),
+                ("=ROUND(FV(6%/12, 10, -200, -500, 1), 2)", "2581.4"),
+                ("=ROUND(NPER(12%/12, -100, -1000, 10000, 1), 4)", "59.6739"),
+                ("=ROUND(IPMT(10%/12, 1, 3*12, 8000), 2)", "-66.67"),
+                ("=ROUND(IPMT(10%, 3, 3, 8000), 2)", "-292.45"),
+                ("=ROUND(PPMT(10%/12, 1, 2*12, 2000), 2)", "-75.62"),
+                ("=IPMT(10%, 4, 3, 8000)", "#NUM!"),
+                ("=ROUND(NPV(10%, -10000, 3000, 4200, 6800), 2)", "1188.44"),
+                ("=ROUND(XNPV(9%, A1:A5, B1:B5), 2)", "2086.65"),
+                ("=ROUND(XIRR(A1:A5, B1:B5), 6)", "0.373363"),
+                ("=ROUND(IRR(C1:C6), 4)", "0.0866"),
+                ("=ROUND(IRR(C1:C4, -10%), 4)", "-0.1821"),
+                ("=IRR(C2:C6)", "#NUM!"),
+                ("=ROUND(RATE(4*12, -200, 8000) * 12, 4)", "0.0924"),
+                ("=RATE(10, 100, 1000)", "#NUM!"),
+                ("=SLN(30000, 7500, 10)", "2250"),
+                ("=SLN(30000, 7500, 0)", "#DIV/0!"),
+                ("=ROUND(DB(1000000, 100000, 6, 1, 7), 2)", "186083.33"),
+                ("=ROUND(DB(1000000, 100000, 6, 2, 7), 2)", "259639.42"),
+                ("=ROUND(DB(1000000, 100000, 6, 7, 7), 2)", "15845.1"),
+                ("=DB(1000000, 100000, 6, 7)", "#NUM!"),
+                ("=DB(1000000, 100000, 1E+15, 1E+15)", "#NUM!"),
+                ("=ROUND(DDB(2400, 300, 10*365, 1), 2)", "1.32"),
+                ("=DDB(2400, 300, 10, 1)", "480"),
+                ("=ROUND(DDB(2400, 300, 10, 10), 2)", "22.12"),
+                ("=ROUND(DDB(2400, 300, 10, 9), 2)", "80.53"),
+                ("=ROUND(DDB(2400, 300, 1E+15, 1E+15), 2)", "0"),
+                ("=DDB(2400, 3000, 10, 1)", "0"),
+            ],
+        );
+    }
+
+    #[test]
+    fn statistical_functions() {
+        let conn = sheet(&[
+            ("A1", "1345"),
+            ("A2", "1301"),
+            ("A3", "1368"),
+            ("A4", "1322"),
+            ("A5", "1310"),
+            ("A6", "1370"),
+            ("A7", "1318"),
+            ("A8", "1350"),
+            ("A9", "1303"),
+            ("A10", "1299"),
+            ("A11", "n/a"),
+            ("B1", "7"),
+            ("B2", "3.5"),
+            ("B3", "3.5"),
+            ("B4", "1"),
+            ("B5", "2"),
+            ("C1", "3"),
+            ("C2", "2"),
+            ("C3", "4"),
+            ("C4", "5"),
+            ("C5", "6"),
+            ("D1", "9"),
+            ("D2", "7"),
+            ("D3", "12"),
+            ("D4", "15"),
+            ("D5", "17"),
+            ("E1", "6"),
+            ("E2", "7"),
+            ("E3", "9"),
+            ("E4", "15"),
+            ("E5", "21"),
+            ("F1", "20"),
+            ("F2", "28"),
+            ("F3", "31"),
+            ("F4", "38"),
+            ("F5", "40"),
+        ]);
+        check(
+            &conn,
+            &[
+                ("=MEDIAN(1, 2, 3, 4, 5, 6)", "3.5"),
+                ("=MEDIAN(B1:B6)", "3.5"),
+                ("=MEDIAN(A11)", "#NUM!"),
+                ("=MODE(5.6, 4, 4, 3, 2, 4)", "4"),
+                ("=MODE(B1:B5)", "3.5"),
+                ("=MODE(1, 2, 3)", "#N/A"),
+                ("=MODE(7, 2, 2, 7, 9)", "7"),
+                ("=MODE(-0, 1, 0)", "0"),
+                ("=ROUND(STDEV.S(A1:A11), 5)", "27.46392"),
+                ("=ROUND(STDEV.P(A1:A11), 5)", "26.05456"),
+                ("=ROUND(VAR.S(A1:A11), 4)", "754.2667"),
+                ("=VAR.P(A1:A11)", "678.84"),
+                ("=VAR.S(1)", "#DIV/0!"),
+                ("=PERCENTILE(C1:C4, 0.3)", "2.9"),
+                ("=PERCENTILE.EXC(C1:C5, 0.25)", "2.5"),
+                ("=PERCENTILE(C1:C4, 2)", "#NUM!"),
+                ("=QUARTILE(D1:D5, 1)", "9"),
+                ("=QUARTILE(D1:D5, 3)", "15"),
+                ("=RANK(B3, B1:B5, 1)", "3"),
+                ("=RANK(B1, B1:B5, 1)", "5"),
+                ("=RANK(B3, B1:B5)", "2"),
+                ("=RANK.AVG(B3, B1:B5)", "2.5"),
+                ("=RANK(8, B1:B5)", "#N/A"),
+                ("=LARGE(B1:B5, 2)", "3.5"),
+                ("=SMALL(B1:B5, 2)", "2"),
+                ("=SMALL(B1:B5, 6)", "#NUM!"),
+                ("=ROUND(CORREL(C1:C5, D1:D5), 6)", "0.997054"),
+                ("=CORREL(C1:C5, D1:D4)", "#N/A"),
+                ("=ROUND(RSQ(C1:C5, D1:D5), 6)", "0.994118"),
+                ("=COVARIANCE.P(C1:C5, D1:D5)", "5.2"),
+                ("=ROUND(FORECAST.LINEAR(30, E1:E5, F1:F5), 6)", "10.607253"),
+                ("=ROUND(SLOPE(E1:E5, F1:F5), 6)", "0.709105"),
+                ("=ROUND(INTERCEPT(E1:E5, F1:F5), 6)", "-10.665895"),
+                ("=SLOPE(E1:E5, B6:B10)", "#DIV/0!"),
+                ("=ROUND(NORM.DIST(42, 40, 1.5, TRUE), 7)", "0.9087888"),
+                ("=ROUND(NORM.DIST(42, 40, 1.5, FALSE), 7)", "0.10934"),
+                ("=ROUND(NORM.INV(0.908789, 40, 1.5), 6)", "42.000002"),
+                ("=NORM.INV(0, 40, 1.5)", "#NUM!"),
+                ("=ROUND(NORM.S.DIST(1.333333, TRUE), 9)", "0.908788726"),
+                ("=ROUND(NORM.S.INV(0.908789), 6)", "1.333335"),
+                ("=ROUND(NORM.S.DIST(-6, TRUE) * 10^10, 6)", "9.865876"),
+                ("=NORM.S.INV(0.5)", "0"),
+            ],
+        );
+    }
+
+    #[test]
+    fn array_functions() {
+        let conn = sheet(&[
+            ("A1", "pear"),
+            ("A2", "apple"),
+            ("A3", "Pear"),
+            ("A4", "fig"),
+            ("A5", "apple"),
+            ("B1", "3"),
+            ("B2", "8"),
+            ("B3", "6"),
+            ("B4", "1"),
+            ("B5", "9"),
+            ("D1", "3"),
+            ("E1", "b"),
+            ("F1", "TRUE"),
+            ("G1", "1"),
+            ("H1", "A"),
+        ]);
+        check(
+            &conn,
+            &[
+                ("=SEQUENCE(2, 3)", "{1,2,3;4,5,6}"),
+                ("=SEQUENCE(3, 1, 10, -5)", "{10;5;0}"),
+                ("=SEQUENCE(1)", "1"),
+                ("=SEQUENCE(0)", "#CALC!"),
+                ("=SEQUENCE(2000, 2000)", "#NUM!"),
+                ("=SEQUENCE(3, 1, 1E+308, 1E+308)", "#NUM!"),
+                ("=SORT(B1:B5)", "{1;3;6;8;9}"),
+                (
+                    "=SORT(A1:B5, 2, -1)",
+                    "{\"apple\",9;\"apple\",8;\"Pear\",6;\"pear\",3;\"fig\",1}",
+                ),
+                ("=SORT(D1:H1, 1, 1, TRUE)", "{1,3,\"A\",\"b\",TRUE}"),
+                ("=SORT(B1:B5, 3)", "#VALUE!"),
+                ("=FILTER(A1:A5, B1:B5>5)", "{\"apple\";\"Pear\";\"apple\"}"),
+                ("=FILTER(A1:B5, B1:B5>8)", "{\"apple\",9}"),
+                ("=FILTER(A1:A5, B1:B5>10)", "#CALC!"),
+                ("=FILTER(A1:A5, B1:B5>10, \"none\")", "none"),
+                ("=FILTER(A1:A5, B1:B3>5)", "#VALUE!"),
+                ("=FILTER(D1:F1, D1:F1<>\"b\")", "{3,TRUE}"),
+                ("=UNIQUE(A1:A5)", "{\"pear\";\"apple\";\"fig\"}"),
+                ("=UNIQUE(A1:A5, FALSE, TRUE)", "fig"),
+                ("=UNIQUE(SEQUENCE(2, 3, 1, 0), TRUE)", "{1;1}"),
+                ("=SORT(UNIQUE(A1:A5))", "{\"apple\";\"fig\";\"pear\"}"),
+                ("=B1:B3*2", "{6;16;12}"),
+                ("=B1:B3+SEQUENCE(1, 2)", "{4,5;9,10;7,8}"),
+                ("=SEQUENCE(1, 3)+SEQUENCE(2)", "{2,3,4;3,4,5}"),
+                ("=SEQUENCE(1, 2)+SEQUENCE(1, 3)", "{2,4,#N/A}"),
+                ("=-SEQUENCE(2)", "{-1;-2}"),
+                ("=SUM(SEQUENCE(4))", "10"),
+                ("=SUM(B1:B5*(B1:B5>5))", "23"),
+                ("=ROWS(FILTER(A1:A5, B1:B5>2))", "4"),
+            ],
+        );
+    }
+
+    #[test]
+    fn lambda_functions() {
+        let conn = sheet(&[
+            ("A1", "1"),
+            ("A2", "2"),
+            ("A3", "3"),
+            ("B1", "10"),
+            ("B2", "20"),
+            ("B3", "30"),
+            ("C1", "x"),
+        ]);
+        for (name, formula) in [
+            ("Hypotenuse", "=LAMBDA(a, b, SQRT(a^2 + b^2))"),
+            (
+                "Factorial",
+                "=LAMBDA(n, IF(n <= 1, 1, n * FACTORIAL(n - 1)))",
+            ),
+            ("Forever", "=LAMBDA(n, FOREVER(n))"),
+        ] {
+            conn.execute(
+                "INSERT INTO names (name, formula) VALUES (?1, ?2)",
+                params![name, formula],
+            )
+            .unwrap();
+        }
+        check(
+            &conn,
+            &[
+                ("=LET(x, 2, y, x * 3, x + y)", "8"),
+                ("=LET(x, 2, LET(x, 5, x) + x)", "7"),
+                ("=LET(total, SUM(A1:A3), total / COUNT(A1:A3))", "2"),
+                ("=LET(x, A1:A3, SUM(x * 2))", "12"),
+                ("=LET(x, 1, y)", "#NAME?"),
+                ("=LET(x, 1)", "#VALUE!"),
+                ("=LET(A1, 1, 2)", "#VALUE!"),
+                ("=LET(x, 1, x(2))", "#VALUE!"),
+                ("=LET(double, LAMBDA(n, n * 2), double(21))", "42"),
+                ("=LET(k, 3, scale, LAMBDA(n, n * k), k, 10, scale(2))", "6"),
+                ("=LET(f, LAMBDA(n, n + 1), MAP(A1:A3, f))", "{2;3;4}"),
+                ("=LAMBDA(x, x + 1)", "#CALC!"),
+                ("=LAMBDA(x, x, 1)", "#VALUE!"),
+                ("=LET(f, LAMBDA(a, b, a + b), f(1))", "#VALUE!"),
+                ("=MAP(A1:A3, LAMBDA(v, v * 10))", "{10;20;30}"),
+                ("=MAP(A1:A3, B1:B3, LAMBDA(a, b, a + b))", "{11;22;33}"),
+                ("=MAP(A1:A3, B1:B2, LAMBDA(a, b, a + b))", "#VALUE!"),
+                ("=MAP(A1:A2, LAMBDA(v, SEQUENCE(2)))", "{#CALC!;#CALC!}"),
+                ("=MAP(C1, LAMBDA(v, UPPER(v)))", "X"),
+                ("=REDUCE(0, A1:B3, LAMBDA(acc, v, acc + v))", "66"),
+                ("=REDUCE(1, A1:A3, LAMBDA(acc, v, acc * v))", "6"),
+                ("=REDUCE(, A1:A3, LAMBDA(acc, v, acc + v))", "6"),
+                ("=SCAN(0, A1:A3, LAMBDA(acc, v, acc + v))", "{1;3;6}"),
+                (
+                    "=SCAN(\"\", A1:B1, LAMBDA(acc, v, acc & v))",
+                    "{\"1\",\"110\"}",
+                ),
+                ("=BYROW(A1:B3, LAMBDA(row, SUM(row)))", "{11;22;33}"),
+                ("=BYCOL(A1:B3, LAMBDA(col, MAX(col)))", "{3,30}"),
+                ("=BYROW(A1:B3, LAMBDA(row, row))", "{#CALC!;#CALC!;#CALC!}"),
+                ("=MAP(A1:A3, 5)", "#VALUE!"),
+                ("=HYPOTENUSE(3, 4)", "5"),
+                ("=hypotenuse(B1, 24)", "26"),
+                ("=MAP(A1:A3 * 3, A1:A3 * 4, Hypotenuse)", "{5;10;15}"),
+                ("=Hypotenuse", "#CALC!"),
+                ("=FACTORIAL(5)", "120"),
+                ("=FOREVER(1)", "#NUM!"),
+                ("=Unknown(1)", "#NAME?"),
+            ],
+        );
+    }
+
+    #[test]
+    fn defined_names() {
+        let conn = sheet(&[("A1", "1"), ("A2", "2"), ("A3", "3")]);
+        conn.execute_batch(
+            "INSERT INTO cells (sheet, row, col, value, value_type) VALUES
+                 ('Data', 0, 0, '10', 'number'),
+                 ('Data', 1, 0, '20', 'number');
+             INSERT INTO names (name, scope, formula) VALUES
+                 ('Revenue', '', '=Data!A1:A2'),
+                 ('Rate', '', '=0.5'),
+                 ('Local', '', '=A1:A3'),
+                 ('Rate', 's', '=2'),
+                 ('Other', 'Data', '=A1'),
+                 ('Doubled', '', '=Revenue * Rate'),
+                 ('Loop', '', '=Loop + 1'),
+                 ('Square', '', '=LAMBDA(x, x * x)');",
+        )
+        .unwrap();
+        check(
+            &conn,
+            &[
+                ("=SUM(Revenue)", "30"),
+                ("=SUM(revenue) + 1", "31"),
+                ("=Rate", "2"),
+                ("=SUM(Local)", "6"),
+                ("=Other", "#NAME?"),
+                ("=SUM(Doubled)", "60"),
+                ("=Loop", "#NUM!"),
+                ("=Square", "#CALC!"),
+                ("=Square(Rate)", "4"),
+                ("=LET(Rate, 3, Rate)", "3"),
+                ("=Missing", "#NAME?"),
+            ],
+        );
+        // Workbook names read the sheet of the formula using them; sheet
+        // names read their own sheet.
+        let on_data = |formula| evaluate(formula, "Data", &conn).unwrap().display();
+        assert_eq!(on_data("=SUM(Local)"), "30");
+        assert_eq!(on_data("=Rate"), "0.5");
+        assert_eq!(on_data("=Other"), "10");
+    }
+
+    #[test]
+    fn structured_references() {
+        let conn = sheet(&[
+            ("B2", "Item"),
+            ("C2", "Qty"),
+            ("D2", "Unit Price"),
+            ("B3", "pen"),
+            ("C3", "2"),
+            ("D3", "1.5"),
+            ("B4", "ink"),
+            ("C4", "3"),
+            ("D4", "4"),
+            ("C5", "100"),
+        ]);
+        conn.execute(
+            "INSERT INTO tables (name, sheet, start_row, start_col, end_row, end_col)
+             VALUES ('Sales', 's', 1, 1, 3, 3)",
+            [],
+        )
+        .unwrap();
+        check(
+            &conn,
+            &[
+                ("=SUM(Sales[Qty])", "5"),
+                ("=SUM(sales[qty])", "5"),
+                ("=SUMPRODUCT(Sales[Qty], Sales[Unit Price])", "15"),
+                ("=ROWS(Sales[])", "2"),
+                ("=ROWS(Sales[#All])", "3"),
+                ("=COUNTA(Sales[[Item]:[Qty]])", "4"),
+                ("=INDEX(Sales[#Headers], 3)", "Unit Price"),
+                ("=SUM(Sales[Price])", "#REF!"),
+                ("=SUM(Orders[Qty])", "#REF!"),
+                ("=Sales[@Qty]", "#VALUE!"),
+            ],
+        );
+        let at = |row, formula| {
+            evaluate_cell(formula, &CellKey::new("s", row, 5), &conn)
+                .unwrap()
+                .value
+                .display()
+        };
+        assert_eq!(at(2, "=Sales[@Qty] * Sales[@[Unit Price]]"), "3");
+        assert_eq!(at(3, "=Sales[@Qty] * Sales[@[Unit Price]]"), "12");
+        assert_eq!(at(4, "=Sales[@Qty]"), "#VALUE!");
+    }
+
+    #[test]
+    fn random_functions() {
+        let conn = sheet(&[]);
+        let number = |formula| match evaluate(formula, "s", &conn).unwrap() {
+            Value::Number(n) => n,
+            other => panic!("{} gave {:?}", formula, other),
+        };
+        for _ in 0..100 {
+            assert!((0.0..1.0).contains(&number("=RAND()")));
+            let n = number("=RANDBETWEEN(-2, 3)");
+            assert!((-2.0..=3.0).contains(&n) && n.fract() == 0.0);
+            assert_eq!(number("=RANDBETWEEN(1.5, 2.5)"), 2.0);
+        }
+        check(
+            &conn,
+            &[
+                ("=RAND(1)", "#VALUE!"),
+                ("=RANDBETWEEN(5, 4)", "#NUM!"),
+                ("=RANDBETWEEN(4, 4)", "4"),
+            ],
+        );
+    }
+
+    #[test]
+    fn reference_functions() {
+        let conn = sheet(&[
+            ("A1", "2"),
+            ("A2", "B"),
+            ("B1", "10"),
+            ("B2", "20"),
+            ("B3", "30"),
+            ("C1", "1"),
+            ("C2", "2"),
+        ]);
+        conn.execute_batch(
+            "INSERT INTO cells (sheet, row, col, value, value_type) VALUES
+                 ('Data', 0, 0, '7', 'number');
+             INSERT INTO names (name, scope, formula) VALUES ('Totals', '', '=B1:B3');
+             INSERT INTO tables (name, sheet, start_row, start_col, end_row, end_col)
+                 VALUES ('Codes', 's', 0, 2, 1, 2);",
+        )
+        .unwrap();
+        check(
+            &conn,
+            &[
+                ("=INDIRECT(\"B\" & A1)", "20"),
+                ("=INDIRECT(A2 & \"3\")", "30"),
+                ("=SUM(INDIRECT(\"B1:B\" & A1))", "30"),
+                ("=INDIRECT(\"Data!A1\")", "7"),
+                ("=SUM(INDIRECT(\"Totals\"))", "60"),
+                ("=SUM(INDIRECT(\"Codes[]\"))", "2"),
+                ("=INDIRECT(\"R2C2\", FALSE)", "20"),
+                ("=SUM(INDIRECT(\"r1c2:r3c2\", FALSE))", "60"),
+                ("=ROWS(INDIRECT(\"B:B\"))", "1048576"),
+                ("=INDIRECT(\"B2\", FALSE)", "#REF!"),
+                ("=INDIRECT(\"R[1]C\", FALSE)", "#REF!"),
+                ("=INDIRECT(\"1+1\")", "#REF!"),
+                ("=INDIRECT(\"Nowhere\")", "#REF!"),
+                ("=INDIRECT(1/0)", "#DIV/0!"),
+                ("=OFFSET(A1, 1, 1)", "20"),
+                ("=SUM(OFFSET(A1, 0, 1, 3))", "60"),
+                ("=SUM(OFFSET(B1:B3, 1, 0, 2))", "50"),
+                ("=ROWS(OFFSET(B1:B3, 0, 0))", "3"),
+                ("=COLUMNS(OFFSET(A1, 0, 0, 1, 3))", "3"),
+                ("=OFFSET(INDIRECT(\"B1\"), A1, 0)", "30"),
+                ("=SUM(OFFSET(A1, , 1, 2))", "30"),
+                ("=OFFSET(A1, -1, 0)", "#REF!"),
+                ("=OFFSET(A1, 0, 0, 0)", "#REF!"),
+                ("=OFFSET(1, 0, 0)", "#VALUE!"),
+                ("=OFFSET(INDIRECT(\"?\"), 0, 0)", "#REF!"),
+                ("=OFFSET(A1, 0)", "#VALUE!"),
+            ],
+        );
+
+        // The ranges reached are reported so the cell can be re-registered.
+        let evaluation = evaluate_cell(
+            "=INDIRECT(\"B\" & A1) + SUM(OFFSET(C1, 0, 0, 2))",
+            &CellKey::new("s", 9, 9),
+            &conn,
+        )
+        .unwrap();
+        assert_eq!(evaluation.value, Value::Number(23.0));
+        assert_eq!(
+            evaluation.dynamic,
+            vec![
+                SheetRange::new("s", CellRange::new(1, 1, 1, 1)),
+                SheetRange::new("s", CellRange::new(0, 2, 1, 2)),
+            ]
+        );
+    }
+}
@@ -0,0 +1,554 @@
+//! Statistical functions.
+
+use super::criteria::{self, Criterion};
+use super::{arity, number, numbers, scalar, scalar_or, shape, single, values};
+use crate::formula::value::{CellError, Value};
+use std::collections::HashMap;
+
+/// The mean of `numbers`, or `#DIV/0!` if there are none.
+fn mean(numbers: &[f64]) -> Result<f64, CellError> {
+    if numbers.is_empty() {
+        return Err(CellError::Div0);
+    }
+    Ok(numbers.iter().sum::<f64>() / numbers.len() as f64)
+}
+
+pub(super) fn average(args: &[Value]) -> Value {
+    number(numbers(args).and_then(|n| mean(&n)))
+}
+
+/// `AVERAGEIF(range, criterion, [average_range])`
+pub(super) fn averageif(args: &[Value]) -> Value {
+    number(arity(args, 2, 3).and_then(|_| {
+        let criterion = Criterion::new(single(&args[1])?)?;
+        let target = args.get(2).unwrap_or(&args[0]);
+        let mask = criteria::mask(&args[0], &criterion);
+        mean(&criteria::selected(target, shape(&args[0]), &mask)?)
+    }))
+}
+
+/// `AVERAGEIFS(average_range, range1, criterion1, ...)`
+pub(super) fn averageifs(args: &[Value]) -> Value {
+    number(arity(args, 3, usize::MAX).and_then(|_| {
+        let mask = criteria::masks(shape(&args[0]), &args[1..])?;
+        mean(&criteria::selected(&args[0], shape(&args[0]), &mask)?)
+    }))
+}
+
+/// `COUNT(...)`: how many numbers there are. Values typed in directly count
+/// if they read as numbers; errors are skipped rather than returned.
+pub(super) fn count(args: &[Value]) -> Value {
+    let count = args
+        .iter()
+        .map(|arg| match arg {
+            Value::Array(array) => array
+                .values
+                .iter()
+                .filter(|v| matches!(v, Value::Number(_)))
+                .count(),
+            Value::Empty => 0,
+            value => usize::from(value.to_number().is_ok()),
+        })
+        .sum::<usize>();
+    Value::Number(count as f64)
+}
+
+/// `COUNTA(...)`: how many values are not blank, errors included.
+pub(super) fn counta(args: &[Value]) -> Value {
+    let count = args
+        .iter()
+        .flat_map(values)
+        .filter(|v| !matches!(v, Value::Empty))
+        .count();
+    Value::Number(count as f64)
+}
+
+/// `COUNTIF(range, criterion)`: how many cells of `range` meet
+/// `criterion`.
+pub(super) fn countif(args: &[Value]) -> Value {
+    number(arity(args, 2, 2).and_then(|_| {
+        let criterion = Criterion::new(single(&args[1])?)?;
+        Ok(criteria::mask(&args[0], &criterion)
+            .into_iter()
+            .filter(|&m| m)
+            .count() as f64)
+    }))
+}
+
+/// `COUNTIFS(range1, criterion1, ...)`: how many positions meet every
+/// criterion.
+pub(super) fn countifs(args: &[Value]) -> Value {
+    number(
+        criteria::masks(shape(args.first().unwrap_or(&Value::Empty)), args)
+            .map(|mask| mask.into_iter().filter(|&m| m).count() as f64),
+    )
+}
+
+/// `MAX(...)`: 0 when there are no numbers.
+pub(super) fn max(args: &[Value]) -> Value {
+    number(numbers(args).map(|n| n.into_iter().reduce(f64::max).unwrap_or(0.0)))
+}
+
+/// `MIN(...)`: 0 when there are no numbers.
+pub(super) fn min(args: &[Value]) -> Value {
+    number(numbers(args).map(|n| n.into_iter().reduce(f64::min).unwrap_or(0.0)))
+}
+
+/// The numbers of an array or range argument, for functions such as
+/// `LARGE` that take one list and then other arguments.
+fn data(arg: &Value) -> Result<Vec<f64>, CellError> {
+    numbers(std::slice::from_ref(arg))
+}
+
+/// `numbers` sorted in ascending order.
+fn sorted(mut numbers: Vec<f64>) -> Vec<f64> {
+    numbers.sort_by(f64::total_cmp);
+    numbers
+}
+
+/// `MEDIAN(...)`: the middle number, or the mean of the middle two.
+pub(super) fn median(args: &[Value]) -> Value {
+    number(numbers(args).and_then(|n| {
+        if n.is_empty() {
+            return Err(CellError::Num);
+        }
+        let n = sorted(n);
+        let mid = n.len() / 2;
+        Ok(if n.len() % 2 == 1 {
+            n[mid]
+        } else {
+            (n[mid - 1] + n[mid]) / 2.0
+        })
+    }))
+}
+
+/// `MODE(...)`: the most frequent number, the first one seen on a tie, or
+/// `#N/A` if no number repeats.
+pub(super) fn mode(args: &[Value]) -> Value {
+    number(numbers(args).and_then(|n| {
+        // Each number's count and where it first appears, keyed by its bits
+        // with -0 counted as 0.
+        let mut seen: HashMap<u64, (usize, usize)> = HashMap::new();
+        for (i, x) in n.iter().enumerate() {
+            seen.entry((x + 0.0).to_bits()).or_insert((0, i)).0 += 1;
+        }
+        seen.into_values()
+            .filter(|&(count, _)| count > 1)
+            .max_by(|(a, first_a), (b, first_b)| a.cmp(b).then(first_b.cmp(first_a)))
+            .map(|(_, first)| n[first])
+            .ok_or(CellError::NA)
+    }))
+}
+
+/// The variance of `numbers`, dividing by one less than their count for a
+/// sample.
+fn variance(numbers: &[f64], sample: bool) -> Result<f64, CellError> {
+    let divisor = numbers.len() as f64 - if sample { 1.0 } else { 0.0 };
+    if divisor <= 0.0 {
+        return Err(CellError::Div0);
+    }
+    let mean = mean(numbers)?;
+    Ok(numbers.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / divisor)
+}
+
+pub(super) fn var_s(args: &[Value]) -> Value {
+    number(numbers(args).and_then(|n| variance(&n, true)))
+}
+
+pub(super) fn var_p(args: &[Value]) -> Value {
+    number(numbers(args).and_then(|n| variance(&n, false)))
+}
+
+pub(super) fn stdev_s(args: &[Value]) -> Value {
+    number(
+        numbers(args)
+            .and_then(|n| variance(&n, true))
+            .map(f64::sqrt),
+    )
+}
+
+pub(super) fn stdev_p(args: &[Value]) -> Value {
+    number(
+        numbers(args)
+            .and_then(|n| variance(&n, false))
+            .map(f64::sqrt),
+    )
+}
+
+/// The value at `rank`, a 0-based position in `sorted` that may fall
+/// between two numbers.
+fn interpolate(sorted: &[f64], rank: f64) -> f64 {
+    let low = rank.floor() as usize;
+    let high = (low + 1).min(sorted.len() - 1);
+    sorted[low] + (rank - low as f64) * (sorted[high] - sorted[low])
+}
+
+/// The `k`-th percentile of `numbers`, where `k` runs from 0 to 1 and, when
+/// `exclusive`, may not reach past the smallest or largest number.
+fn percentile_of(numbers: Vec<f64>, k: f64, exclusive: bool) -> Result<f64, CellError> {
+    if numbers.is_empty() || !(0.0..=1.0).contains(&k) {
+        return Err(CellError::Num);
+    }
+    let n = numbers.len() as f64;
+    let rank = if exclusive {
+        k * (n + 1.0) - 1.0
+    } else {
+        k * (n - 1.0)
+    };
+    if !(0.0..=n - 1.0).contains(&rank) {
+        return Err(CellError::Num);
+    }
+    Ok(interpolate(&sorted(numbers), rank))
+}
+
+/// `PERCENTILE(array, k)`, also `PERCENTILE.INC`.
+pub(super) fn percentile(args: &[Value]) -> Value {
+    number(arity(args, 2, 2).and_then(|_| percentile_of(data(&args[0])?, scalar(&args[1])?, false)))
+}
+
+/// `PERCENTILE.EXC(array, k)`
+pub(super) fn percentile_exc(args: &[Value]) -> Value {
+    number(arity(args, 2, 2).and_then(|_| percentile_of(data(&args[0])?, scalar(&args[1])?, true)))
+}
+
+/// `QUARTILE(array, quart)`, also `QUARTILE.INC`: quart 0 is the minimum
+/// and 4 the maximum.
+pub(super) fn quartile(args: &[Value]) -> Value {
+    number(arity(args, 2, 2).and_then(|_| {
+        let quart = scalar(&args[1])?.trunc();
+        if !(0.0..=4.0).contains(&quart) {
+            return Err(CellError::Num);
+        }
+        percentile_of(data(&args[0])?, quart / 4.0, false)
+    }))
+}
+
+/// `QUARTILE.EXC(array, quart)`, for quart 1 to 3.
+pub(super) fn quartile_exc(args: &[Value]) -> Value {
+    number(arity(args, 2, 2).and_then(|_| {
+        let quart = scalar(&args[1])?.trunc();
+        if !(1.0..=3.0).contains(&quart) {
+            return Err(CellError::Num);
+        }
+        percentile_of(data(&args[0])?, quart / 4.0, true)
+    }))
+}
+
+/// The rank of `args[0]` among the numbers of `args[1]`, largest first
+/// unless `args[2]` is non-zero, with ties given the rank `tie` picks from
+/// the first and last positions the number shares.
+fn rank_with(args: &[Value], tie: fn(f64, f64) -> f64) -> Value {
+    number(arity(args, 2, 3).and_then(|_| {
+        let x = scalar(&args[0])?;
+        let numbers = data(&args[1])?;
+        let ascending = scalar_or(args, 2, 0.0)? != 0.0;
+        let ahead = numbers
+            .iter()
+            .filter(|&&y| if ascending { y < x } else { y > x })
+            .count();
+        let same = numbers.iter().filter(|&&y| y == x).count();
+        if same == 0 {
+            return Err(CellError::NA);
+        }
+        Ok(tie(ahead as f64 + 1.0, (ahead + same) as f64))
+    }))
+}
+
+/// `RANK(number, ref, [order])`, also `RANK.EQ`: tied numbers share the
+/// best rank.
+pub(super) fn rank(args: &[Value]) -> Value {
+    rank_with(args, |first, _| first)
+}
+
+/// `RANK.AVG(number, ref, [order])`: tied numbers share the average rank.
+pub(super) fn rank_avg(args: &[Value]) -> Value {
+    rank_with(args, |first, last| (first + last) / 2.0)
+}
+
+/// The `k`-th largest or smallest number of `args[0]`.
+fn kth(args: &[Value], largest: bool) -> Value {
+    number(arity(args, 2, 2).and_then(|_| {
+        let numbers = sorted(data(&args[0])?);
+        let k = scalar(&args[1])?.ceil();
+        if k < 1.0 || k > numbers.len() as f64 {
+            return Err(CellError::Num);
+        }
+        let k = k as usize;
+        Ok(if largest {
+            numbers[numbers.len() - k]
+        } else {
+            numbers[k - 1]
+        })
+    }))
+}
+
+/// `LARGE(array, k)`: the `k`-th largest number.
+pub(super) fn large(args: &[Value]) -> Value {
+    kth(args, true)
+}
+
+/// `SMALL(array, k)`: the `k`-th smallest number.
+pub(super) fn small(args: &[Value]) -> Value {
+    kth(args, false)
+}
+
+/// The pairs of numbers at the same positions in two ranges, skipping any
+/// position where either is not a number. `#N/A` if the ranges differ in
+/// size.
+fn pairs(ys: &Value, xs: &Value) -> Result<Vec<(f64, f64)>, CellError> {
+    let ys = values(ys);
+    let xs = values(xs);
+    if ys.len() != xs.len() {
+        return Err(CellError::NA);
+    }
+    let mut pairs = Vec::new();
+    for (y, x) in ys.iter().zip(xs) {
+        match (y, x) {
+            (Value::Error(e), _) | (_, Value::Error(e)) => return Err(*e),
+            (Value::Number(y), Value::Number(x)) => pairs.push((*y, *x)),
+            _ => {}
+        }
+    }
+    Ok(pairs)
+}
+
+/// The means of paired y and x values, and their sums of squares and cross
+/// products about those means.
+struct Moments {
+    syy: f64,
+    sxx: f64,
+    sxy: f64,
+    mean_y: f64,
+    mean_x: f64,
+}
+
+fn moments(pairs: &[(f64, f64)]) -> Result<Moments, CellError> {
+    if pairs.is_empty() {
+        return Err(CellError::Div0);
+    }
+    let n = pairs.len() as f64;
+    let mean_y = pairs.iter().map(|p| p.0).sum::<f64>() / n;
+    let mean_x = pairs.iter().map(|p| p.1).sum::<f64>() / n;
+    let mut moments = Moments {
+        syy: 0.0,
+        sxx: 0.0,
+        sxy: 0.0,
+        mean_y,
+        mean_x,
+    };
+    for (y, x) in pairs {
+        moments.syy += (y - mean_y).powi(2);
+        moments.sxx += (x - mean_x).powi(2);
+        moments.sxy += (y - mean_y) * (x - mean_x);
+    }
+    Ok(moments)
+}
+
+/// The slope and intercept of the least-squares line through `pairs`.
+fn regression(pairs: &[(f64, f64)]) -> Result<(f64, f64), CellError> {
+    let m = moments(pairs)?;
+    if m.sxx == 0.0 {
+        return Err(CellError::Div0);
+    }
+    let slope = m.sxy / m.sxx;
+    Ok((slope, m.mean_y - slope * m.mean_x))
+}
+
+/// `CORREL(array1, array2)`, also `PEARSON`: the correlation coefficient.
+pub(super) fn correl(args: &[Value]) -> Value {
+    number(arity(args, 2, 2).and_then(|_| {
+        let m = moments(&pairs(&args[0], &args[1])?)?;
+        if m.sxx == 0.0 || m.syy == 0.0 {
+            return Err(CellError::Div0);
+        }
+        Ok(m.sxy / (m.sxx * m.syy).sqrt())
+    }))
+}
+
+/// `RSQ(known_ys, known_xs)`: the square of the correlation coefficient.
+pub(super) fn rsq(args: &[Value]) -> Value {
+    match correl(args) {
+        Value::Number(r) => Value::Number(r * r),
+        value => value,
+    }
+}
+
+/// The covariance of two ranges, dividing by one less than the number of
+/// pairs for a sample.
+fn covariance(args: &[Value], sample: bool) -> Value {
+    number(arity(args, 2, 2).and_then(|_| {
+        let pairs = pairs(&args[0], &args[1])?;
+        let divisor = pairs.len() as f64 - if sample { 1.0 } else { 0.0 };
+        if divisor <= 0.0 {
+            return Err(CellError::Div0);
+        }
+        Ok(moments(&pairs)?.sxy / divisor)
+    }))
+}
+
+pub(super) fn covariance_s(args: &[Value]) -> Value {
+    covariance(args, true)
+}
+
+pub(super) fn covariance_p(args: &[Value]) -> Value {
+    covariance(args, false)
+}
+
+/// `SLOPE(known_ys, known_xs)`
+pub(super) fn slope(args: &[Value]) -> Value {
+    number(arity(args, 2, 2).and_then(|_| Ok(regression(&pairs(&args[0], &args[1])?)?.0)))
+}
+
+/// `INTERCEPT(known_ys, known_xs)`
+pub(super) fn intercept(args: &[Value]) -> Value {
+    number(arity(args, 2, 2).and_then(|_| Ok(regression(&pairs(&args[0], &args[1])?)?.1)))
+}
+
+/// `FORECAST.LINEAR(x, known_ys, known_xs)`, also `FORECAST`: the value at
+/// `x` on the least-squares line.
+pub(super) fn forecast(args: &[Value]) -> Value {
+    number(arity(args, 3, 3).and_then(|_| {
+        let x = scalar(&args[0])?;
+        let (slope, intercept) = regression(&pairs(&args[1], &args[2])?)?;
+        Ok(intercept + slope * x)
+    }))
+}
+
+/// The error function's complement, from its power series near zero and
+/// its continued fraction further out, both good to about 15 digits.
+fn erfc(x: f64) -> f64 {
+    if x < 0.0 {
+        return 2.0 - erfc(-x);
+    }
+    if x < 3.0 {
+        // erf(x) = 2/√π · e^(-x²) · Σ 2ⁿ x^(2n+1) / (1·3·…·(2n+1))
+        let mut term = x;
+        let mut sum = x;
+        let mut n = 0.0;
+        while term > sum * 1e-17 {
+            n += 1.0;
+            term *= 2.0 * x * x / (2.0 * n + 1.0);
+            sum += term;
+        }
+        return 1.0 - 2.0 / std::f64::consts::PI.sqrt() * (-x * x).exp() * sum;
+    }
+    // erfc(x) = e^(-x²)/√π · 1/(x + (1/2)/(x + 1/(x + (3/2)/(x + ...))))
+    let mut fraction = x;
+    for k in (1..60).rev() {
+        fraction = x + f64::from(k) / 2.0 / fraction;
+    }
+    (-x * x).exp() / std::f64::consts::PI.sqrt() / fraction
+}
+
+/// The standard normal cumulative distribution.
+fn normal_cdf(z: f64) -> f64 {
+    erfc(-z / std::f64::consts::SQRT_2) / 2.0
+}
+
+/// The standard normal density.
+fn normal_pdf(z: f64) -> f64 {
+    (-z * z / 2.0).exp() / (2.0 * std::f64::consts::PI).sqrt()
+}
+
+/// The `z` at which the standard normal cumulative distribution reaches
+/// `p`: Acklam's rational approximation, polished with one Halley step.
+fn normal_inverse(p: f64) -> Result<f64, CellError> {
+    if p <= 0.0 || p >= 1.0 {
+        return Err(CellError::Num);
+    }
+    const A: [f64; 6] = [
+        -3.969683028665376e1,
+        2.209460984245205e2,
+        -2.759285104469687e2,
+        1.38357751867269e2,
+        -3.066479806614716e1,
+        2.506628277459239,
+    ];
+    const B: [f64; 5] = [
+        -5.447609879822406e1,
+        1.615858368580409e2,
+        -1.556989798598866e2,
+        6.680131188771972e1,
+        -1.328068155288572e1,
+    ];
+    const C: [f64; 6] = [
+        -7.784894002430293e-3,
+        -3.223964580411365e-1,
+        -2.400758277161838,
+        -2.549732539343734,
+        4.374664141464968,
+        2.938163982698783,
+    ];
+    const D: [f64; 4] = [
+        7.784695709041462e-3,
+        3.224671290700398e-1,
+        2.445134137142996,
+        3.754408661907416,
+    ];
+    let tail = |q: f64| {
+        (((((C[0] * q + C[1]) * q + C[2]) * q + C[3]) * q + C[4]) * q + C[5])
+            / ((((D[0] * q + D[1]) * q + D[2]) * q + D[3]) * q + 1.0)
+    };
+    let z = if p < 0.02425 {
+        tail((-2.0 * p.ln()).sqrt())
+    } else if p > 1.0 - 0.02425 {
+        -tail((-2.0 * (1.0 - p).ln()).sqrt())
+    } else {
+        let q = p - 0.5;
+        let r = q * q;
+        (((((A[0] * r + A[1]) * r + A[2]) * r + A[3]) * r + A[4]) * r + A[5]) * q
+            / (((((B[0] * r + B[1]) * r + B[2]) * r + B[3]) * r + B[4]) * r + 1.0)
+    };
+    let error = normal_cdf(z) - p;
+    let step = error / normal_pdf(z);
+    Ok(z - step / (1.0 + z * step / 2.0))
+}
+
+/// A standard deviation argument, which must be positive.
+fn deviation(arg: &Value) -> Result<f64, CellError> {
+    let sd = scalar(arg)?;
+    if sd <= 0.0 {
+        return Err(CellError::Num);
+    }
+    Ok(sd)
+}
+
+/// `NORM.DIST(x, mean, standard_dev, cumulative)`
+pub(super) fn norm_dist(args: &[Value]) -> Value {
+    number(arity(args, 4, 4).and_then(|_| {
+        let x = scalar(&args[0])?;
+        let mean = scalar(&args[1])?;
+        let sd = deviation(&args[2])?;
+        let z = (x - mean) / sd;
+        Ok(if single(&args[3])?.to_bool()? {
+            normal_cdf(z)
+        } else {
+            normal_pdf(z) / sd
+        })
+    }))
+}
+
+/// `NORM.INV(probability, mean, standard_dev)`
+pub(super) fn norm_inv(args: &[Value]) -> Value {
+    number(arity(args, 3, 3).and_then(|_| {
+        let z = normal_inverse(scalar(&args[0])?)?;
+        Ok(scalar(&args[1])? + z * deviation(&args[2])?)
+    }))
+}
+
+/// `NORM.S.DIST(z, cumulative)`
+pub(super) fn norm_s_dist(args: &[Value]) -> Value {
+    number(arity(args, 2, 2).and_then(|_| {
+        let z = scalar(&args[0])?;
+        Ok(if single(&args[1])?.to_bool()? {
+            normal_cdf(z)
+        } else {
+            normal_pdf(z)
+        })
+    }))
+}
+
+/// `NORM.S.INV(probability)`
+pub(super) fn norm_s_inv(args: &[Value]) -> Value {
+    number(arity(args, 1, 1).and_then(|_| normal_inverse(scalar(&args[0])?)))
+}
@@ -0,0 +1,289 @@
+//! Text functions. Positions and lengths count characters, starting at 1.
+
+use super::criteria::wildcard;
+use super::format::{format_number_with, format_text_with};
+use super::{arity, scalar, single, values};
+use crate::formula::date;
+use crate::formula::value::{CellError, Value, parse_number};
+
+/// The longest text a cell can hold.
+const MAX_TEXT: usize = 32_767;
+
+/// A text argument.
+fn text(arg: &Value) -> Result<String, CellError> {
+    single(arg)?.to_text()
+}
+
+/// A count of characters, which must not be negative.
+fn count(arg: &Value) -> Result<usize, CellError> {
+    let n = scalar(arg)?;
+    if n < 0.0 {
+        return Err(CellError::Value);
+    }
+    Ok(n.trunc() as usize)
+}
+
+/// A 1-based position, which must be at least 1.
+fn position(arg: &Value) -> Result<usize, CellError> {
+    let n = scalar(arg)?;
+    if n < 1.0 {
+        return Err(CellError::Value);
+    }
+    Ok(n.trunc() as usize)
+}
+
+fn result(text: Result<String, CellError>) -> Value {
+    match text {
+        Ok(text) if text.chars().count() > MAX_TEXT => Value::Error(CellError::Value),
+        Ok(text) => Value::Text(text),
+        Err(e) => Value::Error(e),
+    }
+}
+
+/// `CONCAT(...)`: every value joined together, including each cell of a
+/// range.
+pub(super) fn concat(args: &[Value]) -> Value {
+    result(
+        args.iter()
+            .flat_map(values)
+            .map(Value::to_text)
+            .collect::<Result<String, _>>(),
+    )
+}
+
+/// `TEXTJOIN(delimiter, ignore_empty, text1, ...)`
+pub(super) fn textjoin(args: &[Value]) -> Value {
+    result(arity(args, 3, usize::MAX).and_then(|_| {
+        let delimiter = text(&args[0])?;
+        let ignore_empty = single(&args[1])?.to_bool()?;
+        let parts = args[2..]
+            .iter()
+            .flat_map(values)
+            .map(Value::to_text)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(parts
+            .into_iter()
+            .filter(|part| !(ignore_empty && part.is_empty()))
+            .collect::<Vec<_>>()
+            .join(&delimiter))
+    }))
+}
+
+/// `LEFT(text, [count])`
+pub(super) fn left(args: &[Value]) -> Value {
+    result(arity(args, 1, 2).and_then(|_| {
+        let n = args.get(1).map_or(Ok(1), count)?;
+        Ok(text(&args[0])?.chars().take(n).collect())
+    }))
+}
+
+/// `RIGHT(text, [count])`
+pub(super) fn right(args: &[Value]) -> Value {
+    result(arity(args, 1, 2).and_then(|_| {
+        let n = args.get(1).map_or(Ok(1), count)?;
+        let chars: Vec<char> = text(&args[0])?.chars().collect();
+        Ok(chars[chars.len().saturating_sub(n)..].iter().collect())
+    }))
+}
+
+/// `MID(text, start, count)`
+pub(super) fn mid(args: &[Value]) -> Value {
+    result(arity(args, 3, 3).and_then(|_| {
+        let start = position(&args[1])?;
+        let n = count(&args[2])?;
+        Ok(text(&args[0])?.chars().skip(start - 1).take(n).collect())
+    }))
+}
+
+pub(super) fn len(args: &[Value]) -> Value {
+    match arity(args, 1, 1).and_then(|_| text(&args[0])) {
+        Ok(text) => Value::Number(text.chars().count() as f64),
+        Err(e) => Value::Error(e),
+    }
+}
+
+pub(super) fn lower(args: &[Value]) -> Value {
+    result(arity(args, 1, 1).and_then(|_| Ok(text(&args[0])?.to_lowercase())))
+}
+
+pub(super) fn upper(args: &[Value]) -> Value {
+    result(arity(args, 1, 1).and_then(|_| Ok(text(&args[0])?.to_uppercase())))
+}
+
+/// `PROPER(text)`: capitalises each letter that follows a non-letter and
+/// lowercases the rest.
+pub(super) fn proper(args: &[Value]) -> Value {
+    result(arity(args, 1, 1).and_then(|_| {
+        let mut after_letter = false;
+        let mut out = String::new();
+        for c in text(&args[0])?.chars() {
+            if after_letter {
+                out.extend(c.to_lowercase());
+            } else {
+                out.extend(c.to_uppercase());
+            }
+            after_letter = c.is_alphabetic();
+        }
+        Ok(out)
+    }))
+}
+
+/// `TRIM(text)`: drops leading and trailing spaces and collapses runs of
+/// spaces between words to one.
+pub(super) fn trim(args: &[Value]) -> Value {
+    result(arity(args, 1, 1).and_then(|_| {
+        Ok(text(&args[0])?
+            .split(' ')
+            .filter(|word| !word.is_empty())
+            .collect::<Vec<_>>()
+            .join(" "))
+    }))
+}
+
+/// `SUBSTITUTE(text, old, new, [instance])`: replaces every occurrence of
+/// `old`, or only the `instance`-th.
+pub(super) fn substitute(args: &[Value]) -> Value {
+    result(arity(args, 3, 4).and_then(|_| {
+        let source = text(&args[0])?;
+        let old = text(&args[1])?;
+        let new = text(&args[2])?;
+        let instance = args.get(3).map(position).transpose()?;
+        if old.is_empty() {
+            return Ok(source);
+        }
+        Ok(match instance {
+            None => source.replace(&old, &new),
+            Some(n) => match source.match_indices(&old).nth(n - 1) {
+                Some((at, _)) => {
+                    format!("{}{}{}", &source[..at], new, &source[at + old.len()..])
+                }
+                None => source,
+            },
+        })
+    }))
+}
+
+/// `REPLACE(text, start, count, new)`: replaces `count` characters from
+/// `start`.
+pub(super) fn replace(args: &[Value]) -> Value {
+    result(arity(args, 4, 4).and_then(|_| {
+        let chars: Vec<char> = text(&args[0])?.chars().collect();
+        let start = (position(&args[1])? - 1).min(chars.len());
+        let end = start.saturating_add(count(&args[2])?).min(chars.len());
+        let new = text(&args[3])?;
+        Ok(chars[..start]
+            .iter()
+            .copied()
+            .chain(new.chars())
+            .chain(chars[end..].iter().copied())
+            .collect())
+    }))
+}
+
+/// `FIND(needle, text, [start])`: the position of `needle`, matched
+/// exactly, or `#VALUE!` if it is not there.
+pub(super) fn find(args: &[Value]) -> Value {
+    locate(args, |needle, haystack| {
+        let needle: String = needle.iter().collect();
+        let haystack: String = haystack.iter().collect();
+        haystack
+            .find(&needle)
+            .map(|at| haystack[..at].chars().count())
+    })
+}
+
+/// `SEARCH(needle, text, [start])`: like FIND, but ignoring case and
+/// accepting the wildcards `*` and `?`.
+pub(super) fn search(args: &[Value]) -> Value {
+    locate(args, |needle, haystack| {
+        let mut pattern: Vec<char> = needle
+            .iter()
+            .collect::<String>()
+            .to_lowercase()
+            .chars()
+            .collect();
+        pattern.push('*');
+        let haystack: Vec<char> = haystack
+            .iter()
+            .collect::<String>()
+            .to_lowercase()
+            .chars()
+            .collect();
+        (0..=haystack.len()).find(|&i| wildcard(&pattern, &haystack[i..]))
+    })
+}
+
+/// FIND and SEARCH: `matcher` gives the 0-based offset of the needle in the
+/// text from the start position onwards.
+fn locate(args: &[Value], matcher: impl Fn(&[char], &[char]) -> Option<usize>) -> Value {
+    let found = arity(args, 2, 3).and_then(|_| {
+        let needle: Vec<char> = text(&args[0])?.chars().collect();
+        let haystack: Vec<char> = text(&args[1])?.chars().collect();
+        let start = args.get(2).map_or(Ok(1), position)?;
+        if start > haystack.len() + 1 {
+            return Err(CellError::Value);
+        }
+        let offset = matcher(&needle, &haystack[start - 1..]).ok_or(CellError::Value)?;
+        Ok((start + offset) as f64)
+    });
+    match found {
+        Ok(n) => Value::Number(n),
+        Err(e) => Value::Error(e),
+    }
+}
+
+/// `TEXT(value, format)`: a number or date laid out with an Excel format
+/// code.
+pub(super) fn text_(args: &[Value]) -> Value {
+    result(arity(args, 2, 2).and_then(|_| {
+        let format = text(&args[1])?;
+        match single(&args[0])? {
+            Value::Text(text) => match parse_number(text).or_else(|| date::parse(text)) {
+                Some(n) => format_number_with(n, &format),
+                None => Ok(format_text_with(text, &format)),
+            },
+            Value::Bool(b) => Ok(format_text_with(if *b { "TRUE" } else { "FALSE" }, &format)),
+            value => format_number_with(value.to_number()?, &format),
+        }
+    }))
+}
+
+/// `VALUE(text)`: the number text stands for, allowing thousands
+/// separators, a leading currency sign and a trailing `%`.
+pub(super) fn value(args: &[Value]) -> Value {
+    let parsed = arity(args, 1, 1).and_then(|_| match single(&args[0])? {
+        Value::Number(n) => Ok(*n),
+        Value::Empty => Ok(0.0),
+        Value::Text(text) => {
+            let text = text.trim();
+            let (text, scale) = match text.strip_suffix('%') {
+                Some(rest) => (rest, 0.01),
+                None => (text, 1.0),
+            };
+            let (negative, text) = match text.strip_prefix('-') {
+                Some(rest) => (true, rest),
+                None => (false, text),
+            };
+            let text = text.strip_prefix('$').unwrap_or(text).replace(',', "");
+            let n = parse_number(&text).ok_or(CellError::Value)?;
+            Ok(if negative { -n } else { n } * scale)
+        }
+        _ => Err(CellError::Value),
+    });
+    match parsed {
+        Ok(n) => Value::Number(n),
+        Err(e) => Value::Error(e),
+    }
+}
+
+/// `REPT(text, times)`
+pub(super) fn rept(args: &[Value]) -> Value {
+    result(arity(args, 2, 2).and_then(|_| {
+        let text = text(&args[0])?;
+        let times = count(&args[1])?;
+        if text.chars().count().saturating_mul(times) > MAX_TEXT {
+            return Err(CellError::Value);
+        }
+        Ok(text.repeat(times))
+    }))
+}
@@ -0,0 +1,229 @@
+//! Splits formula text into tokens.
+
+use super::ParseError;
+use super::ast::Span;
+use super::value::CellError;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    Number(f64),
+    Text(String),
+    /// An error literal such as `#REF!`.
+    Error(CellError),
+    /// Function names, defined names, cell references (including `$A$1` and
+    /// `$3`) and `TRUE`/`FALSE`; the parser tells them apart from context.
+    Ident(String),
+    /// A sheet qualifier such as `Sheet2!` or `'My Sheet'!`, unquoted.
+    Sheet(String),
+    /// A table name and the text between the brackets that follow it, as
+    /// in `Sales[Amount]` or `Sales[[Qty]:[Price]]`.
+    Table(String, String),
+    LParen,
+    RParen,
+    /// The braces of an array constant such as `{1,2;3,4}`.
+    LBrace,
+    RBrace,
+    Comma,
+    /// Separates the rows of an array constant.
+    Semicolon,
+    Colon,
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Caret,
+    Ampersand,
+    Percent,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+/// Tokenizes `source`, skipping a leading `=`. Spans are character offsets
+/// into `source` itself, so they can be shown against what the user typed.
+pub fn tokenize(source: &str) -> Result<Vec<Token>, ParseError> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut pos = if chars.first() == Some(&'=') { 1 } else { 0 };
+
+    while pos < chars.len() {
+        let c = chars[pos];
+        let start = pos;
+        if c.is_whitespace() {
+            pos += 1;
+            continue;
+        }
+
+        let kind = if c.is_ascii_digit() || (c == '.' && next_is_digit(&chars, pos)) {
+            pos = scan_number(&chars, pos);
+            let text: String = chars[start..pos].iter().collect();
+            let value = text
+                .parse::<f64>()
+                .map_err(|_| ParseError::new(format!("invalid number '{}'", text), start))?;
+            TokenKind::Number(value)
+        } else if c == '"' {
+            let (text, end) = scan_quoted(&chars, pos, '"')?;
+            pos = end;
+            TokenKind::Text(text)
+        } else if c.is_alphabetic() || c == '_' || c == '$' {
+            while pos < chars.len()
+                && (chars[pos].is_alphanumeric() || matches!(chars[pos], '_' | '.' | '$'))
+            {
+                pos += 1;
+            }
+            let name: String = chars[start..pos].iter().collect();
+            if chars.get(pos) == Some(&'!') {
+                pos += 1;
+                TokenKind::Sheet(name)
+            } else if chars.get(pos) == Some(&'[') {
+                let (spec, end) = scan_brackets(&chars, pos)?;
+                pos = end;
+                TokenKind::Table(name, spec)
+            } else {
+                TokenKind::Ident(name)
+            }
+        } else if c == '#' {
+            let rest: String = chars[pos..].iter().collect();
+            let error = CellError::codes()
+                .find(|code| {
+                    rest.get(..code.len())
+                        .is_some_and(|r| r.eq_ignore_ascii_case(code))
+                })
+                .and_then(CellError::from_code)
+                .ok_or_else(|| ParseError::new("unknown error value", start))?;
+            pos += error.code().chars().count();
+            TokenKind::Error(error)
+        } else if c == '\'' {
+            let (name, end) = scan_quoted(&chars, pos, '\'')?;
+            if chars.get(end) != Some(&'!') {
+                return Err(ParseError::new("expected '!' after quoted sheet name", end));
+            }
+            pos = end + 1;
+            TokenKind::Sheet(name)
+        } else {
+            let two: String = chars[pos..(pos + 2).min(chars.len())].iter().collect();
+            let (kind, len) = match two.as_str() {
+                "<>" => (TokenKind::Ne, 2),
+                "<=" => (TokenKind::Le, 2),
+                ">=" => (TokenKind::Ge, 2),
+                _ => {
+                    let kind = match c {
+                        '(' => TokenKind::LParen,
+                        ')' => TokenKind::RParen,
+                        '{' => TokenKind::LBrace,
+                        '}' => TokenKind::RBrace,
+                        ',' => TokenKind::Comma,
+                        ';' => TokenKind::Semicolon,
+                        ':' => TokenKind::Colon,
+                        '+' => TokenKind::Plus,
+                        '-' => TokenKind::Minus,
+                        '*' => TokenKind::Star,
+                        '/' => TokenKind::Slash,
+                        '^' => TokenKind::Caret,
+                        '&' => TokenKind::Ampersand,
+                        '%' => TokenKind::Percent,
+                        '=' => TokenKind::Eq,
+                        '<' => TokenKind::Lt,
+                        '>' => TokenKind::Gt,
+                        _ => {
+                            return Err(ParseError::new(
+                                format!("unexpected character '{}'", c),
+                                start,
+                            ));
+                        }
+                    };
+                    (kind, 1)
+                }
+            };
+            pos += len;
+            kind
+        };
+
+        tokens.push(Token {
+            kind,
+            span: Span::new(start, pos),
+        });
+    }
+    Ok(tokens)
+}
+
+fn next_is_digit(chars: &[char], pos: usize) -> bool {
+    chars.get(pos + 1).is_some_and(|c| c.is_ascii_digit())
+}
+
+/// Digits with an optional fraction and exponent; returns the end offset.
+fn scan_number(chars: &[char], mut pos: usize) -> usize {
+    while pos < chars.len() && chars[pos].is_ascii_digit() {
+        pos += 1;
+    }
+    if pos < chars.len() && chars[pos] == '.' {
+        pos += 1;
+        while pos < chars.len() && chars[pos].is_ascii_digit() {
+            pos += 1;
+        }
+    }
+    if pos < chars.len() && (chars[pos] == 'e' || chars[pos] == 'E') {
+        let mut end = pos + 1;
+        if end < chars.len() && (chars[end] == '+' || chars[end] == '-') {
+            end += 1;
+        }
+        if end < chars.len() && chars[end].is_ascii_digit() {
+            pos = end;
+            while pos < chars.len() && chars[pos].is_ascii_digit() {
+                pos += 1;
+            }
+        }
+    }
+    pos
+}
+
+/// Text between a `[` and its matching `]`, which may enclose more
+/// bracketed names. Returns the text inside and the end offset.
+fn scan_brackets(chars: &[char], start: usize) -> Result<(String, usize), ParseError> {
+    let mut depth = 0;
+    for (pos, &c) in chars.iter().enumerate().skip(start) {
+        match c {
+            '[' => depth += 1,
+            ']' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok((chars[start + 1..pos].iter().collect(), pos + 1));
+                }
+            }
+            _ => {}
+        }
+    }
+    Err(ParseError::new("unterminated table reference", start))
+}
+
+/// Text between `quote` characters, where a doubled quote stands for one, as
+/// in `"say ""hi"""` or `'Bob''s data'`. Returns the unescaped text and the
+/// end offset.
+fn scan_quoted(chars: &[char], start: usize, quote: char) -> Result<(String, usize), ParseError> {
+    let mut text = String::new();
+    let mut pos = start + 1;
+    loop {
+        match chars.get(pos) {
+            Some(&c) if c == quote && chars.get(pos + 1) == Some(&quote) => {
+                text.push(quote);
+                pos += 2;
+            }
+            Some(&c) if c == quote => return Ok((text, pos + 1)),
+            Some(&c) => {
+                text.push(c);
+                pos += 1;
+            }
+            None if quote == '"' => return Err(ParseError::new("unterminated string", start)),
+            None => return Err(ParseError::new("unterminated sheet name", start)),
+        }
+    }
+}
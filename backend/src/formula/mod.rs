@@ -0,0 +1,524 @@
+//! Formula engine: tokenizer, parser and evaluator.
+//!
+//! Formulas are parsed into an [`Expr`] tree, so references are found and
+//! resolved structurally rather than by rewriting the formula text.
+
+mod ast;
+mod date;
+mod eval;
+mod functions;
+mod lexer;
+mod parser;
+mod value;
+
+pub use ast::{MAX_COLS, MAX_ROWS, Span, column_name, quote_sheet};
+#[cfg(test)]
+pub use eval::evaluate;
+pub use eval::{Step, Trace, evaluate_cell, trace};
+pub use parser::parse;
+pub use value::{CellError, Value, ValueType};
+
+use crate::graph::SheetRange;
+use ast::{ExprKind, TableItem};
+use rusqlite::Connection;
+use std::collections::HashSet;
+use std::fmt;
+
+/// A formula that could not be parsed, with the character offset of the
+/// problem in the formula text (counting the leading `=`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub message: String,
+    pub position: usize,
+}
+
+impl ParseError {
+    pub fn new(message: impl Into<String>, position: usize) -> Self {
+        ParseError {
+            message: message.into(),
+            position,
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at position {}", self.message, self.position)
+    }
+}
+
+/// Why a formula produced no value at all. Errors within the spreadsheet's
+/// own semantics, such as `#DIV/0!`, are values instead.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FormulaError {
+    Parse(ParseError),
+    /// The cells a formula reads could not be loaded.
+    Storage(String),
+}
+
+impl fmt::Display for FormulaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FormulaError::Parse(e) => write!(f, "{}", e),
+            FormulaError::Storage(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl From<ParseError> for FormulaError {
+    fn from(e: ParseError) -> Self {
+        FormulaError::Parse(e)
+    }
+}
+
+/// Every cell or range a formula on `sheet` references; none if it does not
+/// parse. Unqualified references are on `sheet` itself.
+pub fn references(source: &str, sheet: &str) -> Vec<SheetRange> {
+    parse(source)
+        .map(|expr| {
+            expr.references()
+                .into_iter()
+                .map(|(qualifier, reference)| {
+                    SheetRange::new(qualifier.as_deref().unwrap_or(sheet), reference.range())
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Whether `name` can be given to a defined name: it starts
+/// with a letter or `_`, holds only letters, digits, `_` and `.`, and could
+/// not be read as a cell reference, a boolean or a built-in function.
+pub fn is_valid_name(name: &str) -> bool {
+    let upper = name.to_ascii_uppercase();
+    name.starts_with(|c: char| c.is_alphabetic() || c == '_')
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '_' | '.'))
+        && ast::parse_cell_name(name).is_none()
+        && upper != "TRUE"
+        && upper != "FALSE"
+        && !functions::exists(&upper)
+}
+
+/// Everything a formula reads, as the dependency graph records it.
+#[derive(Debug, Default, PartialEq)]
+pub struct Precedents {
+    /// The cells and ranges it references, itself or through defined names
+    /// and tables.
+    pub ranges: Vec<SheetRange>,
+    /// The defined names and tables it uses, directly or through other
+    /// names, uppercase. Names not defined yet are included, so that defining one
+    /// updates the formula.
+    pub names: Vec<String>,
+    /// Whether it calls a volatile function such as `NOW()` or `RAND()`,
+    /// itself or through a defined name, so its value can change with
+    /// nothing it reads changing.
+    pub volatile: bool,
+}
+
+/// What a formula on `sheet` reads; nothing if it does not parse.
+pub fn precedents(conn: &Connection, source: &str, sheet: &str) -> rusqlite::Result<Precedents> {
+    let mut precedents = Precedents::default();
+    let mut seen = HashSet::new();
+    let mut pending = vec![(source.to_string(), sheet.to_string())];
+    while let Some((source, sheet)) = pending.pop() {
+        let Ok(expr) = parse(&source) else {
+            continue;
+        };
+        precedents.ranges.extend(references(&source, &sheet));
+        for (name, item) in expr.tables() {
+            let Some(table) = crate::table::find(conn, &name)? else {
+                continue;
+            };
+            // Column names are read from the header row, and `@` may pick
+            // any row of the column.
+            precedents
+                .ranges
+                .push(SheetRange::new(&table.sheet, table.headers()));
+            let item = match item {
+                TableItem::ThisRow(column) => TableItem::Columns(column.clone(), column),
+                item => item,
+            };
+            if let Ok(target) = eval::structured(conn, &table, &item, None)? {
+                precedents.ranges.push(target);
+            }
+        }
+        for name in expr.names() {
+            precedents.volatile |= functions::is_volatile(&name);
+            if functions::exists(&name) || !seen.insert((name.clone(), sheet.clone())) {
+                continue;
+            }
+            if let Some(definition) = eval::definition(conn, &name, &sheet)? {
+                let scope = definition.sheet.unwrap_or_else(|| sheet.clone());
+                pending.push((definition.formula, scope));
+            }
+            if !precedents.names.contains(&name) {
+                precedents.names.push(name);
+            }
+        }
+    }
+    Ok(precedents)
+}
+
+/// `source` as it reads when copied `rows` down and `cols` across: relative
+/// references move with it and absolute ones stay. A reference that would
+/// move off the sheet becomes `#REF!`. Formulas that do not parse are
+/// returned unchanged.
+pub fn shift_references(source: &str, rows: i32, cols: i32) -> String {
+    let Ok(expr) = parse(source) else {
+        return source.to_string();
+    };
+    let mut edits = Vec::new();
+    expr.walk(&mut |e| {
+        if let ExprKind::Reference { sheet, reference } = &e.kind {
+            let text = match (reference.shifted(rows, cols), sheet) {
+                (None, _) => "#REF!".to_string(),
+                (Some(shifted), Some(sheet)) => format!("{}!{}", quote_sheet(sheet), shifted),
+                (Some(shifted), None) => shifted.to_string(),
+            };
+            edits.push((e.span, text));
+        }
+    });
+    edits.sort_by_key(|(span, _)| span.start);
+
+    let chars: Vec<char> = source.chars().collect();
+    let mut shifted = String::with_capacity(source.len());
+    let mut pos = 0;
+    for (span, text) in edits {
+        shifted.extend(&chars[pos..span.start]);
+        shifted.push_str(&text);
+        pos = span.end;
+    }
+    shifted.extend(&chars[pos..]);
+    shifted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ast::{BinaryOp, Expr, ExprKind};
+    use super::*;
+    use crate::graph::CellRange;
+
+    /// The rectangles a formula references, ignoring sheets.
+    fn ranges(source: &str) -> Vec<CellRange> {
+        references(source, "s")
+            .into_iter()
+            .map(|r| r.range)
+            .collect()
+    }
+
+    #[test]
+    fn references_are_found_structurally() {
+        // A1 inside A10, text that looks like a reference, and a function
+        // name that is also a valid cell name.
+        let refs = ranges(r#"=A1+A10&"B2"&LOG10(C3)"#);
+        assert_eq!(
+            refs,
+            vec![
+                CellRange::new(0, 0, 0, 0),
+                CellRange::new(9, 0, 9, 0),
+                CellRange::new(2, 2, 2, 2)
+            ]
+        );
+    }
+
+    #[test]
+    fn ranges_parse_to_rectangles() {
+        assert_eq!(ranges("=SUM(B10:A1)"), vec![CellRange::new(0, 0, 9, 1)]);
+        assert_eq!(ranges("=SUM(b:c)"), vec![CellRange::new(0, 1, i32::MAX, 2)]);
+        assert_eq!(ranges("=SUM(3:4)"), vec![CellRange::new(2, 0, 3, i32::MAX)]);
+    }
+
+    #[test]
+    fn references_can_name_another_sheet() {
+        assert_eq!(
+            references("=Data!A1+'My Sheet'!B2:C9+'Bob''s'!3:3+A1", "s"),
+            vec![
+                SheetRange::new("Data", CellRange::new(0, 0, 0, 0)),
+                SheetRange::new("My Sheet", CellRange::new(1, 1, 8, 2)),
+                SheetRange::new("Bob's", CellRange::new(2, 0, 2, i32::MAX)),
+                SheetRange::new("s", CellRange::new(0, 0, 0, 0)),
+            ]
+        );
+        assert_eq!(
+            parse("='My Sheet'A1"),
+            Err(ParseError::new("expected '!' after quoted sheet name", 11))
+        );
+        assert_eq!(
+            parse("=Data!SUM(A1)"),
+            Err(ParseError::new(
+                "expected a cell reference after the sheet name",
+                6
+            ))
+        );
+    }
+
+    #[test]
+    fn absolute_references_parse() {
+        assert_eq!(
+            ranges("=$A$1+$B2+C$3+SUM($A:B)+SUM(2:$4)"),
+            vec![
+                CellRange::new(0, 0, 0, 0),
+                CellRange::new(1, 1, 1, 1),
+                CellRange::new(2, 2, 2, 2),
+                CellRange::new(0, 0, i32::MAX, 1),
+                CellRange::new(1, 0, 3, i32::MAX),
+            ]
+        );
+    }
+
+    #[test]
+    fn copied_formulas_shift_relative_references() {
+        assert_eq!(
+            shift_references("=A1+$A1+A$1+$A$1", 2, 1),
+            "=B3+$A3+B$1+$A$1"
+        );
+        assert_eq!(
+            shift_references("=SUM(A1:B2, 'My Sheet'!C:C, 3:3)", 1, 1),
+            "=SUM(B2:C3, 'My Sheet'!D:D, 4:4)"
+        );
+        // Text that looks like a reference is left alone.
+        assert_eq!(shift_references(r#"=(A1)&"A1""#, 0, 1), r#"=(B1)&"A1""#);
+        assert_eq!(shift_references("=A1*2", -1, 0), "=#REF!*2");
+    }
+
+    #[test]
+    fn parse_errors_carry_a_position() {
+        assert_eq!(
+            parse("=SUM(1,2"),
+            Err(ParseError::new("expected ')', found end of formula", 8))
+        );
+        assert_eq!(parse("=1 + * 2"), Err(ParseError::new("unexpected '*'", 5)));
+        assert_eq!(
+            parse("=\"abc"),
+            Err(ParseError::new("unterminated string", 1))
+        );
+        assert_eq!(
+            parse("=A1:"),
+            Err(ParseError::new("unexpected end of formula", 4))
+        );
+    }
+
+    #[test]
+    fn precedence_follows_excel() {
+        let Ok(Expr {
+            kind: ExprKind::Binary { op, .. },
+            ..
+        }) = parse("=1+2&3")
+        else {
+            panic!("expected a binary expression");
+        };
+        assert_eq!(op, BinaryOp::Concat);
+    }
+
+    #[test]
+    fn array_constants_are_arguments() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        assert_eq!(
+            evaluate("=SUM({1,2;3,4})", "test", &conn),
+            Ok(Value::Number(10.0))
+        );
+        assert_eq!(
+            evaluate("=AVERAGE({2;+4}, {\"x\",-3})", "test", &conn),
+            Ok(Value::Number(1.0))
+        );
+        assert_eq!(
+            evaluate("=SUM({1,#N/A})", "test", &conn),
+            Ok(Value::Error(CellError::NA))
+        );
+
+        assert_eq!(
+            parse("={1,2;3}"),
+            Err(ParseError::new("array rows must be the same length", 7))
+        );
+        assert_eq!(
+            parse("={1,A1}"),
+            Err(ParseError::new(
+                "array constants can only hold numbers, text, TRUE, FALSE and errors",
+                4
+            ))
+        );
+        assert_eq!(parse("=1;2"), Err(ParseError::new("unexpected ';'", 2)));
+    }
+
+    #[test]
+    fn numbers_display_like_excel() {
+        assert_eq!(Value::Number(0.1 + 0.2).display(), "0.3");
+        assert_eq!(Value::Number(-7.5).display(), "-7.5");
+        assert_eq!(Value::Number(1e20).display(), "1E+20");
+        assert_eq!(Value::Number(1.5e-12).display(), "1.5E-12");
+        assert_eq!(Value::Number(123456789012.0).display(), "123456789012");
+        assert_eq!(Value::Number(f64::INFINITY).display(), "#NUM!");
+        assert_eq!(Value::Number(f64::NAN).display(), "#NUM!");
+    }
+
+    #[test]
+    fn error_literals_parse() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        assert_eq!(
+            evaluate("=#REF!+1", "test", &conn),
+            Ok(Value::Error(CellError::Ref))
+        );
+        assert_eq!(
+            evaluate("=#n/a", "test", &conn),
+            Ok(Value::Error(CellError::NA))
+        );
+        assert_eq!(
+            parse("=#OOPS"),
+            Err(ParseError::new("unknown error value", 1))
+        );
+    }
+
+    #[test]
+    fn array_constants_round_trip() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        crate::init_db(&conn);
+        for source in [
+            "{1,2;3,4}",
+            "{1;2;3}",
+            "{\"say \"\"hi\"\"\",TRUE;#N/A,-1.5}",
+        ] {
+            let value = evaluate(&format!("={}", source), "test", &conn).unwrap();
+            assert_eq!(value.display(), source);
+        }
+        // Arrays a formula works out can be typed back in as they are shown.
+        let computed = evaluate("=SEQUENCE(2, 3) / 4", "test", &conn).unwrap();
+        let shown = computed.display();
+        assert_eq!(shown, "{0.25,0.5,0.75;1,1.25,1.5}");
+        assert_eq!(
+            evaluate(&format!("={}", shown), "test", &conn),
+            Ok(computed)
+        );
+    }
+
+    #[test]
+    fn deep_nesting_is_rejected() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        let formula = format!("={}1{}", "(".repeat(100), ")".repeat(100));
+        assert_eq!(evaluate(&formula, "test", &conn), Ok(Value::Number(1.0)));
+        let formula = format!("=0{}", "+1".repeat(1000));
+        assert_eq!(evaluate(&formula, "test", &conn), Ok(Value::Number(1000.0)));
+
+        let formula = format!("={}1{}", "(".repeat(1000), ")".repeat(1000));
+        assert!(parse(&formula).is_err());
+        let formula = format!("=1{}", "+1".repeat(5000));
+        assert!(parse(&formula).is_err());
+    }
+
+    #[test]
+    fn structured_references_parse() {
+        let item = |source: &str| match parse(source).map(|e| e.kind) {
+            Ok(ExprKind::Table { table, item }) => Ok((table, item)),
+            other => Err(other),
+        };
+        let columns = |first: &str, last: &str| TableItem::Columns(first.into(), last.into());
+        assert_eq!(
+            item("=Sales[Amount]"),
+            Ok(("Sales".into(), columns("Amount", "Amount")))
+        );
+        assert_eq!(
+            item("=Sales[Unit Price]"),
+            Ok(("Sales".into(), columns("Unit Price", "Unit Price")))
+        );
+        assert_eq!(
+            item("=Sales[[Qty]:[Price]]"),
+            Ok(("Sales".into(), columns("Qty", "Price")))
+        );
+        assert_eq!(
+            item("=Sales[@Price]"),
+            Ok(("Sales".into(), TableItem::ThisRow("Price".into())))
+        );
+        assert_eq!(
+            item("=Sales[@[Unit Price]]"),
+            Ok(("Sales".into(), TableItem::ThisRow("Unit Price".into())))
+        );
+        assert_eq!(item("=Sales[]"), Ok(("Sales".into(), TableItem::Data)));
+        assert_eq!(item("=Sales[#all]"), Ok(("Sales".into(), TableItem::All)));
+        assert_eq!(
+            item("=Sales[#Headers]"),
+            Ok(("Sales".into(), TableItem::Headers))
+        );
+        assert_eq!(
+            parse("=Sales[#Totals]"),
+            Err(ParseError::new("invalid table reference", 1))
+        );
+        assert_eq!(
+            parse("=1+Sales[Amount"),
+            Err(ParseError::new("unterminated table reference", 8))
+        );
+    }
+
+    #[test]
+    fn traces_each_sub_expression() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        crate::init_db(&conn);
+        conn.execute_batch(
+            "INSERT INTO cells (sheet, row, col, value, value_type) VALUES
+                 ('s', 0, 0, '1', 'number'),
+                 ('s', 1, 0, '0', 'number'),
+                 ('s', 0, 1, '5', 'number');",
+        )
+        .unwrap();
+        let Trace { root, read } =
+            trace("=IF(A1 > 0, 10 / A2, 0) + INDIRECT(\"B\" & 1)", "s", &conn).unwrap();
+        let summary = |step: &Step| (step.expression.clone(), step.value.display());
+        assert_eq!(
+            summary(&root),
+            (
+                "IF(A1 > 0, 10 / A2, 0) + INDIRECT(\"B\" & 1)".into(),
+                "#DIV/0!".into()
+            )
+        );
+        let [condition, division] = &root.steps[0].steps[..] else {
+            panic!("{:?}", root.steps[0]);
+        };
+        assert_eq!(summary(condition), ("A1 > 0".into(), "TRUE".into()));
+        assert_eq!(
+            condition.steps[0].reference,
+            Some(SheetRange::new("s", CellRange::new(0, 0, 0, 0)))
+        );
+        assert_eq!(summary(&division.steps[1]), ("A2".into(), "0".into()));
+
+        // The error appeared in the division and passed up from there.
+        let origin = root.first_error().unwrap();
+        assert_eq!(summary(origin), ("10 / A2".into(), "#DIV/0!".into()));
+        assert_eq!(origin.span, Span::new(12, 19));
+
+        let indirect = &root.steps[1];
+        assert_eq!(
+            summary(indirect),
+            ("INDIRECT(\"B\" & 1)".into(), "5".into())
+        );
+        assert_eq!(
+            indirect.reference,
+            Some(SheetRange::new("s", CellRange::new(0, 1, 0, 1)))
+        );
+        assert_eq!(
+            summary(&indirect.steps[0]),
+            ("\"B\" & 1".into(), "B1".into())
+        );
+        assert_eq!(
+            read,
+            [(0, 0), (1, 0), (0, 1)]
+                .map(|(row, col)| SheetRange::new("s", CellRange::new(row, col, row, col)))
+        );
+
+        // Lambda bodies are not stepped through, and errors that are caught
+        // still show where they appeared.
+        let root = trace(
+            "=LET(f, LAMBDA(x, x * 2), IFERROR(f(1/0), f(3)))",
+            "s",
+            &conn,
+        )
+        .unwrap()
+        .root;
+        assert_eq!(root.value, Value::Number(6.0));
+        assert_eq!(root.first_error().unwrap().expression, "1/0");
+
+        let root = trace(&format!("=0{}", "+1".repeat(1000)), "s", &conn)
+            .unwrap()
+            .root;
+        assert_eq!(root.value, Value::Number(1000.0));
+    }
+}
@@ -0,0 +1,559 @@
+//! Recursive-descent parser from tokens to [`Expr`].
+//!
+//! Operator precedence follows Excel, from loosest to tightest: comparisons,
+//! `&`, `+ -`, `* /`, `^`, unary `+ -`, postfix `%`, and finally the `:` of
+//! a range. So `-2^2` is 4 and `1+2&3` is `"33"`.
+
+use super::ParseError;
+use super::ast::*;
+use super::lexer::{Token, TokenKind, tokenize};
+use super::value::{Array, Value};
+
+/// Nesting budget for one formula. Evaluation recurses once per tree level,
+/// so this bounds the stack both parsing and evaluation need.
+const MAX_DEPTH: usize = 1024;
+
+/// Budget spent by a parenthesised expression or function argument, which
+/// re-enters every precedence level of the parser.
+const GROUP_DEPTH: usize = 8;
+
+pub fn parse(source: &str) -> Result<Expr, ParseError> {
+    let tokens = tokenize(source)?;
+    let mut parser = Parser {
+        tokens,
+        pos: 0,
+        end: source.chars().count(),
+        depth: 0,
+    };
+    let expr = parser.expression()?;
+    match parser.tokens.get(parser.pos) {
+        Some(token) => Err(ParseError::new(
+            format!("unexpected {}", describe(&token.kind)),
+            token.span.start,
+        )),
+        None => Ok(expr),
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    /// Offset reported for errors at the end of the input.
+    end: usize,
+    depth: usize,
+}
+
+type Level = fn(&mut Parser) -> Result<Expr, ParseError>;
+
+impl Parser {
+    fn peek(&self) -> Option<&TokenKind> {
+        self.tokens.get(self.pos).map(|t| &t.kind)
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<&TokenKind> {
+        self.tokens.get(self.pos + offset).map(|t| &t.kind)
+    }
+
+    fn position(&self) -> usize {
+        self.tokens.get(self.pos).map_or(self.end, |t| t.span.start)
+    }
+
+    fn advance(&mut self) -> Result<Token, ParseError> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .cloned()
+            .ok_or_else(|| ParseError::new("unexpected end of formula", self.end))?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn expect(&mut self, kind: TokenKind) -> Result<Span, ParseError> {
+        match self.tokens.get(self.pos) {
+            Some(token) if token.kind == kind => {
+                self.pos += 1;
+                Ok(token.span)
+            }
+            Some(token) => Err(ParseError::new(
+                format!(
+                    "expected {}, found {}",
+                    describe(&kind),
+                    describe(&token.kind)
+                ),
+                token.span.start,
+            )),
+            None => Err(ParseError::new(
+                format!("expected {}, found end of formula", describe(&kind)),
+                self.end,
+            )),
+        }
+    }
+
+    fn check_depth(&self, extra: usize) -> Result<(), ParseError> {
+        if self.depth + extra > MAX_DEPTH {
+            return Err(ParseError::new(
+                "formula is nested too deeply",
+                self.position(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Runs `f` `extra` nesting levels deeper, failing if that is too deep.
+    fn nested<T>(
+        &mut self,
+        extra: usize,
+        f: impl FnOnce(&mut Self) -> Result<T, ParseError>,
+    ) -> Result<T, ParseError> {
+        self.check_depth(extra)?;
+        self.depth += extra;
+        let result = f(self);
+        self.depth -= extra;
+        result
+    }
+
+    fn expression(&mut self) -> Result<Expr, ParseError> {
+        self.comparison()
+    }
+
+    /// A left-associative chain of `next`-level operands joined by the
+    /// operators `op` recognises.
+    fn binary(
+        &mut self,
+        next: Level,
+        op: fn(&TokenKind) -> Option<BinaryOp>,
+    ) -> Result<Expr, ParseError> {
+        let mut left = next(self)?;
+        let mut chain = 0;
+        while let Some(op) = self.peek().and_then(op) {
+            self.pos += 1;
+            // Each link deepens the left spine of the tree by one.
+            chain += 1;
+            let right = self.nested(chain, next)?;
+            let span = left.span.to(right.span);
+            left = Expr {
+                kind: ExprKind::Binary {
+                    op,
+                    left: Box::new(left),
+                    right: Box::new(right),
+                },
+                span,
+            };
+        }
+        Ok(left)
+    }
+
+    fn comparison(&mut self) -> Result<Expr, ParseError> {
+        self.binary(Parser::concatenation, |kind| match kind {
+            TokenKind::Eq => Some(BinaryOp::Eq),
+            TokenKind::Ne => Some(BinaryOp::Ne),
+            TokenKind::Lt => Some(BinaryOp::Lt),
+            TokenKind::Le => Some(BinaryOp::Le),
+            TokenKind::Gt => Some(BinaryOp::Gt),
+            TokenKind::Ge => Some(BinaryOp::Ge),
+            _ => None,
+        })
+    }
+
+    fn concatenation(&mut self) -> Result<Expr, ParseError> {
+        self.binary(Parser::additive, |kind| match kind {
+            TokenKind::Ampersand => Some(BinaryOp::Concat),
+            _ => None,
+        })
+    }
+
+    fn additive(&mut self) -> Result<Expr, ParseError> {
+        self.binary(Parser::multiplicative, |kind| match kind {
+            TokenKind::Plus => Some(BinaryOp::Add),
+            TokenKind::Minus => Some(BinaryOp::Sub),
+            _ => None,
+        })
+    }
+
+    fn multiplicative(&mut self) -> Result<Expr, ParseError> {
+        self.binary(Parser::power, |kind| match kind {
+            TokenKind::Star => Some(BinaryOp::Mul),
+            TokenKind::Slash => Some(BinaryOp::Div),
+            _ => None,
+        })
+    }
+
+    fn power(&mut self) -> Result<Expr, ParseError> {
+        self.binary(Parser::unary, |kind| match kind {
+            TokenKind::Caret => Some(BinaryOp::Pow),
+            _ => None,
+        })
+    }
+
+    fn unary(&mut self) -> Result<Expr, ParseError> {
+        let op = match self.peek() {
+            Some(TokenKind::Minus) => UnaryOp::Minus,
+            Some(TokenKind::Plus) => UnaryOp::Plus,
+            _ => return self.postfix(),
+        };
+        let start = self.advance()?.span;
+        let operand = self.nested(1, Parser::unary)?;
+        let span = start.to(operand.span);
+        Ok(Expr {
+            kind: ExprKind::Unary {
+                op,
+                operand: Box::new(operand),
+            },
+            span,
+        })
+    }
+
+    fn postfix(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.primary()?;
+        let mut chain = 0;
+        while self.peek() == Some(&TokenKind::Percent) {
+            chain += 1;
+            self.check_depth(chain)?;
+            let span = expr.span.to(self.advance()?.span);
+            expr = Expr {
+                kind: ExprKind::Unary {
+                    op: UnaryOp::Percent,
+                    operand: Box::new(expr),
+                },
+                span,
+            };
+        }
+        Ok(expr)
+    }
+
+    fn primary(&mut self) -> Result<Expr, ParseError> {
+        let token = self.advance()?;
+        let span = token.span;
+        let kind = match token.kind {
+            TokenKind::Number(n) => {
+                if self.peek() == Some(&TokenKind::Colon) {
+                    let row = row_index(n)
+                        .ok_or_else(|| ParseError::new("invalid row range", span.start))?;
+                    return self.row_range(row, span);
+                }
+                ExprKind::Number(n)
+            }
+            TokenKind::Text(text) => ExprKind::Text(text),
+            TokenKind::LBrace => return self.array(span),
+            TokenKind::Error(error) => ExprKind::Error(error),
+            TokenKind::LParen => {
+                let inner = self.nested(GROUP_DEPTH, Parser::expression)?;
+                let close = self.expect(TokenKind::RParen)?;
+                // A reference keeps the span of its own text, which is what
+                // gets rewritten when the formula is copied.
+                if let ExprKind::Reference { .. } = inner.kind {
+                    return Ok(inner);
+                }
+                return Ok(Expr {
+                    kind: inner.kind,
+                    span: span.to(close),
+                });
+            }
+            TokenKind::Ident(name) => return self.identifier(name, span),
+            TokenKind::Sheet(sheet) => return self.qualified(sheet, span),
+            TokenKind::Table(table, spec) => ExprKind::Table {
+                table,
+                item: table_item(&spec)
+                    .ok_or_else(|| ParseError::new("invalid table reference", span.start))?,
+            },
+            other => {
+                return Err(ParseError::new(
+                    format!("unexpected {}", describe(&other)),
+                    span.start,
+                ));
+            }
+        };
+        Ok(Expr { kind, span })
+    }
+
+    fn identifier(&mut self, name: String, span: Span) -> Result<Expr, ParseError> {
+        if self.peek() == Some(&TokenKind::LParen) {
+            return self.call(name, span);
+        }
+        if name.eq_ignore_ascii_case("TRUE") || name.eq_ignore_ascii_case("FALSE") {
+            return Ok(Expr {
+                kind: ExprKind::Bool(name.eq_ignore_ascii_case("TRUE")),
+                span,
+            });
+        }
+
+        if let Some(cell) = parse_cell_name(&name) {
+            if self.peek() != Some(&TokenKind::Colon) {
+                return Ok(reference(Reference::Cell(cell), span));
+            }
+            self.pos += 1;
+            let end = self.advance()?;
+            let other = match &end.kind {
+                TokenKind::Ident(other) => parse_cell_name(other),
+                _ => None,
+            };
+            return match other {
+                Some(other) => Ok(reference(Reference::Range(cell, other), span.to(end.span))),
+                None => Err(ParseError::new(
+                    "expected a cell reference after ':'",
+                    end.span.start,
+                )),
+            };
+        }
+
+        if let Some(col) = parse_column_name(&name)
+            && self.peek() == Some(&TokenKind::Colon)
+            && let Some(TokenKind::Ident(other)) = self.peek_at(1)
+            && let Some(other) = parse_column_name(other)
+        {
+            self.pos += 1;
+            let end = self.advance()?.span;
+            return Ok(reference(Reference::Columns(col, other), span.to(end)));
+        }
+
+        if let Some(row) = parse_row_name(&name)
+            && self.peek() == Some(&TokenKind::Colon)
+        {
+            return self.row_range(row, span);
+        }
+
+        Ok(Expr {
+            kind: ExprKind::Name(name),
+            span,
+        })
+    }
+
+    /// `3:5`, having just consumed the `3`.
+    fn row_range(&mut self, first: LineRef, span: Span) -> Result<Expr, ParseError> {
+        self.pos += 1;
+        let end = self.advance()?;
+        let last = match &end.kind {
+            TokenKind::Number(last) => row_index(*last),
+            TokenKind::Ident(last) => parse_row_name(last),
+            _ => None,
+        };
+        match last {
+            Some(last) => Ok(reference(Reference::Rows(first, last), span.to(end.span))),
+            None => Err(ParseError::new("invalid row range", span.start)),
+        }
+    }
+
+    /// `{1,2;3,4}`, having just consumed the `{`: rows of constants split by
+    /// `;`, each the same length, with their values split by `,`.
+    fn array(&mut self, open: Span) -> Result<Expr, ParseError> {
+        let mut values = Vec::new();
+        let mut cols = None;
+        let mut row = 0;
+        loop {
+            values.push(self.array_value()?);
+            row += 1;
+            let token = self.advance()?;
+            let row_ends = matches!(token.kind, TokenKind::Semicolon | TokenKind::RBrace);
+            if row_ends && cols.is_some_and(|cols| cols != row) {
+                return Err(ParseError::new(
+                    "array rows must be the same length",
+                    token.span.start,
+                ));
+            }
+            match token.kind {
+                TokenKind::Comma => {}
+                TokenKind::Semicolon => {
+                    cols = Some(row);
+                    row = 0;
+                }
+                TokenKind::RBrace => {
+                    let cols = cols.unwrap_or(row);
+                    return Ok(Expr {
+                        kind: ExprKind::Array(Array::new(values.len() / cols, cols, values)),
+                        span: open.to(token.span),
+                    });
+                }
+                other => {
+                    return Err(ParseError::new(
+                        format!("expected ',', ';' or '}}', found {}", describe(&other)),
+                        token.span.start,
+                    ));
+                }
+            }
+        }
+    }
+
+    /// One value of an array constant: a number, possibly signed, text,
+    /// `TRUE`/`FALSE` or an error.
+    fn array_value(&mut self) -> Result<Value, ParseError> {
+        let start = self.position();
+        let sign = match self.peek() {
+            Some(TokenKind::Minus) => Some(-1.0),
+            Some(TokenKind::Plus) => Some(1.0),
+            _ => None,
+        };
+        if sign.is_some() {
+            self.pos += 1;
+        }
+        let value = match (sign, self.advance()?.kind) {
+            (sign, TokenKind::Number(n)) => Some(Value::Number(sign.unwrap_or(1.0) * n)),
+            (None, TokenKind::Text(text)) => Some(Value::Text(text)),
+            (None, TokenKind::Error(error)) => Some(Value::Error(error)),
+            (None, TokenKind::Ident(name)) => match name.to_ascii_uppercase().as_str() {
+                "TRUE" => Some(Value::Bool(true)),
+                "FALSE" => Some(Value::Bool(false)),
+                _ => None,
+            },
+            _ => None,
+        };
+        value.ok_or_else(|| {
+            ParseError::new(
+                "array constants can only hold numbers, text, TRUE, FALSE and errors",
+                start,
+            )
+        })
+    }
+
+    /// `Sheet2!A1` or `'My Sheet'!B2:C9`, having just consumed the qualifier.
+    fn qualified(&mut self, sheet: String, span: Span) -> Result<Expr, ParseError> {
+        let start = self.position();
+        let token = self.advance()?;
+        let expr = match token.kind {
+            TokenKind::Ident(name) if self.peek() != Some(&TokenKind::LParen) => {
+                Some(self.identifier(name, token.span)?)
+            }
+            TokenKind::Number(n) if self.peek() == Some(&TokenKind::Colon) => match row_index(n) {
+                Some(row) => Some(self.row_range(row, token.span)?),
+                None => None,
+            },
+            _ => None,
+        };
+        match expr {
+            Some(Expr {
+                kind: ExprKind::Reference { reference, .. },
+                span: end,
+            }) => Ok(Expr {
+                kind: ExprKind::Reference {
+                    sheet: Some(sheet),
+                    reference,
+                },
+                span: span.to(end),
+            }),
+            _ => Err(ParseError::new(
+                "expected a cell reference after the sheet name",
+                start,
+            )),
+        }
+    }
+
+    /// `NAME(arg, ...)`, having just consumed the name.
+    fn call(&mut self, name: String, span: Span) -> Result<Expr, ParseError> {
+        self.expect(TokenKind::LParen)?;
+        let mut args = Vec::new();
+        if self.peek() != Some(&TokenKind::RParen) {
+            loop {
+                // An argument left out, as in `IF(A1,,1)`.
+                if matches!(self.peek(), Some(TokenKind::Comma | TokenKind::RParen)) {
+                    let at = self.position();
+                    args.push(Expr {
+                        kind: ExprKind::Missing,
+                        span: Span::new(at, at),
+                    });
+                } else {
+                    args.push(self.nested(GROUP_DEPTH, Parser::expression)?);
+                }
+                if self.peek() == Some(&TokenKind::Comma) {
+                    self.pos += 1;
+                } else {
+                    break;
+                }
+            }
+        }
+        let close = self.expect(TokenKind::RParen)?;
+        Ok(Expr {
+            kind: ExprKind::Call {
+                name: name.to_ascii_uppercase(),
+                args,
+            },
+            span: span.to(close),
+        })
+    }
+}
+
+/// An unqualified reference expression.
+fn reference(reference: Reference, span: Span) -> Expr {
+    Expr {
+        kind: ExprKind::Reference {
+            sheet: None,
+            reference,
+        },
+        span,
+    }
+}
+
+/// A row number lexed as a number token, as in the `3` of `3:5`.
+fn row_index(n: f64) -> Option<LineRef> {
+    (n.fract() == 0.0 && n >= 1.0 && n <= MAX_ROWS as f64).then_some(LineRef {
+        index: n as i32 - 1,
+        absolute: false,
+    })
+}
+
+/// What the brackets of a structured reference select: `#All`, `#Data`
+/// or `#Headers`, a column, `[First]:[Last]` columns, or `@` and a column
+/// for the formula's own row. Empty brackets select the data.
+fn table_item(spec: &str) -> Option<TableItem> {
+    let spec = spec.trim();
+    if spec.is_empty() {
+        return Some(TableItem::Data);
+    }
+    if let Some(special) = spec.strip_prefix('#') {
+        return match special.to_ascii_uppercase().as_str() {
+            "ALL" => Some(TableItem::All),
+            "DATA" => Some(TableItem::Data),
+            "HEADERS" => Some(TableItem::Headers),
+            _ => None,
+        };
+    }
+    if let Some(name) = spec.strip_prefix('@') {
+        return column(name).map(TableItem::ThisRow);
+    }
+    if spec.starts_with('[')
+        && let Some((first, last)) = spec.split_once(':')
+    {
+        return Some(TableItem::Columns(column(first)?, column(last)?));
+    }
+    let name = column(spec)?;
+    Some(TableItem::Columns(name.clone(), name))
+}
+
+/// A column name in a structured reference, bare or in brackets.
+fn column(text: &str) -> Option<String> {
+    let text = text.trim();
+    let name = text
+        .strip_prefix('[')
+        .and_then(|rest| rest.strip_suffix(']'))
+        .unwrap_or(text);
+    (!name.is_empty() && !name.contains(['[', ']'])).then(|| name.to_string())
+}
+
+fn describe(kind: &TokenKind) -> String {
+    match kind {
+        TokenKind::Number(n) => format!("number {}", n),
+        TokenKind::Text(_) => "text".to_string(),
+        TokenKind::Error(error) => error.code().to_string(),
+        TokenKind::Ident(name) => format!("'{}'", name),
+        TokenKind::Sheet(name) => format!("sheet name '{}'", name),
+        TokenKind::Table(table, spec) => format!("'{}[{}]'", table, spec),
+        TokenKind::LParen => "'('".to_string(),
+        TokenKind::RParen => "')'".to_string(),
+        TokenKind::LBrace => "'{'".to_string(),
+        TokenKind::RBrace => "'}'".to_string(),
+        TokenKind::Comma => "','".to_string(),
+        TokenKind::Semicolon => "';'".to_string(),
+        TokenKind::Colon => "':'".to_string(),
+        TokenKind::Plus => "'+'".to_string(),
+        TokenKind::Minus => "'-'".to_string(),
+        TokenKind::Star => "'*'".to_string(),
+        TokenKind::Slash => "'/'".to_string(),
+        TokenKind::Caret => "'^'".to_string(),
+        TokenKind::Ampersand => "'&'".to_string(),
+        TokenKind::Percent => "'%'".to_string(),
+        TokenKind::Eq => "'='".to_string(),
+        TokenKind::Ne => "'<>'".to_string(),
+        TokenKind::Lt => "'<'".to_string(),
+        TokenKind::Le => "'<='".to_string(),
+        TokenKind::Gt => "'>'".to_string(),
+        TokenKind::Ge => "'>='".to_string(),
+    }
+}
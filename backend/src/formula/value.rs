@@ -0,0 +1,348 @@
+//! Values formulas produce and read back from cells.
+
+use super::date;
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// An Excel error value. Errors are ordinary values: they are stored in
+/// cells and flow through the formulas that read them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CellError {
+    /// `#NULL!`
+    Null,
+    /// `#DIV/0!`
+    Div0,
+    /// `#VALUE!`, an argument of the wrong type.
+    Value,
+    /// `#REF!`, a reference to a cell that does not exist.
+    Ref,
+    /// `#NAME?`, an unknown function or name.
+    Name,
+    /// `#NUM!`, a number out of a function's domain.
+    Num,
+    /// `#N/A`, a value that is not available, such as a failed lookup.
+    NA,
+    /// `#CIRC!`, a cell in a reference loop.
+    Circular,
+    /// `#SPILL!`, an array result blocked by cells that are not empty.
+    Spill,
+    /// `#CALC!`, an array with nothing in it, such as a FILTER that keeps
+    /// no rows.
+    Calc,
+}
+
+impl CellError {
+    const ALL: [CellError; 10] = [
+        CellError::Null,
+        CellError::Div0,
+        CellError::Value,
+        CellError::Ref,
+        CellError::Name,
+        CellError::Num,
+        CellError::NA,
+        CellError::Circular,
+        CellError::Spill,
+        CellError::Calc,
+    ];
+
+    pub fn code(self) -> &'static str {
+        match self {
+            CellError::Null => "#NULL!",
+            CellError::Div0 => "#DIV/0!",
+            CellError::Value => "#VALUE!",
+            CellError::Ref => "#REF!",
+            CellError::Name => "#NAME?",
+            CellError::Num => "#NUM!",
+            CellError::NA => "#N/A",
+            CellError::Circular => "#CIRC!",
+            CellError::Spill => "#SPILL!",
+            CellError::Calc => "#CALC!",
+        }
+    }
+
+    /// The error written as `code`, case-insensitive.
+    pub fn from_code(code: &str) -> Option<CellError> {
+        CellError::ALL
+            .into_iter()
+            .find(|e| e.code().eq_ignore_ascii_case(code))
+    }
+
+    /// Every error code, for the lexer to recognise.
+    pub fn codes() -> impl Iterator<Item = &'static str> {
+        CellError::ALL.into_iter().map(CellError::code)
+    }
+}
+
+impl fmt::Display for CellError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+/// The type of a cell's value, as stored in the `value_type` column and
+/// reported in the JSON.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ValueType {
+    Number,
+    Text,
+    Boolean,
+    /// A number shown as a date or time.
+    Date,
+    Empty,
+    Error,
+}
+
+impl ValueType {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ValueType::Number => "number",
+            ValueType::Text => "text",
+            ValueType::Boolean => "boolean",
+            ValueType::Date => "date",
+            ValueType::Empty => "empty",
+            ValueType::Error => "error",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<ValueType> {
+        match name {
+            "number" => Some(ValueType::Number),
+            "text" => Some(ValueType::Text),
+            "boolean" => Some(ValueType::Boolean),
+            "date" => Some(ValueType::Date),
+            "empty" => Some(ValueType::Empty),
+            "error" => Some(ValueType::Error),
+            _ => None,
+        }
+    }
+}
+
+impl ToSql for ValueType {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+impl FromSql for ValueType {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        let name = value.as_str()?;
+        ValueType::parse(name).ok_or_else(|| FromSqlError::Other(name.into()))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Empty,
+    Number(f64),
+    Text(String),
+    Bool(bool),
+    /// A date serial number as a cell holds it, to be shown as a date.
+    /// Formulas only ever see the plain number: cells read back as
+    /// [`Value::Number`], and only a formula's final result is marked.
+    Date(f64),
+    Error(CellError),
+    /// The cells of a range, or an array computed from them.
+    Array(Array),
+}
+
+/// A rectangular block of values, stored row by row.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Array {
+    pub rows: usize,
+    pub cols: usize,
+    pub values: Vec<Value>,
+}
+
+impl Array {
+    pub fn new(rows: usize, cols: usize, values: Vec<Value>) -> Self {
+        debug_assert_eq!(rows * cols, values.len());
+        Array { rows, cols, values }
+    }
+
+    /// The value at `row` and `col`, counting from 0.
+    pub fn get(&self, row: usize, col: usize) -> &Value {
+        &self.values[row * self.cols + col]
+    }
+
+    /// The array written as an array constant, as in `{1,2;"a",TRUE}`, so
+    /// it can be typed back into a formula. Dates are written as their
+    /// serial numbers, since array constants cannot hold them.
+    fn display(&self) -> String {
+        let rows: Vec<String> = self
+            .values
+            .chunks(self.cols.max(1))
+            .map(|row| {
+                row.iter()
+                    .map(|value| match value {
+                        Value::Text(text) => format!("\"{}\"", text.replace('"', "\"\"")),
+                        Value::Date(n) => format_number(*n),
+                        value => value.display(),
+                    })
+                    .collect::<Vec<_>>()
+                    .join(",")
+            })
+            .collect();
+        format!("{{{}}}", rows.join(";"))
+    }
+}
+
+impl Value {
+    pub fn value_type(&self) -> ValueType {
+        match self {
+            Value::Empty => ValueType::Empty,
+            Value::Number(_) => ValueType::Number,
+            Value::Text(_) => ValueType::Text,
+            Value::Bool(_) => ValueType::Boolean,
+            Value::Date(_) => ValueType::Date,
+            Value::Error(_) | Value::Array(_) => ValueType::Error,
+        }
+    }
+
+    /// The value as shown in a cell.
+    pub fn display(&self) -> String {
+        match self {
+            Value::Empty => String::new(),
+            Value::Number(n) => format_number(*n),
+            Value::Text(text) => text.clone(),
+            Value::Bool(b) => if *b { "TRUE" } else { "FALSE" }.to_string(),
+            Value::Date(serial) => date::display(*serial),
+            Value::Error(e) => e.code().to_string(),
+            Value::Array(array) => array.display(),
+        }
+    }
+
+    /// A value read back from the `cells` table. Numbers and dates come
+    /// from `number`, which holds them exactly, where it is set; rows written
+    /// before it was stored fall back on the text, and rows written before
+    /// types were stored are typed the way constant input is.
+    pub fn from_stored(
+        text: Option<&str>,
+        value_type: Option<ValueType>,
+        number: Option<f64>,
+    ) -> Value {
+        let text = text.unwrap_or_default();
+        match value_type {
+            None => Value::from_input(text),
+            Some(ValueType::Empty) => Value::Empty,
+            Some(ValueType::Text) => Value::Text(text.to_string()),
+            Some(ValueType::Number) => number
+                .or_else(|| text.parse().ok())
+                .map_or(Value::Error(CellError::Value), Value::Number),
+            Some(ValueType::Boolean) => Value::Bool(text.eq_ignore_ascii_case("TRUE")),
+            Some(ValueType::Date) => number
+                .or_else(|| date::parse(text))
+                .map_or(Value::Error(CellError::Value), Value::Number),
+            Some(ValueType::Error) => {
+                Value::Error(CellError::from_code(text).unwrap_or(CellError::Value))
+            }
+        }
+    }
+
+    /// The value of a constant typed into a cell: a number, a date or time,
+    /// `TRUE`/`FALSE`, an error code, or otherwise text.
+    pub fn from_input(text: &str) -> Value {
+        if text.is_empty() {
+            return Value::Empty;
+        }
+        if let Some(n) = parse_number(text) {
+            return Value::Number(n);
+        }
+        if let Some(serial) = date::parse(text) {
+            return Value::Date(serial);
+        }
+        if text.eq_ignore_ascii_case("TRUE") || text.eq_ignore_ascii_case("FALSE") {
+            return Value::Bool(text.eq_ignore_ascii_case("TRUE"));
+        }
+        if let Some(e) = CellError::from_code(text) {
+            return Value::Error(e);
+        }
+        Value::Text(text.to_string())
+    }
+
+    /// The number this value stands for in arithmetic. Blank is 0, booleans
+    /// are 0 or 1, and text must read as a number or a date.
+    pub fn to_number(&self) -> Result<f64, CellError> {
+        match self {
+            Value::Empty => Ok(0.0),
+            Value::Number(n) | Value::Date(n) => Ok(*n),
+            Value::Bool(b) => Ok(if *b { 1.0 } else { 0.0 }),
+            Value::Text(text) => parse_number(text)
+                .or_else(|| date::parse(text))
+                .ok_or(CellError::Value),
+            Value::Error(e) => Err(*e),
+            Value::Array(_) => Err(CellError::Value),
+        }
+    }
+
+    /// The truth value this value stands for in a condition. Blank is
+    /// FALSE, numbers are TRUE unless 0, and text must read `TRUE` or
+    /// `FALSE`.
+    pub fn to_bool(&self) -> Result<bool, CellError> {
+        match self {
+            Value::Empty => Ok(false),
+            Value::Number(n) | Value::Date(n) => Ok(*n != 0.0),
+            Value::Bool(b) => Ok(*b),
+            Value::Text(text) if text.eq_ignore_ascii_case("TRUE") => Ok(true),
+            Value::Text(text) if text.eq_ignore_ascii_case("FALSE") => Ok(false),
+            Value::Text(_) | Value::Array(_) => Err(CellError::Value),
+            Value::Error(e) => Err(*e),
+        }
+    }
+
+    /// The text this value stands for in concatenation. Dates join as their
+    /// serial numbers, as in Excel.
+    pub fn to_text(&self) -> Result<String, CellError> {
+        match self {
+            Value::Error(e) => Err(*e),
+            Value::Array(_) => Err(CellError::Value),
+            Value::Date(n) => Ok(format_number(*n)),
+            value => Ok(value.display()),
+        }
+    }
+}
+
+/// Text that reads as a finite number, ignoring surrounding spaces.
+pub fn parse_number(text: &str) -> Option<f64> {
+    let text = text.trim();
+    // Rust also reads "inf" and "NaN", which are not numbers to Excel.
+    if !text.starts_with(|c: char| c.is_ascii_digit() || matches!(c, '.' | '-' | '+')) {
+        return None;
+    }
+    text.parse().ok().filter(|n: &f64| n.is_finite())
+}
+
+/// A number as Excel's General format shows it: up to 15 significant
+/// digits, switching to scientific notation for very large or small values.
+/// Infinities and NaN, which Excel cannot hold, show as `#NUM!`.
+pub fn format_number(n: f64) -> String {
+    if !n.is_finite() {
+        return CellError::Num.code().to_string();
+    }
+    if n == 0.0 {
+        return "0".to_string();
+    }
+    let magnitude = n.abs().log10().floor() as i32;
+    if !(-9..15).contains(&magnitude) {
+        let formatted = format!("{:.14e}", n);
+        let (mantissa, exponent) = formatted.split_once('e').unwrap();
+        let exponent: i32 = exponent.parse().unwrap();
+        return format!(
+            "{}E{}{:02}",
+            trim_fraction(mantissa),
+            if exponent < 0 { '-' } else { '+' },
+            exponent.abs()
+        );
+    }
+    let decimals = (14 - magnitude).max(0) as usize;
+    trim_fraction(&format!("{:.*}", decimals, n)).to_string()
+}
+
+fn trim_fraction(number: &str) -> &str {
+    if number.contains('.') {
+        number.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        number
+    }
+}
@@ -0,0 +1,855 @@
+//! Formula dependency graph.
+//!
+//! Every formula cell records what it references, on its own sheet or any
+//! other. Single-cell references go to the `cell_dependencies` table, one row
+//! per (formula cell, referenced cell) edge, so a lookup of "who depends on
+//! A1" is a single indexed query. Multi-cell ranges go to
+//! `range_dependencies` as one row per rectangle rather than one per cell, so
+//! `A:A` costs the same as `A1`; finding the ranges that contain a cell scans
+//! the ranges on that cell's sheet, which are far fewer than its single
+//! references. References made through a defined name count as the formula's
+//! own, and `name_dependencies` records the names each formula uses, so it
+//! can be recalculated when a name is redefined. `volatile_cells` lists the
+//! formulas that call a volatile function such as `NOW()` and so are
+//! recalculated on every pass. All four tables are derived entirely from the
+//! stored formulas and names, and are rebuilt from them whenever the
+//! database is opened.
+//!
+//! References built while evaluating, by `INDIRECT("B" & A1)` or `OFFSET`,
+//! cannot be read from the formula. `dynamic_dependencies` holds the ranges
+//! each formula reached on its last evaluation, replaced every time it is
+//! evaluated again. It is kept across restarts, less the rows of cells that
+//! no longer hold formulas.
+
+use rusqlite::{Connection, params};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct CellKey {
+    pub sheet: String,
+    pub row: i32,
+    pub col: i32,
+}
+
+impl std::fmt::Display for CellKey {
+    /// `Sheet!A1`, quoting sheet names that are not plain identifiers.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}!", crate::formula::quote_sheet(&self.sheet))?;
+        write!(
+            f,
+            "{}{}",
+            crate::formula::column_name(self.col),
+            self.row + 1
+        )
+    }
+}
+
+impl CellKey {
+    pub fn new(sheet: &str, row: i32, col: i32) -> Self {
+        CellKey {
+            sheet: sheet.to_string(),
+            row,
+            col,
+        }
+    }
+}
+
+/// A rectangle of cells, inclusive on both ends. Whole columns and rows
+/// extend to `i32::MAX`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct CellRange {
+    pub start_row: i32,
+    pub start_col: i32,
+    pub end_row: i32,
+    pub end_col: i32,
+}
+
+impl CellRange {
+    /// The range spanning both corners, in whichever order they were given.
+    pub fn new(row1: i32, col1: i32, row2: i32, col2: i32) -> Self {
+        CellRange {
+            start_row: row1.min(row2),
+            start_col: col1.min(col2),
+            end_row: row1.max(row2),
+            end_col: col1.max(col2),
+        }
+    }
+
+    pub fn is_single(&self) -> bool {
+        self.start_row == self.end_row && self.start_col == self.end_col
+    }
+
+    pub fn rows(&self) -> i32 {
+        self.end_row - self.start_row + 1
+    }
+
+    pub fn cols(&self) -> i32 {
+        self.end_col - self.start_col + 1
+    }
+}
+
+/// A [`CellRange`] on a particular sheet.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SheetRange {
+    pub sheet: String,
+    pub range: CellRange,
+}
+
+impl SheetRange {
+    pub fn new(sheet: &str, range: CellRange) -> Self {
+        SheetRange {
+            sheet: sheet.to_string(),
+            range,
+        }
+    }
+}
+
+impl std::fmt::Display for SheetRange {
+    /// `Sheet!A1:B2`, or `Sheet!A:B` and `Sheet!3:5` for whole columns and
+    /// rows.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use crate::formula::column_name;
+        let range = &self.range;
+        write!(f, "{}!", crate::formula::quote_sheet(&self.sheet))?;
+        if range.end_row == i32::MAX {
+            write!(
+                f,
+                "{}:{}",
+                column_name(range.start_col),
+                column_name(range.end_col)
+            )
+        } else if range.end_col == i32::MAX {
+            write!(f, "{}:{}", range.start_row + 1, range.end_row + 1)
+        } else if range.is_single() {
+            write!(f, "{}{}", column_name(range.start_col), range.start_row + 1)
+        } else {
+            write!(
+                f,
+                "{}{}:{}{}",
+                column_name(range.start_col),
+                range.start_row + 1,
+                column_name(range.end_col),
+                range.end_row + 1
+            )
+        }
+    }
+}
+
+/// Recreates the dependency tables and fills them from every stored formula.
+pub fn rebuild(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute("DROP TABLE IF EXISTS cell_dependencies", [])?;
+    conn.execute("DROP TABLE IF EXISTS range_dependencies", [])?;
+    conn.execute("DROP TABLE IF EXISTS name_dependencies", [])?;
+    conn.execute("DROP TABLE IF EXISTS volatile_cells", [])?;
+    conn.execute(
+        "CREATE TABLE cell_dependencies (
+            sheet TEXT NOT NULL,
+            row INTEGER NOT NULL,
+            col INTEGER NOT NULL,
+            ref_sheet TEXT NOT NULL,
+            ref_row INTEGER NOT NULL,
+            ref_col INTEGER NOT NULL,
+            PRIMARY KEY (sheet, row, col, ref_sheet, ref_row, ref_col)
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE INDEX idx_cell_dependencies_ref
+            ON cell_dependencies (ref_sheet, ref_row, ref_col, sheet, row, col)",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE range_dependencies (
+            sheet TEXT NOT NULL,
+            row INTEGER NOT NULL,
+            col INTEGER NOT NULL,
+            ref_sheet TEXT NOT NULL,
+            start_row INTEGER NOT NULL,
+            start_col INTEGER NOT NULL,
+            end_row INTEGER NOT NULL,
+            end_col INTEGER NOT NULL,
+            PRIMARY KEY (sheet, row, col, ref_sheet, start_row, start_col, end_row, end_col)
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE INDEX idx_range_dependencies_ref ON range_dependencies (ref_sheet)",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE name_dependencies (
+            sheet TEXT NOT NULL,
+            row INTEGER NOT NULL,
+            col INTEGER NOT NULL,
+            name TEXT NOT NULL,
+            PRIMARY KEY (sheet, row, col, name)
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE INDEX idx_name_dependencies_name ON name_dependencies (name)",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE volatile_cells (
+            sheet TEXT NOT NULL,
+            row INTEGER NOT NULL,
+            col INTEGER NOT NULL,
+            PRIMARY KEY (sheet, row, col)
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS dynamic_dependencies (
+            sheet TEXT NOT NULL,
+            row INTEGER NOT NULL,
+            col INTEGER NOT NULL,
+            ref_sheet TEXT NOT NULL,
+            start_row INTEGER NOT NULL,
+            start_col INTEGER NOT NULL,
+            end_row INTEGER NOT NULL,
+            end_col INTEGER NOT NULL,
+            PRIMARY KEY (sheet, row, col, ref_sheet, start_row, start_col, end_row, end_col)
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_dynamic_dependencies_ref
+            ON dynamic_dependencies (ref_sheet)",
+        [],
+    )?;
+    conn.execute(
+        "DELETE FROM dynamic_dependencies AS d WHERE NOT EXISTS (
+            SELECT 1 FROM cells AS c
+            WHERE c.sheet = d.sheet AND c.row = d.row AND c.col = d.col
+              AND c.formula IS NOT NULL
+        )",
+        [],
+    )?;
+
+    let formulas = {
+        let mut stmt =
+            conn.prepare("SELECT sheet, row, col, formula FROM cells WHERE formula IS NOT NULL")?;
+        stmt.query_map([], |r| {
+            Ok((
+                CellKey {
+                    sheet: r.get(0)?,
+                    row: r.get(1)?,
+                    col: r.get(2)?,
+                },
+                r.get::<_, String>(3)?,
+            ))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?
+    };
+    for (cell, formula) in formulas {
+        let precedents = crate::formula::precedents(conn, &formula, &cell.sheet)?;
+        set_precedents(conn, &cell, &precedents.ranges)?;
+        set_names(conn, &cell, &precedents.names)?;
+        set_volatile(conn, &cell, precedents.volatile)?;
+    }
+    Ok(())
+}
+
+/// Replaces the recorded precedents of `cell` with `refs`.
+pub fn set_precedents(
+    conn: &Connection,
+    cell: &CellKey,
+    refs: &[SheetRange],
+) -> rusqlite::Result<()> {
+    for table in ["cell_dependencies", "range_dependencies"] {
+        conn.prepare_cached(&format!(
+            "DELETE FROM {} WHERE sheet = ?1 AND row = ?2 AND col = ?3",
+            table
+        ))?
+        .execute(params![cell.sheet, cell.row, cell.col])?;
+    }
+    let mut insert_cell = conn.prepare_cached(
+        "INSERT OR IGNORE INTO cell_dependencies (sheet, row, col, ref_sheet, ref_row, ref_col)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+    )?;
+    let mut insert_range = conn.prepare_cached(
+        "INSERT OR IGNORE INTO range_dependencies
+            (sheet, row, col, ref_sheet, start_row, start_col, end_row, end_col)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+    )?;
+    for SheetRange { sheet, range } in refs {
+        if range.is_single() {
+            insert_cell.execute(params![
+                cell.sheet,
+                cell.row,
+                cell.col,
+                sheet,
+                range.start_row,
+                range.start_col
+            ])?;
+        } else {
+            insert_range.execute(params![
+                cell.sheet,
+                cell.row,
+                cell.col,
+                sheet,
+                range.start_row,
+                range.start_col,
+                range.end_row,
+                range.end_col
+            ])?;
+        }
+    }
+    Ok(())
+}
+
+/// Replaces the ranges `cell`'s formula reached through references built
+/// while it was evaluated with `refs`.
+pub fn set_dynamic_precedents(
+    conn: &Connection,
+    cell: &CellKey,
+    refs: &[SheetRange],
+) -> rusqlite::Result<()> {
+    conn.prepare_cached(
+        "DELETE FROM dynamic_dependencies WHERE sheet = ?1 AND row = ?2 AND col = ?3",
+    )?
+    .execute(params![cell.sheet, cell.row, cell.col])?;
+    let mut insert = conn.prepare_cached(
+        "INSERT OR IGNORE INTO dynamic_dependencies
+            (sheet, row, col, ref_sheet, start_row, start_col, end_row, end_col)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+    )?;
+    for SheetRange { sheet, range } in refs {
+        insert.execute(params![
+            cell.sheet,
+            cell.row,
+            cell.col,
+            sheet,
+            range.start_row,
+            range.start_col,
+            range.end_row,
+            range.end_col
+        ])?;
+    }
+    Ok(())
+}
+
+/// Replaces the defined names `cell`'s formula is recorded as using with
+/// `names`, which are uppercase.
+pub fn set_names(conn: &Connection, cell: &CellKey, names: &[String]) -> rusqlite::Result<()> {
+    conn.prepare_cached(
+        "DELETE FROM name_dependencies WHERE sheet = ?1 AND row = ?2 AND col = ?3",
+    )?
+    .execute(params![cell.sheet, cell.row, cell.col])?;
+    let mut insert = conn.prepare_cached(
+        "INSERT OR IGNORE INTO name_dependencies (sheet, row, col, name) VALUES (?1, ?2, ?3, ?4)",
+    )?;
+    for name in names {
+        insert.execute(params![cell.sheet, cell.row, cell.col, name])?;
+    }
+    Ok(())
+}
+
+/// Cells whose formulas use the defined name `name`, directly or through
+/// another name.
+pub fn name_dependents(conn: &Connection, name: &str) -> rusqlite::Result<Vec<CellKey>> {
+    let mut stmt = conn.prepare_cached(
+        "SELECT sheet, row, col FROM name_dependencies WHERE name = ?1 ORDER BY sheet, row, col",
+    )?;
+    stmt.query_map(params![name.to_ascii_uppercase()], |r| {
+        Ok(CellKey {
+            sheet: r.get(0)?,
+            row: r.get(1)?,
+            col: r.get(2)?,
+        })
+    })?
+    .collect()
+}
+
+/// Records whether `cell`'s formula is volatile.
+pub fn set_volatile(conn: &Connection, cell: &CellKey, volatile: bool) -> rusqlite::Result<()> {
+    let sql = if volatile {
+        "INSERT OR IGNORE INTO volatile_cells (sheet, row, col) VALUES (?1, ?2, ?3)"
+    } else {
+        "DELETE FROM volatile_cells WHERE sheet = ?1 AND row = ?2 AND col = ?3"
+    };
+    conn.prepare_cached(sql)?
+        .execute(params![cell.sheet, cell.row, cell.col])?;
+    Ok(())
+}
+
+/// Cells whose formulas are volatile.
+pub fn volatile_cells(conn: &Connection) -> rusqlite::Result<Vec<CellKey>> {
+    let mut stmt =
+        conn.prepare_cached("SELECT sheet, row, col FROM volatile_cells ORDER BY sheet, row, col")?;
+    stmt.query_map([], |r| {
+        Ok(CellKey {
+            sheet: r.get(0)?,
+            row: r.get(1)?,
+            col: r.get(2)?,
+        })
+    })?
+    .collect()
+}
+
+/// Forgets every precedent of `cell`, e.g. when it stops being a formula.
+pub fn clear_precedents(conn: &Connection, cell: &CellKey) -> rusqlite::Result<()> {
+    for table in [
+        "cell_dependencies",
+        "range_dependencies",
+        "name_dependencies",
+        "volatile_cells",
+        "dynamic_dependencies",
+    ] {
+        conn.prepare_cached(&format!(
+            "DELETE FROM {} WHERE sheet = ?1 AND row = ?2 AND col = ?3",
+            table
+        ))?
+        .execute(params![cell.sheet, cell.row, cell.col])?;
+    }
+    Ok(())
+}
+
+/// Cells whose formulas reference `cell` directly, on its own or as part of
+/// a range, from any sheet, including through references they built when
+/// last evaluated.
+pub fn direct_dependents(conn: &Connection, cell: &CellKey) -> rusqlite::Result<Vec<CellKey>> {
+    let mut stmt = conn.prepare_cached(
+        "SELECT sheet, row, col FROM cell_dependencies
+         WHERE ref_sheet = ?1 AND ref_row = ?2 AND ref_col = ?3
+         UNION
+         SELECT sheet, row, col FROM range_dependencies
+         WHERE ref_sheet = ?1
+           AND start_row <= ?2 AND end_row >= ?2
+           AND start_col <= ?3 AND end_col >= ?3
+         UNION
+         SELECT sheet, row, col FROM dynamic_dependencies
+         WHERE ref_sheet = ?1
+           AND start_row <= ?2 AND end_row >= ?2
+           AND start_col <= ?3 AND end_col >= ?3",
+    )?;
+    stmt.query_map(params![cell.sheet, cell.row, cell.col], |r| {
+        Ok(CellKey {
+            sheet: r.get(0)?,
+            row: r.get(1)?,
+            col: r.get(2)?,
+        })
+    })?
+    .collect()
+}
+
+/// The ranges `cell`'s formula reads: written out, through defined names and
+/// tables, or built when it was last evaluated. Single cells come back as
+/// one-cell ranges.
+pub fn direct_precedents(conn: &Connection, cell: &CellKey) -> rusqlite::Result<Vec<SheetRange>> {
+    let mut stmt = conn.prepare_cached(
+        "SELECT ref_sheet, ref_row, ref_col, ref_row, ref_col FROM cell_dependencies
+         WHERE sheet = ?1 AND row = ?2 AND col = ?3
+         UNION
+         SELECT ref_sheet, start_row, start_col, end_row, end_col FROM range_dependencies
+         WHERE sheet = ?1 AND row = ?2 AND col = ?3
+         UNION
+         SELECT ref_sheet, start_row, start_col, end_row, end_col FROM dynamic_dependencies
+         WHERE sheet = ?1 AND row = ?2 AND col = ?3
+         ORDER BY 1, 2, 3, 4, 5",
+    )?;
+    stmt.query_map(params![cell.sheet, cell.row, cell.col], |r| {
+        Ok(SheetRange::new(
+            &r.get::<_, String>(0)?,
+            CellRange::new(r.get(1)?, r.get(2)?, r.get(3)?, r.get(4)?),
+        ))
+    })?
+    .collect()
+}
+
+/// The cells inside `range` whose formulas read anything.
+fn formulas_within(conn: &Connection, range: &SheetRange) -> rusqlite::Result<Vec<CellKey>> {
+    let mut stmt = conn.prepare_cached(
+        "SELECT sheet, row, col FROM cell_dependencies
+         WHERE sheet = ?1 AND row BETWEEN ?2 AND ?4 AND col BETWEEN ?3 AND ?5
+         UNION
+         SELECT sheet, row, col FROM range_dependencies
+         WHERE sheet = ?1 AND row BETWEEN ?2 AND ?4 AND col BETWEEN ?3 AND ?5
+         UNION
+         SELECT sheet, row, col FROM dynamic_dependencies
+         WHERE sheet = ?1 AND row BETWEEN ?2 AND ?4 AND col BETWEEN ?3 AND ?5
+         ORDER BY 1, 2, 3",
+    )?;
+    let SheetRange { sheet, range } = range;
+    stmt.query_map(
+        params![
+            sheet,
+            range.start_row,
+            range.start_col,
+            range.end_row,
+            range.end_col
+        ],
+        |r| {
+            Ok(CellKey {
+                sheet: r.get(0)?,
+                row: r.get(1)?,
+                col: r.get(2)?,
+            })
+        },
+    )?
+    .collect()
+}
+
+/// Every range `cell`'s formula reads, directly or through the formulas in
+/// the ranges it reads, nearest first.
+pub fn all_precedents(conn: &Connection, cell: &CellKey) -> rusqlite::Result<Vec<SheetRange>> {
+    let mut ranges = Vec::new();
+    let mut seen = HashSet::new();
+    let mut visited = HashSet::from([cell.clone()]);
+    let mut queue = VecDeque::from([cell.clone()]);
+    while let Some(cell) = queue.pop_front() {
+        for range in direct_precedents(conn, &cell)? {
+            if !seen.insert(range.clone()) {
+                continue;
+            }
+            for formula in formulas_within(conn, &range)? {
+                if visited.insert(formula.clone()) {
+                    queue.push_back(formula);
+                }
+            }
+            ranges.push(range);
+        }
+    }
+    Ok(ranges)
+}
+
+/// Every cell whose formula depends on `cell`, directly or through other
+/// formulas, nearest first. `cell` is among them if it is caught in a loop.
+pub fn all_dependents(conn: &Connection, cell: &CellKey) -> rusqlite::Result<Vec<CellKey>> {
+    let mut cells = Vec::new();
+    let mut seen = HashSet::new();
+    let mut queue = VecDeque::from([cell.clone()]);
+    while let Some(cell) = queue.pop_front() {
+        let mut dependents = direct_dependents(conn, &cell)?;
+        dependents.sort();
+        for dependent in dependents {
+            if seen.insert(dependent.clone()) {
+                cells.push(dependent.clone());
+                queue.push_back(dependent);
+            }
+        }
+    }
+    Ok(cells)
+}
+
+/// One unit of work in a recalculation pass.
+#[derive(Debug, PartialEq)]
+pub enum RecalcStep {
+    /// A formula whose precedents are all up to date when it is reached.
+    Cell(CellKey),
+    /// Formulas that reference each other in a loop.
+    Cycle {
+        /// The loop, following references and ending with the cell it
+        /// started from.
+        path: Vec<CellKey>,
+        /// Every formula caught up in it, by position.
+        cells: Vec<CellKey>,
+    },
+}
+
+/// Every cell that transitively depends on one of `changed`, ordered so that
+/// each cell comes after all of its precedents within the set.
+///
+/// Reference loops cannot be ordered, so each one is returned as a single
+/// [`RecalcStep::Cycle`] at the point where it would have been evaluated.
+pub fn recalc_plan(conn: &Connection, changed: &[CellKey]) -> rusqlite::Result<Vec<RecalcStep>> {
+    let mut affected: HashSet<CellKey> = HashSet::new();
+    let mut dependents_of: HashMap<CellKey, Vec<CellKey>> = HashMap::new();
+    let mut queue: VecDeque<CellKey> = changed.iter().cloned().collect();
+    while let Some(cell) = queue.pop_front() {
+        if dependents_of.contains_key(&cell) {
+            continue;
+        }
+        let dependents = direct_dependents(conn, &cell)?;
+        for dependent in &dependents {
+            if affected.insert(dependent.clone()) {
+                queue.push_back(dependent.clone());
+            }
+        }
+        dependents_of.insert(cell, dependents);
+    }
+
+    let mut nodes: Vec<CellKey> = affected.into_iter().collect();
+    nodes.sort();
+    let index: HashMap<&CellKey, usize> = nodes.iter().enumerate().map(|(i, c)| (c, i)).collect();
+    let mut successors = vec![Vec::new(); nodes.len()];
+    let mut precedents = vec![Vec::new(); nodes.len()];
+    for (i, cell) in nodes.iter().enumerate() {
+        for dependent in &dependents_of[cell] {
+            if let Some(&d) = index.get(dependent) {
+                successors[i].push(d);
+                precedents[d].push(i);
+            }
+        }
+    }
+
+    // Tarjan yields components sinks-first; reversed, that is evaluation order.
+    let mut plan = Vec::new();
+    for component in strongly_connected(&successors).into_iter().rev() {
+        let v = component[0];
+        if component.len() == 1 && !successors[v].contains(&v) {
+            plan.push(RecalcStep::Cell(nodes[v].clone()));
+        } else {
+            let path = cycle_path(&component, &precedents);
+            let mut cells = component.clone();
+            cells.sort();
+            plan.push(RecalcStep::Cycle {
+                path: path.into_iter().map(|i| nodes[i].clone()).collect(),
+                cells: cells.into_iter().map(|i| nodes[i].clone()).collect(),
+            });
+        }
+    }
+    Ok(plan)
+}
+
+/// Iterative Tarjan's algorithm, so deep reference chains cannot overflow the
+/// worker's stack.
+fn strongly_connected(successors: &[Vec<usize>]) -> Vec<Vec<usize>> {
+    const UNVISITED: usize = usize::MAX;
+    let n = successors.len();
+    let mut index = vec![UNVISITED; n];
+    let mut low = vec![0; n];
+    let mut on_stack = vec![false; n];
+    let mut stack = Vec::new();
+    let mut components = Vec::new();
+    let mut next = 0;
+
+    for root in 0..n {
+        if index[root] != UNVISITED {
+            continue;
+        }
+        index[root] = next;
+        low[root] = next;
+        next += 1;
+        stack.push(root);
+        on_stack[root] = true;
+        let mut work = vec![(root, 0)];
+
+        while let Some(&(v, i)) = work.last() {
+            if let Some(&w) = successors[v].get(i) {
+                work.last_mut().unwrap().1 += 1;
+                if index[w] == UNVISITED {
+                    index[w] = next;
+                    low[w] = next;
+                    next += 1;
+                    stack.push(w);
+                    on_stack[w] = true;
+                    work.push((w, 0));
+                } else if on_stack[w] {
+                    low[v] = low[v].min(index[w]);
+                }
+                continue;
+            }
+
+            work.pop();
+            if let Some(&(parent, _)) = work.last() {
+                low[parent] = low[parent].min(low[v]);
+            }
+            if low[v] == index[v] {
+                let mut component = Vec::new();
+                while let Some(w) = stack.pop() {
+                    on_stack[w] = false;
+                    component.push(w);
+                    if w == v {
+                        break;
+                    }
+                }
+                components.push(component);
+            }
+        }
+    }
+    components
+}
+
+/// Shortest loop through the first member of a cyclic component, found by a
+/// breadth-first walk along references that stays inside the component.
+fn cycle_path(component: &[usize], precedents: &[Vec<usize>]) -> Vec<usize> {
+    let members: HashSet<usize> = component.iter().copied().collect();
+    let start = *component.iter().min().unwrap();
+    let mut parent: HashMap<usize, usize> = HashMap::new();
+    let mut queue = VecDeque::from([start]);
+    while let Some(v) = queue.pop_front() {
+        for &w in &precedents[v] {
+            if w == start {
+                let mut path = vec![v];
+                let mut current = v;
+                while current != start {
+                    current = parent[&current];
+                    path.push(current);
+                }
+                path.reverse();
+                path.push(start);
+                return path;
+            }
+            if members.contains(&w) && !parent.contains_key(&w) {
+                parent.insert(w, v);
+                queue.push_back(w);
+            }
+        }
+    }
+    unreachable!("a strongly connected component always loops back to its members")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn single(row: i32, col: i32) -> SheetRange {
+        SheetRange::new("s", CellRange::new(row, col, row, col))
+    }
+
+    fn range(row1: i32, col1: i32, row2: i32, col2: i32) -> SheetRange {
+        SheetRange::new("s", CellRange::new(row1, col1, row2, col2))
+    }
+
+    #[test]
+    fn orders_dependents_after_their_precedents() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::init_db(&conn);
+
+        // A1 <- B1 <- C1, and D1 depends on both A1 and C1.
+        let b1 = CellKey::new("s", 0, 1);
+        let c1 = CellKey::new("s", 0, 2);
+        let d1 = CellKey::new("s", 0, 3);
+        set_precedents(&conn, &d1, &[single(0, 0), single(0, 2)]).unwrap();
+        set_precedents(&conn, &c1, &[single(0, 1)]).unwrap();
+        set_precedents(&conn, &b1, &[single(0, 0)]).unwrap();
+
+        let plan = recalc_plan(&conn, &[CellKey::new("s", 0, 0)]).unwrap();
+        assert_eq!(
+            plan,
+            vec![
+                RecalcStep::Cell(b1),
+                RecalcStep::Cell(c1),
+                RecalcStep::Cell(d1)
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_reference_loops_as_cycles() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::init_db(&conn);
+
+        // A1 -> B1 -> C1 -> A1, with D1 downstream of the loop.
+        let a1 = CellKey::new("s", 0, 0);
+        let b1 = CellKey::new("s", 0, 1);
+        let c1 = CellKey::new("s", 0, 2);
+        let d1 = CellKey::new("s", 0, 3);
+        set_precedents(&conn, &a1, &[single(0, 1)]).unwrap();
+        set_precedents(&conn, &b1, &[single(0, 2)]).unwrap();
+        set_precedents(&conn, &c1, &[single(0, 0)]).unwrap();
+        set_precedents(&conn, &d1, &[single(0, 2)]).unwrap();
+
+        let plan = recalc_plan(&conn, std::slice::from_ref(&b1)).unwrap();
+        assert_eq!(
+            plan,
+            vec![
+                RecalcStep::Cycle {
+                    path: vec![a1.clone(), b1.clone(), c1.clone(), a1.clone()],
+                    cells: vec![a1, b1, c1],
+                },
+                RecalcStep::Cell(d1)
+            ]
+        );
+    }
+
+    #[test]
+    fn ranges_reach_every_cell_inside_them() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::init_db(&conn);
+
+        // B1 = SUM(A1:A10), C1 = SUM(A:A), D1 = SUM(5:5)
+        let b1 = CellKey::new("s", 0, 1);
+        let c1 = CellKey::new("s", 0, 2);
+        let d1 = CellKey::new("s", 0, 3);
+        set_precedents(&conn, &b1, &[range(0, 0, 9, 0)]).unwrap();
+        set_precedents(&conn, &c1, &[range(0, 0, i32::MAX, 0)]).unwrap();
+        set_precedents(&conn, &d1, &[range(4, 0, 4, i32::MAX)]).unwrap();
+
+        let mut dependents = direct_dependents(&conn, &CellKey::new("s", 4, 0)).unwrap();
+        dependents.sort();
+        assert_eq!(dependents, vec![b1, c1.clone(), d1]);
+        let dependents = direct_dependents(&conn, &CellKey::new("s", 500, 0)).unwrap();
+        assert_eq!(dependents, vec![c1]);
+    }
+
+    #[test]
+    fn dependents_are_found_across_sheets() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::init_db(&conn);
+
+        // Summary!A1 = Data!A1, Summary!B1 = SUM(Data!A1:A10), and s!A1 = A1
+        // on its own sheet, which must not pick up Data!A1.
+        let total = CellKey::new("Summary", 0, 0);
+        let sum = CellKey::new("Summary", 0, 1);
+        let data = CellRange::new(0, 0, 0, 0);
+        set_precedents(&conn, &total, &[SheetRange::new("Data", data)]).unwrap();
+        set_precedents(
+            &conn,
+            &sum,
+            &[SheetRange::new("Data", CellRange::new(0, 0, 9, 0))],
+        )
+        .unwrap();
+        set_precedents(&conn, &CellKey::new("s", 0, 1), &[single(0, 0)]).unwrap();
+
+        let mut dependents = direct_dependents(&conn, &CellKey::new("Data", 0, 0)).unwrap();
+        dependents.sort();
+        assert_eq!(dependents, vec![total, sum]);
+    }
+
+    #[test]
+    fn traces_precedents_and_dependents_transitively() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::init_db(&conn);
+
+        // Data!A1 -> s!B1 = SUM(Data!A1:A3) -> s!C1 = B1, with s!D1 reading
+        // Data!A2 through a reference built while evaluating.
+        let b1 = CellKey::new("s", 0, 1);
+        let c1 = CellKey::new("s", 0, 2);
+        let d1 = CellKey::new("s", 0, 3);
+        let data = SheetRange::new("Data", CellRange::new(0, 0, 2, 0));
+        set_precedents(&conn, &b1, std::slice::from_ref(&data)).unwrap();
+        set_precedents(&conn, &c1, &[single(0, 1)]).unwrap();
+        let dynamic = SheetRange::new("Data", CellRange::new(1, 0, 1, 0));
+        set_dynamic_precedents(&conn, &d1, std::slice::from_ref(&dynamic)).unwrap();
+
+        assert_eq!(direct_precedents(&conn, &c1).unwrap(), vec![single(0, 1)]);
+        assert_eq!(
+            all_precedents(&conn, &c1).unwrap(),
+            vec![single(0, 1), data.clone()]
+        );
+        assert_eq!(direct_precedents(&conn, &d1).unwrap(), vec![dynamic]);
+        assert_eq!(
+            all_dependents(&conn, &CellKey::new("Data", 1, 0)).unwrap(),
+            vec![b1.clone(), d1, c1.clone()]
+        );
+        assert_eq!(
+            all_dependents(&conn, &CellKey::new("Data", 2, 0)).unwrap(),
+            vec![b1, c1]
+        );
+        assert_eq!(data.to_string(), "Data!A1:A3");
+        assert_eq!(
+            SheetRange::new("My Sheet", CellRange::new(0, 1, i32::MAX, 2)).to_string(),
+            "'My Sheet'!B:C"
+        );
+    }
+
+    #[test]
+    fn long_chains_do_not_overflow_the_stack() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::init_db(&conn);
+
+        let tx = conn.unchecked_transaction().unwrap();
+        for row in 1..50_000 {
+            set_precedents(&tx, &CellKey::new("s", row, 0), &[single(row - 1, 0)]).unwrap();
+        }
+        set_precedents(&tx, &CellKey::new("s", 0, 0), &[single(49_999, 0)]).unwrap();
+        tx.commit().unwrap();
+
+        let plan = recalc_plan(&conn, &[CellKey::new("s", 0, 0)]).unwrap();
+        assert!(matches!(&plan[..], [RecalcStep::Cycle { path, .. }] if path.len() == 50_001));
+    }
+}
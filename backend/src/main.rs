@@ -2,14 +2,20 @@ use actix::{Actor, Addr, AsyncContext, Message, StreamHandler};
 use actix_cors::Cors;
 use actix_web::{App, HttpRequest, HttpResponse, HttpServer, Responder, web};
 use actix_web_actors::ws;
-use evalexpr::*;
-use regex;
-use rusqlite::{Connection, params};
+use formula::{CellError, FormulaError, Value, ValueType};
+use graph::{CellKey, CellRange, SheetRange};
+use rusqlite::{Connection, OptionalExtension, params};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use uuid::Uuid;
 
+mod formula;
+mod graph;
+mod settings;
+mod table;
+
 pub struct AppState {
     pub db: Mutex<Connection>,
     pub sessions: Arc<Mutex<HashMap<String, Addr<WebSocketSession>>>>,
@@ -21,9 +27,109 @@ struct Cell {
     row: i32,
     col: i32,
     value: String,
+    formula: Option<String>,
+    value_type: Option<ValueType>,
     font_weight: Option<String>,
     font_style: Option<String>,
     background_color: Option<String>,
+    /// For a formula returning an array: the block its result spills over,
+    /// starting at its own cell. Still set when the spill is blocked and the
+    /// formula shows `#SPILL!`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    spill: Option<CellRange>,
+    /// For a cell filled by another formula's array result: that formula's
+    /// cell. Such cells are computed and cannot be edited directly.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    spilled_from: Option<Anchor>,
+    /// The exact number behind `value` for a number or date, which `value`
+    /// only shows to 15 significant digits. Formulas read this.
+    #[serde(skip)]
+    number: Option<f64>,
+}
+
+/// Position of the formula a spilled cell belongs to, on the same sheet.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Anchor {
+    pub row: i32,
+    pub col: i32,
+}
+
+/// Columns read by [`Cell::from_row`], in order.
+const CELL_COLUMNS: &str = "sheet, row, col, value, formula, value_type, font_weight, font_style, \
+                            background_color, spill_rows, spill_cols, anchor_row, anchor_col, number";
+
+impl Cell {
+    fn from_row(r: &rusqlite::Row) -> rusqlite::Result<Cell> {
+        let (row, col) = (r.get(1)?, r.get(2)?);
+        let spill = match (r.get::<_, Option<i32>>(9)?, r.get::<_, Option<i32>>(10)?) {
+            (Some(rows), Some(cols)) => {
+                Some(CellRange::new(row, col, row + rows - 1, col + cols - 1))
+            }
+            _ => None,
+        };
+        let spilled_from = match (r.get(11)?, r.get(12)?) {
+            (Some(row), Some(col)) => Some(Anchor { row, col }),
+            _ => None,
+        };
+        Ok(Cell {
+            sheet: Some(r.get(0)?),
+            row,
+            col,
+            value: r.get(3)?,
+            formula: r.get(4)?,
+            value_type: r.get(5)?,
+            font_weight: r.get(6)?,
+            font_style: r.get(7)?,
+            background_color: r.get(8)?,
+            spill,
+            spilled_from,
+            number: r.get(13)?,
+        })
+    }
+
+    /// Moves formula input into `formula`, leaving `value` for the computed result.
+    /// Constant input is typed on the spot, so `TRUE` is stored as a boolean.
+    ///
+    /// Clients may send the raw input either as `value` (as the grid does when a
+    /// user types `=SUM(A1,B1)`) or explicitly as `formula`. Spill fields are
+    /// computed, so any the client sent are dropped.
+    fn split_input(&mut self) {
+        self.spill = None;
+        self.spilled_from = None;
+        let input = match self.formula.take() {
+            Some(formula) if !formula.is_empty() => formula,
+            _ => std::mem::take(&mut self.value),
+        };
+        if input.starts_with('=') {
+            self.formula = Some(input);
+        } else {
+            self.set_value(&Value::from_input(&input));
+        }
+    }
+
+    fn set_value(&mut self, value: &Value) {
+        self.value = value.display();
+        self.value_type = Some(value.value_type());
+        self.number = match value {
+            Value::Number(n) | Value::Date(n) => Some(*n),
+            _ => None,
+        };
+    }
+
+    /// Result of a formula that failed to evaluate: its own source, as text.
+    fn set_unevaluated(&mut self) {
+        self.value = self.formula.clone().unwrap_or_default();
+        self.value_type = Some(ValueType::Text);
+        self.number = None;
+    }
+
+    fn key(&self) -> CellKey {
+        CellKey::new(
+            self.sheet.as_deref().unwrap_or("default"),
+            self.row,
+            self.col,
+        )
+    }
 }
 
 // WebSocket message types
@@ -34,9 +140,15 @@ pub struct CellUpdate {
     pub row: i32,
     pub col: i32,
     pub value: String,
+    pub formula: Option<String>,
+    pub value_type: Option<ValueType>,
     pub font_weight: Option<String>,
     pub font_style: Option<String>,
     pub background_color: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spill: Option<CellRange>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spilled_from: Option<Anchor>,
     pub user_id: String,
 }
 
@@ -128,63 +240,476 @@ impl actix::Handler<WebSocketMessage> for WebSocketSession {
     }
 }
 
-fn eval_formula(expr: &str, sheet: &str, db_conn: &Connection) -> Result<String, String> {
-    let expr = expr.trim_start_matches('=');
-    let mut ctx = HashMapContext::new();
-    ctx.set_function(
-        "SUM".to_string(),
-        Function::new(|arg| -> EvalexprResult<Value> {
-            let args = arg.as_tuple()?;
-            let mut sum = 0.0;
-            for v in args.iter() {
-                sum += v.as_number()?;
-            }
-            Ok(Value::from_float(sum))
-        }),
+/// Evaluates the formula in `key`, recording the ranges it reached through
+/// references it built, such as `INDIRECT("B" & A1)`.
+fn eval_formula(expr: &str, key: &CellKey, db_conn: &Connection) -> Result<Value, FormulaError> {
+    let evaluation = formula::evaluate_cell(expr, key, db_conn)?;
+    graph::set_dynamic_precedents(db_conn, key, &evaluation.dynamic)
+        .map_err(|e| FormulaError::Storage(e.to_string()))?;
+    Ok(evaluation.value)
+}
+
+/// Records which cells and defined names `cell`'s formula reads, or forgets
+/// them for constants.
+fn track_dependencies(conn: &Connection, cell: &Cell) -> rusqlite::Result<()> {
+    match &cell.formula {
+        Some(formula) => {
+            let key = cell.key();
+            let precedents = formula::precedents(conn, formula, &key.sheet)?;
+            graph::set_precedents(conn, &key, &precedents.ranges)?;
+            graph::set_names(conn, &key, &precedents.names)?;
+            graph::set_volatile(conn, &key, precedents.volatile)
+        }
+        None => graph::clear_precedents(conn, &cell.key()),
+    }
+}
+
+/// Outcome of a recalculation pass.
+#[derive(Default)]
+struct Recalculation {
+    /// Cells whose values were recomputed, in evaluation order.
+    cells: Vec<Cell>,
+    /// Reference loops that were found, each as a path of cells.
+    cycles: Vec<Vec<CellKey>>,
+}
+
+fn load_formula_cell(conn: &Connection, key: &CellKey) -> rusqlite::Result<Option<Cell>> {
+    conn.query_row(
+        &format!(
+            "SELECT {} FROM cells
+             WHERE sheet = ?1 AND row = ?2 AND col = ?3 AND formula IS NOT NULL",
+            CELL_COLUMNS
+        ),
+        params![key.sheet, key.row, key.col],
+        Cell::from_row,
     )
-    .unwrap();
-    ctx.set_function(
-        "AVERAGE".to_string(),
-        Function::new(|arg| -> EvalexprResult<Value> {
-            let args = arg.as_tuple()?;
-            let mut sum = 0.0;
-            for v in args.iter() {
-                sum += v.as_number()?;
-            }
-            let avg = sum / args.len() as f64;
-            Ok(Value::from_float(avg))
-        }),
+    .optional()
+}
+
+/// Inserts `cell`, or overwrites the cell already at its position.
+fn upsert_cell(conn: &Connection, cell: &Cell) -> rusqlite::Result<()> {
+    let key = cell.key();
+    let (spill_rows, spill_cols) = cell.spill.map(|s| (s.rows(), s.cols())).unzip();
+    let (anchor_row, anchor_col) = cell.spilled_from.map(|a| (a.row, a.col)).unzip();
+    conn.prepare_cached(
+        "INSERT INTO cells
+            (sheet, row, col, value, formula, value_type, font_weight, font_style, background_color,
+             spill_rows, spill_cols, anchor_row, anchor_col, number)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)
+         ON CONFLICT(sheet, row, col) DO UPDATE SET
+            value=excluded.value,
+            formula=excluded.formula,
+            value_type=excluded.value_type,
+            font_weight=excluded.font_weight,
+            font_style=excluded.font_style,
+            background_color=excluded.background_color,
+            spill_rows=excluded.spill_rows,
+            spill_cols=excluded.spill_cols,
+            anchor_row=excluded.anchor_row,
+            anchor_col=excluded.anchor_col,
+            number=excluded.number",
+    )?
+    .execute(params![
+        key.sheet,
+        key.row,
+        key.col,
+        cell.value,
+        cell.formula,
+        cell.value_type,
+        cell.font_weight,
+        cell.font_style,
+        cell.background_color,
+        spill_rows,
+        spill_cols,
+        anchor_row,
+        anchor_col,
+        cell.number,
+    ])?;
+    Ok(())
+}
+
+fn store_value(conn: &Connection, cell: &Cell) -> rusqlite::Result<()> {
+    let key = cell.key();
+    let (spill_rows, spill_cols) = cell.spill.map(|s| (s.rows(), s.cols())).unzip();
+    conn.execute(
+        "UPDATE cells SET value = ?4, value_type = ?5, spill_rows = ?6, spill_cols = ?7,
+             number = ?8
+         WHERE sheet = ?1 AND row = ?2 AND col = ?3",
+        params![
+            key.sheet,
+            key.row,
+            key.col,
+            cell.value,
+            cell.value_type,
+            spill_rows,
+            spill_cols,
+            cell.number
+        ],
+    )?;
+    Ok(())
+}
+
+/// The formula `key` belongs to if it is a spilled cell.
+fn spilled_from(conn: &Connection, key: &CellKey) -> rusqlite::Result<Option<Anchor>> {
+    conn.query_row(
+        "SELECT anchor_row, anchor_col FROM cells
+         WHERE sheet = ?1 AND row = ?2 AND col = ?3 AND anchor_row IS NOT NULL",
+        params![key.sheet, key.row, key.col],
+        |r| {
+            Ok(Anchor {
+                row: r.get(0)?,
+                col: r.get(1)?,
+            })
+        },
     )
-    .unwrap();
+    .optional()
+}
+
+/// Removes the cells the formula at `anchor` spilled into, apart from those
+/// inside `keep`. Cells with formatting are left behind blank. Returns the
+/// removed cells, blanked.
+fn retire_spill(
+    conn: &Connection,
+    anchor: &CellKey,
+    keep: Option<&CellRange>,
+) -> rusqlite::Result<Vec<Cell>> {
+    let spilled: Vec<Cell> = conn
+        .prepare_cached(&format!(
+            "SELECT {} FROM cells WHERE sheet = ?1 AND anchor_row = ?2 AND anchor_col = ?3",
+            CELL_COLUMNS
+        ))?
+        .query_map(
+            params![anchor.sheet, anchor.row, anchor.col],
+            Cell::from_row,
+        )?
+        .collect::<rusqlite::Result<_>>()?;
+    let mut retired = Vec::new();
+    for mut cell in spilled {
+        if keep.is_some_and(|keep| contains(keep, cell.row, cell.col)) {
+            continue;
+        }
+        let key = cell.key();
+        cell.set_value(&Value::Empty);
+        cell.spilled_from = None;
+        if cell.font_weight.is_none()
+            && cell.font_style.is_none()
+            && cell.background_color.is_none()
+        {
+            conn.execute(
+                "DELETE FROM cells WHERE sheet = ?1 AND row = ?2 AND col = ?3",
+                params![key.sheet, key.row, key.col],
+            )?;
+        } else {
+            upsert_cell(conn, &cell)?;
+        }
+        retired.push(cell);
+    }
+    Ok(retired)
+}
+
+fn contains(range: &CellRange, row: i32, col: i32) -> bool {
+    (range.start_row..=range.end_row).contains(&row)
+        && (range.start_col..=range.end_col).contains(&col)
+}
+
+/// Gives the formula `cell` the evaluation `result`. An array spills from
+/// the cell over the block below and to its right, unless that block runs
+/// off the sheet or holds anything else, in which case the cell shows
+/// `#SPILL!`. Returns the cells around it whose spilled values changed,
+/// including those no longer spilled into. `cell` itself is left for the
+/// caller to save.
+fn set_result(
+    conn: &Connection,
+    cell: &mut Cell,
+    result: Result<Value, FormulaError>,
+) -> rusqlite::Result<Vec<Cell>> {
+    let key = cell.key();
+    let anchor = Anchor {
+        row: cell.row,
+        col: cell.col,
+    };
+    cell.spill = None;
+    let array = match result {
+        Ok(Value::Array(array)) => array,
+        Ok(value) => {
+            cell.set_value(&value);
+            return retire_spill(conn, &key, None);
+        }
+        Err(_) => {
+            cell.set_unevaluated();
+            return retire_spill(conn, &key, None);
+        }
+    };
 
-    let re = regex::Regex::new(r"([A-Z]+)(\d+)").unwrap();
-    let mut final_expr = expr.to_string();
+    let area = CellRange::new(
+        cell.row,
+        cell.col,
+        cell.row + array.rows as i32 - 1,
+        cell.col + array.cols as i32 - 1,
+    );
+    cell.spill = Some(area);
+    let current: HashMap<(i32, i32), Cell> = match on_sheet(&area) {
+        Some(area) => load_cells(conn, &key.sheet, &area)?
+            .into_iter()
+            .map(|c| ((c.row, c.col), c))
+            .collect(),
+        None => {
+            cell.set_value(&Value::Error(CellError::Spill));
+            return retire_spill(conn, &key, None);
+        }
+    };
+    let blocked = current.values().any(|c| {
+        c.key() != key
+            && c.spilled_from != Some(anchor)
+            && (!c.value.is_empty() || c.formula.is_some())
+    });
+    if blocked {
+        cell.set_value(&Value::Error(CellError::Spill));
+        return retire_spill(conn, &key, None);
+    }
 
-    for cap in re.captures_iter(expr) {
-        let col_str = cap.get(1).map_or("", |m| m.as_str());
-        let row_str = cap.get(2).map_or("", |m| m.as_str());
+    let mut changed = retire_spill(conn, &key, Some(&area))?;
+    for (i, value) in array.values.iter().enumerate() {
+        let row = cell.row + (i / array.cols) as i32;
+        let col = cell.col + (i % array.cols) as i32;
+        if i == 0 {
+            cell.set_value(value);
+            continue;
+        }
+        let previous = current.get(&(row, col));
+        let mut spilled = match previous {
+            Some(previous) => previous.clone(),
+            None => Cell {
+                sheet: Some(key.sheet.clone()),
+                row,
+                col,
+                value: String::new(),
+                formula: None,
+                value_type: None,
+                font_weight: None,
+                font_style: None,
+                background_color: None,
+                spill: None,
+                spilled_from: None,
+                number: None,
+            },
+        };
+        spilled.set_value(value);
+        spilled.spilled_from = Some(anchor);
+        let unchanged = previous.is_some_and(|p| {
+            p.spilled_from == Some(anchor)
+                && p.value == spilled.value
+                && p.number == spilled.number
+                && p.value_type == spilled.value_type
+        });
+        if !unchanged {
+            upsert_cell(conn, &spilled)?;
+            changed.push(spilled);
+        }
+    }
+    Ok(changed)
+}
 
-        let col = col_str
-            .chars()
-            .fold(0, |acc, c| acc * 26 + (c as i32 - 'A' as i32 + 1))
-            - 1;
-        let row = row_str.parse::<i32>().unwrap() - 1;
+/// Most times [`recalculate`] goes round when spills change the cells other
+/// formulas read.
+const MAX_SPILL_PASSES: usize = 100;
 
-        let mut stmt = db_conn
-            .prepare("SELECT value FROM cells WHERE sheet = ?1 AND row = ?2 AND col = ?3")
-            .unwrap();
-        let value: Result<String, _> = stmt.query_row(params![sheet, row, col], |r| r.get(0));
+/// Formulas showing `#SPILL!` whose spill block covers one of `keys`, so may
+/// fit now that those cells changed.
+fn blocked_anchors(conn: &Connection, keys: &[CellKey]) -> rusqlite::Result<Vec<CellKey>> {
+    let blocked: Vec<Cell> = conn
+        .prepare_cached(&format!(
+            "SELECT {} FROM cells WHERE value = ?1 AND spill_rows IS NOT NULL",
+            CELL_COLUMNS
+        ))?
+        .query_map(params![CellError::Spill.code()], Cell::from_row)?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(blocked
+        .iter()
+        .filter(|anchor| {
+            let spill = anchor.spill.as_ref().unwrap();
+            keys.iter().any(|key| {
+                Some(key.sheet.as_str()) == anchor.sheet.as_deref()
+                    && contains(spill, key.row, key.col)
+            })
+        })
+        .map(Cell::key)
+        .collect())
+}
 
-        if let Ok(val) = value {
-            if let Ok(num) = val.parse::<f64>() {
-                final_expr = final_expr.replace(&cap[0], &num.to_string());
+/// Re-evaluates every formula that depends on `changed`, transitively and in
+/// dependency order, persisting each new value. Formulas caught in a reference
+/// loop are set to `#CIRC!` instead of being evaluated, unless the workbook
+/// calculates iteratively, when the loop is iterated until it settles.
+///
+/// Cells whose spilled values change are treated as changed in turn, and
+/// formulas showing `#SPILL!` are retried when their block changes.
+/// Volatile formulas, such as those calling `NOW()` or `RAND()`, are
+/// recalculated whatever changed.
+fn recalculate(conn: &Connection, changed: &[CellKey]) -> rusqlite::Result<Recalculation> {
+    let mut result = Recalculation::default();
+    let settings = settings::calculation(conn)?;
+    let mut volatile: Vec<CellKey> = graph::volatile_cells(conn)?
+        .into_iter()
+        .filter(|key| !changed.contains(key))
+        .collect();
+    let mut pending = changed.to_vec();
+    for _ in 0..MAX_SPILL_PASSES {
+        if pending.is_empty() && volatile.is_empty() {
+            break;
+        }
+        let mut spilled = Vec::new();
+        let mut roots = pending.clone();
+        let reevaluate = blocked_anchors(conn, &pending)?;
+        for key in reevaluate.into_iter().chain(std::mem::take(&mut volatile)) {
+            if let Some(mut cell) = load_formula_cell(conn, &key)? {
+                let formula = cell.formula.clone().unwrap_or_default();
+                let value = eval_formula(&formula, &key, conn);
+                spilled.extend(set_result(conn, &mut cell, value)?);
+                store_value(conn, &cell)?;
+                result.cells.push(cell);
+                roots.push(key);
+            }
+        }
+        for step in graph::recalc_plan(conn, &roots)? {
+            match step {
+                graph::RecalcStep::Cell(key) => {
+                    let Some(mut cell) = load_formula_cell(conn, &key)? else {
+                        continue;
+                    };
+                    let formula = cell.formula.clone().unwrap_or_default();
+                    let value = eval_formula(&formula, &key, conn);
+                    spilled.extend(set_result(conn, &mut cell, value)?);
+                    store_value(conn, &cell)?;
+                    result.cells.push(cell);
+                }
+                graph::RecalcStep::Cycle { cells, .. } if settings.iterative => {
+                    spilled.extend(iterate(conn, &cells, &settings, &mut result)?);
+                }
+                graph::RecalcStep::Cycle { path, cells } => {
+                    for key in &cells {
+                        if let Some(mut cell) = load_formula_cell(conn, key)? {
+                            let circular = Ok(Value::Error(CellError::Circular));
+                            spilled.extend(set_result(conn, &mut cell, circular)?);
+                            store_value(conn, &cell)?;
+                            result.cells.push(cell);
+                        }
+                    }
+                    result.cycles.push(path);
+                }
             }
         }
+        pending = spilled.iter().map(Cell::key).collect();
+        result.cells.extend(spilled);
+    }
+
+    result.cells = final_states(result.cells);
+    Ok(result)
+}
+
+/// Evaluates the formulas of a reference loop again and again, in order of
+/// position and each reading the others' latest values, until a pass moves
+/// none of them by more than `max_change` or `max_iterations` passes have
+/// run. A loop left showing `#CIRC!` starts again from blank. Returns the
+/// cells spilled into.
+fn iterate(
+    conn: &Connection,
+    cells: &[CellKey],
+    settings: &settings::Calculation,
+    result: &mut Recalculation,
+) -> rusqlite::Result<Vec<Cell>> {
+    let circular = Value::Error(CellError::Circular).display();
+    for key in cells {
+        if let Some(mut cell) = load_formula_cell(conn, key)?
+            && cell.value == circular
+        {
+            cell.set_value(&Value::Empty);
+            store_value(conn, &cell)?;
+        }
+    }
+
+    let mut spilled = Vec::new();
+    for _ in 0..settings.max_iterations {
+        let mut settled = true;
+        for key in cells {
+            let Some(mut cell) = load_formula_cell(conn, key)? else {
+                continue;
+            };
+            let (before, before_number) = (cell.value.clone(), cell.number);
+            let formula = cell.formula.clone().unwrap_or_default();
+            let value = eval_formula(&formula, key, conn);
+            spilled.extend(set_result(conn, &mut cell, value)?);
+            store_value(conn, &cell)?;
+            settled &= match (before_number, cell.number) {
+                (Some(before), Some(after)) => (after - before).abs() <= settings.max_change,
+                _ => before == cell.value,
+            };
+            result.cells.push(cell);
+        }
+        if settled {
+            break;
+        }
+    }
+    Ok(spilled)
+}
+
+/// `cells` with only the last state of any cell that changed more than once.
+fn final_states(cells: Vec<Cell>) -> Vec<Cell> {
+    let mut seen = HashSet::new();
+    let mut cells: Vec<Cell> = cells
+        .into_iter()
+        .rev()
+        .filter(|cell| seen.insert(cell.key()))
+        .collect();
+    cells.reverse();
+    cells
+}
+
+/// Re-evaluates every formula using the defined name `name`, after it was
+/// created, changed or deleted, and everything depending on them in turn.
+fn refresh_name(conn: &Connection, name: &str) -> rusqlite::Result<Recalculation> {
+    let mut cells = Vec::new();
+    let mut changed = Vec::new();
+    for key in graph::name_dependents(conn, name)? {
+        let Some(mut cell) = load_formula_cell(conn, &key)? else {
+            continue;
+        };
+        // The name may now point somewhere else.
+        track_dependencies(conn, &cell)?;
+        let formula = cell.formula.clone().unwrap_or_default();
+        let value = eval_formula(&formula, &key, conn);
+        let spilled = set_result(conn, &mut cell, value)?;
+        store_value(conn, &cell)?;
+        changed.push(key);
+        changed.extend(spilled.iter().map(Cell::key));
+        cells.push(cell);
+        cells.extend(spilled);
     }
+    let mut result = recalculate(conn, &changed)?;
+    cells.append(&mut result.cells);
+    result.cells = final_states(cells);
+    Ok(result)
+}
 
-    eval_with_context(&final_expr, &ctx)
-        .map(|v| v.to_string())
-        .map_err(|e| e.to_string())
+#[derive(Serialize, Deserialize)]
+struct SaveResponse {
+    status: String,
+    /// Reference loops found while recalculating, each as the chain of cell
+    /// addresses followed from a cell back to itself.
+    circular_references: Vec<Vec<String>>,
+}
+
+impl SaveResponse {
+    fn saved(recalculation: &Recalculation) -> Self {
+        SaveResponse {
+            status: "saved".to_string(),
+            circular_references: recalculation
+                .cycles
+                .iter()
+                .map(|path| path.iter().map(CellKey::to_string).collect())
+                .collect(),
+        }
+    }
 }
 
 async fn health() -> impl Responder {
@@ -205,7 +730,8 @@ async fn list_cells(
         }
     };
 
-    let mut stmt = match conn.prepare("SELECT sheet, row, col, value, font_weight, font_style, background_color FROM cells WHERE sheet = ?1") {
+    let query = format!("SELECT {} FROM cells WHERE sheet = ?1", CELL_COLUMNS);
+    let mut stmt = match conn.prepare(&query) {
         Ok(stmt) => stmt,
         Err(e) => {
             eprintln!("Failed to prepare statement: {}", e);
@@ -213,17 +739,7 @@ async fn list_cells(
         }
     };
 
-    let rows = match stmt.query_map(params![sheet], |r| {
-        Ok(Cell {
-            sheet: Some(r.get(0)?),
-            row: r.get(1)?,
-            col: r.get(2)?,
-            value: r.get(3)?,
-            font_weight: r.get(4)?,
-            font_style: r.get(5)?,
-            background_color: r.get(6)?,
-        })
-    }) {
+    let rows = match stmt.query_map(params![sheet], Cell::from_row) {
         Ok(rows) => rows,
         Err(e) => {
             eprintln!("Failed to query cells: {}", e);
@@ -244,6 +760,31 @@ async fn list_cells(
     HttpResponse::Ok().json(cells)
 }
 
+/// Response to a request for the cell at `row` and `col` if it lies beyond
+/// the sheet, where no formula could refer to it.
+fn off_sheet(row: i32, col: i32) -> Option<HttpResponse> {
+    on_sheet(&CellRange::new(row, col, row, col))
+        .is_none()
+        .then(|| {
+            HttpResponse::BadRequest().body(format!(
+                "row {} and col {} must be from 0 to {} and 0 to {}",
+                row,
+                col,
+                formula::MAX_ROWS - 1,
+                formula::MAX_COLS - 1
+            ))
+        })
+}
+
+/// Response to an edit of a cell filled by another formula's array result.
+fn spilled_conflict(key: &CellKey, anchor: Anchor) -> HttpResponse {
+    let anchor = CellKey::new(&key.sheet, anchor.row, anchor.col);
+    HttpResponse::Conflict().body(format!(
+        "{} is part of the array spilled from {}; edit that formula instead",
+        key, anchor
+    ))
+}
+
 async fn set_cell(data: web::Data<AppState>, item: web::Json<Cell>) -> impl Responder {
     let conn = match data.db.lock() {
         Ok(conn) => conn,
@@ -253,49 +794,91 @@ async fn set_cell(data: web::Data<AppState>, item: web::Json<Cell>) -> impl Resp
         }
     };
 
+    if let Some(response) = off_sheet(item.row, item.col) {
+        return response;
+    }
     let mut cell_to_save = item.clone();
     let sheet = cell_to_save
         .sheet
         .clone()
         .unwrap_or_else(|| "default".to_string());
     cell_to_save.sheet = Some(sheet.clone());
+    cell_to_save.split_input();
+    let key = cell_to_save.key();
+
+    // Nothing is kept unless the cell and everything it changes are saved.
+    let tx = match conn.unchecked_transaction() {
+        Ok(tx) => tx,
+        Err(e) => {
+            eprintln!("Failed to start transaction: {}", e);
+            return HttpResponse::InternalServerError().body("Transaction error");
+        }
+    };
+
+    match spilled_from(&tx, &key) {
+        Ok(None) => {}
+        Ok(Some(anchor)) => return spilled_conflict(&key, anchor),
+        Err(e) => {
+            eprintln!("Failed to load cell: {}", e);
+            return HttpResponse::InternalServerError().body("Failed to save cell");
+        }
+    }
 
-    if cell_to_save.value.starts_with('=') {
-        match eval_formula(&cell_to_save.value, &sheet, &conn) {
-            Ok(res) => cell_to_save.value = res,
+    let spilled = match &cell_to_save.formula {
+        Some(formula) => match eval_formula(formula, &key, &tx) {
+            Ok(res) => set_result(&tx, &mut cell_to_save, Ok(res)),
+            Err(FormulaError::Parse(e)) => {
+                return HttpResponse::BadRequest().body(format!("Formula error: {}", e));
+            }
             Err(e) => {
                 eprintln!("Formula evaluation error: {}", e);
-                return HttpResponse::BadRequest().body(format!("Formula error: {}", e));
+                return HttpResponse::InternalServerError().body("Failed to evaluate formula");
             }
+        },
+        None => retire_spill(&tx, &key, None),
+    };
+    let spilled = match spilled {
+        Ok(spilled) => spilled,
+        Err(e) => {
+            eprintln!("Failed to spill cell: {}", e);
+            return HttpResponse::InternalServerError().body("Failed to save cell");
         }
-    }
+    };
 
-    if let Err(e) = conn.execute(
-        "INSERT INTO cells (sheet, row, col, value, font_weight, font_style, background_color)
-         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
-         ON CONFLICT(sheet, row, col) DO UPDATE SET
-            value=excluded.value,
-            font_weight=excluded.font_weight,
-            font_style=excluded.font_style,
-            background_color=excluded.background_color",
-        params![
-            cell_to_save.sheet,
-            cell_to_save.row,
-            cell_to_save.col,
-            cell_to_save.value,
-            cell_to_save.font_weight,
-            cell_to_save.font_style,
-            cell_to_save.background_color,
-        ],
-    ) {
+    if let Err(e) = upsert_cell(&tx, &cell_to_save) {
         eprintln!("Failed to save cell: {}", e);
         return HttpResponse::InternalServerError().body("Failed to save cell");
     }
 
-    // Broadcast the update to all connected WebSocket sessions
-    broadcast_cell_update(&data.sessions, &cell_to_save, "system".to_string());
+    let mut changed = vec![key];
+    changed.extend(spilled.iter().map(Cell::key));
+    let recalculated = match track_dependencies(&tx, &cell_to_save)
+        .and_then(|_| recalculate(&tx, &changed))
+    {
+        Ok(cells) => cells,
+        Err(e) => {
+            eprintln!("Failed to recalculate dependents: {}", e);
+            return HttpResponse::InternalServerError().body("Failed to recalculate dependents");
+        }
+    };
+
+    if let Err(e) = tx.commit() {
+        eprintln!("Failed to commit transaction: {}", e);
+        return HttpResponse::InternalServerError().body("Failed to commit changes");
+    }
+
+    // Broadcast the update and its cascade to all connected WebSocket sessions
+    let recalculated_keys: HashSet<CellKey> = recalculated.cells.iter().map(Cell::key).collect();
+    for cell in std::iter::once(&cell_to_save).chain(&spilled) {
+        if !recalculated_keys.contains(&cell.key()) {
+            broadcast_cell_update(&data.sessions, cell, "system".to_string());
+        }
+    }
+    for cell in &recalculated.cells {
+        broadcast_cell_update(&data.sessions, cell, "system".to_string());
+    }
 
-    HttpResponse::Ok().body("saved")
+    HttpResponse::Ok().json(SaveResponse::saved(&recalculated))
 }
 
 async fn set_cells_bulk(data: web::Data<AppState>, items: web::Json<Vec<Cell>>) -> impl Responder {
@@ -307,6 +890,23 @@ async fn set_cells_bulk(data: web::Data<AppState>, items: web::Json<Vec<Cell>>)
         }
     };
 
+    // A cell beyond the sheet or a formula that does not parse is rejected,
+    // and with it the whole batch, before anything is written.
+    for item in items.iter() {
+        if let Some(response) = off_sheet(item.row, item.col) {
+            return response;
+        }
+        let mut cell = item.clone();
+        cell.split_input();
+        if let Some(Err(e)) = cell.formula.as_deref().map(formula::parse) {
+            return HttpResponse::BadRequest().body(format!(
+                "Formula error in {}: {}",
+                cell.key(),
+                e
+            ));
+        }
+    }
+
     // Start transaction for better performance
     let tx = match conn.unchecked_transaction() {
         Ok(tx) => tx,
@@ -316,82 +916,158 @@ async fn set_cells_bulk(data: web::Data<AppState>, items: web::Json<Vec<Cell>>)
         }
     };
 
-    for item in items.iter() {
-        let mut cell_to_save = item.clone();
-        let sheet = cell_to_save
-            .sheet
-            .clone()
-            .unwrap_or_else(|| "default".to_string());
-        cell_to_save.sheet = Some(sheet.clone());
-
-        if cell_to_save.value.starts_with('=') {
-            if let Ok(res) = eval_formula(&cell_to_save.value, &sheet, &conn) {
-                cell_to_save.value = res;
+    let cells: Vec<Cell> = items
+        .iter()
+        .map(|item| {
+            let mut cell = item.clone();
+            cell.sheet = Some(cell.sheet.unwrap_or_else(|| "default".to_string()));
+            cell.split_input();
+            cell
+        })
+        .collect();
+    // Rows appended below a table join it before any formula reads it.
+    let extended = match extend_tables(&tx, &cells) {
+        Ok(extended) => extended,
+        Err(e) => {
+            eprintln!("Failed to extend tables: {}", e);
+            return HttpResponse::InternalServerError().body("Failed to save cells");
+        }
+    };
+
+    let mut changed = Vec::with_capacity(items.len());
+    let mut spilled = Vec::new();
+    for mut cell_to_save in cells {
+        let key = cell_to_save.key();
+
+        match spilled_from(&tx, &key) {
+            Ok(None) => {}
+            Ok(Some(anchor)) => return spilled_conflict(&key, anchor),
+            Err(e) => {
+                eprintln!("Failed to load cell: {}", e);
+                return HttpResponse::InternalServerError().body("Failed to save cells");
+            }
+        }
+
+        let cells = match &cell_to_save.formula {
+            Some(formula) => {
+                let result = eval_formula(formula, &key, &tx);
+                set_result(&tx, &mut cell_to_save, result)
+            }
+            None => retire_spill(&tx, &key, None),
+        };
+        match cells {
+            Ok(cells) => {
+                changed.extend(cells.iter().map(Cell::key));
+                spilled.extend(cells);
+            }
+            Err(e) => {
+                eprintln!("Failed to spill cell: {}", e);
+                return HttpResponse::InternalServerError().body("Failed to save cells");
             }
         }
 
-        if let Err(e) = tx.execute(
-            "INSERT INTO cells (sheet, row, col, value, font_weight, font_style, background_color)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
-             ON CONFLICT(sheet, row, col) DO UPDATE SET
-                value=excluded.value,
-                font_weight=excluded.font_weight,
-                font_style=excluded.font_style,
-                background_color=excluded.background_color",
-            params![
-                sheet,
-                cell_to_save.row,
-                cell_to_save.col,
-                cell_to_save.value,
-                cell_to_save.font_weight,
-                cell_to_save.font_style,
-                cell_to_save.background_color,
-            ],
-        ) {
+        if let Err(e) = upsert_cell(&tx, &cell_to_save) {
             eprintln!("Failed to execute bulk insert: {}", e);
             return HttpResponse::InternalServerError().body("Failed to save cells");
         }
+
+        if let Err(e) = track_dependencies(&tx, &cell_to_save) {
+            eprintln!("Failed to record dependencies: {}", e);
+            return HttpResponse::InternalServerError().body("Failed to save cells");
+        }
+        changed.push(key);
+    }
+
+    let mut recalculated = Recalculation::default();
+    let refreshed = extended
+        .iter()
+        .map(|table| refresh_name(&tx, table))
+        .chain(std::iter::once_with(|| recalculate(&tx, &changed)));
+    for refresh in refreshed {
+        match refresh {
+            Ok(mut refresh) => {
+                recalculated.cells.append(&mut refresh.cells);
+                recalculated.cycles.append(&mut refresh.cycles);
+            }
+            Err(e) => {
+                eprintln!("Failed to recalculate dependents: {}", e);
+                return HttpResponse::InternalServerError()
+                    .body("Failed to recalculate dependents");
+            }
+        }
     }
+    recalculated.cells = final_states(recalculated.cells);
 
     if let Err(e) = tx.commit() {
         eprintln!("Failed to commit transaction: {}", e);
         return HttpResponse::InternalServerError().body("Failed to commit changes");
     }
 
-    HttpResponse::Ok().body("saved")
+    let recalculated_keys: HashSet<CellKey> = recalculated.cells.iter().map(Cell::key).collect();
+    for cell in &spilled {
+        if !recalculated_keys.contains(&cell.key()) {
+            broadcast_cell_update(&data.sessions, cell, "system".to_string());
+        }
+    }
+    for cell in &recalculated.cells {
+        broadcast_cell_update(&data.sessions, cell, "system".to_string());
+    }
+
+    HttpResponse::Ok().json(SaveResponse::saved(&recalculated))
 }
 
-#[derive(Serialize, Deserialize)]
-struct EvalRequest {
-    expr: String,
+/// The cell to trace, as `?sheet=Sheet1&row=0&col=0`.
+#[derive(Deserialize)]
+struct CellQuery {
     sheet: Option<String>,
+    row: i32,
+    col: i32,
 }
 
-async fn evaluate(query: web::Json<EvalRequest>, data: web::Data<AppState>) -> impl Responder {
-    let conn = data.db.lock().unwrap();
-    let sheet = query.sheet.as_deref().unwrap_or("default");
-    match eval_formula(&query.expr, sheet, &conn) {
-        Ok(result) => HttpResponse::Ok().body(result),
-        Err(e) => HttpResponse::BadRequest().body(e),
-    }
+/// Cells at one end of a trace arrow.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct TracedRange {
+    sheet: String,
+    range: CellRange,
+    /// The range as a formula would write it, as in `Data!A1:A10`.
+    address: String,
 }
 
-#[derive(Serialize, Deserialize)]
-struct ClearRequest {
-    cells: Vec<CellPosition>,
+impl From<SheetRange> for TracedRange {
+    fn from(range: SheetRange) -> Self {
+        TracedRange {
+            address: range.to_string(),
+            sheet: range.sheet,
+            range: range.range,
+        }
+    }
 }
 
+/// What a cell's formula reads, or which formulas read the cell: `direct`
+/// references only, and everything reached by following them on, nearest
+/// first.
 #[derive(Serialize, Deserialize)]
-struct CellPosition {
-    sheet: Option<String>,
-    row: i32,
-    col: i32,
+struct Trace {
+    cell: String,
+    direct: Vec<TracedRange>,
+    transitive: Vec<TracedRange>,
 }
 
-async fn clear_cells_bulk(
-    data: web::Data<AppState>,
-    request: web::Json<ClearRequest>,
-) -> impl Responder {
+/// Looks up the direct and transitive ranges `find` gives for the cell in
+/// `query`.
+fn trace(
+    data: &AppState,
+    query: &CellQuery,
+    find: impl FnOnce(&Connection, &CellKey) -> rusqlite::Result<(Vec<SheetRange>, Vec<SheetRange>)>,
+) -> HttpResponse {
+    if let Some(response) = off_sheet(query.row, query.col) {
+        return response;
+    }
+    let key = CellKey::new(
+        query.sheet.as_deref().unwrap_or("default"),
+        query.row,
+        query.col,
+    );
     let conn = match data.db.lock() {
         Ok(conn) => conn,
         Err(e) => {
@@ -399,138 +1075,2780 @@ async fn clear_cells_bulk(
             return HttpResponse::InternalServerError().body("Database lock error");
         }
     };
-
-    // Start transaction for better performance
-    let tx = match conn.unchecked_transaction() {
-        Ok(tx) => tx,
+    match find(&conn, &key) {
+        Ok((direct, transitive)) => HttpResponse::Ok().json(Trace {
+            cell: key.to_string(),
+            direct: direct.into_iter().map(TracedRange::from).collect(),
+            transitive: transitive.into_iter().map(TracedRange::from).collect(),
+        }),
         Err(e) => {
-            eprintln!("Failed to start transaction: {}", e);
-            return HttpResponse::InternalServerError().body("Transaction error");
-        }
-    };
-
-    for pos in request.cells.iter() {
-        if let Err(e) = tx.execute(
-            "DELETE FROM cells WHERE sheet = ?1 AND row = ?2 AND col = ?3",
-            params![pos.sheet.as_deref().unwrap_or("default"), pos.row, pos.col],
-        ) {
-            eprintln!("Failed to delete cell: {}", e);
-            return HttpResponse::InternalServerError().body("Failed to clear cells");
+            eprintln!("Failed to trace {}: {}", key, e);
+            HttpResponse::InternalServerError().body("Database query error")
         }
     }
+}
 
-    if let Err(e) = tx.commit() {
-        eprintln!("Failed to commit transaction: {}", e);
-        return HttpResponse::InternalServerError().body("Failed to commit changes");
-    }
+/// The ranges a cell's formula reads, and those their formulas read in turn.
+async fn cell_precedents(
+    data: web::Data<AppState>,
+    query: web::Query<CellQuery>,
+) -> impl Responder {
+    trace(&data, &query, |conn, key| {
+        Ok((
+            graph::direct_precedents(conn, key)?,
+            graph::all_precedents(conn, key)?,
+        ))
+    })
+}
 
-    HttpResponse::Ok().body("cleared")
+/// The formulas that read a cell, and those that read them in turn.
+async fn cell_dependents(
+    data: web::Data<AppState>,
+    query: web::Query<CellQuery>,
+) -> impl Responder {
+    let cells = |keys: Vec<CellKey>| {
+        keys.into_iter()
+            .map(|key| {
+                SheetRange::new(
+                    &key.sheet,
+                    CellRange::new(key.row, key.col, key.row, key.col),
+                )
+            })
+            .collect()
+    };
+    trace(&data, &query, |conn, key| {
+        let mut direct = graph::direct_dependents(conn, key)?;
+        direct.sort();
+        Ok((cells(direct), cells(graph::all_dependents(conn, key)?)))
+    })
 }
 
-pub fn init_db(conn: &Connection) {
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS cells (
-            sheet TEXT NOT NULL DEFAULT 'default',
-            row INTEGER,
-            col INTEGER,
-            value TEXT,
-            font_weight TEXT,
-            font_style TEXT,
-            background_color TEXT,
-            PRIMARY KEY (sheet, row, col)
-        )",
-        [],
-    )
-    .unwrap();
+#[derive(Serialize, Deserialize)]
+struct EvalRequest {
+    expr: String,
+    sheet: Option<String>,
+}
+
+/// Recalculates the volatile formulas and everything depending on them,
+/// pushing the new values to every session.
+fn recalculate_volatile(data: &AppState) -> rusqlite::Result<Recalculation> {
+    let conn = data.db.lock().unwrap();
+    let recalculated = recalculate(&conn, &[])?;
+    for cell in &recalculated.cells {
+        broadcast_cell_update(&data.sessions, cell, "system".to_string());
+    }
+    Ok(recalculated)
+}
+
+/// Recalculates the volatile formulas, as Excel's F9 does.
+async fn recalculate_all(data: web::Data<AppState>) -> impl Responder {
+    match recalculate_volatile(&data) {
+        Ok(recalculated) => HttpResponse::Ok().json(SaveResponse::saved(&recalculated)),
+        Err(e) => {
+            eprintln!("Failed to recalculate: {}", e);
+            HttpResponse::InternalServerError().body("Failed to recalculate")
+        }
+    }
+}
+
+async fn get_calculation_settings(data: web::Data<AppState>) -> impl Responder {
+    let conn = match data.db.lock() {
+        Ok(conn) => conn,
+        Err(e) => {
+            eprintln!("Failed to acquire database lock: {}", e);
+            return HttpResponse::InternalServerError().body("Database lock error");
+        }
+    };
+    match settings::calculation(&conn) {
+        Ok(settings) => HttpResponse::Ok().json(settings),
+        Err(e) => {
+            eprintln!("Failed to load settings: {}", e);
+            HttpResponse::InternalServerError().body("Database query error")
+        }
+    }
+}
+
+/// Saves the workbook's calculation settings and recalculates every formula
+/// under them, so loops start or stop iterating straight away.
+async fn set_calculation_settings(
+    data: web::Data<AppState>,
+    item: web::Json<settings::Calculation>,
+) -> impl Responder {
+    if let Err(e) = item.validate() {
+        return HttpResponse::BadRequest().body(e);
+    }
+    let conn = match data.db.lock() {
+        Ok(conn) => conn,
+        Err(e) => {
+            eprintln!("Failed to acquire database lock: {}", e);
+            return HttpResponse::InternalServerError().body("Database lock error");
+        }
+    };
+    if let Err(e) = settings::save_calculation(&conn, &item) {
+        eprintln!("Failed to save settings: {}", e);
+        return HttpResponse::InternalServerError().body("Failed to save settings");
+    }
+    let recalculated = formula_cells(&conn).and_then(|cells| recalculate(&conn, &cells));
+    match recalculated {
+        Ok(recalculated) => {
+            for cell in &recalculated.cells {
+                broadcast_cell_update(&data.sessions, cell, "system".to_string());
+            }
+        }
+        Err(e) => {
+            eprintln!("Failed to recalculate: {}", e);
+            return HttpResponse::InternalServerError().body("Failed to recalculate");
+        }
+    }
+    HttpResponse::Ok().json(item.into_inner())
+}
+
+/// Every formula cell in the workbook.
+fn formula_cells(conn: &Connection) -> rusqlite::Result<Vec<CellKey>> {
+    conn.prepare("SELECT sheet, row, col FROM cells WHERE formula IS NOT NULL")?
+        .query_map([], |r| {
+            Ok(CellKey::new(&r.get::<_, String>(0)?, r.get(1)?, r.get(2)?))
+        })?
+        .collect()
+}
+
+/// The result of `/evaluate`. `value`, `display` and `value_type` are
+/// missing only when the formula produced no value at all, in which case
+/// `error` says why.
+#[derive(Serialize, Deserialize, Debug)]
+struct EvaluationResult {
+    value: serde_json::Value,
+    display: Option<String>,
+    value_type: Option<String>,
+    error: Option<EvaluationError>,
+    /// The cells the formula read, in the order it first read them.
+    cells_read: Vec<TracedRange>,
+}
+
+/// Why a formula failed, or gave an error value.
+#[derive(Serialize, Deserialize, Debug)]
+struct EvaluationError {
+    /// A stable code such as `PARSE_ERROR` or `DIVISION_BY_ZERO`.
+    code: String,
+    message: String,
+    /// The characters of the formula at fault, where known.
+    span: Option<formula::Span>,
+}
+
+impl EvaluationError {
+    fn new(code: &str, message: String, span: Option<formula::Span>) -> Self {
+        EvaluationError {
+            code: code.to_string(),
+            message,
+            span,
+        }
+    }
+
+    /// The error a formula failed with, pointing at the spot in `source`
+    /// where parsing stopped.
+    fn failure(error: &formula::FormulaError, source: &str) -> Self {
+        match error {
+            formula::FormulaError::Parse(e) => {
+                let end = (e.position + 1).min(source.chars().count()).max(e.position);
+                EvaluationError::new(
+                    "PARSE_ERROR",
+                    e.to_string(),
+                    Some(formula::Span::new(e.position, end)),
+                )
+            }
+            formula::FormulaError::Storage(e) => {
+                EvaluationError::new("STORAGE_ERROR", e.clone(), None)
+            }
+        }
+    }
+
+    /// The error value `origin` gave, as its first appearance in the formula.
+    fn value(error: CellError, origin: &formula::Step) -> Self {
+        let (code, problem) = match error {
+            CellError::Name if origin.function.is_some() => {
+                ("UNKNOWN_FUNCTION", "unknown function")
+            }
+            CellError::Name => ("UNKNOWN_NAME", "unknown name"),
+            CellError::Ref => ("REF_OUT_OF_RANGE", "reference out of range"),
+            CellError::Div0 => ("DIVISION_BY_ZERO", "division by zero"),
+            CellError::Value => ("WRONG_TYPE", "value of the wrong type"),
+            CellError::Num => ("INVALID_NUMBER", "number out of range"),
+            CellError::NA => ("NOT_AVAILABLE", "value not available"),
+            CellError::Null => ("EMPTY_INTERSECTION", "empty intersection"),
+            CellError::Circular => ("CIRCULAR_REFERENCE", "reference loop"),
+            CellError::Spill => ("SPILL_BLOCKED", "array blocked from spilling"),
+            CellError::Calc => ("CALCULATION_ERROR", "nothing to show"),
+        };
+        EvaluationError::new(
+            code,
+            format!("{} in {}", problem, origin.expression),
+            Some(origin.span),
+        )
+    }
+}
+
+/// Evaluates a formula outside any cell. Error values are results like any
+/// other, so they come back with 200 and an `error` saying where they
+/// appeared; a formula that does not parse is a 400.
+async fn evaluate(query: web::Json<EvalRequest>, data: web::Data<AppState>) -> impl Responder {
+    let conn = data.db.lock().unwrap();
+    let sheet = query.sheet.as_deref().unwrap_or("default");
+    match formula::trace(&query.expr, sheet, &conn) {
+        Ok(trace) => {
+            let value = &trace.root.value;
+            let error = match value {
+                Value::Error(e) => trace
+                    .root
+                    .first_error()
+                    .map(|origin| EvaluationError::value(*e, origin)),
+                _ => None,
+            };
+            HttpResponse::Ok().json(EvaluationResult {
+                value: json_value(value),
+                display: Some(value.display()),
+                value_type: Some(type_name(value).to_string()),
+                error,
+                cells_read: trace.read.into_iter().map(TracedRange::from).collect(),
+            })
+        }
+        Err(e) => failed(
+            &e,
+            EvaluationResult {
+                value: serde_json::Value::Null,
+                display: None,
+                value_type: None,
+                error: Some(EvaluationError::failure(&e, &query.expr)),
+                cells_read: Vec::new(),
+            },
+        ),
+    }
+}
+
+/// The response to a formula that failed with `error`: 400 if it does not
+/// parse, 500 if the database failed.
+fn failed(error: &formula::FormulaError, body: impl Serialize) -> HttpResponse {
+    match error {
+        formula::FormulaError::Parse(_) => HttpResponse::BadRequest().json(body),
+        formula::FormulaError::Storage(_) => HttpResponse::InternalServerError().json(body),
+    }
+}
+
+/// A value as JSON: numbers (dates as their serial numbers), text and
+/// booleans as themselves, blanks as `null`, errors by their code and
+/// arrays as a list of rows.
+fn json_value(value: &Value) -> serde_json::Value {
+    match value {
+        Value::Empty => serde_json::Value::Null,
+        Value::Number(n) | Value::Date(n) => serde_json::json!(n),
+        Value::Text(text) => serde_json::json!(text),
+        Value::Bool(b) => serde_json::json!(b),
+        Value::Error(e) => serde_json::json!(e.code()),
+        Value::Array(array) => array
+            .values
+            .chunks(array.cols.max(1))
+            .map(|row| row.iter().map(json_value).collect::<serde_json::Value>())
+            .collect(),
+    }
+}
+
+/// The type of a value, as `value_type` names it, or `array`.
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Array(_) => "array",
+        value => value.value_type().as_str(),
+    }
+}
+
+/// A step of a traced formula: a sub-expression, the cells it refers to,
+/// its value, and the steps that worked it out.
+#[derive(Serialize, Deserialize, Debug)]
+struct TraceStep {
+    expression: String,
+    span: formula::Span,
+    reference: Option<TracedRange>,
+    value: serde_json::Value,
+    display: String,
+    value_type: String,
+    steps: Vec<TraceStep>,
+}
+
+impl From<&formula::Step> for TraceStep {
+    fn from(step: &formula::Step) -> Self {
+        TraceStep {
+            expression: step.expression.clone(),
+            span: step.span,
+            reference: step.reference.clone().map(TracedRange::from),
+            value: json_value(&step.value),
+            display: step.value.display(),
+            value_type: type_name(&step.value).to_string(),
+            steps: step.steps.iter().map(TraceStep::from).collect(),
+        }
+    }
+}
+
+/// Where an error first appeared in a traced formula.
+#[derive(Serialize, Deserialize, Debug)]
+struct ErrorOrigin {
+    expression: String,
+    span: formula::Span,
+    error: String,
+}
+
+/// A formula's evaluation tree, rooted at the whole formula.
+#[derive(Serialize, Deserialize, Debug)]
+struct EvaluationTrace {
+    root: TraceStep,
+    first_error: Option<ErrorOrigin>,
+}
+
+/// Why a formula could not be traced, as `/evaluate` reports it.
+#[derive(Serialize, Deserialize, Debug)]
+struct TraceFailure {
+    error: EvaluationError,
+}
+
+/// Evaluates a formula like `/evaluate`, returning every step on the way.
+async fn evaluate_trace(
+    query: web::Json<EvalRequest>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let conn = data.db.lock().unwrap();
+    let sheet = query.sheet.as_deref().unwrap_or("default");
+    match formula::trace(&query.expr, sheet, &conn) {
+        Ok(formula::Trace { root, .. }) => HttpResponse::Ok().json(EvaluationTrace {
+            first_error: root.first_error().map(|step| ErrorOrigin {
+                expression: step.expression.clone(),
+                span: step.span,
+                error: step.value.display(),
+            }),
+            root: TraceStep::from(&root),
+        }),
+        Err(e) => failed(
+            &e,
+            TraceFailure {
+                error: EvaluationError::failure(&e, &query.expr),
+            },
+        ),
+    }
+}
+
+/// A formula saved under a name, such as `Revenue` for `=Data!B2:B500`.
+/// It may be a reference, a constant, any other expression, or a `LAMBDA`
+/// that formulas call like a built-in function.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct DefinedName {
+    name: String,
+    formula: String,
+    /// The sheet whose formulas see the name, or `None` for the whole
+    /// workbook. A sheet's own name hides a workbook name spelled the same.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    scope: Option<String>,
+}
+
+impl DefinedName {
+    fn from_row(r: &rusqlite::Row) -> rusqlite::Result<Self> {
+        let scope: String = r.get(2)?;
+        Ok(DefinedName {
+            name: r.get(0)?,
+            formula: r.get(1)?,
+            scope: (!scope.is_empty()).then_some(scope),
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct NameScope {
+    scope: Option<String>,
+}
+
+async fn list_names(data: web::Data<AppState>) -> impl Responder {
+    let conn = match data.db.lock() {
+        Ok(conn) => conn,
+        Err(e) => {
+            eprintln!("Failed to acquire database lock: {}", e);
+            return HttpResponse::InternalServerError().body("Database lock error");
+        }
+    };
+    let names = conn
+        .prepare("SELECT name, formula, scope FROM names ORDER BY scope, name")
+        .and_then(|mut stmt| {
+            stmt.query_map([], DefinedName::from_row)?
+                .collect::<rusqlite::Result<Vec<_>>>()
+        });
+    match names {
+        Ok(names) => HttpResponse::Ok().json(names),
+        Err(e) => {
+            eprintln!("Failed to query names: {}", e);
+            HttpResponse::InternalServerError().body("Database query error")
+        }
+    }
+}
+
+async fn get_name(
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    query: web::Query<NameScope>,
+) -> impl Responder {
+    let conn = match data.db.lock() {
+        Ok(conn) => conn,
+        Err(e) => {
+            eprintln!("Failed to acquire database lock: {}", e);
+            return HttpResponse::InternalServerError().body("Database lock error");
+        }
+    };
+    let name = conn
+        .query_row(
+            "SELECT name, formula, scope FROM names WHERE name = ?1 AND scope = ?2",
+            params![path.as_str(), query.scope.as_deref().unwrap_or("")],
+            DefinedName::from_row,
+        )
+        .optional();
+    match name {
+        Ok(Some(name)) => HttpResponse::Ok().json(name),
+        Ok(None) => HttpResponse::NotFound().body(format!("No name {}", path)),
+        Err(e) => {
+            eprintln!("Failed to query name: {}", e);
+            HttpResponse::InternalServerError().body("Database query error")
+        }
+    }
+}
+
+/// Saves a formula under a name, replacing any formula already saved under
+/// it in the same scope, and recalculates the formulas using it.
+async fn set_name(data: web::Data<AppState>, item: web::Json<DefinedName>) -> impl Responder {
+    if !formula::is_valid_name(&item.name) {
+        return HttpResponse::BadRequest().body(format!("Invalid name: {}", item.name));
+    }
+    if let Err(e) = formula::parse(&item.formula) {
+        return HttpResponse::BadRequest().body(format!("Formula error: {}", e));
+    }
+
+    let conn = match data.db.lock() {
+        Ok(conn) => conn,
+        Err(e) => {
+            eprintln!("Failed to acquire database lock: {}", e);
+            return HttpResponse::InternalServerError().body("Database lock error");
+        }
+    };
+    match table::find(&conn, &item.name) {
+        Ok(None) => {}
+        Ok(Some(table)) => {
+            return HttpResponse::Conflict().body(format!("{} is a table", table.name));
+        }
+        Err(e) => {
+            eprintln!("Failed to query tables: {}", e);
+            return HttpResponse::InternalServerError().body("Database query error");
+        }
+    }
+    if let Err(e) = conn.execute(
+        "INSERT INTO names (name, scope, formula) VALUES (?1, ?2, ?3)
+         ON CONFLICT(name, scope) DO UPDATE SET name = excluded.name, formula = excluded.formula",
+        params![item.name, item.scope.as_deref().unwrap_or(""), item.formula],
+    ) {
+        eprintln!("Failed to save name: {}", e);
+        return HttpResponse::InternalServerError().body("Failed to save name");
+    }
+    match refresh_name(&conn, &item.name) {
+        Ok(recalculated) => {
+            for cell in &recalculated.cells {
+                broadcast_cell_update(&data.sessions, cell, "system".to_string());
+            }
+        }
+        Err(e) => {
+            eprintln!("Failed to recalculate dependents: {}", e);
+            return HttpResponse::InternalServerError().body("Failed to recalculate dependents");
+        }
+    }
+    HttpResponse::Ok().json(item.into_inner())
+}
+
+/// Deletes a name; formulas still using it show `#NAME?`.
+async fn delete_name(
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    query: web::Query<NameScope>,
+) -> impl Responder {
+    let conn = match data.db.lock() {
+        Ok(conn) => conn,
+        Err(e) => {
+            eprintln!("Failed to acquire database lock: {}", e);
+            return HttpResponse::InternalServerError().body("Database lock error");
+        }
+    };
+    let deleted = conn.execute(
+        "DELETE FROM names WHERE name = ?1 AND scope = ?2",
+        params![path.as_str(), query.scope.as_deref().unwrap_or("")],
+    );
+    match deleted {
+        Ok(0) => return HttpResponse::NotFound().body(format!("No name {}", path)),
+        Ok(_) => {}
+        Err(e) => {
+            eprintln!("Failed to delete name: {}", e);
+            return HttpResponse::InternalServerError().body("Failed to delete name");
+        }
+    }
+    match refresh_name(&conn, &path) {
+        Ok(recalculated) => {
+            for cell in &recalculated.cells {
+                broadcast_cell_update(&data.sessions, cell, "system".to_string());
+            }
+            HttpResponse::Ok().json(SaveResponse::saved(&recalculated))
+        }
+        Err(e) => {
+            eprintln!("Failed to recalculate dependents: {}", e);
+            HttpResponse::InternalServerError().body("Failed to recalculate dependents")
+        }
+    }
+}
+
+/// A range declared as a table, whose header row names its columns so
+/// formulas can read them as `Sales[Amount]`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct TableDefinition {
+    name: String,
+    sheet: Option<String>,
+    /// The header row and the data below it.
+    range: CellRange,
+    /// The header cells' text, left to right. Ignored when creating a table.
+    #[serde(default)]
+    columns: Vec<String>,
+}
+
+impl TableDefinition {
+    fn new(conn: &Connection, table: table::Table) -> rusqlite::Result<Self> {
+        Ok(TableDefinition {
+            columns: table::columns(conn, &table)?,
+            name: table.name,
+            sheet: Some(table.sheet),
+            range: table.range,
+        })
+    }
+}
+
+async fn list_tables(data: web::Data<AppState>) -> impl Responder {
+    let conn = match data.db.lock() {
+        Ok(conn) => conn,
+        Err(e) => {
+            eprintln!("Failed to acquire database lock: {}", e);
+            return HttpResponse::InternalServerError().body("Database lock error");
+        }
+    };
+    let tables = table::list(&conn).and_then(|tables| {
+        tables
+            .into_iter()
+            .map(|table| TableDefinition::new(&conn, table))
+            .collect::<rusqlite::Result<Vec<_>>>()
+    });
+    match tables {
+        Ok(tables) => HttpResponse::Ok().json(tables),
+        Err(e) => {
+            eprintln!("Failed to query tables: {}", e);
+            HttpResponse::InternalServerError().body("Database query error")
+        }
+    }
+}
+
+/// Declares a range as a table. Its first row must hold a distinct name for
+/// each column, and at least one data row must follow.
+async fn create_table(
+    data: web::Data<AppState>,
+    item: web::Json<TableDefinition>,
+) -> impl Responder {
+    if !formula::is_valid_name(&item.name) {
+        return HttpResponse::BadRequest().body(format!("Invalid name: {}", item.name));
+    }
+    let range = item.range;
+    if range.start_row < 0
+        || range.start_col < 0
+        || range.end_row >= formula::MAX_ROWS
+        || range.end_col >= formula::MAX_COLS
+        || range.rows() < 2
+        || range.cols() < 1
+    {
+        return HttpResponse::BadRequest()
+            .body("A table needs a header row and at least one data row on the sheet");
+    }
+    let new = table::Table {
+        name: item.name.clone(),
+        sheet: item.sheet.clone().unwrap_or_else(|| "default".to_string()),
+        range,
+    };
+
+    let conn = match data.db.lock() {
+        Ok(conn) => conn,
+        Err(e) => {
+            eprintln!("Failed to acquire database lock: {}", e);
+            return HttpResponse::InternalServerError().body("Database lock error");
+        }
+    };
+    let taken = conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM names WHERE name = ?1)
+             OR EXISTS (SELECT 1 FROM tables WHERE name = ?1)",
+        params![new.name],
+        |r| r.get::<_, bool>(0),
+    );
+    let conflict = match taken {
+        Ok(true) => Some(format!("The name {} is already in use", new.name)),
+        Ok(false) => match table::overlapping(&conn, &new.sheet, &range, &new.name) {
+            Ok(Some(other)) => Some(format!("The range overlaps the table {}", other.name)),
+            Ok(None) => None,
+            Err(e) => {
+                eprintln!("Failed to query tables: {}", e);
+                return HttpResponse::InternalServerError().body("Database query error");
+            }
+        },
+        Err(e) => {
+            eprintln!("Failed to query names: {}", e);
+            return HttpResponse::InternalServerError().body("Database query error");
+        }
+    };
+    if let Some(conflict) = conflict {
+        return HttpResponse::Conflict().body(conflict);
+    }
+
+    let columns = match table::columns(&conn, &new) {
+        Ok(columns) => columns,
+        Err(e) => {
+            eprintln!("Failed to read table headers: {}", e);
+            return HttpResponse::InternalServerError().body("Database query error");
+        }
+    };
+    let mut seen = HashSet::new();
+    if let Some(column) = columns
+        .iter()
+        .find(|column| column.is_empty() || !seen.insert(column.to_lowercase()))
+    {
+        return HttpResponse::BadRequest().body(if column.is_empty() {
+            "Every column needs a header".to_string()
+        } else {
+            format!("The header {} is used twice", column)
+        });
+    }
+
+    if let Err(e) = table::save(&conn, &new) {
+        eprintln!("Failed to save table: {}", e);
+        return HttpResponse::InternalServerError().body("Failed to save table");
+    }
+    // Formulas may have used the name before the table existed.
+    match refresh_name(&conn, &new.name) {
+        Ok(recalculated) => {
+            for cell in &recalculated.cells {
+                broadcast_cell_update(&data.sessions, cell, "system".to_string());
+            }
+        }
+        Err(e) => {
+            eprintln!("Failed to recalculate dependents: {}", e);
+            return HttpResponse::InternalServerError().body("Failed to recalculate dependents");
+        }
+    }
+    HttpResponse::Ok().json(TableDefinition {
+        sheet: Some(new.sheet),
+        columns,
+        ..item.into_inner()
+    })
 }
 
-// WebSocket endpoint
-async fn ws_index(
-    req: HttpRequest,
-    stream: web::Payload,
-    data: web::Data<AppState>,
-) -> Result<HttpResponse, actix_web::Error> {
-    let session_id = Uuid::new_v4().to_string();
-    let sessions = data.sessions.clone();
+/// Grows each table over the rows of `cells` that continue it, so rows
+/// pasted directly below a table join it. Returns the tables that grew.
+fn extend_tables(conn: &Connection, cells: &[Cell]) -> rusqlite::Result<Vec<String>> {
+    let mut extended = Vec::new();
+    for mut table in table::list(conn)? {
+        let range = table.range;
+        let rows: HashSet<i32> = cells
+            .iter()
+            .filter(|cell| {
+                cell.sheet.as_deref() == Some(&table.sheet)
+                    && (range.start_col..=range.end_col).contains(&cell.col)
+                    && (cell.formula.is_some() || !cell.value.is_empty())
+            })
+            .map(|cell| cell.row)
+            .collect();
+        let mut end_row = range.end_row;
+        while rows.contains(&(end_row + 1)) {
+            end_row += 1;
+        }
+        if end_row == range.end_row {
+            continue;
+        }
+        table.range.end_row = end_row;
+        // A table never grows into another.
+        if table::overlapping(conn, &table.sheet, &table.range, &table.name)?.is_some() {
+            continue;
+        }
+        table::save(conn, &table)?;
+        extended.push(table.name);
+    }
+    Ok(extended)
+}
+
+#[derive(Serialize, Deserialize)]
+struct ClearRequest {
+    cells: Vec<CellPosition>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CellPosition {
+    sheet: Option<String>,
+    row: i32,
+    col: i32,
+}
+
+async fn clear_cells_bulk(
+    data: web::Data<AppState>,
+    request: web::Json<ClearRequest>,
+) -> impl Responder {
+    let conn = match data.db.lock() {
+        Ok(conn) => conn,
+        Err(e) => {
+            eprintln!("Failed to acquire database lock: {}", e);
+            return HttpResponse::InternalServerError().body("Database lock error");
+        }
+    };
+
+    // Start transaction for better performance
+    let tx = match conn.unchecked_transaction() {
+        Ok(tx) => tx,
+        Err(e) => {
+            eprintln!("Failed to start transaction: {}", e);
+            return HttpResponse::InternalServerError().body("Transaction error");
+        }
+    };
+
+    let mut cleared = Vec::with_capacity(request.cells.len());
+    let mut retired = Vec::new();
+    for pos in request.cells.iter() {
+        let key = CellKey::new(pos.sheet.as_deref().unwrap_or("default"), pos.row, pos.col);
+        // Spilled cells belong to their formula, and go when it is cleared.
+        match spilled_from(&tx, &key) {
+            Ok(None) => {}
+            Ok(Some(_)) => continue,
+            Err(e) => {
+                eprintln!("Failed to load cell: {}", e);
+                return HttpResponse::InternalServerError().body("Failed to clear cells");
+            }
+        }
+        match retire_spill(&tx, &key, None).and_then(|cells| {
+            tx.execute(
+                "DELETE FROM cells WHERE sheet = ?1 AND row = ?2 AND col = ?3",
+                params![key.sheet, key.row, key.col],
+            )?;
+            graph::clear_precedents(&tx, &key)?;
+            Ok(cells)
+        }) {
+            Ok(cells) => {
+                cleared.extend(cells.iter().map(Cell::key));
+                retired.extend(cells);
+            }
+            Err(e) => {
+                eprintln!("Failed to delete cell: {}", e);
+                return HttpResponse::InternalServerError().body("Failed to clear cells");
+            }
+        }
+        cleared.push(key);
+    }
+
+    let recalculated = match recalculate(&tx, &cleared) {
+        Ok(cells) => cells,
+        Err(e) => {
+            eprintln!("Failed to recalculate dependents: {}", e);
+            return HttpResponse::InternalServerError().body("Failed to recalculate dependents");
+        }
+    };
+
+    if let Err(e) = tx.commit() {
+        eprintln!("Failed to commit transaction: {}", e);
+        return HttpResponse::InternalServerError().body("Failed to commit changes");
+    }
+
+    let recalculated_keys: HashSet<CellKey> = recalculated.cells.iter().map(Cell::key).collect();
+    for cell in &retired {
+        if !recalculated_keys.contains(&cell.key()) {
+            broadcast_cell_update(&data.sessions, cell, "system".to_string());
+        }
+    }
+    for cell in &recalculated.cells {
+        broadcast_cell_update(&data.sessions, cell, "system".to_string());
+    }
+
+    HttpResponse::Ok().body("cleared")
+}
+
+/// Largest destination `/cells/copy` will fill in one request.
+const MAX_COPY_CELLS: i64 = 100_000;
+
+#[derive(Serialize, Deserialize)]
+struct CopyRequest {
+    sheet: Option<String>,
+    source: CellRange,
+    /// Sheet to paste onto; the source sheet if omitted.
+    destination_sheet: Option<String>,
+    /// Filled by repeating the source block. A single cell takes the whole
+    /// block with that cell as its top-left corner.
+    destination: CellRange,
+}
+
+/// `range` with its corners in order, if it lies within the sheet.
+fn on_sheet(range: &CellRange) -> Option<CellRange> {
+    let range = CellRange::new(
+        range.start_row,
+        range.start_col,
+        range.end_row,
+        range.end_col,
+    );
+    (range.start_row >= 0
+        && range.start_col >= 0
+        && range.end_row < formula::MAX_ROWS
+        && range.end_col < formula::MAX_COLS)
+        .then_some(range)
+}
+
+fn load_cells(conn: &Connection, sheet: &str, range: &CellRange) -> rusqlite::Result<Vec<Cell>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM cells
+         WHERE sheet = ?1 AND row BETWEEN ?2 AND ?3 AND col BETWEEN ?4 AND ?5",
+        CELL_COLUMNS
+    ))?;
+    stmt.query_map(
+        params![
+            sheet,
+            range.start_row,
+            range.end_row,
+            range.start_col,
+            range.end_col
+        ],
+        Cell::from_row,
+    )?
+    .collect()
+}
+
+/// Pastes the source block over `destination`, shifting the relative
+/// references of copied formulas by how far each one moved. Blank source
+/// cells clear their destination, and spilled source cells paste as plain
+/// values. Returns the cells written, and every destination position as the
+/// cells that changed.
+fn copy_cells(
+    conn: &Connection,
+    sheet: &str,
+    source: &CellRange,
+    destination_sheet: &str,
+    destination: &CellRange,
+) -> rusqlite::Result<(Vec<Cell>, Vec<CellKey>)> {
+    let originals: HashMap<(i32, i32), Cell> = load_cells(conn, sheet, source)?
+        .into_iter()
+        .map(|cell| ((cell.row, cell.col), cell))
+        .collect();
+
+    let mut written = Vec::new();
+    let mut changed = Vec::new();
+    let mut formulas = Vec::new();
+    for row in destination.start_row..=destination.end_row {
+        for col in destination.start_col..=destination.end_col {
+            let from_row = source.start_row + (row - destination.start_row) % source.rows();
+            let from_col = source.start_col + (col - destination.start_col) % source.cols();
+            let key = CellKey::new(destination_sheet, row, col);
+            let retired = retire_spill(conn, &key, None)?;
+            changed.extend(retired.iter().map(Cell::key));
+            written.extend(retired);
+            match originals.get(&(from_row, from_col)) {
+                Some(original) => {
+                    let mut cell = Cell {
+                        sheet: Some(destination_sheet.to_string()),
+                        row,
+                        col,
+                        spill: None,
+                        spilled_from: None,
+                        number: None,
+                        ..original.clone()
+                    };
+                    if let Some(formula) = &original.formula {
+                        cell.formula = Some(formula::shift_references(
+                            formula,
+                            row - from_row,
+                            col - from_col,
+                        ));
+                    }
+                    upsert_cell(conn, &cell)?;
+                    track_dependencies(conn, &cell)?;
+                    match cell.formula {
+                        Some(_) => formulas.push(cell),
+                        None => written.push(cell),
+                    }
+                }
+                None => {
+                    conn.execute(
+                        "DELETE FROM cells WHERE sheet = ?1 AND row = ?2 AND col = ?3",
+                        params![key.sheet, key.row, key.col],
+                    )?;
+                    graph::clear_precedents(conn, &key)?;
+                }
+            }
+            changed.push(key);
+        }
+    }
+
+    // Formulas are evaluated once the whole block is in place, so that
+    // their spills see the pasted cells.
+    for mut cell in formulas {
+        let key = cell.key();
+        let value = eval_formula(cell.formula.as_deref().unwrap_or_default(), &key, conn);
+        let spilled = set_result(conn, &mut cell, value)?;
+        store_value(conn, &cell)?;
+        changed.extend(spilled.iter().map(Cell::key));
+        written.extend(spilled);
+        written.push(cell);
+    }
+    Ok((written, changed))
+}
+
+/// A spilled cell in `range` whose formula lies outside it, so would be
+/// pasted over while its formula stays.
+fn spill_inside(
+    conn: &Connection,
+    sheet: &str,
+    range: &CellRange,
+) -> rusqlite::Result<Option<(CellKey, Anchor)>> {
+    Ok(load_cells(conn, sheet, range)?
+        .into_iter()
+        .find_map(|cell| {
+            let anchor = cell.spilled_from?;
+            (!contains(range, anchor.row, anchor.col)).then(|| (cell.key(), anchor))
+        }))
+}
+
+async fn copy_cells_range(
+    data: web::Data<AppState>,
+    request: web::Json<CopyRequest>,
+) -> impl Responder {
+    let (Some(source), Some(destination)) =
+        (on_sheet(&request.source), on_sheet(&request.destination))
+    else {
+        return HttpResponse::BadRequest().body("Ranges must lie within the sheet");
+    };
+    let destination = if destination.is_single() {
+        CellRange::new(
+            destination.start_row,
+            destination.start_col,
+            destination.start_row + source.rows() - 1,
+            destination.start_col + source.cols() - 1,
+        )
+    } else {
+        destination
+    };
+    let Some(destination) = on_sheet(&destination) else {
+        return HttpResponse::BadRequest().body("Ranges must lie within the sheet");
+    };
+    if destination.rows() as i64 * destination.cols() as i64 > MAX_COPY_CELLS {
+        return HttpResponse::BadRequest().body(format!(
+            "Cannot fill more than {} cells at once",
+            MAX_COPY_CELLS
+        ));
+    }
+    let sheet = request.sheet.as_deref().unwrap_or("default");
+    let destination_sheet = request.destination_sheet.as_deref().unwrap_or(sheet);
+
+    let conn = match data.db.lock() {
+        Ok(conn) => conn,
+        Err(e) => {
+            eprintln!("Failed to acquire database lock: {}", e);
+            return HttpResponse::InternalServerError().body("Database lock error");
+        }
+    };
+
+    let tx = match conn.unchecked_transaction() {
+        Ok(tx) => tx,
+        Err(e) => {
+            eprintln!("Failed to start transaction: {}", e);
+            return HttpResponse::InternalServerError().body("Transaction error");
+        }
+    };
+
+    match spill_inside(&tx, destination_sheet, &destination) {
+        Ok(None) => {}
+        Ok(Some((key, anchor))) => return spilled_conflict(&key, anchor),
+        Err(e) => {
+            eprintln!("Failed to load cells: {}", e);
+            return HttpResponse::InternalServerError().body("Failed to copy cells");
+        }
+    }
+
+    let (written, changed) = match copy_cells(&tx, sheet, &source, destination_sheet, &destination)
+    {
+        Ok(result) => result,
+        Err(e) => {
+            eprintln!("Failed to copy cells: {}", e);
+            return HttpResponse::InternalServerError().body("Failed to copy cells");
+        }
+    };
+
+    let recalculated = match recalculate(&tx, &changed) {
+        Ok(cells) => cells,
+        Err(e) => {
+            eprintln!("Failed to recalculate dependents: {}", e);
+            return HttpResponse::InternalServerError().body("Failed to recalculate dependents");
+        }
+    };
+
+    if let Err(e) = tx.commit() {
+        eprintln!("Failed to commit transaction: {}", e);
+        return HttpResponse::InternalServerError().body("Failed to commit changes");
+    }
+
+    let recalculated_keys: HashSet<CellKey> = recalculated.cells.iter().map(Cell::key).collect();
+    for cell in &written {
+        if !recalculated_keys.contains(&cell.key()) {
+            broadcast_cell_update(&data.sessions, cell, "system".to_string());
+        }
+    }
+    for cell in &recalculated.cells {
+        broadcast_cell_update(&data.sessions, cell, "system".to_string());
+    }
+
+    HttpResponse::Ok().json(SaveResponse::saved(&recalculated))
+}
+
+pub fn init_db(conn: &Connection) {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS cells (
+            sheet TEXT NOT NULL DEFAULT 'default',
+            row INTEGER,
+            col INTEGER,
+            value TEXT,
+            formula TEXT,
+            value_type TEXT,
+            font_weight TEXT,
+            font_style TEXT,
+            background_color TEXT,
+            number REAL,
+            spill_rows INTEGER,
+            spill_cols INTEGER,
+            anchor_row INTEGER,
+            anchor_col INTEGER,
+            PRIMARY KEY (sheet, row, col)
+        )",
+        [],
+    )
+    .unwrap();
+
+    // Names used to be workbook-wide only, keyed on the name alone.
+    let unscoped = has_table(conn, "names") && !has_column(conn, "names", "scope");
+    if unscoped {
+        conn.execute("ALTER TABLE names RENAME TO unscoped_names", [])
+            .unwrap();
+    }
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS names (
+            name TEXT NOT NULL COLLATE NOCASE,
+            scope TEXT NOT NULL DEFAULT '',
+            formula TEXT NOT NULL,
+            PRIMARY KEY (name, scope)
+        )",
+        [],
+    )
+    .unwrap();
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS calculation_settings (
+            id INTEGER PRIMARY KEY CHECK (id = 1),
+            iterative INTEGER NOT NULL,
+            max_iterations INTEGER NOT NULL,
+            max_change REAL NOT NULL
+        )",
+        [],
+    )
+    .unwrap();
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS tables (
+            name TEXT PRIMARY KEY COLLATE NOCASE,
+            sheet TEXT NOT NULL,
+            start_row INTEGER NOT NULL,
+            start_col INTEGER NOT NULL,
+            end_row INTEGER NOT NULL,
+            end_col INTEGER NOT NULL
+        )",
+        [],
+    )
+    .unwrap();
+    if unscoped {
+        conn.execute_batch(
+            "INSERT INTO names (name, formula) SELECT name, formula FROM unscoped_names;
+             DROP TABLE unscoped_names;",
+        )
+        .unwrap();
+    }
+
+    // Databases created before a column existed get it added in place.
+    ensure_column(conn, "cells", "formula", "TEXT");
+    ensure_column(conn, "cells", "value_type", "TEXT");
+    ensure_column(conn, "cells", "number", "REAL");
+    ensure_column(conn, "cells", "spill_rows", "INTEGER");
+    ensure_column(conn, "cells", "spill_cols", "INTEGER");
+    ensure_column(conn, "cells", "anchor_row", "INTEGER");
+    ensure_column(conn, "cells", "anchor_col", "INTEGER");
+
+    graph::rebuild(conn).unwrap();
+}
+
+fn has_table(conn: &Connection, table: &str) -> bool {
+    conn.query_row(
+        "SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?1",
+        params![table],
+        |_| Ok(()),
+    )
+    .optional()
+    .unwrap()
+    .is_some()
+}
+
+fn has_column(conn: &Connection, table: &str, column: &str) -> bool {
+    let mut stmt = conn
+        .prepare(&format!("PRAGMA table_info({})", table))
+        .unwrap();
+    stmt.query_map([], |r| r.get::<_, String>(1))
+        .unwrap()
+        .filter_map(Result::ok)
+        .any(|name| name == column)
+}
+
+fn ensure_column(conn: &Connection, table: &str, column: &str, definition: &str) {
+    if !has_column(conn, table, column) {
+        conn.execute(
+            &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition),
+            [],
+        )
+        .unwrap();
+    }
+}
+
+// WebSocket endpoint
+async fn ws_index(
+    req: HttpRequest,
+    stream: web::Payload,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let session_id = Uuid::new_v4().to_string();
+    let sessions = data.sessions.clone();
+
+    ws::start(
+        WebSocketSession {
+            id: session_id,
+            sessions,
+        },
+        &req,
+        stream,
+    )
+}
+
+// Helper function to broadcast cell updates
+fn broadcast_cell_update(
+    sessions: &Arc<Mutex<HashMap<String, Addr<WebSocketSession>>>>,
+    cell: &Cell,
+    user_id: String,
+) {
+    let update = CellUpdate {
+        sheet: cell.sheet.clone().unwrap_or_else(|| "default".to_string()),
+        row: cell.row,
+        col: cell.col,
+        value: cell.value.clone(),
+        formula: cell.formula.clone(),
+        value_type: cell.value_type,
+        font_weight: cell.font_weight.clone(),
+        font_style: cell.font_style.clone(),
+        background_color: cell.background_color.clone(),
+        spill: cell.spill,
+        spilled_from: cell.spilled_from,
+        user_id,
+    };
+
+    if let Ok(msg_str) = serde_json::to_string(&update) {
+        let sessions_guard = sessions.lock().unwrap();
+        for (_, addr) in sessions_guard.iter() {
+            addr.do_send(WebSocketMessage(msg_str.clone()));
+        }
+    }
+}
+
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+    let conn = Connection::open("cells.db").unwrap();
+    init_db(&conn);
+
+    let data = web::Data::new(AppState {
+        db: Mutex::new(conn),
+        sessions: Arc::new(Mutex::new(HashMap::new())),
+    });
+
+    // Volatile formulas such as NOW() are also refreshed every
+    // RECALC_INTERVAL_SECS seconds, if set.
+    let interval = std::env::var("RECALC_INTERVAL_SECS")
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .filter(|&secs| secs > 0);
+    if let Some(secs) = interval {
+        let data = data.clone();
+        actix_web::rt::spawn(async move {
+            let mut timer = actix_web::rt::time::interval(Duration::from_secs(secs));
+            loop {
+                timer.tick().await;
+                if let Err(e) = recalculate_volatile(&data) {
+                    eprintln!("Failed to recalculate: {}", e);
+                }
+            }
+        });
+    }
+
+    HttpServer::new(move || {
+        App::new()
+            .wrap(
+                Cors::default()
+                    .allowed_origin("http://localhost:3000")
+                    .allowed_origin("http://127.0.0.1:3000")
+                    .allowed_origin("http://192.168.10.161:3000")
+                    .allow_any_method()
+                    .allow_any_header()
+                    .supports_credentials(),
+            )
+            .app_data(data.clone())
+            .route("/health", web::get().to(health))
+            .route("/cells", web::get().to(list_cells))
+            .route("/cells", web::post().to(set_cell))
+            .route("/cells/bulk", web::post().to(set_cells_bulk))
+            .route("/cells/clear", web::post().to(clear_cells_bulk))
+            .route("/cells/copy", web::post().to(copy_cells_range))
+            .route("/cells/precedents", web::get().to(cell_precedents))
+            .route("/cells/dependents", web::get().to(cell_dependents))
+            .route("/evaluate", web::post().to(evaluate))
+            .route("/evaluate/trace", web::post().to(evaluate_trace))
+            .route("/recalculate", web::post().to(recalculate_all))
+            .route(
+                "/settings/calculation",
+                web::get().to(get_calculation_settings),
+            )
+            .route(
+                "/settings/calculation",
+                web::post().to(set_calculation_settings),
+            )
+            .route("/names", web::get().to(list_names))
+            .route("/names", web::post().to(set_name))
+            .route("/names/{name}", web::get().to(get_name))
+            .route("/names/{name}", web::delete().to(delete_name))
+            .route("/tables", web::get().to(list_tables))
+            .route("/tables", web::post().to(create_table))
+            .route("/ws", web::get().to(ws_index))
+            .route("/ws", web::get().to(ws_index)) // WebSocket route
+    })
+    .bind(("0.0.0.0", 6889))?
+    .run()
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_http::Request;
+    use actix_web::dev::{Service, ServiceResponse};
+    use actix_web::http::StatusCode;
+    use actix_web::{body::to_bytes, test};
+
+    /// A cell on `sheet` with `value` typed into it and no formatting.
+    fn input(sheet: &str, row: i32, col: i32, value: &str) -> Cell {
+        Cell {
+            sheet: Some(sheet.into()),
+            row,
+            col,
+            value: value.into(),
+            formula: None,
+            value_type: None,
+            font_weight: None,
+            font_style: None,
+            background_color: None,
+            spill: None,
+            spilled_from: None,
+            number: None,
+        }
+    }
+
+    /// Posts `body` to `uri` as JSON, returning the response's status.
+    async fn post(
+        app: &impl Service<Request, Response = ServiceResponse, Error = actix_web::Error>,
+        uri: &str,
+        body: impl Serialize,
+    ) -> StatusCode {
+        let req = test::TestRequest::post()
+            .uri(uri)
+            .set_json(body)
+            .to_request();
+        test::call_service(app, req).await.status()
+    }
+
+    /// The cells `GET /cells` lists for `sheet`.
+    async fn cells(
+        app: &impl Service<Request, Response = ServiceResponse, Error = actix_web::Error>,
+        sheet: &str,
+    ) -> Vec<Cell> {
+        let req = test::TestRequest::get()
+            .uri(&format!("/cells?sheet={}", sheet))
+            .to_request();
+        test::call_and_read_body_json(app, req).await
+    }
+
+    /// The values shown on `sheet`, by row and column.
+    async fn values(
+        app: &impl Service<Request, Response = ServiceResponse, Error = actix_web::Error>,
+        sheet: &str,
+    ) -> HashMap<(i32, i32), String> {
+        cells(app, sheet)
+            .await
+            .into_iter()
+            .map(|c| ((c.row, c.col), c.value))
+            .collect()
+    }
+
+    #[actix_rt::test]
+    async fn health_works() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_db(&conn);
+        let data = web::Data::new(AppState {
+            db: Mutex::new(conn),
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+        });
+        let app = test::init_service(
+            App::new()
+                .app_data(data.clone())
+                .route("/health", web::get().to(health))
+                .route("/cells", web::get().to(list_cells))
+                .route("/cells", web::post().to(set_cell))
+                .route("/evaluate", web::post().to(evaluate)),
+        )
+        .await;
+        let req = test::TestRequest::get().uri("/health").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+    }
+
+    #[actix_rt::test]
+    async fn create_and_list_cells() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_db(&conn);
+        let data = web::Data::new(AppState {
+            db: Mutex::new(conn),
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+        });
+        let app = test::init_service(
+            App::new()
+                .app_data(data.clone())
+                .route("/health", web::get().to(health))
+                .route("/cells", web::get().to(list_cells))
+                .route("/cells", web::post().to(set_cell)),
+        )
+        .await;
+
+        let new_cell = input("test", 1, 1, "42");
+        assert!(post(&app, "/cells", &new_cell).await.is_success());
+
+        let req = test::TestRequest::get()
+            .uri("/cells?sheet=test")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+        let bytes = to_bytes(resp.into_body()).await.unwrap();
+        let cells: Vec<Cell> = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(cells.len(), 1);
+        assert_eq!(cells[0].value, "42");
+    }
+
+    #[actix_rt::test]
+    async fn evaluate_formula() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_db(&conn);
+        let data = web::Data::new(AppState {
+            db: Mutex::new(conn),
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+        });
+        let app = test::init_service(
+            App::new()
+                .app_data(data.clone())
+                .route("/evaluate", web::post().to(evaluate)),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/evaluate")
+            .set_json(&EvalRequest {
+                expr: "=SUM(1,2,3)".into(),
+                sheet: Some("test".into()),
+            })
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+        let bytes = to_bytes(resp.into_body()).await.unwrap();
+        let result: EvaluationResult = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(result.value, serde_json::json!(6.0));
+        assert_eq!(result.display.as_deref(), Some("6"));
+        assert_eq!(result.value_type.as_deref(), Some("number"));
+        assert!(result.error.is_none());
+    }
+
+    #[actix_rt::test]
+    async fn evaluate_reports_error_codes_and_cells_read() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_db(&conn);
+        conn.execute_batch(
+            "INSERT INTO cells (sheet, row, col, value, value_type) VALUES
+                 ('test', 0, 0, '1', 'number'),
+                 ('test', 1, 0, '0', 'number');",
+        )
+        .unwrap();
+        let data = web::Data::new(AppState {
+            db: Mutex::new(conn),
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+        });
+        let app = test::init_service(
+            App::new()
+                .app_data(data.clone())
+                .route("/evaluate", web::post().to(evaluate)),
+        )
+        .await;
+        let evaluate = async |expr: &str| {
+            let req = test::TestRequest::post()
+                .uri("/evaluate")
+                .set_json(&EvalRequest {
+                    expr: expr.into(),
+                    sheet: Some("test".into()),
+                })
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            let status = resp.status();
+            let bytes = to_bytes(resp.into_body()).await.unwrap();
+            let result: EvaluationResult = serde_json::from_slice(&bytes).unwrap();
+            (status, result)
+        };
+        let read = |result: &EvaluationResult| -> Vec<String> {
+            result
+                .cells_read
+                .iter()
+                .map(|range| range.address.clone())
+                .collect()
+        };
+        let fault = |result: &EvaluationResult| {
+            let error = result.error.as_ref().unwrap();
+            (error.code.clone(), error.span)
+        };
+
+        let (status, result) = evaluate("=SUM(A1:A2) + A1").await;
+        assert!(status.is_success());
+        assert_eq!(result.display.as_deref(), Some("2"));
+        assert!(result.error.is_none());
+        assert_eq!(read(&result), ["test!A1:A2", "test!A1"]);
+
+        let (status, result) = evaluate("=IF(A1, A1/A2, 0)").await;
+        assert!(status.is_success());
+        assert_eq!(result.value, serde_json::json!("#DIV/0!"));
+        assert_eq!(result.value_type.as_deref(), Some("error"));
+        assert_eq!(
+            fault(&result),
+            ("DIVISION_BY_ZERO".into(), Some(formula::Span::new(8, 13)))
+        );
+        assert_eq!(
+            result.error.as_ref().unwrap().message,
+            "division by zero in A1/A2"
+        );
+        assert_eq!(read(&result), ["test!A1", "test!A2"]);
+
+        let (_, result) = evaluate("=1 + FOO(A1)").await;
+        assert_eq!(
+            fault(&result),
+            ("UNKNOWN_FUNCTION".into(), Some(formula::Span::new(5, 12)))
+        );
+        let (_, result) = evaluate("=Nope * 2").await;
+        assert_eq!(
+            fault(&result),
+            ("UNKNOWN_NAME".into(), Some(formula::Span::new(1, 5)))
+        );
+        let (_, result) = evaluate("=OFFSET(A1, -1, 0)").await;
+        assert_eq!(fault(&result).0, "REF_OUT_OF_RANGE");
+
+        let (status, result) = evaluate("=1 + )").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(result.display, None);
+        assert_eq!(
+            fault(&result),
+            ("PARSE_ERROR".into(), Some(formula::Span::new(5, 6)))
+        );
+    }
+
+    #[actix_rt::test]
+    async fn evaluate_trace_steps_through_the_formula() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_db(&conn);
+        conn.execute_batch(
+            "INSERT INTO cells (sheet, row, col, value, value_type) VALUES
+                 ('test', 0, 0, '4', 'number'),
+                 ('test', 1, 0, '0', 'number');",
+        )
+        .unwrap();
+        let data = web::Data::new(AppState {
+            db: Mutex::new(conn),
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+        });
+        let app = test::init_service(
+            App::new()
+                .app_data(data.clone())
+                .route("/evaluate/trace", web::post().to(evaluate_trace)),
+        )
+        .await;
+        macro_rules! trace {
+            ($expr:expr) => {{
+                let req = test::TestRequest::post()
+                    .uri("/evaluate/trace")
+                    .set_json(&EvalRequest {
+                        expr: $expr.into(),
+                        sheet: Some("test".into()),
+                    })
+                    .to_request();
+                let trace: EvaluationTrace = test::call_and_read_body_json(&app, req).await;
+                trace
+            }};
+        }
+
+        let trace = trace!("=SUM(A1:A2) / A2 + 1");
+        let root = &trace.root;
+        assert_eq!(root.expression, "SUM(A1:A2) / A2 + 1");
+        assert_eq!(root.display, "#DIV/0!");
+        assert_eq!(root.value_type, "error");
+        let division = &root.steps[0];
+        assert_eq!(division.expression, "SUM(A1:A2) / A2");
+        let sum = &division.steps[0];
+        assert_eq!(sum.value, serde_json::json!(4.0));
+        let range = &sum.steps[0];
+        assert_eq!(range.expression, "A1:A2");
+        assert_eq!(range.value_type, "array");
+        assert_eq!(range.value, serde_json::json!([[4.0], [0.0]]));
+        assert_eq!(range.reference.as_ref().unwrap().address, "test!A1:A2");
+        let origin = trace.first_error.unwrap();
+        assert_eq!(origin.expression, "SUM(A1:A2) / A2");
+        assert_eq!(origin.error, "#DIV/0!");
+        assert_eq!((origin.span.start, origin.span.end), (1, 16));
+
+        let trace = trace!("=IFERROR(A1 / A2, \"none\")");
+        assert_eq!(trace.root.value, serde_json::json!("none"));
+        assert_eq!(trace.first_error.unwrap().expression, "A1 / A2");
+        assert!(trace!("=A1 * 2").first_error.is_none());
+
+        let req = test::TestRequest::post()
+            .uri("/evaluate/trace")
+            .set_json(&EvalRequest {
+                expr: "=SUM(A1".into(),
+                sheet: Some("test".into()),
+            })
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let bytes = to_bytes(resp.into_body()).await.unwrap();
+        let failure: TraceFailure = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(failure.error.code, "PARSE_ERROR");
+        assert_eq!(failure.error.span, Some(formula::Span::new(7, 7)));
+    }
+
+    #[actix_rt::test]
+    async fn evaluate_average() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_db(&conn);
+        let data = web::Data::new(AppState {
+            db: Mutex::new(conn),
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+        });
+        let app = test::init_service(
+            App::new()
+                .app_data(data.clone())
+                .route("/evaluate", web::post().to(evaluate)),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/evaluate")
+            .set_json(&EvalRequest {
+                expr: "=AVERAGE(2,4,6)".into(),
+                sheet: Some("test".into()),
+            })
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+        let bytes = to_bytes(resp.into_body()).await.unwrap();
+        let result: EvaluationResult = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(result.display.as_deref(), Some("4"));
+    }
+
+    #[actix_rt::test]
+    async fn evaluate_solver_without_solution() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_db(&conn);
+        let data = web::Data::new(AppState {
+            db: Mutex::new(conn),
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+        });
+        let app = test::init_service(
+            App::new()
+                .app_data(data.clone())
+                .route("/evaluate", web::post().to(evaluate)),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/evaluate")
+            .set_json(&EvalRequest {
+                expr: "=RATE(10, 100, 1000)".into(),
+                sheet: Some("test".into()),
+            })
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+        let bytes = to_bytes(resp.into_body()).await.unwrap();
+        let result: EvaluationResult = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(result.display.as_deref(), Some("#NUM!"));
+        let error = result.error.unwrap();
+        assert_eq!(error.code, "INVALID_NUMBER");
+        assert_eq!(error.span, Some(formula::Span::new(1, 20)));
+    }
+
+    #[actix_rt::test]
+    async fn set_formula_cell() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_db(&conn);
+        let data = web::Data::new(AppState {
+            db: Mutex::new(conn),
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+        });
+        let app = test::init_service(
+            App::new()
+                .app_data(data.clone())
+                .route("/cells", web::post().to(set_cell))
+                .route("/cells", web::get().to(list_cells)),
+        )
+        .await;
+
+        let new_cell = input("test", 1, 1, "=SUM(2,3)");
+        assert!(post(&app, "/cells", &new_cell).await.is_success());
+
+        let cells = cells(&app, "test").await;
+        assert_eq!(cells[0].value, "5");
+        assert_eq!(cells[0].formula.as_deref(), Some("=SUM(2,3)"));
+    }
+
+    #[actix_rt::test]
+    async fn typed_dates_are_stored_as_dates() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_db(&conn);
+        let data = web::Data::new(AppState {
+            db: Mutex::new(conn),
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+        });
+        let app = test::init_service(
+            App::new()
+                .app_data(data.clone())
+                .route("/cells", web::post().to(set_cell))
+                .route("/cells", web::get().to(list_cells)),
+        )
+        .await;
+
+        for (col, value) in [(0, "1/31/2026"), (1, "=A1+1"), (2, "=A1-DATE(2026,1,1)")] {
+            let new_cell = input("test", 0, col, value);
+            assert!(post(&app, "/cells", &new_cell).await.is_success());
+        }
+
+        let mut cells = cells(&app, "test").await;
+        cells.sort_by_key(|c| c.col);
+        assert_eq!(cells[0].value, "2026-01-31");
+        assert_eq!(cells[0].value_type, Some(ValueType::Date));
+        assert_eq!(cells[1].value, "2026-02-01");
+        assert_eq!(cells[1].value_type, Some(ValueType::Date));
+        assert_eq!(cells[2].value, "30");
+        assert_eq!(cells[2].value_type, Some(ValueType::Number));
+    }
+
+    #[actix_rt::test]
+    async fn array_results_spill() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_db(&conn);
+        let data = web::Data::new(AppState {
+            db: Mutex::new(conn),
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+        });
+        let app = test::init_service(
+            App::new()
+                .app_data(data.clone())
+                .route("/cells", web::post().to(set_cell))
+                .route("/cells", web::get().to(list_cells))
+                .route("/cells/clear", web::post().to(clear_cells_bulk)),
+        )
+        .await;
+
+        // Cells on the sheet by position, as "value" or "value<-anchor".
+        let shown = |cells: Vec<Cell>| {
+            cells
+                .into_iter()
+                .map(|c| {
+                    let shown = match c.spilled_from {
+                        Some(a) => format!("{}<-{},{}", c.value, a.row, a.col),
+                        None => c.value,
+                    };
+                    ((c.row, c.col), shown)
+                })
+                .collect::<HashMap<_, _>>()
+        };
+
+        for cell in [
+            input("test", 0, 0, "5"),
+            input("test", 1, 0, "1"),
+            input("test", 2, 0, "3"),
+            input("test", 0, 1, "=SORT(A1:A3)"),
+            input("test", 0, 2, "=B2*10"),
+        ] {
+            assert!(post(&app, "/cells", &cell).await.is_success());
+        }
+        let sheet = shown(cells(&app, "test").await);
+        assert_eq!(sheet[&(0, 1)], "1");
+        assert_eq!(sheet[&(1, 1)], "3<-0,1");
+        assert_eq!(sheet[&(2, 1)], "5<-0,1");
+        assert_eq!(sheet[&(0, 2)], "30");
+
+        let listed = cells(&app, "test").await;
+        let anchor = listed.iter().find(|c| (c.row, c.col) == (0, 1)).unwrap();
+        assert_eq!(anchor.spill, Some(CellRange::new(0, 1, 2, 1)));
+
+        // Spilled cells are computed, so cannot be edited.
+        assert_eq!(
+            post(&app, "/cells", input("test", 1, 1, "x")).await,
+            StatusCode::CONFLICT
+        );
+
+        // Changing the source re-spills and updates readers of spilled cells.
+        assert!(
+            post(&app, "/cells", input("test", 0, 0, "0"))
+                .await
+                .is_success()
+        );
+        let sheet = shown(cells(&app, "test").await);
+        assert_eq!(sheet[&(0, 1)], "0");
+        assert_eq!(sheet[&(1, 1)], "1<-0,1");
+        assert_eq!(sheet[&(2, 1)], "3<-0,1");
+        assert_eq!(sheet[&(0, 2)], "10");
+
+        // A blocked spill shows #SPILL! until the way is clear.
+        for cell in [
+            input("test", 1, 3, "in the way"),
+            input("test", 0, 3, "=SEQUENCE(3)"),
+        ] {
+            assert!(post(&app, "/cells", &cell).await.is_success());
+        }
+        let sheet = shown(cells(&app, "test").await);
+        assert_eq!(sheet[&(0, 3)], "#SPILL!");
+        assert!(!sheet.contains_key(&(2, 3)));
+
+        assert!(
+            post(
+                &app,
+                "/cells/clear",
+                &ClearRequest {
+                    cells: vec![CellPosition {
+                        sheet: Some("test".into()),
+                        row: 1,
+                        col: 3,
+                    }],
+                }
+            )
+            .await
+            .is_success()
+        );
+        let sheet = shown(cells(&app, "test").await);
+        assert_eq!(sheet[&(0, 3)], "1");
+        assert_eq!(sheet[&(1, 3)], "2<-0,3");
+        assert_eq!(sheet[&(2, 3)], "3<-0,3");
+
+        // Clearing the formula takes its spill with it.
+        assert!(
+            post(
+                &app,
+                "/cells/clear",
+                &ClearRequest {
+                    cells: vec![CellPosition {
+                        sheet: Some("test".into()),
+                        row: 0,
+                        col: 1,
+                    }],
+                }
+            )
+            .await
+            .is_success()
+        );
+        let sheet = shown(cells(&app, "test").await);
+        assert!(!sheet.contains_key(&(1, 1)));
+        assert!(!sheet.contains_key(&(2, 1)));
+        assert_eq!(sheet[&(0, 2)], "0");
+    }
+
+    #[actix_rt::test]
+    async fn saved_lambdas_are_callable_from_any_sheet() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_db(&conn);
+        let data = web::Data::new(AppState {
+            db: Mutex::new(conn),
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+        });
+        let app = test::init_service(
+            App::new()
+                .app_data(data.clone())
+                .route("/cells", web::post().to(set_cell))
+                .route("/cells", web::get().to(list_cells))
+                .route("/names", web::get().to(list_names))
+                .route("/names", web::post().to(set_name)),
+        )
+        .await;
+
+        let name = DefinedName {
+            name: "Tax".into(),
+            formula: "=LAMBDA(amount, ROUND(amount * 0.2, 2))".into(),
+            scope: None,
+        };
+        assert!(post(&app, "/names", &name).await.is_success());
+
+        for (name, formula) in [
+            ("A1", "=LAMBDA(x, x)"),
+            ("SUM", "=LAMBDA(x, x)"),
+            ("Twice", "=2*"),
+            ("Twice", "=LAMBDA(x, x"),
+        ] {
+            assert_eq!(
+                post(
+                    &app,
+                    "/names",
+                    DefinedName {
+                        name: name.into(),
+                        formula: formula.into(),
+                        scope: None,
+                    }
+                )
+                .await,
+                StatusCode::BAD_REQUEST
+            );
+        }
+
+        let req = test::TestRequest::get().uri("/names").to_request();
+        let resp = test::call_service(&app, req).await;
+        let bytes = to_bytes(resp.into_body()).await.unwrap();
+        let names: Vec<DefinedName> = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(names.len(), 1);
+        assert_eq!(names[0].name, "Tax");
+
+        for (sheet, value) in [("first", "=TAX(100)"), ("second", "=tax(12.34)")] {
+            let cell = Cell {
+                sheet: Some(sheet.into()),
+                row: 0,
+                col: 0,
+                value: value.into(),
+                formula: None,
+                value_type: None,
+                font_weight: None,
+                font_style: None,
+                background_color: None,
+                spill: None,
+                spilled_from: None,
+                number: None,
+            };
+            assert!(post(&app, "/cells", &cell).await.is_success());
+        }
+        for (sheet, expected) in [("first", "20"), ("second", "2.47")] {
+            let req = test::TestRequest::get()
+                .uri(&format!("/cells?sheet={}", sheet))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            let bytes = to_bytes(resp.into_body()).await.unwrap();
+            let cells: Vec<Cell> = serde_json::from_slice(&bytes).unwrap();
+            assert_eq!(cells[0].value, expected);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn defined_names_update_their_dependents() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_db(&conn);
+        let data = web::Data::new(AppState {
+            db: Mutex::new(conn),
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+        });
+        let app = test::init_service(
+            App::new()
+                .app_data(data.clone())
+                .route("/cells", web::post().to(set_cell))
+                .route("/cells", web::get().to(list_cells))
+                .route("/names", web::get().to(list_names))
+                .route("/names", web::post().to(set_name))
+                .route("/names/{name}", web::get().to(get_name))
+                .route("/names/{name}", web::delete().to(delete_name)),
+        )
+        .await;
+        let name = |name: &str, formula: &str, scope: Option<&str>| DefinedName {
+            name: name.into(),
+            formula: formula.into(),
+            scope: scope.map(Into::into),
+        };
+
+        for (row, value) in ["10", "20", "30"].into_iter().enumerate() {
+            assert!(
+                post(&app, "/cells", input("Data", row as i32, 1, value))
+                    .await
+                    .is_success()
+            );
+        }
+        assert!(
+            post(&app, "/cells", input("Report", 0, 0, "=SUM(Revenue)"))
+                .await
+                .is_success()
+        );
+        assert_eq!(values(&app, "Report").await[&(0, 0)], "#NAME?");
+
+        // Defining the name recalculates formulas already using it.
+        assert!(
+            post(&app, "/names", name("Revenue", "=Data!B1:B3", None))
+                .await
+                .is_success()
+        );
+        assert_eq!(values(&app, "Report").await[&(0, 0)], "60");
+        assert!(
+            post(&app, "/cells", input("Report", 1, 0, "=Revenue"))
+                .await
+                .is_success()
+        );
+        assert_eq!(values(&app, "Report").await[&(2, 0)], "20");
+
+        // So does changing a cell it points at, or what it points at.
+        assert!(
+            post(&app, "/cells", input("Data", 0, 1, "40"))
+                .await
+                .is_success()
+        );
+        assert_eq!(values(&app, "Report").await[&(0, 0)], "90");
+        assert!(
+            post(&app, "/names", name("revenue", "=Data!B1:B2", None))
+                .await
+                .is_success()
+        );
+        assert_eq!(values(&app, "Report").await[&(0, 0)], "60");
+        assert!(
+            post(&app, "/cells", input("Data", 2, 1, "100"))
+                .await
+                .is_success()
+        );
+        assert_eq!(values(&app, "Report").await[&(0, 0)], "60");
+
+        // A sheet's own name hides the workbook's.
+        assert!(
+            post(&app, "/names", name("Revenue", "=1", Some("Report")))
+                .await
+                .is_success()
+        );
+        assert_eq!(values(&app, "Report").await[&(0, 0)], "1");
+        assert!(
+            post(&app, "/cells", input("Data", 0, 0, "=SUM(Revenue)"))
+                .await
+                .is_success()
+        );
+        assert_eq!(values(&app, "Data").await[&(0, 0)], "60");
+
+        let req = test::TestRequest::get().uri("/names").to_request();
+        let resp = test::call_service(&app, req).await;
+        let bytes = to_bytes(resp.into_body()).await.unwrap();
+        let names: Vec<DefinedName> = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(names.len(), 2);
+        let req = test::TestRequest::get()
+            .uri("/names/Revenue?scope=Report")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        let bytes = to_bytes(resp.into_body()).await.unwrap();
+        let found: DefinedName = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(found.formula, "=1");
+        assert_eq!(post(&app, "/names", name("Bad", "=SUM(", None)).await, 400);
+
+        // Deleting names falls back to the workbook's, then to #NAME?.
+        for (uri, value) in [
+            ("/names/Revenue?scope=Report", "60"),
+            ("/names/Revenue", "#NAME?"),
+        ] {
+            let req = test::TestRequest::delete().uri(uri).to_request();
+            assert!(test::call_service(&app, req).await.status().is_success());
+            assert_eq!(values(&app, "Report").await[&(0, 0)], value);
+        }
+        let req = test::TestRequest::delete()
+            .uri("/names/Revenue")
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 404);
+    }
+
+    #[actix_rt::test]
+    async fn tables_grow_with_rows_appended_below() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_db(&conn);
+        let data = web::Data::new(AppState {
+            db: Mutex::new(conn),
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+        });
+        let app = test::init_service(
+            App::new()
+                .app_data(data.clone())
+                .route("/cells", web::get().to(list_cells))
+                .route("/cells/bulk", web::post().to(set_cells_bulk))
+                .route("/names", web::post().to(set_name))
+                .route("/tables", web::get().to(list_tables))
+                .route("/tables", web::post().to(create_table)),
+        )
+        .await;
+        let table = |name: &str, end_row: i32| TableDefinition {
+            name: name.into(),
+            sheet: Some("test".into()),
+            range: CellRange::new(0, 0, end_row, 1),
+            columns: Vec::new(),
+        };
+
+        let rows = vec![
+            input("test", 0, 0, "Item"),
+            input("test", 0, 1, "Amount"),
+            input("test", 1, 0, "pen"),
+            input("test", 1, 1, "2"),
+            input("test", 0, 3, "=SUM(Sales[Amount])"),
+        ];
+        assert!(post(&app, "/cells/bulk", &rows).await.is_success());
+        assert_eq!(values(&app, "test").await[&(0, 3)], "#REF!");
+
+        assert!(post(&app, "/tables", table("Sales", 1)).await.is_success());
+        assert_eq!(values(&app, "test").await[&(0, 3)], "2");
+        assert_eq!(post(&app, "/tables", table("sales", 1)).await, 409);
+        assert_eq!(post(&app, "/tables", table("Other", 2)).await, 409);
+        assert_eq!(post(&app, "/tables", table("Short", 0)).await, 400);
+        for (start_row, start_col, end_col) in [(-1, 0, 1), (0, -1, 1), (0, 1, 0)] {
+            let mut off = table("Off", 5);
+            off.range = CellRange {
+                start_row,
+                start_col,
+                end_row: 5,
+                end_col,
+            };
+            assert_eq!(post(&app, "/tables", off).await, 400);
+        }
+        assert_eq!(
+            post(
+                &app,
+                "/names",
+                DefinedName {
+                    name: "Sales".into(),
+                    formula: "=1".into(),
+                    scope: None,
+                }
+            )
+            .await,
+            409
+        );
+
+        // Rows pasted directly below join the table, formulas included.
+        let rows = vec![
+            input("test", 2, 0, "ink"),
+            input("test", 2, 1, "3"),
+            input("test", 3, 0, "pad"),
+            input("test", 3, 1, "=2 * 2"),
+            input("test", 3, 2, "=Sales[@Amount] * 2"),
+            input("test", 5, 1, "100"),
+            input("test", 5, 2, "=Sales[@Item]"),
+        ];
+        assert!(post(&app, "/cells/bulk", &rows).await.is_success());
+        let cells = values(&app, "test").await;
+        assert_eq!(cells[&(0, 3)], "9");
+        assert_eq!(cells[&(3, 2)], "8");
+        assert_eq!(cells[&(5, 2)], "#VALUE!");
+
+        let req = test::TestRequest::get().uri("/tables").to_request();
+        let resp = test::call_service(&app, req).await;
+        let bytes = to_bytes(resp.into_body()).await.unwrap();
+        let tables: Vec<TableDefinition> = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(tables.len(), 1);
+        assert_eq!(tables[0].range, CellRange::new(0, 0, 3, 1));
+        assert_eq!(tables[0].columns, vec!["Item", "Amount"]);
+    }
+
+    #[actix_rt::test]
+    async fn volatile_formulas_recalculate_on_every_pass() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_db(&conn);
+        let data = web::Data::new(AppState {
+            db: Mutex::new(conn),
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+        });
+        let app = test::init_service(
+            App::new()
+                .app_data(data.clone())
+                .route("/cells", web::post().to(set_cell))
+                .route("/cells", web::get().to(list_cells))
+                .route("/names", web::post().to(set_name))
+                .route("/recalculate", web::post().to(recalculate_all)),
+        )
+        .await;
+
+        let name = DefinedName {
+            name: "Noise".into(),
+            formula: "=RAND()".into(),
+            scope: None,
+        };
+        assert!(post(&app, "/names", &name).await.is_success());
+        for cell in [
+            input("test", 0, 0, "=RAND()"),
+            input("test", 0, 1, "=A1 * 1000000"),
+            input("test", 0, 2, "=Noise"),
+            input("test", 0, 3, "=1 + 1"),
+        ] {
+            assert!(post(&app, "/cells", &cell).await.is_success());
+        }
+        let before = values(&app, "test").await;
+
+        // Editing any cell recalculates the volatile ones and their
+        // dependents, but not other formulas.
+        assert!(
+            post(&app, "/cells", input("test", 5, 5, "x"))
+                .await
+                .is_success()
+        );
+        let after = values(&app, "test").await;
+        for col in 0..3 {
+            assert_ne!(before[&(0, col)], after[&(0, col)]);
+        }
+        // B1 read A1's exact value, not the 15 digits A1 shows.
+        let number = |col: i32| -> f64 {
+            data.db
+                .lock()
+                .unwrap()
+                .query_row(
+                    "SELECT number FROM cells WHERE sheet = 'test' AND row = 0 AND col = ?1",
+                    params![col],
+                    |r| r.get(0),
+                )
+                .unwrap()
+        };
+        assert_eq!(number(1), number(0) * 1000000.0);
+
+        let req = test::TestRequest::post().uri("/recalculate").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+        let again = values(&app, "test").await;
+        for col in 0..3 {
+            assert_ne!(after[&(0, col)], again[&(0, col)]);
+        }
+        assert_eq!(again[&(0, 3)], "2");
+
+        // A formula that stops calling RAND stops changing.
+        assert!(
+            post(&app, "/cells", input("test", 0, 0, "=0.5"))
+                .await
+                .is_success()
+        );
+        assert!(
+            post(&app, "/recalculate", serde_json::json!({}))
+                .await
+                .is_success()
+        );
+        assert_eq!(values(&app, "test").await[&(0, 1)], "500000");
+    }
+
+    #[actix_rt::test]
+    async fn indirect_references_follow_the_cells_they_reach() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_db(&conn);
+        let data = web::Data::new(AppState {
+            db: Mutex::new(conn),
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+        });
+        let app = test::init_service(
+            App::new()
+                .app_data(data.clone())
+                .route("/cells", web::post().to(set_cell))
+                .route("/cells", web::get().to(list_cells)),
+        )
+        .await;
+
+        // A1 picks the row; B2 is itself a formula over C1.
+        for cell in [
+            input("test", 0, 0, "2"),
+            input("test", 1, 1, "=C1 * 4"),
+            input("test", 2, 1, "30"),
+            input("test", 0, 2, "5"),
+            input("test", 0, 3, "=INDIRECT(\"B\" & A1) + 1"),
+            input("test", 0, 4, "=D1 * 10"),
+        ] {
+            assert!(post(&app, "/cells", &cell).await.is_success());
+        }
+        assert_eq!(values(&app, "test").await[&(0, 3)], "21");
+
+        // A change that reaches the pointed-to cell through a chain of
+        // formulas reaches the INDIRECT and its own dependents.
+        assert!(
+            post(&app, "/cells", input("test", 0, 2, "10"))
+                .await
+                .is_success()
+        );
+        let cells = values(&app, "test").await;
+        assert_eq!(cells[&(0, 3)], "41");
+        assert_eq!(cells[&(0, 4)], "410");
+
+        // Pointing elsewhere re-registers the dependency.
+        assert!(
+            post(&app, "/cells", input("test", 0, 0, "3"))
+                .await
+                .is_success()
+        );
+        assert_eq!(values(&app, "test").await[&(0, 3)], "31");
+        assert!(
+            post(&app, "/cells", input("test", 2, 1, "50"))
+                .await
+                .is_success()
+        );
+        assert_eq!(values(&app, "test").await[&(0, 4)], "510");
+
+        // A reference built to the formula's own cell is a loop.
+        assert!(
+            post(&app, "/cells", input("test", 0, 0, "4"))
+                .await
+                .is_success()
+        );
+        assert!(
+            post(&app, "/cells", input("test", 3, 1, "=D1"))
+                .await
+                .is_success()
+        );
+        assert_eq!(values(&app, "test").await[&(0, 3)], "#CIRC!");
+    }
+
+    #[actix_rt::test]
+    async fn iterative_calculation_settles_intentional_loops() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_db(&conn);
+        let data = web::Data::new(AppState {
+            db: Mutex::new(conn),
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+        });
+        let app = test::init_service(
+            App::new()
+                .app_data(data.clone())
+                .route("/cells", web::post().to(set_cell))
+                .route("/cells", web::get().to(list_cells))
+                .route(
+                    "/settings/calculation",
+                    web::get().to(get_calculation_settings),
+                )
+                .route(
+                    "/settings/calculation",
+                    web::post().to(set_calculation_settings),
+                ),
+        )
+        .await;
+
+        let number = |value: &String| value.parse::<f64>().unwrap();
+
+        let req = test::TestRequest::get()
+            .uri("/settings/calculation")
+            .to_request();
+        let settings: settings::Calculation = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(settings, settings::Calculation::default());
+
+        // Interest on the average of the opening and closing balances.
+        for cell in [
+            input("test", 0, 1, "1000"),
+            input("test", 1, 1, "=(B1 + B3) / 2 * 0.1"),
+            input("test", 2, 1, "=B1 + B2"),
+        ] {
+            assert!(post(&app, "/cells", &cell).await.is_success());
+        }
+        assert_eq!(values(&app, "test").await[&(2, 1)], "#CIRC!");
+
+        let iterative = serde_json::json!({ "iterative": true, "max_change": 0.00001 });
+        assert!(
+            post(&app, "/settings/calculation", &iterative)
+                .await
+                .is_success()
+        );
+        let cells = values(&app, "test").await;
+        assert!((number(&cells[&(2, 1)]) - 1050.0 / 0.95).abs() < 0.001);
+        assert!((number(&cells[&(1, 1)]) - 100.0 / 0.95).abs() < 0.001);
+
+        // A change feeding the loop iterates it again.
+        assert!(
+            post(&app, "/cells", input("test", 0, 1, "2000"))
+                .await
+                .is_success()
+        );
+        assert!((number(&values(&app, "test").await[&(2, 1)]) - 2100.0 / 0.95).abs() < 0.001);
+
+        // A loop that never settles stops after max_iterations passes.
+        let capped = serde_json::json!({ "iterative": true, "max_iterations": 5 });
+        assert!(
+            post(&app, "/settings/calculation", &capped)
+                .await
+                .is_success()
+        );
+        assert!(
+            post(&app, "/cells", input("test", 0, 3, "=D1 + 1"))
+                .await
+                .is_success()
+        );
+        assert_eq!(values(&app, "test").await[&(0, 3)], "6");
+
+        let invalid = serde_json::json!({ "iterative": true, "max_iterations": 0 });
+        assert_eq!(
+            post(&app, "/settings/calculation", &invalid).await,
+            StatusCode::BAD_REQUEST
+        );
+        let negative = serde_json::json!({ "max_change": -1 });
+        assert_eq!(
+            post(&app, "/settings/calculation", &negative).await,
+            StatusCode::BAD_REQUEST
+        );
+
+        // Turning it off shows the loops as errors again.
+        assert!(
+            post(&app, "/settings/calculation", serde_json::json!({}))
+                .await
+                .is_success()
+        );
+        let cells = values(&app, "test").await;
+        assert_eq!(cells[&(2, 1)], "#CIRC!");
+        assert_eq!(cells[&(0, 3)], "#CIRC!");
+    }
+
+    #[actix_rt::test]
+    async fn traces_precedents_and_dependents_across_sheets() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_db(&conn);
+        let data = web::Data::new(AppState {
+            db: Mutex::new(conn),
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+        });
+        let app = test::init_service(
+            App::new()
+                .app_data(data.clone())
+                .route("/cells", web::post().to(set_cell))
+                .route("/cells/precedents", web::get().to(cell_precedents))
+                .route("/cells/dependents", web::get().to(cell_dependents))
+                .route("/names", web::post().to(set_name)),
+        )
+        .await;
+        // The addresses of the direct and transitive ranges of a trace.
+        macro_rules! trace {
+            ($uri:expr) => {{
+                let req = test::TestRequest::get().uri($uri).to_request();
+                let trace: Trace = test::call_and_read_body_json(&app, req).await;
+                let addresses = |ranges: Vec<TracedRange>| {
+                    ranges.into_iter().map(|r| r.address).collect::<Vec<_>>()
+                };
+                (addresses(trace.direct), addresses(trace.transitive))
+            }};
+        }
+
+        let share = DefinedName {
+            name: "Share".into(),
+            formula: "=Inputs!A2".into(),
+            scope: None,
+        };
+        assert!(post(&app, "/names", &share).await.is_success());
+        for cell in [
+            input("Inputs", 0, 0, "100"),
+            input("Inputs", 1, 0, "5"),
+            input("Inputs", 2, 0, "1"),
+            input("Model", 0, 0, "=Inputs!A1 * 2"),
+            input("Model", 0, 1, "=SUM(A1, Inputs!A2)"),
+            input("Model", 0, 2, "=B1 + INDIRECT(\"Inputs!A3\")"),
+            input("Model", 0, 3, "=Share * B1"),
+        ] {
+            assert!(post(&app, "/cells", &cell).await.is_success());
+        }
+
+        assert_eq!(
+            trace!("/cells/precedents?sheet=Model&row=0&col=2"),
+            (
+                vec!["Inputs!A3".to_string(), "Model!B1".to_string()],
+                vec![
+                    "Inputs!A3".to_string(),
+                    "Model!B1".to_string(),
+                    "Inputs!A2".to_string(),
+                    "Model!A1".to_string(),
+                    "Inputs!A1".to_string(),
+                ]
+            )
+        );
+        assert_eq!(
+            trace!("/cells/dependents?sheet=Inputs&row=0&col=0"),
+            (
+                vec!["Model!A1".to_string()],
+                vec![
+                    "Model!A1".to_string(),
+                    "Model!B1".to_string(),
+                    "Model!C1".to_string(),
+                    "Model!D1".to_string(),
+                ]
+            )
+        );
+        // References through a defined name count as the formula's own.
+        assert_eq!(
+            trace!("/cells/dependents?sheet=Inputs&row=1&col=0"),
+            (
+                vec!["Model!B1".to_string(), "Model!D1".to_string()],
+                vec![
+                    "Model!B1".to_string(),
+                    "Model!D1".to_string(),
+                    "Model!C1".to_string(),
+                ]
+            )
+        );
+        assert_eq!(
+            trace!("/cells/precedents?sheet=Inputs&row=0&col=0"),
+            (vec![], vec![])
+        );
+
+        let req = test::TestRequest::get()
+            .uri("/cells/precedents?sheet=Model&row=-1&col=0")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_rt::test]
+    async fn test_cell_with_formatting() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_db(&conn);
+        let data = web::Data::new(AppState {
+            db: Mutex::new(conn),
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+        });
+        let app = test::init_service(
+            App::new()
+                .app_data(data.clone())
+                .route("/cells", web::post().to(set_cell))
+                .route("/cells", web::get().to(list_cells)),
+        )
+        .await;
+
+        let new_cell = Cell {
+            sheet: Some("test".into()),
+            row: 0,
+            col: 0,
+            value: "Formatted".into(),
+            formula: None,
+            value_type: None,
+            font_weight: Some("bold".into()),
+            font_style: Some("italic".into()),
+            background_color: Some("#ff0000".into()),
+            spill: None,
+            spilled_from: None,
+            number: None,
+        };
+        assert!(post(&app, "/cells", &new_cell).await.is_success());
+
+        let cells = cells(&app, "test").await;
+        assert_eq!(cells[0].value, "Formatted");
+        assert_eq!(cells[0].font_weight, Some("bold".into()));
+        assert_eq!(cells[0].font_style, Some("italic".into()));
+        assert_eq!(cells[0].background_color, Some("#ff0000".into()));
+    }
+
+    #[actix_rt::test]
+    async fn test_bulk_operations() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_db(&conn);
+        let data = web::Data::new(AppState {
+            db: Mutex::new(conn),
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+        });
+        let app = test::init_service(
+            App::new()
+                .app_data(data.clone())
+                .route("/cells/bulk", web::post().to(set_cells_bulk))
+                .route("/cells", web::get().to(list_cells)),
+        )
+        .await;
+
+        let batch = vec![
+            input("test", 0, 0, "1"),
+            input("test", 1, 0, "2"),
+            input("test", 2, 0, "3"),
+        ];
+
+        assert!(post(&app, "/cells/bulk", &batch).await.is_success());
+
+        let result_cells = cells(&app, "test").await;
+        assert_eq!(result_cells.len(), 3);
+    }
+
+    #[actix_rt::test]
+    async fn test_clear_cells() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_db(&conn);
+        let data = web::Data::new(AppState {
+            db: Mutex::new(conn),
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+        });
+        let app = test::init_service(
+            App::new()
+                .app_data(data.clone())
+                .route("/cells", web::post().to(set_cell))
+                .route("/cells", web::get().to(list_cells))
+                .route("/cells/clear", web::post().to(clear_cells_bulk)),
+        )
+        .await;
+
+        // Create a cell
+        let new_cell = input("test", 0, 0, "Delete Me");
+        let req = test::TestRequest::post()
+            .uri("/cells")
+            .set_json(&new_cell)
+            .to_request();
+        test::call_service(&app, req).await;
+
+        // Clear the cell
+        let clear_request = ClearRequest {
+            cells: vec![CellPosition {
+                sheet: Some("test".into()),
+                row: 0,
+                col: 0,
+            }],
+        };
+        assert!(
+            post(&app, "/cells/clear", &clear_request)
+                .await
+                .is_success()
+        );
+
+        // Verify cell is deleted
+        let cells = cells(&app, "test").await;
+        assert_eq!(cells.len(), 0);
+    }
+
+    #[actix_rt::test]
+    async fn test_formula_with_cell_references() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_db(&conn);
+        let data = web::Data::new(AppState {
+            db: Mutex::new(conn),
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+        });
+        let app = test::init_service(
+            App::new()
+                .app_data(data.clone())
+                .route("/cells", web::post().to(set_cell))
+                .route("/cells", web::get().to(list_cells)),
+        )
+        .await;
+
+        // Create A1 with value 10
+        let cell_a1 = input("test", 0, 0, "10");
+        test::call_service(
+            &app,
+            test::TestRequest::post()
+                .uri("/cells")
+                .set_json(&cell_a1)
+                .to_request(),
+        )
+        .await;
+
+        // Create B1 with value 20
+        let cell_b1 = input("test", 0, 1, "20");
+        test::call_service(
+            &app,
+            test::TestRequest::post()
+                .uri("/cells")
+                .set_json(&cell_b1)
+                .to_request(),
+        )
+        .await;
+
+        // Create C1 with formula =SUM(A1,B1)
+        let cell_c1 = input("test", 0, 2, "=SUM(A1,B1)");
+        assert!(post(&app, "/cells", &cell_c1).await.is_success());
+
+        // Verify C1 contains evaluated result
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri("/cells?sheet=test")
+                .to_request(),
+        )
+        .await;
+        let bytes = to_bytes(resp.into_body()).await.unwrap();
+        let cells: Vec<Cell> = serde_json::from_slice(&bytes).unwrap();
+        let cell_c1_result = cells.iter().find(|c| c.row == 0 && c.col == 2).unwrap();
+        assert_eq!(cell_c1_result.value, "30");
+        assert_eq!(cell_c1_result.formula.as_deref(), Some("=SUM(A1,B1)"));
+    }
+
+    #[actix_rt::test]
+    async fn test_bulk_keeps_formula_source() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_db(&conn);
+        let data = web::Data::new(AppState {
+            db: Mutex::new(conn),
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+        });
+        let app = test::init_service(
+            App::new()
+                .app_data(data.clone())
+                .route("/cells/bulk", web::post().to(set_cells_bulk))
+                .route("/cells", web::get().to(list_cells)),
+        )
+        .await;
+
+        let cells = vec![input("test", 0, 0, "4"), input("test", 0, 1, "=A1*2")];
+        assert!(post(&app, "/cells/bulk", &cells).await.is_success());
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri("/cells?sheet=test")
+                .to_request(),
+        )
+        .await;
+        let bytes = to_bytes(resp.into_body()).await.unwrap();
+        let cells: Vec<Cell> = serde_json::from_slice(&bytes).unwrap();
+        let b1 = cells.iter().find(|c| c.row == 0 && c.col == 1).unwrap();
+        assert_eq!(b1.value, "8");
+        assert_eq!(b1.formula.as_deref(), Some("=A1*2"));
+        let a1 = cells.iter().find(|c| c.row == 0 && c.col == 0).unwrap();
+        assert_eq!(a1.formula, None);
+    }
+
+    #[actix_rt::test]
+    async fn test_dependents_recalculate() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_db(&conn);
+        let data = web::Data::new(AppState {
+            db: Mutex::new(conn),
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+        });
+        let app = test::init_service(
+            App::new()
+                .app_data(data.clone())
+                .route("/cells", web::post().to(set_cell))
+                .route("/cells", web::get().to(list_cells))
+                .route("/cells/bulk", web::post().to(set_cells_bulk))
+                .route("/cells/clear", web::post().to(clear_cells_bulk)),
+        )
+        .await;
+
+        // C1 depends on B1, which depends on A1
+        let cells: Vec<Cell> = [(0, "2"), (1, "=A1*2"), (2, "=B1+1")]
+            .into_iter()
+            .map(|(col, value)| input("test", 0, col, value))
+            .collect();
+        test::call_service(
+            &app,
+            test::TestRequest::post()
+                .uri("/cells/bulk")
+                .set_json(&cells)
+                .to_request(),
+        )
+        .await;
 
-    ws::start(
-        WebSocketSession {
-            id: session_id,
-            sessions,
-        },
-        &req,
-        stream,
-    )
-}
+        // Changing A1 cascades through B1 to C1
+        let cell_a1 = input("test", 0, 0, "5");
+        assert!(post(&app, "/cells", &cell_a1).await.is_success());
 
-// Helper function to broadcast cell updates
-fn broadcast_cell_update(
-    sessions: &Arc<Mutex<HashMap<String, Addr<WebSocketSession>>>>,
-    cell: &Cell,
-    user_id: String,
-) {
-    let update = CellUpdate {
-        sheet: cell.sheet.clone().unwrap_or_else(|| "default".to_string()),
-        row: cell.row,
-        col: cell.col,
-        value: cell.value.clone(),
-        font_weight: cell.font_weight.clone(),
-        font_style: cell.font_style.clone(),
-        background_color: cell.background_color.clone(),
-        user_id,
-    };
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri("/cells?sheet=test")
+                .to_request(),
+        )
+        .await;
+        let bytes = to_bytes(resp.into_body()).await.unwrap();
+        let cells: Vec<Cell> = serde_json::from_slice(&bytes).unwrap();
+        let value_at = |col: i32| cells.iter().find(|c| c.col == col).unwrap().value.clone();
+        assert_eq!(value_at(1), "10");
+        assert_eq!(value_at(2), "11");
 
-    if let Ok(msg_str) = serde_json::to_string(&update) {
-        let sessions_guard = sessions.lock().unwrap();
-        for (_, addr) in sessions_guard.iter() {
-            addr.do_send(WebSocketMessage(msg_str.clone()));
-        }
-    }
-}
+        // Replacing B1 with a constant stops it from tracking A1
+        let cell_b1 = input("test", 0, 1, "1");
+        test::call_service(
+            &app,
+            test::TestRequest::post()
+                .uri("/cells")
+                .set_json(&cell_b1)
+                .to_request(),
+        )
+        .await;
+        let clear_request = ClearRequest {
+            cells: vec![CellPosition {
+                sheet: Some("test".into()),
+                row: 0,
+                col: 0,
+            }],
+        };
+        test::call_service(
+            &app,
+            test::TestRequest::post()
+                .uri("/cells/clear")
+                .set_json(&clear_request)
+                .to_request(),
+        )
+        .await;
 
-#[actix_web::main]
-async fn main() -> std::io::Result<()> {
-    let conn = Connection::open("cells.db").unwrap();
-    init_db(&conn);
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri("/cells?sheet=test")
+                .to_request(),
+        )
+        .await;
+        let bytes = to_bytes(resp.into_body()).await.unwrap();
+        let cells: Vec<Cell> = serde_json::from_slice(&bytes).unwrap();
+        let value_at = |col: i32| cells.iter().find(|c| c.col == col).unwrap().value.clone();
+        assert_eq!(value_at(1), "1");
+        assert_eq!(value_at(2), "2");
+    }
 
-    let data = web::Data::new(AppState {
-        db: Mutex::new(conn),
-        sessions: Arc::new(Mutex::new(HashMap::new())),
-    });
+    #[actix_rt::test]
+    async fn bulk_saves_with_an_unparsable_formula_are_rejected() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_db(&conn);
+        let data = web::Data::new(AppState {
+            db: Mutex::new(conn),
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+        });
+        let app = test::init_service(
+            App::new()
+                .app_data(data.clone())
+                .route("/cells", web::get().to(list_cells))
+                .route("/cells/bulk", web::post().to(set_cells_bulk)),
+        )
+        .await;
+        assert!(
+            post(
+                &app,
+                "/cells/bulk",
+                vec![input("test", 0, 0, "5"), input("test", 0, 1, "=A1*2")]
+            )
+            .await
+            .is_success()
+        );
 
-    HttpServer::new(move || {
-        App::new()
-            .wrap(
-                Cors::default()
-                    .allowed_origin("http://localhost:3000")
-                    .allowed_origin("http://127.0.0.1:3000")
-                    .allowed_origin("http://192.168.10.161:3000")
-                    .allow_any_method()
-                    .allow_any_header()
-                    .supports_credentials(),
+        // Neither A1 nor C1 is written, so B1 keeps reading the old A1.
+        assert_eq!(
+            post(
+                &app,
+                "/cells/bulk",
+                vec![input("test", 0, 0, "7"), input("test", 0, 2, "=SUM(1,")]
             )
-            .app_data(data.clone())
-            .route("/health", web::get().to(health))
-            .route("/cells", web::get().to(list_cells))
-            .route("/cells", web::post().to(set_cell))
-            .route("/cells/bulk", web::post().to(set_cells_bulk))
-            .route("/cells/clear", web::post().to(clear_cells_bulk))
-            .route("/evaluate", web::post().to(evaluate))
-            .route("/ws", web::get().to(ws_index))
-            .route("/ws", web::get().to(ws_index)) // WebSocket route
-    })
-    .bind(("0.0.0.0", 6889))?
-    .run()
-    .await
-}
+            .await,
+            StatusCode::BAD_REQUEST
+        );
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use actix_web::{body::to_bytes, test};
+        let mut cells = cells(&app, "test").await;
+        cells.sort_by_key(|cell| cell.col);
+        let values: Vec<&str> = cells.iter().map(|cell| cell.value.as_str()).collect();
+        assert_eq!(values, ["5", "10"]);
+    }
 
     #[actix_rt::test]
-    async fn health_works() {
+    async fn cell_saves_tell_bad_formulas_from_storage_failures() {
         let conn = Connection::open_in_memory().unwrap();
         init_db(&conn);
         let data = web::Data::new(AppState {
@@ -540,19 +3858,34 @@ mod tests {
         let app = test::init_service(
             App::new()
                 .app_data(data.clone())
-                .route("/health", web::get().to(health))
                 .route("/cells", web::get().to(list_cells))
-                .route("/cells", web::post().to(set_cell))
-                .route("/evaluate", web::post().to(evaluate)),
+                .route("/cells", web::post().to(set_cell)),
         )
         .await;
-        let req = test::TestRequest::get().uri("/health").to_request();
-        let resp = test::call_service(&app, req).await;
-        assert!(resp.status().is_success());
+
+        assert_eq!(
+            post(&app, "/cells", input("test", 0, 0, "=SUM(1,")).await,
+            StatusCode::BAD_REQUEST
+        );
+
+        // A formula that cannot be evaluated for want of its names is the
+        // server's fault, not the formula's.
+        data.db
+            .lock()
+            .unwrap()
+            .execute("DROP TABLE names", [])
+            .unwrap();
+        assert_eq!(
+            post(&app, "/cells", input("test", 0, 0, "=Revenue*2")).await,
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+
+        let cells = cells(&app, "test").await;
+        assert!(cells.is_empty());
     }
 
     #[actix_rt::test]
-    async fn create_and_list_cells() {
+    async fn test_circular_references() {
         let conn = Connection::open_in_memory().unwrap();
         init_db(&conn);
         let data = web::Data::new(AppState {
@@ -562,41 +3895,77 @@ mod tests {
         let app = test::init_service(
             App::new()
                 .app_data(data.clone())
-                .route("/health", web::get().to(health))
+                .route("/cells", web::post().to(set_cell))
                 .route("/cells", web::get().to(list_cells))
-                .route("/cells", web::post().to(set_cell)),
+                .route("/cells/bulk", web::post().to(set_cells_bulk)),
         )
         .await;
 
-        let new_cell = Cell {
-            sheet: Some("test".into()),
-            row: 1,
-            col: 1,
-            value: "42".into(),
-            font_weight: None,
-            font_style: None,
-            background_color: None,
-        };
-        let req = test::TestRequest::post()
-            .uri("/cells")
-            .set_json(&new_cell)
-            .to_request();
-        let resp = test::call_service(&app, req).await;
+        // A1 -> B1 -> A1
+        let cells: Vec<Cell> = [(0, "=B1+1"), (1, "=A1+1")]
+            .into_iter()
+            .map(|(col, value)| input("test", 0, col, value))
+            .collect();
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::post()
+                .uri("/cells/bulk")
+                .set_json(&cells)
+                .to_request(),
+        )
+        .await;
         assert!(resp.status().is_success());
+        let bytes = to_bytes(resp.into_body()).await.unwrap();
+        let saved: SaveResponse = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(
+            saved.circular_references,
+            vec![vec!["test!A1", "test!B1", "test!A1"]]
+        );
 
-        let req = test::TestRequest::get()
-            .uri("/cells?sheet=test")
-            .to_request();
-        let resp = test::call_service(&app, req).await;
-        assert!(resp.status().is_success());
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri("/cells?sheet=test")
+                .to_request(),
+        )
+        .await;
         let bytes = to_bytes(resp.into_body()).await.unwrap();
         let cells: Vec<Cell> = serde_json::from_slice(&bytes).unwrap();
-        assert_eq!(cells.len(), 1);
-        assert_eq!(cells[0].value, "42");
+        assert!(
+            cells
+                .iter()
+                .all(|c| c.value == "#CIRC!" && c.value_type == Some(ValueType::Error))
+        );
+
+        // Breaking the loop lets A1 evaluate again
+        let cell_b1 = input("test", 0, 1, "1");
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::post()
+                .uri("/cells")
+                .set_json(&cell_b1)
+                .to_request(),
+        )
+        .await;
+        let bytes = to_bytes(resp.into_body()).await.unwrap();
+        let saved: SaveResponse = serde_json::from_slice(&bytes).unwrap();
+        assert!(saved.circular_references.is_empty());
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri("/cells?sheet=test")
+                .to_request(),
+        )
+        .await;
+        let bytes = to_bytes(resp.into_body()).await.unwrap();
+        let cells: Vec<Cell> = serde_json::from_slice(&bytes).unwrap();
+        let a1 = cells.iter().find(|c| c.col == 0).unwrap();
+        assert_eq!(a1.value, "2");
     }
 
     #[actix_rt::test]
-    async fn evaluate_formula() {
+    async fn test_range_references() {
         let conn = Connection::open_in_memory().unwrap();
         init_db(&conn);
         let data = web::Data::new(AppState {
@@ -606,25 +3975,67 @@ mod tests {
         let app = test::init_service(
             App::new()
                 .app_data(data.clone())
-                .route("/evaluate", web::post().to(evaluate)),
+                .route("/cells", web::post().to(set_cell))
+                .route("/cells", web::get().to(list_cells))
+                .route("/cells/bulk", web::post().to(set_cells_bulk)),
         )
         .await;
 
-        let req = test::TestRequest::post()
-            .uri("/evaluate")
-            .set_json(&EvalRequest {
-                expr: "=SUM(1,2,3)".into(),
-                sheet: Some("test".into()),
-            })
-            .to_request();
-        let resp = test::call_service(&app, req).await;
-        assert!(resp.status().is_success());
+        let cells: Vec<Cell> = [
+            (0, 0, "1"),
+            (1, 0, "2"),
+            (2, 0, "3"),
+            (0, 1, "=SUM(A1:A3)"),
+            (1, 1, "=SUM(A:A)"),
+            (2, 1, "=AVERAGE(1:1)"),
+        ]
+        .into_iter()
+        .map(|(row, col, value)| input("test", row, col, value))
+        .collect();
+        test::call_service(
+            &app,
+            test::TestRequest::post()
+                .uri("/cells/bulk")
+                .set_json(&cells)
+                .to_request(),
+        )
+        .await;
+
+        // A2 feeds B1 and B2 directly, and B3 through B1
+        let cell_a2 = input("test", 1, 0, "10");
+        test::call_service(
+            &app,
+            test::TestRequest::post()
+                .uri("/cells")
+                .set_json(&cell_a2)
+                .to_request(),
+        )
+        .await;
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri("/cells?sheet=test")
+                .to_request(),
+        )
+        .await;
         let bytes = to_bytes(resp.into_body()).await.unwrap();
-        assert_eq!(&bytes[..], b"6");
+        let cells: Vec<Cell> = serde_json::from_slice(&bytes).unwrap();
+        let value_at = |row: i32| {
+            cells
+                .iter()
+                .find(|c| c.row == row && c.col == 1)
+                .unwrap()
+                .value
+                .clone()
+        };
+        assert_eq!(value_at(0), "14");
+        assert_eq!(value_at(1), "14");
+        assert_eq!(value_at(2), "7.5");
     }
 
     #[actix_rt::test]
-    async fn evaluate_average() {
+    async fn cells_beyond_the_sheet_are_rejected() {
         let conn = Connection::open_in_memory().unwrap();
         init_db(&conn);
         let data = web::Data::new(AppState {
@@ -634,25 +4045,54 @@ mod tests {
         let app = test::init_service(
             App::new()
                 .app_data(data.clone())
-                .route("/evaluate", web::post().to(evaluate)),
+                .route("/cells", web::get().to(list_cells))
+                .route("/cells", web::post().to(set_cell))
+                .route("/cells/bulk", web::post().to(set_cells_bulk)),
         )
         .await;
 
-        let req = test::TestRequest::post()
-            .uri("/evaluate")
-            .set_json(&EvalRequest {
-                expr: "=AVERAGE(2,4,6)".into(),
-                sheet: Some("test".into()),
-            })
-            .to_request();
-        let resp = test::call_service(&app, req).await;
-        assert!(resp.status().is_success());
-        let bytes = to_bytes(resp.into_body()).await.unwrap();
-        assert_eq!(&bytes[..], b"4");
+        let last_row = formula::MAX_ROWS - 1;
+        assert!(
+            post(&app, "/cells", input("test", last_row, 0, "1"))
+                .await
+                .is_success()
+        );
+        assert!(
+            post(&app, "/cells", input("test", 0, 1, "=SUM(A:A)"))
+                .await
+                .is_success()
+        );
+        for (row, col) in [
+            (formula::MAX_ROWS, 0),
+            (2_000_000, 0),
+            (0, formula::MAX_COLS),
+            (-1, 0),
+            (0, -1),
+        ] {
+            assert_eq!(
+                post(&app, "/cells", input("test", row, col, "5")).await,
+                StatusCode::BAD_REQUEST
+            );
+            assert_eq!(
+                post(
+                    &app,
+                    "/cells/bulk",
+                    vec![input("test", 1, 0, "5"), input("test", row, col, "5")]
+                )
+                .await,
+                StatusCode::BAD_REQUEST
+            );
+        }
+
+        // Nothing was written, so the sum still only sees the last row.
+        let cells = cells(&app, "test").await;
+        assert_eq!(cells.len(), 2);
+        let sum = cells.iter().find(|cell| cell.col == 1).unwrap();
+        assert_eq!(sum.value, "1");
     }
 
     #[actix_rt::test]
-    async fn set_formula_cell() {
+    async fn test_cross_sheet_references() {
         let conn = Connection::open_in_memory().unwrap();
         init_db(&conn);
         let data = web::Data::new(AppState {
@@ -663,37 +4103,72 @@ mod tests {
             App::new()
                 .app_data(data.clone())
                 .route("/cells", web::post().to(set_cell))
-                .route("/cells", web::get().to(list_cells)),
+                .route("/cells", web::get().to(list_cells))
+                .route("/cells/bulk", web::post().to(set_cells_bulk)),
         )
         .await;
 
-        let new_cell = Cell {
-            sheet: Some("test".into()),
-            row: 1,
-            col: 1,
-            value: "=SUM(2,3)".into(),
+        // Raw data on "My Data", summaries on "summary"
+        let cells: Vec<Cell> = [
+            ("My Data", 0, 0, "4"),
+            ("My Data", 1, 0, "6"),
+            ("summary", 0, 0, "='My Data'!A1*2"),
+            ("summary", 1, 0, "=SUM('My Data'!A1:A10)"),
+            ("summary", 2, 0, "=A1+A2"),
+        ]
+        .into_iter()
+        .map(|(sheet, row, col, value)| Cell {
+            sheet: Some(sheet.into()),
+            row,
+            col,
+            value: value.into(),
+            formula: None,
+            value_type: None,
             font_weight: None,
             font_style: None,
             background_color: None,
-        };
-        let req = test::TestRequest::post()
-            .uri("/cells")
-            .set_json(&new_cell)
-            .to_request();
-        let resp = test::call_service(&app, req).await;
-        assert!(resp.status().is_success());
+            spill: None,
+            spilled_from: None,
+            number: None,
+        })
+        .collect();
+        test::call_service(
+            &app,
+            test::TestRequest::post()
+                .uri("/cells/bulk")
+                .set_json(&cells)
+                .to_request(),
+        )
+        .await;
 
-        let req = test::TestRequest::get()
-            .uri("/cells?sheet=test")
-            .to_request();
-        let resp = test::call_service(&app, req).await;
+        // Changing the data recalculates the summaries on the other sheet
+        let cell_a1 = input("My Data", 0, 0, "10");
+        test::call_service(
+            &app,
+            test::TestRequest::post()
+                .uri("/cells")
+                .set_json(&cell_a1)
+                .to_request(),
+        )
+        .await;
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri("/cells?sheet=summary")
+                .to_request(),
+        )
+        .await;
         let bytes = to_bytes(resp.into_body()).await.unwrap();
         let cells: Vec<Cell> = serde_json::from_slice(&bytes).unwrap();
-        assert_eq!(cells[0].value, "5");
+        let value_at = |row: i32| cells.iter().find(|c| c.row == row).unwrap().value.clone();
+        assert_eq!(value_at(0), "20");
+        assert_eq!(value_at(1), "16");
+        assert_eq!(value_at(2), "36");
     }
 
     #[actix_rt::test]
-    async fn test_cell_with_formatting() {
+    async fn test_copy_shifts_relative_references() {
         let conn = Connection::open_in_memory().unwrap();
         init_db(&conn);
         let data = web::Data::new(AppState {
@@ -703,103 +4178,93 @@ mod tests {
         let app = test::init_service(
             App::new()
                 .app_data(data.clone())
-                .route("/cells", web::post().to(set_cell))
-                .route("/cells", web::get().to(list_cells)),
+                .route("/cells", web::get().to(list_cells))
+                .route("/cells/bulk", web::post().to(set_cells_bulk))
+                .route("/cells/copy", web::post().to(copy_cells_range)),
         )
         .await;
 
-        let new_cell = Cell {
+        let cells: Vec<Cell> = [
+            (0, 0, "10"),
+            (1, 0, "20"),
+            (2, 0, "30"),
+            (0, 1, "=A1*$A$1+A$1"),
+        ]
+        .into_iter()
+        .map(|(row, col, value)| Cell {
             sheet: Some("test".into()),
-            row: 0,
-            col: 0,
-            value: "Formatted".into(),
+            row,
+            col,
+            value: value.into(),
+            formula: None,
+            value_type: None,
             font_weight: Some("bold".into()),
-            font_style: Some("italic".into()),
-            background_color: Some("#ff0000".into()),
-        };
-        let req = test::TestRequest::post()
-            .uri("/cells")
-            .set_json(&new_cell)
-            .to_request();
-        let resp = test::call_service(&app, req).await;
-        assert!(resp.status().is_success());
-
-        let req = test::TestRequest::get()
-            .uri("/cells?sheet=test")
-            .to_request();
-        let resp = test::call_service(&app, req).await;
-        let bytes = to_bytes(resp.into_body()).await.unwrap();
-        let cells: Vec<Cell> = serde_json::from_slice(&bytes).unwrap();
-        assert_eq!(cells[0].value, "Formatted");
-        assert_eq!(cells[0].font_weight, Some("bold".into()));
-        assert_eq!(cells[0].font_style, Some("italic".into()));
-        assert_eq!(cells[0].background_color, Some("#ff0000".into()));
-    }
-
-    #[actix_rt::test]
-    async fn test_bulk_operations() {
-        let conn = Connection::open_in_memory().unwrap();
-        init_db(&conn);
-        let data = web::Data::new(AppState {
-            db: Mutex::new(conn),
-            sessions: Arc::new(Mutex::new(HashMap::new())),
-        });
-        let app = test::init_service(
-            App::new()
-                .app_data(data.clone())
-                .route("/cells/bulk", web::post().to(set_cells_bulk))
-                .route("/cells", web::get().to(list_cells)),
+            font_style: None,
+            background_color: None,
+            spill: None,
+            spilled_from: None,
+            number: None,
+        })
+        .collect();
+        test::call_service(
+            &app,
+            test::TestRequest::post()
+                .uri("/cells/bulk")
+                .set_json(&cells)
+                .to_request(),
         )
         .await;
 
-        let cells = vec![
-            Cell {
-                sheet: Some("test".into()),
-                row: 0,
-                col: 0,
-                value: "1".into(),
-                font_weight: None,
-                font_style: None,
-                background_color: None,
-            },
-            Cell {
-                sheet: Some("test".into()),
-                row: 1,
-                col: 0,
-                value: "2".into(),
-                font_weight: None,
-                font_style: None,
-                background_color: None,
-            },
-            Cell {
+        // Fill B1 down to B3, then paste A1:B1 with its top-left at D5
+        for (source, destination, destination_sheet) in [
+            (CellRange::new(0, 1, 0, 1), CellRange::new(1, 1, 2, 1), None),
+            (
+                CellRange::new(0, 0, 0, 1),
+                CellRange::new(4, 3, 4, 3),
+                Some("other".to_string()),
+            ),
+        ] {
+            let request = CopyRequest {
                 sheet: Some("test".into()),
-                row: 2,
-                col: 0,
-                value: "3".into(),
-                font_weight: None,
-                font_style: None,
-                background_color: None,
-            },
-        ];
+                source,
+                destination_sheet,
+                destination,
+            };
+            assert!(post(&app, "/cells/copy", &request).await.is_success());
+        }
 
-        let req = test::TestRequest::post()
-            .uri("/cells/bulk")
-            .set_json(&cells)
-            .to_request();
-        let resp = test::call_service(&app, req).await;
-        assert!(resp.status().is_success());
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri("/cells?sheet=test")
+                .to_request(),
+        )
+        .await;
+        let bytes = to_bytes(resp.into_body()).await.unwrap();
+        let cells: Vec<Cell> = serde_json::from_slice(&bytes).unwrap();
+        let b2 = cells.iter().find(|c| c.row == 1 && c.col == 1).unwrap();
+        assert_eq!(b2.formula.as_deref(), Some("=A2*$A$1+A$1"));
+        assert_eq!(b2.value, "210");
+        assert_eq!(b2.font_weight.as_deref(), Some("bold"));
+        let b3 = cells.iter().find(|c| c.row == 2 && c.col == 1).unwrap();
+        assert_eq!(b3.formula.as_deref(), Some("=A3*$A$1+A$1"));
+        assert_eq!(b3.value, "310");
 
-        let req = test::TestRequest::get()
-            .uri("/cells?sheet=test")
-            .to_request();
-        let resp = test::call_service(&app, req).await;
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri("/cells?sheet=other")
+                .to_request(),
+        )
+        .await;
         let bytes = to_bytes(resp.into_body()).await.unwrap();
-        let result_cells: Vec<Cell> = serde_json::from_slice(&bytes).unwrap();
-        assert_eq!(result_cells.len(), 3);
+        let cells: Vec<Cell> = serde_json::from_slice(&bytes).unwrap();
+        let e5 = cells.iter().find(|c| c.row == 4 && c.col == 4).unwrap();
+        assert_eq!(e5.formula.as_deref(), Some("=D5*$A$1+D$1"));
     }
 
     #[actix_rt::test]
-    async fn test_clear_cells() {
+    async fn computed_numbers_keep_full_precision() {
         let conn = Connection::open_in_memory().unwrap();
         init_db(&conn);
         let data = web::Data::new(AppState {
@@ -809,55 +4274,27 @@ mod tests {
         let app = test::init_service(
             App::new()
                 .app_data(data.clone())
-                .route("/cells", web::post().to(set_cell))
                 .route("/cells", web::get().to(list_cells))
-                .route("/cells/clear", web::post().to(clear_cells_bulk)),
+                .route("/cells/bulk", web::post().to(set_cells_bulk)),
         )
         .await;
 
-        // Create a cell
-        let new_cell = Cell {
-            sheet: Some("test".into()),
-            row: 0,
-            col: 0,
-            value: "Delete Me".into(),
-            font_weight: None,
-            font_style: None,
-            background_color: None,
-        };
-        let req = test::TestRequest::post()
-            .uri("/cells")
-            .set_json(&new_cell)
-            .to_request();
-        test::call_service(&app, req).await;
-
-        // Clear the cell
-        let clear_request = ClearRequest {
-            cells: vec![CellPosition {
-                sheet: Some("test".into()),
-                row: 0,
-                col: 0,
-            }],
-        };
-        let req = test::TestRequest::post()
-            .uri("/cells/clear")
-            .set_json(&clear_request)
-            .to_request();
-        let resp = test::call_service(&app, req).await;
-        assert!(resp.status().is_success());
+        // A1 shows 0.333333333333333, but B1 to D1 see the exact third.
+        let batch: Vec<Cell> = ["=1/3", "=A1*3", "=A1*3=1", "=B1-1"]
+            .iter()
+            .enumerate()
+            .map(|(col, value)| input("test", 0, col as i32, value))
+            .collect();
+        assert!(post(&app, "/cells/bulk", &batch).await.is_success());
 
-        // Verify cell is deleted
-        let req = test::TestRequest::get()
-            .uri("/cells?sheet=test")
-            .to_request();
-        let resp = test::call_service(&app, req).await;
-        let bytes = to_bytes(resp.into_body()).await.unwrap();
-        let cells: Vec<Cell> = serde_json::from_slice(&bytes).unwrap();
-        assert_eq!(cells.len(), 0);
+        let mut cells = cells(&app, "test").await;
+        cells.sort_by_key(|cell| cell.col);
+        let values: Vec<&str> = cells.iter().map(|cell| cell.value.as_str()).collect();
+        assert_eq!(values, ["0.333333333333333", "1", "TRUE", "0"]);
     }
 
     #[actix_rt::test]
-    async fn test_formula_with_cell_references() {
+    async fn test_typed_values_and_errors() {
         let conn = Connection::open_in_memory().unwrap();
         init_db(&conn);
         let data = web::Data::new(AppState {
@@ -867,70 +4304,38 @@ mod tests {
         let app = test::init_service(
             App::new()
                 .app_data(data.clone())
-                .route("/cells", web::post().to(set_cell))
-                .route("/cells", web::get().to(list_cells)),
-        )
-        .await;
-
-        // Create A1 with value 10
-        let cell_a1 = Cell {
-            sheet: Some("test".into()),
-            row: 0,
-            col: 0,
-            value: "10".into(),
-            font_weight: None,
-            font_style: None,
-            background_color: None,
-        };
-        test::call_service(
-            &app,
-            test::TestRequest::post()
-                .uri("/cells")
-                .set_json(&cell_a1)
-                .to_request(),
+                .route("/cells", web::get().to(list_cells))
+                .route("/cells/bulk", web::post().to(set_cells_bulk)),
         )
         .await;
 
-        // Create B1 with value 20
-        let cell_b1 = Cell {
-            sheet: Some("test".into()),
-            row: 0,
-            col: 1,
-            value: "20".into(),
-            font_weight: None,
-            font_style: None,
-            background_color: None,
-        };
+        let inputs = [
+            "10",
+            "hello",
+            "true",
+            "=A1/0",
+            "=A4+1",
+            "=A2*2",
+            "=NOPE(1)",
+            "=SUM(A1:A3)",
+            "=A1+A3",
+            "=A2&\" world\"",
+            "=#N/A",
+        ];
+        let cells: Vec<Cell> = inputs
+            .iter()
+            .enumerate()
+            .map(|(row, value)| input("test", row as i32, 0, value))
+            .collect();
         test::call_service(
             &app,
             test::TestRequest::post()
-                .uri("/cells")
-                .set_json(&cell_b1)
-                .to_request(),
-        )
-        .await;
-
-        // Create C1 with formula =SUM(A1,B1)
-        let cell_c1 = Cell {
-            sheet: Some("test".into()),
-            row: 0,
-            col: 2,
-            value: "=SUM(A1,B1)".into(),
-            font_weight: None,
-            font_style: None,
-            background_color: None,
-        };
-        let resp = test::call_service(
-            &app,
-            test::TestRequest::post()
-                .uri("/cells")
-                .set_json(&cell_c1)
+                .uri("/cells/bulk")
+                .set_json(&cells)
                 .to_request(),
         )
         .await;
-        assert!(resp.status().is_success());
 
-        // Verify C1 contains evaluated result
         let resp = test::call_service(
             &app,
             test::TestRequest::get()
@@ -939,9 +4344,40 @@ mod tests {
         )
         .await;
         let bytes = to_bytes(resp.into_body()).await.unwrap();
-        let cells: Vec<Cell> = serde_json::from_slice(&bytes).unwrap();
-        let cell_c1_result = cells.iter().find(|c| c.row == 0 && c.col == 2).unwrap();
-        assert_eq!(cell_c1_result.value, "30");
+        let json: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        let at = |row: i64| {
+            let cell = json
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|c| c["row"] == row)
+                .unwrap();
+            (
+                cell["value"].as_str().unwrap().to_string(),
+                cell["value_type"].as_str().unwrap().to_string(),
+            )
+        };
+        let expected = [
+            ("10", "number"),
+            ("hello", "text"),
+            ("TRUE", "boolean"),
+            ("#DIV/0!", "error"),
+            ("#DIV/0!", "error"),
+            ("#VALUE!", "error"),
+            ("#NAME?", "error"),
+            ("10", "number"),
+            ("11", "number"),
+            ("hello world", "text"),
+            ("#N/A", "error"),
+        ];
+        for (row, (value, value_type)) in expected.into_iter().enumerate() {
+            assert_eq!(
+                at(row as i64),
+                (value.into(), value_type.into()),
+                "row {}",
+                row
+            );
+        }
     }
 
     #[actix_rt::test]
@@ -961,15 +4397,7 @@ mod tests {
         .await;
 
         // Add cell to sheet1
-        let cell1 = Cell {
-            sheet: Some("sheet1".into()),
-            row: 0,
-            col: 0,
-            value: "Sheet 1 Data".into(),
-            font_weight: None,
-            font_style: None,
-            background_color: None,
-        };
+        let cell1 = input("sheet1", 0, 0, "Sheet 1 Data");
         test::call_service(
             &app,
             test::TestRequest::post()
@@ -980,15 +4408,7 @@ mod tests {
         .await;
 
         // Add cell to sheet2
-        let cell2 = Cell {
-            sheet: Some("sheet2".into()),
-            row: 0,
-            col: 0,
-            value: "Sheet 2 Data".into(),
-            font_weight: None,
-            font_style: None,
-            background_color: None,
-        };
+        let cell2 = input("sheet2", 0, 0, "Sheet 2 Data");
         test::call_service(
             &app,
             test::TestRequest::post()
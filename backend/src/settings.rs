@@ -0,0 +1,76 @@
+//! Workbook settings. They live in the database alongside the cells, in a
+//! table holding a single row, so each workbook keeps its own.
+
+use rusqlite::{Connection, OptionalExtension, params};
+use serde::{Deserialize, Serialize};
+
+/// How reference loops are calculated.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Calculation {
+    /// Evaluate the formulas in a loop repeatedly, each reading the others'
+    /// latest values, rather than showing `#CIRC!`.
+    pub iterative: bool,
+    /// The most passes made over a loop.
+    pub max_iterations: u32,
+    /// A loop settles once no value in it moves by more than this in a pass.
+    pub max_change: f64,
+}
+
+impl Default for Calculation {
+    /// Excel's defaults: loops are errors, and iterating stops after 100
+    /// passes or a change of at most 0.001.
+    fn default() -> Self {
+        Calculation {
+            iterative: false,
+            max_iterations: 100,
+            max_change: 0.001,
+        }
+    }
+}
+
+impl Calculation {
+    /// Why the settings cannot be used, if they cannot.
+    pub fn validate(&self) -> Result<(), String> {
+        if !(1..=32767).contains(&self.max_iterations) {
+            return Err("max_iterations must be between 1 and 32767".into());
+        }
+        if !self.max_change.is_finite() || self.max_change < 0.0 {
+            return Err("max_change must be a number of at least 0".into());
+        }
+        Ok(())
+    }
+}
+
+/// The workbook's calculation settings, or the defaults if none were saved.
+pub fn calculation(conn: &Connection) -> rusqlite::Result<Calculation> {
+    let saved = conn
+        .prepare_cached(
+            "SELECT iterative, max_iterations, max_change FROM calculation_settings WHERE id = 1",
+        )?
+        .query_row([], |r| {
+            Ok(Calculation {
+                iterative: r.get(0)?,
+                max_iterations: r.get(1)?,
+                max_change: r.get(2)?,
+            })
+        })
+        .optional()?;
+    Ok(saved.unwrap_or_default())
+}
+
+/// Replaces the workbook's calculation settings.
+pub fn save_calculation(conn: &Connection, settings: &Calculation) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO calculation_settings (id, iterative, max_iterations, max_change)
+         VALUES (1, ?1, ?2, ?3)
+         ON CONFLICT(id) DO UPDATE SET iterative = excluded.iterative,
+             max_iterations = excluded.max_iterations, max_change = excluded.max_change",
+        params![
+            settings.iterative,
+            settings.max_iterations,
+            settings.max_change
+        ],
+    )?;
+    Ok(())
+}
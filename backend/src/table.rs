@@ -0,0 +1,133 @@
+//! Tables: ranges whose first row names their columns, so formulas can
+//! refer to them as `Sales[Amount]` rather than by address.
+//!
+//! A table is stored as its name, sheet and full range, header row
+//! included. Column names are not stored; they are whatever the header
+//! cells hold when a formula is evaluated.
+
+use crate::graph::CellRange;
+use rusqlite::{Connection, OptionalExtension, params};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Table {
+    pub name: String,
+    pub sheet: String,
+    /// The header row and the data below it.
+    pub range: CellRange,
+}
+
+impl Table {
+    fn from_row(r: &rusqlite::Row) -> rusqlite::Result<Self> {
+        Ok(Table {
+            name: r.get(0)?,
+            sheet: r.get(1)?,
+            range: CellRange::new(r.get(2)?, r.get(3)?, r.get(4)?, r.get(5)?),
+        })
+    }
+
+    /// The header row.
+    pub fn headers(&self) -> CellRange {
+        CellRange {
+            end_row: self.range.start_row,
+            ..self.range
+        }
+    }
+
+    /// The rows below the header. A table always has at least one.
+    pub fn data(&self) -> CellRange {
+        CellRange {
+            start_row: self.range.start_row + 1,
+            ..self.range
+        }
+    }
+}
+
+/// The table called `name`, ignoring case.
+pub fn find(conn: &Connection, name: &str) -> rusqlite::Result<Option<Table>> {
+    conn.prepare_cached(
+        "SELECT name, sheet, start_row, start_col, end_row, end_col FROM tables WHERE name = ?1",
+    )?
+    .query_row(params![name], Table::from_row)
+    .optional()
+}
+
+/// Every table, by name.
+pub fn list(conn: &Connection) -> rusqlite::Result<Vec<Table>> {
+    conn.prepare_cached(
+        "SELECT name, sheet, start_row, start_col, end_row, end_col FROM tables ORDER BY name",
+    )?
+    .query_map([], Table::from_row)?
+    .collect()
+}
+
+/// Saves `table`, or moves the table of that name to its new range.
+pub fn save(conn: &Connection, table: &Table) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO tables (name, sheet, start_row, start_col, end_row, end_col)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+         ON CONFLICT(name) DO UPDATE SET sheet = excluded.sheet,
+             start_row = excluded.start_row, start_col = excluded.start_col,
+             end_row = excluded.end_row, end_col = excluded.end_col",
+        params![
+            table.name,
+            table.sheet,
+            table.range.start_row,
+            table.range.start_col,
+            table.range.end_row,
+            table.range.end_col
+        ],
+    )?;
+    Ok(())
+}
+
+/// The column names: the text shown in each header cell, left to right,
+/// with blank headers as empty strings.
+pub fn columns(conn: &Connection, table: &Table) -> rusqlite::Result<Vec<String>> {
+    let headers = table.headers();
+    let mut names = vec![String::new(); headers.cols() as usize];
+    let mut stmt = conn.prepare_cached(
+        "SELECT col, value FROM cells
+         WHERE sheet = ?1 AND row = ?2 AND col BETWEEN ?3 AND ?4",
+    )?;
+    let cells = stmt.query_map(
+        params![
+            table.sheet,
+            headers.start_row,
+            headers.start_col,
+            headers.end_col
+        ],
+        |r| Ok((r.get::<_, i32>(0)?, r.get::<_, Option<String>>(1)?)),
+    )?;
+    for cell in cells {
+        let (col, value) = cell?;
+        names[(col - headers.start_col) as usize] = value.unwrap_or_default();
+    }
+    Ok(names)
+}
+
+/// The table on `sheet` whose range overlaps `range`, other than `except`.
+pub fn overlapping(
+    conn: &Connection,
+    sheet: &str,
+    range: &CellRange,
+    except: &str,
+) -> rusqlite::Result<Option<Table>> {
+    conn.prepare_cached(
+        "SELECT name, sheet, start_row, start_col, end_row, end_col FROM tables
+         WHERE sheet = ?1 AND name <> ?2
+           AND start_row <= ?5 AND end_row >= ?3 AND start_col <= ?6 AND end_col >= ?4
+         LIMIT 1",
+    )?
+    .query_row(
+        params![
+            sheet,
+            except,
+            range.start_row,
+            range.start_col,
+            range.end_row,
+            range.end_col
+        ],
+        Table::from_row,
+    )
+    .optional()
+}
@@ -182,7 +182,7 @@ test_formulas() {
   # Test SUM function
   local res
   res=$(curl -sf -X POST "$BASE_URL/evaluate" -H 'Content-Type: application/json' \
-    -d '{"expr":"=SUM(1,2,3,4,5)"}' || fail "evaluate SUM formula")
+    -d '{"expr":"=SUM(1,2,3,4,5)"}' | jq -r '.display' || fail "evaluate SUM formula")
   if [[ "$res" == "15" ]]; then
     pass "SUM formula evaluation"
   else
@@ -191,7 +191,7 @@ test_formulas() {
   
   # Test AVERAGE function
   res=$(curl -sf -X POST "$BASE_URL/evaluate" -H 'Content-Type: application/json' \
-    -d '{"expr":"=AVERAGE(2,4,6)"}' || fail "evaluate AVERAGE formula")
+    -d '{"expr":"=AVERAGE(2,4,6)"}' | jq -r '.display' || fail "evaluate AVERAGE formula")
   if [[ "$res" == "4" ]]; then
     pass "AVERAGE formula evaluation"
   else
@@ -200,7 +200,7 @@ test_formulas() {
   
   # Test basic arithmetic
   res=$(curl -sf -X POST "$BASE_URL/evaluate" -H 'Content-Type: application/json' \
-    -d '{"expr":"=10+5*2"}' || fail "evaluate arithmetic formula")
+    -d '{"expr":"=10+5*2"}' | jq -r '.display' || fail "evaluate arithmetic formula")
   if [[ "$res" == "20" ]]; then
     pass "arithmetic formula evaluation"
   else
@@ -209,7 +209,7 @@ test_formulas() {
   
   # Test complex expression
   res=$(curl -sf -X POST "$BASE_URL/evaluate" -H 'Content-Type: application/json' \
-    -d '{"expr":"=SUM(1,2,3) + AVERAGE(4,6)"}' || fail "evaluate complex formula")
+    -d '{"expr":"=SUM(1,2,3) + AVERAGE(4,6)"}' | jq -r '.display' || fail "evaluate complex formula")
   if [[ "$res" == "11" ]]; then
     pass "complex formula evaluation"
   else
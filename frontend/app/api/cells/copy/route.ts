@@ -0,0 +1,36 @@
+import { NextRequest, NextResponse } from 'next/server';
+
+const BACKEND_URL = process.env.BACKEND_URL || 'http://192.168.10.161:6889';
+
+interface CellRange {
+  start_row: number;
+  start_col: number;
+  end_row: number;
+  end_col: number;
+}
+
+interface CopyRequest {
+  sheet?: string;
+  source: CellRange;
+  destination_sheet?: string;
+  destination: CellRange;
+}
+
+export async function POST(request: NextRequest) {
+  try {
+    const body = await request.json() as CopyRequest;
+    const response = await fetch(`${BACKEND_URL}/cells/copy`, {
+      method: 'POST',
+      headers: {
+        'Content-Type': 'application/json',
+      },
+      body: JSON.stringify(body),
+    });
+
+    const result = await response.text();
+    return new NextResponse(result, { status: response.status });
+  } catch (error) {
+    console.error('Error copying cells:', error);
+    return NextResponse.json({ error: 'Failed to copy cells' }, { status: 500 });
+  }
+}
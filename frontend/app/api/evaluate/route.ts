@@ -14,13 +14,10 @@ export async function POST(request: NextRequest) {
       },
       body: JSON.stringify(body),
     });
-    
-    if (!response.ok) {
-      throw new Error(`HTTP error! status: ${response.status}`);
-    }
-    
-    const result = await response.text();
-    return new NextResponse(result, { status: 200 });
+
+    // Parse errors come back as JSON too, so pass them on as they are.
+    const result = await response.json();
+    return NextResponse.json(result, { status: response.status });
   } catch (error) {
     console.error('Error evaluating formula:', error);
     return NextResponse.json({ error: 'Failed to evaluate formula' }, { status: 500 });
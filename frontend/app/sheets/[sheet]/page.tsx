@@ -7,9 +7,15 @@ interface Cell {
   row: number;
   col: number;
   value: string;
+  formula?: string;
+  value_type?: 'number' | 'text' | 'boolean' | 'date' | 'empty' | 'error';
   font_weight?: string;
   font_style?: string;
   background_color?: string;
+  // Set on a formula whose array result spills over this block
+  spill?: { start_row: number; start_col: number; end_row: number; end_col: number };
+  // Set on cells filled by another formula's array result; edits go to that formula
+  spilled_from?: { row: number; col: number };
 }
 
 // Dynamic infinite grid - no more hardcoded limits
@@ -79,12 +85,17 @@ export default function SheetPage() {
         const data = JSON.parse(event.data);
         
         if (data.row !== undefined && data.col !== undefined) {
+          // Recalculation pushes updates for every sheet; keep this one's
+          if (data.sheet !== undefined && data.sheet !== sheet) {
+            return;
+          }
           // Cell update from another user
           setCells(prev => {
             const existing = prev.findIndex(c => c.row === data.row && c.col === data.col);
             if (existing >= 0) {
               const updated = [...prev];
-              updated[existing] = { ...updated[existing], ...data };
+              // Spill fields are omitted when unset, so clear them before merging
+              updated[existing] = { ...updated[existing], spill: undefined, spilled_from: undefined, ...data };
               return updated;
             } else {
               return [...prev, data];
@@ -114,7 +125,7 @@ export default function SheetPage() {
     return () => {
       websocket.close();
     };
-  }, []);
+  }, [sheet]);
 
   // Update visible area when scrolling or container size changes
   useEffect(() => {
@@ -205,13 +216,17 @@ export default function SheetPage() {
       // Enter key to edit cell
       if (primarySelection && e.key === 'Enter') {
         e.preventDefault();
-        setEditingCell(primarySelection);
-        setEditValue(getCellValue(primarySelection.row, primarySelection.col));
+        const target = editTarget(primarySelection.row, primarySelection.col);
+        selectSingleCell(target.row, target.col);
+        setEditingCell(target);
+        setEditValue(getCellInput(target.row, target.col));
         return;
       }
 
       if (primarySelection && e.key.length === 1 && !e.ctrlKey && !e.metaKey) {
         e.preventDefault();
+        // Spilled cells can't be typed over
+        if (isSpilled(primarySelection.row, primarySelection.col)) return;
         setEditingCell(primarySelection);
         setEditValue(e.key);
         return;
@@ -241,6 +256,23 @@ export default function SheetPage() {
     return cell?.value || "";
   };
 
+  // Raw input for editing: the formula source if the cell has one, otherwise its value
+  const getCellInput = (row: number, col: number) => {
+    const cell = cells.find(c => c.row === row && c.col === col);
+    return cell?.formula || cell?.value || "";
+  };
+
+  const isSpilled = (row: number, col: number) => {
+    const cell = cells.find(c => c.row === row && c.col === col);
+    return cell?.spilled_from !== undefined;
+  };
+
+  // Spilled cells are computed by the formula they spill from, so edit that instead
+  const editTarget = (row: number, col: number) => {
+    const cell = cells.find(c => c.row === row && c.col === col);
+    return cell?.spilled_from ? { row: cell.spilled_from.row, col: cell.spilled_from.col } : { row, col };
+  };
+
   const getCellFormatting = (row: number, col: number) => {
     const cell = cells.find(c => c.row === row && c.col === col);
     return {
@@ -702,9 +734,24 @@ export default function SheetPage() {
     // const extendLeft = targetCol < minCol;
     // const extendUp = targetRow < minRow;
     
+    // Formulas are filled on the server, which shifts their relative references
+    const hasFormulas = cells.some(c =>
+      c.formula && c.row >= minRow && c.row <= maxRow && c.col >= minCol && c.col <= maxCol
+    );
+    if (hasFormulas && (extendRight || extendDown)) {
+      await copyCells(
+        { start_row: minRow, start_col: minCol, end_row: maxRow, end_col: maxCol },
+        extendRight
+          ? { start_row: minRow, start_col: maxCol + 1, end_row: maxRow, end_col: targetCol }
+          : { start_row: maxRow + 1, start_col: minCol, end_row: targetRow, end_col: maxCol }
+      );
+    }
+
     const cellsToUpdate: Cell[] = [];
     
-    if (extendRight) {
+    if (hasFormulas) {
+      // Already filled above
+    } else if (extendRight) {
       // Extend to the right
       for (let r = minRow; r <= maxRow; r++) {
         const rowValues: string[] = [];
@@ -833,6 +880,35 @@ export default function SheetPage() {
     }
   };
 
+  const copyCells = async (
+    source: { start_row: number, start_col: number, end_row: number, end_col: number },
+    destination: { start_row: number, start_col: number, end_row: number, end_col: number },
+  ) => {
+    try {
+      const response = await fetch('/api/cells/copy', {
+        method: "POST",
+        headers: { "Content-Type": "application/json" },
+        body: JSON.stringify({ sheet, source, destination }),
+      });
+
+      if (!response.ok) {
+        const errorText = await response.text();
+        setError(`Failed to fill cells: ${errorText}`);
+        setTimeout(() => setError(null), 5000);
+        return;
+      }
+
+      // The server computed the copied formulas; reload to pick them up
+      const res = await fetch(`/api/cells?sheet=${sheet}`);
+      if (res.ok) {
+        setCells(await res.json());
+      }
+    } catch (err) {
+      setError(`Failed to fill cells: ${err instanceof Error ? err.message : 'Network error'}`);
+      setTimeout(() => setError(null), 5000);
+    }
+  };
+
   const clearCellsBulk = async (positions: {row: number, col: number}[]) => {
     const url = '/api/cells/clear';
     try {
@@ -968,9 +1044,10 @@ export default function SheetPage() {
   };
 
   const handleCellDoubleClick = (row: number, col: number) => {
-    selectSingleCell(row, col);
-    setEditingCell({ row, col });
-    setEditValue(getCellValue(row, col));
+    const target = editTarget(row, col);
+    selectSingleCell(target.row, target.col);
+    setEditingCell(target);
+    setEditValue(getCellInput(target.row, target.col));
   };
 
   const handleCellEdit = (value: string) => {
@@ -1023,7 +1100,7 @@ export default function SheetPage() {
       return {
         row: cell.row,
         col: cell.col,
-        value: getCellValue(cell.row, cell.col),
+        value: getCellInput(cell.row, cell.col),
         font_weight: newFormatting.font_weight,
         font_style: newFormatting.font_style,
         background_color: newFormatting.background_color,
@@ -1067,15 +1144,15 @@ export default function SheetPage() {
         body: JSON.stringify({ expr: formula, sheet }),
       });
 
+      const body = await res.json();
       if (!res.ok) {
-        const errorText = await res.text();
-        setEvaluationResult(`Error: ${errorText}`);
+        setEvaluationResult(`Error: ${body.error?.message ?? body.error}`);
         setTimeout(() => setEvaluationResult(null), 5000);
         return;
       }
 
-      const result = await res.text();
-      setEvaluationResult(`Result: ${result}`);
+      const result: string = body.display;
+      setEvaluationResult(body.error ? `Result: ${result} (${body.error.message})` : `Result: ${result}`);
 
       // If a cell is selected, optionally insert the result
       if (primarySelection) {
@@ -1292,6 +1369,7 @@ export default function SheetPage() {
                         fontWeight: getCellFormatting(row, col).font_weight || 'normal',
                         fontStyle: getCellFormatting(row, col).font_style || 'normal',
                         backgroundColor: getCellFormatting(row, col).background_color || 'white',
+                        color: isSpilled(row, col) ? '#555' : undefined,
                         border: '1px solid #ddd',
                         boxSizing: 'border-box',
                       }}
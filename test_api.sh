@@ -161,7 +161,7 @@ echo "Running Formula Tests..."
 EVAL_DATA='{"expr": "=SUM(1,2,3)", "sheet": "'"$TEST_SHEET"'"}'
 RESPONSE=$(api_call POST "/evaluate" "$EVAL_DATA")
 HTTP_CODE=$(echo "$RESPONSE" | tail -n 1)
-BODY=$(echo "$RESPONSE" | head -n -1)
+BODY=$(echo "$RESPONSE" | head -n -1 | jq -r '.display')
 
 if [ "$HTTP_CODE" == "200" ] && [ "$BODY" == "6" ]; then
     print_test "SUM formula evaluates correctly" "PASS"
@@ -173,7 +173,7 @@ fi
 EVAL_DATA='{"expr": "=AVERAGE(2,4,6)", "sheet": "'"$TEST_SHEET"'"}'
 RESPONSE=$(api_call POST "/evaluate" "$EVAL_DATA")
 HTTP_CODE=$(echo "$RESPONSE" | tail -n 1)
-BODY=$(echo "$RESPONSE" | head -n -1)
+BODY=$(echo "$RESPONSE" | head -n -1 | jq -r '.display')
 
 if [ "$HTTP_CODE" == "200" ] && [ "$BODY" == "4" ]; then
     print_test "AVERAGE formula evaluates correctly" "PASS"